@@ -112,6 +112,8 @@ impl<'a> XmlRead<'a> for Expr {
                         break;
                     }
                 }
+            } else {
+                return Err(XmlError::UnexpectedEof);
             }
             // consume it
             let _ = reader.next();
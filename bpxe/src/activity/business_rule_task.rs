@@ -0,0 +1,326 @@
+//! # Business Rule Task flow node
+//!
+//! Business rule tasks delegate their decisions to the decision evaluator of the model (see
+//! [`crate::decision`]). The evaluator receives the input sets of the task: named ones by
+//! their names, the unnamed one as `input`. Output variables of the decision are set before
+//! the task passes its token on.
+//!
+//! Failing evaluations (including missing evaluators) are reported with
+//! [`Log::DecisionError`] and handled according to the failure policy of the task or its
+//! process (see [`crate::model::Model::with_failure_policy`]).
+//!
+//! If the model limits concurrency of the task (see
+//! [`crate::model::Model::with_concurrency_limit`]), the decision waits for its turn before
+//! it is evaluated, and the evaluation is abandoned once it exceeds the execution time limit
+//! of the task (see [`crate::model::Model::with_activity_time_limit`]).
+use crate::activity::{
+    acquire_permit, handle_failure, input_variables, time_limit_exceeded, within_time_limit,
+    Activity, InputSet, TaskCompletion,
+};
+use crate::bpmn::schema::{BusinessRuleTask as Element, FlowNodeType};
+use crate::decision::{self, decision_ref};
+use crate::error::ErrorReport;
+use crate::event::ProcessEvent;
+use crate::flow_node::{self, Action, FlowNode};
+use crate::process::{self, Incident, Log};
+use crate::sys::task;
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use tokio::sync::broadcast;
+
+/// Business Rule Task flow node
+pub struct Task {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    notifier: broadcast::Sender<TaskCompletion>,
+    notifier_receiver: broadcast::Receiver<TaskCompletion>,
+    input_sets: Vec<InputSet>,
+    sandboxed: bool,
+    process: Option<process::Handle>,
+}
+
+impl Task {
+    /// Creates new Business Rule Task flow node
+    pub fn new(element: Element) -> Self {
+        let (notifier, notifier_receiver) = broadcast::channel(1);
+        Self {
+            element: Arc::new(element),
+            state: State::Initialized,
+            waker: None,
+            notifier,
+            notifier_receiver,
+            input_sets: vec![],
+            sandboxed: false,
+            process: None,
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum State {
+    Initialized,
+    Ready,
+    Execute,
+    /// Waiting for the decision to be evaluated
+    Waiting,
+    Errored,
+    Done,
+}
+
+impl FlowNode for Task {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::BusinessRuleTask(state) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::BusinessRuleTask(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn set_process(&mut self, process: crate::process::Handle) {
+        self.sandboxed = process.is_sandboxed();
+        if let State::Initialized = self.state {
+            self.state = State::Ready;
+            self.wake();
+        }
+        self.process.replace(process);
+    }
+}
+
+impl Activity for Task {
+    fn execute(&mut self) {
+        self.state = State::Execute;
+        self.wake();
+    }
+
+    fn input_sets(&mut self, input_sets: Vec<InputSet>) {
+        self.input_sets = input_sets;
+    }
+}
+
+impl From<Element> for Task {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Task {
+    type Item = Action;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.state {
+            State::Initialized | State::Ready | State::Errored => {
+                self.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Execute if self.sandboxed => {
+                // decisions are not evaluated in a sandbox
+                self.state = State::Waiting;
+                let _ = self.notifier.send(TaskCompletion::Success(None));
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            State::Execute => {
+                self.state = State::Waiting;
+                let process = match self.process.clone() {
+                    Some(process) => process,
+                    None => {
+                        self.waker.replace(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                };
+                let waker = cx.waker().clone();
+                let element = self.element.clone();
+                let notifier = self.notifier.clone();
+                // We only need input once, we can drain it
+                let variables = input_variables(std::mem::take(&mut self.input_sets));
+                task::spawn(async move {
+                    let model = process.model();
+                    let decision = decision_ref(&model, &element).unwrap_or_default();
+                    let _permit = acquire_permit(&process, element.id.as_deref()).await;
+                    let evaluation = decision::evaluate(&model, &decision, variables);
+                    let result = match within_time_limit(
+                        &process,
+                        element.id.as_deref(),
+                        evaluation,
+                    )
+                    .await
+                    {
+                        Ok(result) => result,
+                        Err(limit) => {
+                            let completion =
+                                time_limit_exceeded(&process, element.id.as_ref(), limit).await;
+                            let _ = notifier.send(completion);
+                            waker.wake();
+                            return;
+                        }
+                    };
+                    let completion = match result {
+                        Ok(variables) => {
+                            for (id, value) in variables {
+                                if let Err(err) = process.set_variable(&id, value).await {
+                                    let _ = process.log_broadcast().send(Log::DecisionError {
+                                        element: element.id.clone(),
+                                        error: ErrorReport::new(&err),
+                                    });
+                                }
+                            }
+                            TaskCompletion::Success(None)
+                        }
+                        Err(err) => {
+                            let _ = process.log_broadcast().send(Log::DecisionError {
+                                element: element.id.clone(),
+                                error: ErrorReport::new(&err),
+                            });
+                            let policy = process.failure_policy(element.id.as_deref());
+                            handle_failure(
+                                &process,
+                                Incident {
+                                    node: element.id.clone(),
+                                    error: ProcessEvent::ErrorEvent {
+                                        error_ref: None,
+                                        error_code: Some("DecisionError".into()),
+                                        error_message: Some(err.to_string()),
+                                    },
+                                },
+                                policy,
+                            )
+                            .await
+                        }
+                    };
+                    let _ = notifier.send(completion);
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+            State::Waiting => match self.notifier_receiver.try_recv() {
+                Ok(TaskCompletion::Success(_)) => {
+                    self.waker.replace(cx.waker().clone());
+                    self.state = State::Done;
+                    Poll::Ready(Some(Action::Flow(
+                        (0..self.element.outgoings().len()).collect(),
+                    )))
+                }
+                Ok(TaskCompletion::Error) => {
+                    self.state = State::Errored;
+                    Poll::Ready(Some(Action::Complete))
+                }
+                Ok(TaskCompletion::Suspended) | Ok(TaskCompletion::Caught) => {
+                    // the token stays here until the process is modified (or it has been
+                    // taken over by an error boundary event, which cancels this task)
+                    self.state = State::Errored;
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
+                Err(broadcast::error::TryRecvError::Empty)
+                | Err(broadcast::error::TryRecvError::Lagged(_)) => {
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
+                Err(broadcast::error::TryRecvError::Closed) => Poll::Ready(None),
+            },
+            State::Done => {
+                self.state = State::Ready;
+                Poll::Ready(Some(Action::Complete))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bpmn::parse;
+    use crate::data_object;
+    use crate::decision::{self, DecisionError, DecisionEvaluator};
+    use crate::model;
+    use crate::process::Variables;
+    use async_trait::async_trait;
+    use bpxe_internal_macros as bpxe_im;
+
+    struct Discount;
+
+    #[async_trait]
+    impl DecisionEvaluator for Discount {
+        async fn evaluate(
+            &self,
+            decision: &str,
+            _input: Variables,
+        ) -> Result<Variables, DecisionError> {
+            if decision != "discount" {
+                return Err(DecisionError::UnknownDecision(decision.into()));
+            }
+            let mut variables = Variables::new();
+            variables.insert("discount".into(), Box::new(data_object::Container(10i64)));
+            Ok(variables)
+        }
+    }
+
+    #[bpxe_im::test]
+    async fn evaluates_decision() {
+        let definitions = parse(include_str!("test_models/task_business_rule.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_decision_evaluator(Discount)
+            .with_decision_ref("rule", "discount")
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        let variables = handle.result().await.unwrap();
+        assert!(matches!(
+            variables
+                .get("discount")
+                .and_then(|v| v.downcast_ref::<data_object::Container<i64>>()),
+            Some(data_object::Container(10))
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn evaluates_decision_standalone() {
+        let definitions = parse(include_str!("test_models/task_business_rule.bpmn")).unwrap();
+        let model = model::Model::new(definitions.clone())
+            .with_decision_evaluator(Discount)
+            .spawn()
+            .await;
+        let variables = decision::evaluate(&model, "discount", Variables::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            variables
+                .get("discount")
+                .and_then(|v| v.downcast_ref::<data_object::Container<i64>>()),
+            Some(data_object::Container(10))
+        ));
+        assert!(matches!(
+            decision::evaluate(&model, "unknown", Variables::new()).await,
+            Err(DecisionError::UnknownDecision(decision)) if decision == "unknown"
+        ));
+        model.terminate().await;
+
+        let model = model::Model::new(definitions).spawn().await;
+        assert!(matches!(
+            decision::evaluate(&model, "discount", Variables::new()).await,
+            Err(DecisionError::NoEvaluator)
+        ));
+        model.terminate().await;
+    }
+}
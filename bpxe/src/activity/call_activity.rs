@@ -0,0 +1,561 @@
+//! # Call Activity flow node
+//!
+//! Call activities spawn an instance of the process referenced by their `calledElement`
+//! (within the same model) and wait for it to complete before passing their token on.
+//!
+//! Data inputs of the call activity become the initial variables of the called process,
+//! by their names (or identifiers, if unnamed). Once the called process completes, its
+//! variables are passed back through the data outputs of the call activity, matched the
+//! same way, and data output associations set them in the calling process.
+//!
+//! If the called process fails, the error event of its incident can be caught by an error
+//! boundary event attached to the call activity, which then takes over its token. Otherwise
+//! the error event is emitted on behalf of the call activity, so it can be caught in the
+//! calling process, and the failure is handled according to the failure policy of the call
+//! activity or its process (see [`crate::model::Model::with_failure_policy`]). Failures are
+//! reported with [`Log::CallActivityError`].
+//!
+//! If the model limits concurrency of the call activity (see
+//! [`crate::model::Model::with_concurrency_limit`]), the process is only called once it's
+//! the call activity's turn. If the call exceeds the execution time limit of the call
+//! activity (see [`crate::model::Model::with_activity_time_limit`]), it's abandoned.
+//!
+//! If the call activity is cancelled (or times out) before the called process ends, the
+//! called process is terminated.
+use crate::activity::{
+    acquire_permit, handle_failure, time_limit_exceeded, within_time_limit, Activity, InputSet,
+    OutputSet, TaskCompletion,
+};
+use crate::bpmn::schema::{CallActivity as Element, FlowNodeType};
+use crate::data_object::{self, DataObject};
+use crate::error::ErrorReport;
+use crate::event::ProcessEvent;
+use crate::flow_node::{self, Action, FlowNode};
+use crate::process::{self, Incident, InstanceState, Log, Outcome, StartError, Variables};
+use crate::sys::task;
+use futures::future::{AbortHandle, Abortable};
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+/// Call activity error
+#[derive(Error, Debug, PartialEq)]
+pub enum CallActivityError {
+    /// Call activity doesn't reference a process
+    #[error("call activity has no called element")]
+    NoCalledElement,
+    /// Called process is not in the model
+    #[error("unknown called process {0}")]
+    UnknownProcess(String),
+    /// Called process could not be started
+    #[error("called process has failed to start")]
+    Start(#[from] StartError),
+    /// Called process has failed
+    #[error("called process has failed")]
+    Failed(Option<Incident>),
+    /// Called process has ended without completing
+    #[error("called process has ended with {0:?}")]
+    NotCompleted(Outcome),
+}
+
+/// Call Activity flow node
+pub struct Task {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    notifier: broadcast::Sender<TaskCompletion>,
+    notifier_receiver: broadcast::Receiver<TaskCompletion>,
+    input_sets: Vec<InputSet>,
+    output_sets: Option<Vec<OutputSet>>,
+    sandboxed: bool,
+    process: Option<process::Handle>,
+    // in-flight work, abandoned if the node is dropped before it completes
+    work: Option<AbortHandle>,
+}
+
+impl Task {
+    /// Creates new Call Activity flow node
+    pub fn new(element: Element) -> Self {
+        let (notifier, notifier_receiver) = broadcast::channel(1);
+        Self {
+            element: Arc::new(element),
+            state: State::Initialized,
+            waker: None,
+            notifier,
+            notifier_receiver,
+            input_sets: vec![],
+            output_sets: None,
+            sandboxed: false,
+            process: None,
+            work: None,
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some(work) = self.work.take() {
+            work.abort();
+        }
+    }
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum State {
+    Initialized,
+    Ready,
+    Execute,
+    /// Waiting for the called process to complete
+    Waiting,
+    Errored,
+    Done,
+}
+
+/// Maps data inputs of the call activity to variables of the called process
+fn child_variables(element: &Element, input_sets: Vec<InputSet>) -> Variables {
+    let io_spec = match element.io_specification {
+        Some(ref io_spec) => io_spec,
+        None => return Variables::new(),
+    };
+    let mut variables = Variables::new();
+    // input sets are reported in the order of their definitions, with values in the order
+    // of their (declared) data inputs
+    for (input_set, (_, values)) in io_spec.input_sets.iter().zip(input_sets) {
+        let data_inputs = input_set.data_input_refses.iter().filter_map(|id| {
+            io_spec
+                .data_inputs
+                .iter()
+                .find(|data_input| data_input.id.as_ref() == Some(id))
+        });
+        for (data_input, value) in data_inputs.zip(values) {
+            if let Some(name) = data_input.name.as_ref().or(data_input.id.as_ref()) {
+                variables.insert(name.clone(), value);
+            }
+        }
+    }
+    variables
+}
+
+/// Maps variables of the called process to data outputs of the call activity
+fn output_sets(element: &Element, variables: &Variables) -> Vec<OutputSet> {
+    let io_spec = match element.io_specification {
+        Some(ref io_spec) => io_spec,
+        None => return vec![],
+    };
+    io_spec
+        .output_sets
+        .iter()
+        .map(|output_set| {
+            let values = output_set
+                .data_output_refses
+                .iter()
+                .map(|id| {
+                    io_spec
+                        .data_outputs
+                        .iter()
+                        .find(|data_output| data_output.id.as_ref() == Some(id))
+                        .and_then(|data_output| data_output.name.as_ref().or(Some(id)))
+                        .and_then(|name| variables.get(name))
+                        .map(|value| dyn_clone::clone_box(&**value))
+                        .unwrap_or_else(|| Box::new(data_object::Empty) as Box<dyn DataObject>)
+                })
+                .collect();
+            (output_set.id.clone(), values)
+        })
+        .collect()
+}
+
+// Terminates the called process if the call activity is abandoned before it ends
+struct CalledProcess(process::Handle);
+
+impl Drop for CalledProcess {
+    fn drop(&mut self) {
+        if !matches!(self.0.state(), InstanceState::Finished(_)) {
+            let child = self.0.clone();
+            task::spawn(async move { child.terminate().await });
+        }
+    }
+}
+
+/// Spawns the called process and waits for it to complete, returning output sets
+async fn call(
+    process: &process::Handle,
+    element: &Element,
+    input_sets: Vec<InputSet>,
+) -> Result<Vec<OutputSet>, CallActivityError> {
+    let called_element = element
+        .called_element
+        .clone()
+        .ok_or(CallActivityError::NoCalledElement)?;
+    let model = process.model();
+    let definition = model
+        .process_definition(&called_element)
+        .ok_or(CallActivityError::UnknownProcess(called_element))?;
+    let child = process::Process::shared(definition, model.internal())
+        .spawn()
+        .await;
+    // the called process is terminated along with the model
+    model.adopt(child.clone()).await;
+    let called = CalledProcess(child);
+    let child = &called.0;
+    let request = process::StartRequest {
+        variables: child_variables(element, input_sets),
+        ..Default::default()
+    };
+    child.start_request(request).start().await?;
+    match child.join().await {
+        Outcome::Completed => {}
+        Outcome::Failed => return Err(CallActivityError::Failed(child.incident().await)),
+        outcome => return Err(CallActivityError::NotCompleted(outcome)),
+    }
+    let variables = child.result().await.unwrap_or_default();
+    Ok(output_sets(element, &variables))
+}
+
+impl FlowNode for Task {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::CallActivity(state) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::CallActivity(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn set_process(&mut self, process: crate::process::Handle) {
+        self.sandboxed = process.is_sandboxed();
+        if let State::Initialized = self.state {
+            self.state = State::Ready;
+            self.wake();
+        }
+        self.process.replace(process);
+    }
+}
+
+impl Activity for Task {
+    fn execute(&mut self) {
+        self.state = State::Execute;
+        self.wake();
+    }
+
+    fn input_sets(&mut self, input_sets: Vec<InputSet>) {
+        self.input_sets = input_sets;
+    }
+
+    fn take_output_sets(&mut self) -> Option<Vec<OutputSet>> {
+        self.output_sets.take()
+    }
+}
+
+impl From<Element> for Task {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Task {
+    type Item = Action;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.state {
+            State::Initialized | State::Ready | State::Errored => {
+                self.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Execute if self.sandboxed => {
+                // called processes are not spawned in a sandbox
+                self.state = State::Waiting;
+                let _ = self.notifier.send(TaskCompletion::Success(None));
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            State::Execute => {
+                self.state = State::Waiting;
+                let process = match self.process.clone() {
+                    Some(process) => process,
+                    None => {
+                        self.waker.replace(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                };
+                let waker = cx.waker().clone();
+                let element = self.element.clone();
+                let notifier = self.notifier.clone();
+                // We only need input once, we can drain it
+                let input_sets = std::mem::take(&mut self.input_sets);
+                let (work, registration) = AbortHandle::new_pair();
+                self.work = Some(work);
+                task::spawn(Abortable::new(
+                    async move {
+                        let _permit = acquire_permit(&process, element.id.as_deref()).await;
+                        let execution = call(&process, &element, input_sets);
+                        let result =
+                            match within_time_limit(&process, element.id.as_deref(), execution)
+                                .await
+                            {
+                                Ok(result) => result,
+                                Err(limit) => {
+                                    let completion =
+                                        time_limit_exceeded(&process, element.id.as_ref(), limit)
+                                            .await;
+                                    let _ = notifier.send(completion);
+                                    waker.wake();
+                                    return;
+                                }
+                            };
+                        let completion = match result {
+                            Ok(output_sets) => TaskCompletion::Success(Some(output_sets)),
+                            Err(err) => {
+                                let _ = process.log_broadcast().send(Log::CallActivityError {
+                                    element: element.id.clone(),
+                                    error: ErrorReport::new(&err),
+                                });
+                                let error = match err {
+                                    CallActivityError::Failed(Some(incident)) => {
+                                        let id = element.id.clone().unwrap_or_default();
+                                        if process
+                                            .catch_on_boundary(&id, incident.error.clone())
+                                            .await
+                                        {
+                                            // the call activity is cancelled along with
+                                            // this task
+                                            return;
+                                        }
+                                        // let the calling process catch the error of the called one
+                                        process
+                                            .emit_event(element.id.clone(), incident.error.clone());
+                                        incident.error
+                                    }
+                                    _ => ProcessEvent::ErrorEvent {
+                                        error_ref: None,
+                                        error_code: Some("CallActivityError".into()),
+                                        error_message: Some(err.to_string()),
+                                    },
+                                };
+                                let policy = process.failure_policy(element.id.as_deref());
+                                handle_failure(
+                                    &process,
+                                    Incident {
+                                        node: element.id.clone(),
+                                        error,
+                                    },
+                                    policy,
+                                )
+                                .await
+                            }
+                        };
+                        let _ = notifier.send(completion);
+                        waker.wake();
+                    },
+                    registration,
+                ));
+                Poll::Pending
+            }
+            State::Waiting => match self.notifier_receiver.try_recv() {
+                Ok(TaskCompletion::Success(output_sets)) => {
+                    self.output_sets = output_sets;
+                    self.waker.replace(cx.waker().clone());
+                    self.state = State::Done;
+                    Poll::Ready(Some(Action::Flow(
+                        (0..self.element.outgoings().len()).collect(),
+                    )))
+                }
+                Ok(TaskCompletion::Error) => {
+                    self.state = State::Errored;
+                    Poll::Ready(Some(Action::Complete))
+                }
+                Ok(TaskCompletion::Suspended) | Ok(TaskCompletion::Caught) => {
+                    // the token stays here until the process is modified (or it has been
+                    // taken over by an error boundary event, which cancels this task)
+                    self.state = State::Errored;
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
+                Err(broadcast::error::TryRecvError::Empty)
+                | Err(broadcast::error::TryRecvError::Lagged(_)) => {
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
+                Err(broadcast::error::TryRecvError::Closed) => Poll::Ready(None),
+            },
+            State::Done => {
+                self.state = State::Ready;
+                Poll::Ready(Some(Action::Complete))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bpmn::parse;
+    use crate::data_object;
+    use crate::event::ProcessEvent;
+    use crate::model;
+    use crate::process::{FailurePolicy, Incident, Log, Outcome};
+    use crate::test::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    async fn maps_variables() {
+        let definitions = parse(include_str!("test_models/call_activity.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_result_variable("pay", "receipt")
+            .spawn()
+            .await;
+        let handle = model
+            .processes()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|handle| handle.element().id.as_deref() == Some("order"))
+            .unwrap();
+        assert!(handle
+            .start_with()
+            .variable("amount", Box::new(data_object::Container(10i64)))
+            .start()
+            .await
+            .is_ok());
+        let variables = handle.result().await.unwrap();
+        // passed to the called process and back
+        assert!(matches!(
+            variables
+                .get("charged")
+                .and_then(|v| v.downcast_ref::<data_object::Container<i64>>()),
+            Some(data_object::Container(10))
+        ));
+        // set by the called process
+        assert!(matches!(
+            variables
+                .get("receipt")
+                .and_then(|v| v.downcast_ref::<data_object::Container<i64>>()),
+            Some(data_object::Container(42))
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn propagates_error() {
+        let definitions = parse(include_str!("test_models/call_activity_error.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_failure_policy("call", FailurePolicy::Fail)
+            .spawn()
+            .await;
+        let handle = model
+            .processes()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|handle| handle.element().id.as_deref() == Some("order"))
+            .unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(
+            mailbox
+                .receive(
+                    |e| matches!(&e.event, ProcessEvent::ErrorEvent { error_code, .. }
+                    if e.source.as_deref() == Some("call") && error_code.as_deref() == Some("E42"))
+                )
+                .await
+        );
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some(node),
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+            }) if node == "call" && code == "E42"
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn boundary_catches_error() {
+        let definitions = parse(include_str!("test_models/call_activity_boundary.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_failure_policy("call", FailurePolicy::Fail)
+            .spawn()
+            .await;
+        let handle = model
+            .processes()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|handle| handle.element().id.as_deref() == Some("order"))
+            .unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. }
+                    if node.id().as_deref() == Some("declined_end")))
+                .await
+        );
+        assert_eq!(handle.join().await, Outcome::Completed);
+        assert!(handle.incident().await.is_none());
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn cancel_terminates_called_process() {
+        use crate::model::InstanceQuery;
+        use crate::process::{InstanceState, TimeoutAction};
+        use std::time::Duration;
+        let definitions = parse(include_str!("test_models/call_activity_wait.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_task_timeout(
+                "call",
+                Duration::from_millis(50),
+                TimeoutAction::Flow("Flow_end".into()),
+            )
+            .spawn()
+            .await;
+        let handle = model
+            .processes()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|handle| handle.element().id.as_deref() == Some("order"))
+            .unwrap();
+        let running = InstanceQuery::new()
+            .process("approval")
+            .state(InstanceState::Running);
+        assert!(handle.start().await.is_ok());
+        assert!(timeout(async {
+            while model.query_instances(&running).await.unwrap().is_empty() {
+                crate::sys::task::yield_now().await;
+            }
+        })
+        .await
+        .is_ok());
+        // the call activity times out, abandoning the called process
+        assert_eq!(handle.join().await, Outcome::Completed);
+        assert!(timeout(async {
+            while !model.query_instances(&running).await.unwrap().is_empty() {
+                crate::sys::task::yield_now().await;
+            }
+        })
+        .await
+        .is_ok());
+        model.terminate().await;
+    }
+}
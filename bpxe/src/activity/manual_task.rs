@@ -0,0 +1,185 @@
+//! # Manual Task flow node
+//!
+//! Manual tasks are performed without the help of any application. Once executed, a manual
+//! task keeps its token until it's completed with [`process::Handle::complete_manual_task`],
+//! which is reported with [`process::Log::FlowNodeCompleted`] as usual.
+//!
+//! In a sandbox (see [`process::Handle::fork`]), manual tasks pass their tokens on right away.
+use crate::activity::Activity;
+use crate::bpmn::schema::{FlowNodeType, ManualTask as Element};
+use crate::flow_node::{self, Action, FlowNode};
+use crate::process;
+use futures::future::FutureExt;
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+/// Manual task completion error
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ManualTaskError {
+    /// Manual task doesn't hold a token waiting for completion
+    #[error("manual task {0} is not waiting for completion")]
+    NotWaiting(String),
+}
+
+/// Manual Task flow node
+pub struct Task {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    completion: Option<oneshot::Receiver<()>>,
+    sandboxed: bool,
+    process: Option<process::Handle>,
+}
+
+impl Task {
+    /// Creates new Manual Task flow node
+    pub fn new(element: Element) -> Self {
+        Self {
+            element: Arc::new(element),
+            state: State::Initialized,
+            waker: None,
+            completion: None,
+            sandboxed: false,
+            process: None,
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum State {
+    Initialized,
+    Ready,
+    /// Waiting for completion
+    Waiting,
+    Done,
+}
+
+impl FlowNode for Task {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::ManualTask(state) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::ManualTask(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn set_process(&mut self, process: crate::process::Handle) {
+        self.sandboxed = process.is_sandboxed();
+        if let State::Initialized = self.state {
+            self.state = State::Ready;
+            self.wake();
+        }
+        self.process.replace(process);
+    }
+}
+
+impl Activity for Task {
+    fn execute(&mut self) {
+        // registered right away, so that the task can be completed before it's polled
+        if let (false, Some(process)) = (self.sandboxed, self.process.as_ref()) {
+            self.completion =
+                Some(process.await_manual_task(self.element.id.clone().unwrap_or_default()));
+        }
+        self.state = State::Waiting;
+        self.wake();
+    }
+}
+
+impl From<Element> for Task {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Task {
+    type Item = Action;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.state {
+            State::Initialized | State::Ready => {
+                self.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Waiting => {
+                if let Some(completion) = self.completion.as_mut() {
+                    match completion.poll_unpin(cx) {
+                        Poll::Ready(Ok(())) => {}
+                        Poll::Ready(Err(_)) => return Poll::Ready(None),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                self.completion = None;
+                self.state = State::Done;
+                self.waker.replace(cx.waker().clone());
+                Poll::Ready(Some(Action::Flow(
+                    (0..self.element.outgoings().len()).collect(),
+                )))
+            }
+            State::Done => {
+                self.state = State::Ready;
+                Poll::Ready(Some(Action::Complete))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ManualTaskError;
+    use crate::bpmn::parse;
+    use crate::model;
+    use crate::process::Log;
+    use crate::test::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    async fn complete() {
+        let definitions = parse(include_str!("test_models/task_manual.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert_eq!(
+            handle.complete_manual_task("sign"),
+            Err(ManualTaskError::NotWaiting("sign".into()))
+        );
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. }
+                    if node.id().as_deref() == Some("sign")))
+                .await
+        );
+        while handle.complete_manual_task("sign").is_err() {
+            crate::sys::task::yield_now().await;
+        }
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::FlowNodeCompleted { node }
+                    if node.id().as_deref() == Some("sign")))
+                .await
+        );
+        handle.join().await;
+        model.terminate().await;
+    }
+}
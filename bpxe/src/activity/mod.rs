@@ -9,11 +9,14 @@ use crate::bpmn::schema::{
     StandardLoopCharacteristics, StandardLoopCharacteristicsLoopCondition,
 };
 use crate::data_object::{self, DataObject, DataObjectExt};
+use crate::error::ErrorReport;
+use crate::event::ProcessEvent;
 use crate::flow_node::{self, Action, FlowNode, IncomingIndex, OutgoingIndex, StateError};
 use crate::language::{Engine as _, EngineContextProvider, MultiLanguageEngine};
 use crate::process::{self, Log};
-use crate::sys::task;
+use crate::sys::{task, time};
 use factory::ParameterizedFactory;
+use futures::future::Future;
 use futures::stream::{Stream, StreamExt};
 use num_bigint::BigInt;
 use serde::{Deserialize, Serialize};
@@ -21,10 +24,18 @@ use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 use streamunordered::{StreamUnordered, StreamYield};
-use tokio::sync::{broadcast, oneshot, watch};
+use tokio::sync::{broadcast, oneshot, watch, OwnedSemaphorePermit};
 
+pub mod business_rule_task;
+pub mod call_activity;
+pub mod manual_task;
+pub mod receive_task;
 pub mod script_task;
+pub mod send_task;
+pub mod service_task;
+pub mod user_task;
 
 pub trait Activity: FlowNode {
     /// Signals execution request
@@ -57,6 +68,181 @@ pub type InputSet = (Option<String>, Vec<Box<dyn DataObject>>);
 /// Optionally named output set
 pub type OutputSet = (Option<String>, Vec<Box<dyn DataObject>>);
 
+/// Turns input sets into variables: named ones by their names, the unnamed one as `input`
+pub(crate) fn input_variables(input_sets: Vec<InputSet>) -> process::Variables {
+    input_sets
+        .into_iter()
+        .map(|(name, input_set)| {
+            (
+                name.unwrap_or_else(|| "input".into()),
+                Box::new(data_object::Collection(input_set)) as Box<dyn DataObject>,
+            )
+        })
+        .collect()
+}
+
+/// Returns the message event sent or received by a task
+///
+/// The message is the one referenced by the task or, if there's none, the input message of its
+/// operation.
+pub(crate) fn message_event(
+    message_ref: Option<&String>,
+    operation_ref: Option<&String>,
+    definitions: &schema::Definitions,
+    payload: Option<serde_json::Value>,
+) -> ProcessEvent {
+    let message_ref = message_ref.cloned().or_else(|| {
+        operation_ref
+            .and_then(|id| definitions.find_by_id(id))
+            .and_then(|e| e.downcast_ref::<schema::Operation>())
+            .map(|operation| operation.in_message_ref.clone())
+    });
+    ProcessEvent::MessageEvent {
+        message_ref,
+        operation_ref: operation_ref.cloned(),
+        payload,
+    }
+}
+
+/// Completion of a task executed outside of its flow node (for example, of a script)
+#[derive(Clone)]
+pub(crate) enum TaskCompletion {
+    Success(Option<Vec<OutputSet>>),
+    Error,
+    Suspended,
+    Caught,
+}
+
+/// Handles a failure of a task, returning completion of the task
+///
+/// The error is offered to error boundary events attached to the task first. If none of
+/// them catches it, the failure is handled according to the failure policy.
+pub(crate) async fn handle_failure(
+    process: &process::Handle,
+    incident: process::Incident,
+    policy: Option<process::FailurePolicy>,
+) -> TaskCompletion {
+    if let Some(ref activity) = incident.node {
+        if process
+            .catch_on_boundary(activity, incident.error.clone())
+            .await
+        {
+            return TaskCompletion::Caught;
+        }
+    }
+    match policy {
+        Some(process::FailurePolicy::Fail) => {
+            process.raise_incident(incident).await;
+            TaskCompletion::Error
+        }
+        Some(process::FailurePolicy::Suspend) => {
+            let _ = process.log_broadcast().send(Log::ActivitySuspended {
+                activity: incident.node,
+                error: incident.error,
+            });
+            TaskCompletion::Suspended
+        }
+        Some(process::FailurePolicy::Skip) => TaskCompletion::Success(None),
+        None => TaskCompletion::Error,
+    }
+}
+
+/// Waits for a permit to execute an activity, if it has a concurrency limit (see
+/// [`model::Model::with_concurrency_limit`])
+///
+/// The permit is to be held for as long as the activity executes.
+///
+/// [`model::Model::with_concurrency_limit`]: crate::model::Model::with_concurrency_limit
+pub(crate) async fn acquire_permit(
+    process: &process::Handle,
+    activity: Option<&str>,
+) -> Option<OwnedSemaphorePermit> {
+    let semaphore = process.model().concurrency_limit(activity?)?;
+    semaphore.acquire_owned().await.ok()
+}
+
+/// Executes an activity within its execution time limit, if it has one (see
+/// [`model::Model::with_activity_time_limit`]), returning the limit if it has been exceeded
+///
+/// [`model::Model::with_activity_time_limit`]: crate::model::Model::with_activity_time_limit
+pub(crate) async fn within_time_limit<F>(
+    process: &process::Handle,
+    activity: Option<&str>,
+    execution: F,
+) -> Result<F::Output, Duration>
+where
+    F: Future,
+{
+    match activity.and_then(|id| process.model().activity_time_limit(id)) {
+        Some(limit) => time::timeout(limit, execution).await.map_err(|_| limit),
+        None => Ok(execution.await),
+    }
+}
+
+/// Reports an activity that has exceeded its execution time limit and handles the failure
+/// according to the failure policy (failing the process if there's none), returning
+/// completion of the activity
+pub(crate) async fn time_limit_exceeded(
+    process: &process::Handle,
+    activity: Option<&String>,
+    limit: Duration,
+) -> TaskCompletion {
+    let _ = process.log_broadcast().send(Log::ActivityTimedOut {
+        activity: activity.cloned(),
+        limit,
+    });
+    let policy = process
+        .failure_policy(activity.map(String::as_str))
+        .unwrap_or(process::FailurePolicy::Fail);
+    handle_failure(
+        process,
+        process::Incident {
+            node: activity.cloned(),
+            error: ProcessEvent::ErrorEvent {
+                error_ref: None,
+                error_code: Some("ActivityTimedOut".into()),
+                error_message: Some(format!("execution time limit of {:?} exceeded", limit)),
+            },
+        },
+        Some(policy),
+    )
+    .await
+}
+
+/// Reports a failed attempt of a task and returns the delay before retrying it, if the task
+/// has a retry policy that isn't exhausted yet (see [`process::RetryPolicy`])
+pub(crate) fn retry_delay(
+    process: &process::Handle,
+    task: Option<&String>,
+    attempt: u32,
+    error: ErrorReport,
+) -> Option<Duration> {
+    let policy = process.retry_policy(task.map(String::as_str))?;
+    let retry_in = policy.delay(attempt);
+    let _ = process.log_broadcast().send(Log::AttemptFailed {
+        element: task.cloned(),
+        attempt,
+        error,
+        retry_in,
+    });
+    retry_in
+}
+
+/// Returns failure policy of a failed task
+///
+/// Tasks with a retry policy that have exhausted their retries fail the process unless they
+/// have a failure policy.
+pub(crate) fn task_failure_policy(
+    process: &process::Handle,
+    task: Option<&str>,
+) -> Option<process::FailurePolicy> {
+    process.failure_policy(task).or_else(|| {
+        process
+            .retry_policy(task)
+            .map(|_| process::FailurePolicy::Fail)
+    })
+}
+
 pub struct ActivityContainer<T, E, F>
 where
     T: Activity,
@@ -227,7 +413,8 @@ where
                                 .collect(),
                             Err(err) => {
                                 let _ = process.log_broadcast().send(Log::ExpressionError {
-                                    error: format!("{:?}", err),
+                                    element: element.id().clone(),
+                                    error: ErrorReport::new(&err),
                                 });
                                 vec![]
                             }
@@ -559,12 +746,11 @@ where
                             while let Some((data_object_name, new_object)) =
                                 async_associations.pop()
                             {
-                                if let Ok(data_object) =
-                                    process.data_object(&data_object_name).await
-                                {
-                                    let mut write = data_object.write().await;
-                                    write.send(*new_object);
-                                }
+                                let _ = process
+                                    .update_variable(&data_object_name, move |data_object| {
+                                        data_object.send(*new_object)
+                                    })
+                                    .await;
                             }
                             let waker = waker_receiver.borrow();
                             if let Some(waker) = waker.as_ref() {
@@ -717,6 +903,7 @@ where
         let notifier = self.notifier.clone();
         let log_broadcast = self.log_broadcast.clone();
         let waker_receiver = self.waker_receiver.clone();
+        let element_id = self.element.id().clone();
         task::spawn(async move {
             let result = engine.eval(&expression, &mut engine.new_context()).await;
             // we're holding it until the end of the block
@@ -731,7 +918,8 @@ where
                     let _ = notifier.send(Completion::Error);
                     if let Some(ref log_broadcast) = log_broadcast {
                         let _ = log_broadcast.send(Log::ScriptError {
-                            error: format!("{:?}", err),
+                            element: element_id,
+                            error: ErrorReport::new(&err),
                         });
                     }
                 }
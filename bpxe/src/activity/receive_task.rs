@@ -0,0 +1,346 @@
+//! # Receive Task flow node
+//!
+//! Receive tasks wait for their [`ProcessEvent::MessageEvent`] once executed and pass their
+//! tokens on when it arrives. The message is the one referenced by the task or, if there's
+//! none, the input message of its operation. Payload of the message (if any) makes up the
+//! unnamed output set of the task. Unless the task references an operation, the message is
+//! received regardless of the operation it was sent for (see [`receives`]).
+//!
+//! Receive tasks marked with `instantiate` (and without incoming sequence flows) are entry
+//! points of their process, equivalent to message start events: they are started along with
+//! start events and pass their tokens on right away (see [`instantiates`]).
+use crate::activity::{message_event, Activity, OutputSet};
+use crate::bpmn::schema::{FlowNodeType, ReceiveTask as Element};
+use crate::data_object::DataObject;
+use crate::event::{Envelope, EventKind, ProcessEvent};
+use crate::flow_node::{self, Action, FlowNode};
+use crate::process;
+use crate::sys::task;
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use tokio::sync::broadcast;
+
+/// Returns `true` if a receive task is an entry point that instantiates its process
+pub fn instantiates(element: &Element) -> bool {
+    element.instantiate == Some(true) && element.incomings.is_empty()
+}
+
+/// Returns `true` if a receive task waiting for the `expected` message receives the `thrown` one
+///
+/// Unlike [`ProcessEvent::matches`], messages are received regardless of the operation they
+/// were sent for if the expected message doesn't reference one.
+pub fn receives(expected: &ProcessEvent, thrown: &ProcessEvent) -> bool {
+    match (expected, thrown) {
+        (
+            ProcessEvent::MessageEvent {
+                message_ref,
+                operation_ref: None,
+                ..
+            },
+            ProcessEvent::MessageEvent {
+                message_ref: thrown_message_ref,
+                ..
+            },
+        ) => message_ref == thrown_message_ref,
+        _ => expected.matches(thrown),
+    }
+}
+
+/// Receive Task flow node
+pub struct Task {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    // awaited event and the receiver it's going to arrive through
+    subscription: Option<(ProcessEvent, broadcast::Receiver<Envelope>)>,
+    notifier: broadcast::Sender<Option<serde_json::Value>>,
+    notifier_receiver: broadcast::Receiver<Option<serde_json::Value>>,
+    output_sets: Option<Vec<OutputSet>>,
+    process: Option<process::Handle>,
+}
+
+impl Task {
+    /// Creates new Receive Task flow node
+    pub fn new(element: Element) -> Self {
+        let (notifier, notifier_receiver) = broadcast::channel(1);
+        Self {
+            element: Arc::new(element),
+            state: State::Initialized,
+            waker: None,
+            subscription: None,
+            notifier,
+            notifier_receiver,
+            output_sets: None,
+            process: None,
+        }
+    }
+
+    /// Starts receiving the awaited event (before waiting for it, so that it's not missed)
+    fn subscribe(&mut self) {
+        let process = match self.process.as_ref() {
+            Some(process) => process,
+            None => return,
+        };
+        let subscription = if instantiates(&self.element) {
+            (ProcessEvent::Start, process.subscribe(EventKind::Start))
+        } else {
+            let definitions = process.model().definitions();
+            let event = message_event(
+                self.element.message_ref.as_ref(),
+                self.element.operation_ref.as_ref(),
+                &definitions,
+                None,
+            );
+            let receiver = process.subscribe(EventKind::MessageEvent);
+            // only listed once the message can't be missed anymore
+            process.subscription_registry().subscribe(
+                self.element.id.clone(),
+                vec![event.clone()],
+                &definitions,
+            );
+            (event, receiver)
+        };
+        self.subscription = Some(subscription);
+    }
+
+    fn unsubscribe(&mut self) {
+        self.subscription = None;
+        if let Some(process) = self.process.as_ref() {
+            process
+                .subscription_registry()
+                .unsubscribe(&self.element.id);
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        self.unsubscribe();
+    }
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum State {
+    Initialized,
+    Ready,
+    /// Waiting for the message (or the start of the process, if the task instantiates it)
+    Waiting,
+    Complete,
+    Done,
+}
+
+impl FlowNode for Task {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::ReceiveTask(state) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::ReceiveTask(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn set_process(&mut self, process: crate::process::Handle) {
+        self.process.replace(process);
+        if let State::Initialized = self.state {
+            if instantiates(&self.element) {
+                // waits for the process to start right away
+                self.subscribe();
+                self.state = State::Waiting;
+            } else {
+                self.state = State::Ready;
+            }
+            self.wake();
+        }
+    }
+}
+
+impl Activity for Task {
+    fn execute(&mut self) {
+        self.subscribe();
+        self.state = State::Waiting;
+        self.wake();
+    }
+
+    fn take_output_sets(&mut self) -> Option<Vec<OutputSet>> {
+        self.output_sets.take()
+    }
+}
+
+impl From<Element> for Task {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Task {
+    type Item = Action;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.state {
+            State::Initialized | State::Ready | State::Done => {
+                self.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Waiting => {
+                if let Some((expected, mut receiver)) = self.subscription.take() {
+                    let notifier = self.notifier.clone();
+                    let waker = cx.waker().clone();
+                    task::spawn(async move {
+                        loop {
+                            match receiver.recv().await {
+                                Ok(envelope) if receives(&expected, &envelope.event) => {
+                                    let payload = match envelope.event {
+                                        ProcessEvent::MessageEvent { payload, .. } => payload,
+                                        _ => None,
+                                    };
+                                    let _ = notifier.send(payload);
+                                    waker.wake();
+                                    break;
+                                }
+                                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(broadcast::error::RecvError::Closed) => break,
+                            }
+                        }
+                    });
+                }
+                match self.notifier_receiver.try_recv() {
+                    Ok(payload) => {
+                        self.unsubscribe();
+                        self.output_sets = payload.map(|payload| {
+                            vec![(None, vec![Box::new(payload) as Box<dyn DataObject>])]
+                        });
+                        self.waker.replace(cx.waker().clone());
+                        self.state = State::Complete;
+                        Poll::Ready(Some(Action::Flow(
+                            (0..self.element.outgoings().len()).collect(),
+                        )))
+                    }
+                    Err(broadcast::error::TryRecvError::Empty)
+                    | Err(broadcast::error::TryRecvError::Lagged(_)) => {
+                        self.waker.replace(cx.waker().clone());
+                        Poll::Pending
+                    }
+                    Err(broadcast::error::TryRecvError::Closed) => Poll::Ready(None),
+                }
+            }
+            State::Complete => {
+                self.state = State::Done;
+                Poll::Ready(Some(Action::Complete))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::receives;
+    use crate::bpmn::parse;
+    use crate::event::ProcessEvent;
+    use crate::model;
+    use crate::process::{Log, Outcome};
+    use crate::test::*;
+    use bpxe_internal_macros as bpxe_im;
+    use serde_json::json;
+
+    #[bpxe_im::test]
+    async fn receives_message() {
+        let definitions = parse(include_str!("test_models/task_receive.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_deref() == Some("receive")))
+            .await);
+        // the receive task subscribes once it is executed
+        assert!(timeout(handle.settled()).await.is_ok());
+        assert_eq!(
+            handle.subscriptions()[0].name.as_deref(),
+            Some("OrderPlaced")
+        );
+        // a different message is not received
+        handle.send_event(ProcessEvent::MessageEvent {
+            message_ref: Some("order_cancelled".into()),
+            operation_ref: None,
+            payload: None,
+        });
+        handle.send_event(ProcessEvent::MessageEvent {
+            message_ref: Some("order_placed".into()),
+            operation_ref: None,
+            payload: Some(json!({"amount": 42})),
+        });
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Completed);
+        assert_eq!(
+            handle
+                .data_object("order")
+                .await
+                .unwrap()
+                .read()
+                .await
+                .downcast_ref::<serde_json::Value>(),
+            Some(&json!({"amount": 42}))
+        );
+        assert!(handle.subscriptions().is_empty());
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    fn receives_any_operation() {
+        let expected = ProcessEvent::MessageEvent {
+            message_ref: Some("order_placed".into()),
+            operation_ref: None,
+            payload: None,
+        };
+        let thrown = ProcessEvent::MessageEvent {
+            message_ref: Some("order_placed".into()),
+            operation_ref: Some("place_order".into()),
+            payload: None,
+        };
+        assert!(receives(&expected, &thrown));
+        // catch events still require the same operation
+        assert!(!expected.matches(&thrown));
+        // receive tasks referencing an operation only receive messages sent for it
+        let expected = ProcessEvent::MessageEvent {
+            message_ref: Some("order_placed".into()),
+            operation_ref: Some("cancel_order".into()),
+            payload: None,
+        };
+        assert!(!receives(&expected, &thrown));
+        assert!(!receives(
+            &ProcessEvent::MessageEvent {
+                message_ref: Some("order_cancelled".into()),
+                operation_ref: None,
+                payload: None,
+            },
+            &thrown
+        ));
+    }
+
+    #[bpxe_im::test]
+    async fn instantiate() {
+        let definitions = parse(include_str!("test_models/task_receive_instantiate.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Completed);
+        model.terminate().await;
+    }
+}
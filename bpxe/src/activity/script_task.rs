@@ -1,14 +1,33 @@
 //! # Script Task flow node
-use crate::activity::{Activity, InputSet, OutputSet};
+//!
+//! If the model specifies an execution time limit for the task (see
+//! [`crate::model::Model::with_activity_time_limit`]) and the script runs longer than that,
+//! the script is abandoned and the process fails with an incident.
+//!
+//! If the model limits concurrency of the task (see
+//! [`crate::model::Model::with_concurrency_limit`]), the script waits for its turn before
+//! it is evaluated. Waiting doesn't count towards the execution time limit.
+//!
+//! Failing scripts are retried according to the retry policy of the task or its process, if
+//! any (see [`crate::model::Model::with_retry_policy`]), and then handled according to the
+//! failure policy of the task or its process (see
+//! [`crate::model::Model::with_failure_policy`]).
+use crate::activity::{
+    acquire_permit, handle_failure, retry_delay, task_failure_policy, time_limit_exceeded,
+    within_time_limit, Activity, InputSet, OutputSet, TaskCompletion,
+};
 use crate::bpmn::schema::{FlowNodeType, ScriptTask as Element};
 
 use crate::data_object::{self, DataObject};
+use crate::error::ErrorReport;
+use crate::event::ProcessEvent;
 use crate::flow_node::{self, Action, FlowNode};
 use crate::language::{
     Engine as _, EngineContext, EngineContextProvider, EvaluationError, MultiLanguageEngine,
 };
-use crate::process::Log;
-use crate::sys::task;
+use crate::process::{self, Incident, Log};
+use crate::sys::{task, time};
+use futures::future::{AbortHandle, Abortable};
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use std::pin::Pin;
@@ -22,17 +41,15 @@ pub struct Task {
     state: State,
     engine: Arc<MultiLanguageEngine>,
     waker: Option<Waker>,
-    notifier: broadcast::Sender<Completion>,
-    notifier_receiver: broadcast::Receiver<Completion>,
+    notifier: broadcast::Sender<TaskCompletion>,
+    notifier_receiver: broadcast::Receiver<TaskCompletion>,
     log_broadcast: Option<broadcast::Sender<Log>>,
     input_sets: Vec<InputSet>,
     output_sets: Option<Vec<OutputSet>>,
-}
-
-#[derive(Clone)]
-enum Completion {
-    Success(Option<Vec<OutputSet>>),
-    Error,
+    sandboxed: bool,
+    process: Option<process::Handle>,
+    // in-flight work, abandoned if the node is dropped before it completes
+    work: Option<AbortHandle>,
 }
 
 impl Task {
@@ -49,6 +66,9 @@ impl Task {
             log_broadcast: None,
             input_sets: vec![],
             output_sets: None,
+            sandboxed: false,
+            process: None,
+            work: None,
         }
     }
 
@@ -59,6 +79,14 @@ impl Task {
     }
 }
 
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some(work) = self.work.take() {
+            work.abort();
+        }
+    }
+}
+
 /// Node state
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum State {
@@ -90,12 +118,14 @@ impl FlowNode for Task {
     }
 
     fn set_process(&mut self, process: crate::process::Handle) {
+        self.sandboxed = process.is_sandboxed();
         if let State::Initialized = self.state {
             self.state = State::Ready;
             self.log_broadcast.replace(process.log_broadcast());
             self.engine = Arc::new(process.model().script_engine_factory().create());
             self.wake();
         }
+        self.process.replace(process);
     }
 }
 
@@ -132,6 +162,13 @@ impl Stream for Task {
                 self.waker.replace(cx.waker().clone());
                 Poll::Pending
             }
+            State::Execute if self.sandboxed => {
+                // scripts are not evaluated in a sandbox
+                self.state = State::Executing;
+                let _ = self.notifier.send(TaskCompletion::Success(None));
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
             State::Execute => {
                 self.state = State::Executing;
                 let waker = cx.waker().clone();
@@ -139,45 +176,133 @@ impl Stream for Task {
                 let element = self.element.as_ref().clone();
                 let notifier = self.notifier.clone();
                 let log_broadcast = self.log_broadcast.clone();
-                let mut context = engine.new_context();
                 // We only need input once, we can drain it
-                for (name, input_set) in
-                    std::mem::replace(&mut self.input_sets, Vec::new()).drain(..)
-                {
-                    if let Some(name) = name {
-                        context.set(&name, Box::new(data_object::Collection(input_set)));
-                    } else {
-                        context.set("input", Box::new(data_object::Collection(input_set)));
-                    }
-                }
+                let input_sets = std::mem::take(&mut self.input_sets);
 
-                task::spawn(async move {
-                    match engine
-                        .eval::<Vec<Box<dyn DataObject>>>(&element, &mut context)
-                        .await
-                    {
-                        Ok(data_objects) => {
-                            let _ = notifier
-                                .send(Completion::Success(Some(vec![(None, data_objects)])));
-                        }
-                        Err(EvaluationError::ResultTypeError { got, .. }) if got == "()" => {
-                            let _ = notifier.send(Completion::Success(None));
-                        }
-                        Err(err) => {
-                            let _ = notifier.send(Completion::Error);
-                            if let Some(log_broadcast) = log_broadcast {
-                                let _ = log_broadcast.send(Log::ScriptError {
-                                    error: format!("{:?}", err),
-                                });
+                let process = self.process.clone();
+
+                let (work, registration) = AbortHandle::new_pair();
+                self.work = Some(work);
+                task::spawn(Abortable::new(
+                    async move {
+                        let _permit = match process {
+                            Some(ref process) => {
+                                acquire_permit(process, element.id.as_deref()).await
+                            }
+                            None => None,
+                        };
+                        let mut attempt = 1;
+                        let result = loop {
+                            // every attempt evaluates the script in a fresh context
+                            let mut context = engine.new_context();
+                            for (name, input_set) in input_sets.iter().cloned() {
+                                context.set(
+                                    name.as_deref().unwrap_or("input"),
+                                    Box::new(data_object::Collection(input_set)),
+                                );
+                            }
+                            let evaluation_started = instant::Instant::now();
+                            let eval =
+                                engine.eval::<Vec<Box<dyn DataObject>>>(&element, &mut context);
+                            let result = match process {
+                                Some(ref process) => {
+                                    match within_time_limit(process, element.id.as_deref(), eval)
+                                        .await
+                                    {
+                                        Ok(result) => result,
+                                        Err(limit) => {
+                                            process
+                                                .count_execution_time(evaluation_started.elapsed());
+                                            let completion = time_limit_exceeded(
+                                                process,
+                                                element.id.as_ref(),
+                                                limit,
+                                            )
+                                            .await;
+                                            let _ = notifier.send(completion);
+                                            waker.wake();
+                                            return;
+                                        }
+                                    }
+                                }
+                                None => eval.await,
+                            };
+                            if let Some(ref process) = process {
+                                process.count_execution_time(evaluation_started.elapsed());
+                            }
+                            let retry = match (&result, &process) {
+                                (Err(EvaluationError::ResultTypeError { got, .. }), _)
+                                    if got == "()" =>
+                                {
+                                    None
+                                }
+                                (Err(err), Some(process)) => retry_delay(
+                                    process,
+                                    element.id.as_ref(),
+                                    attempt,
+                                    ErrorReport::new(err),
+                                ),
+                                _ => None,
+                            };
+                            match retry {
+                                Some(delay) => {
+                                    time::sleep(delay).await;
+                                    attempt += 1;
+                                }
+                                None => break result,
+                            }
+                        };
+                        match result {
+                            Ok(data_objects) => {
+                                if let Some(ref process) = process {
+                                    store_result(process, &element, &data_objects).await;
+                                }
+                                let _ = notifier.send(TaskCompletion::Success(Some(vec![(
+                                    None,
+                                    data_objects,
+                                )])));
+                            }
+                            Err(EvaluationError::ResultTypeError { got, .. }) if got == "()" => {
+                                let _ = notifier.send(TaskCompletion::Success(None));
+                            }
+                            Err(err) => {
+                                if let Some(log_broadcast) = log_broadcast {
+                                    let _ = log_broadcast.send(Log::ScriptError {
+                                        element: element.id.clone(),
+                                        error: ErrorReport::new(&err),
+                                    });
+                                }
+                                let completion = match process {
+                                    Some(ref process) => {
+                                        let policy =
+                                            task_failure_policy(process, element.id.as_deref());
+                                        handle_failure(
+                                            process,
+                                            Incident {
+                                                node: element.id.clone(),
+                                                error: ProcessEvent::ErrorEvent {
+                                                    error_ref: None,
+                                                    error_code: Some("ScriptError".into()),
+                                                    error_message: Some(err.to_string()),
+                                                },
+                                            },
+                                            policy,
+                                        )
+                                        .await
+                                    }
+                                    None => TaskCompletion::Error,
+                                };
+                                let _ = notifier.send(completion);
                             }
                         }
-                    }
-                    waker.wake();
-                });
+                        waker.wake();
+                    },
+                    registration,
+                ));
                 Poll::Pending
             }
             State::Executing => match self.notifier_receiver.try_recv() {
-                Ok(Completion::Success(data_objects)) => {
+                Ok(TaskCompletion::Success(data_objects)) => {
                     self.output_sets = data_objects;
                     self.waker.replace(cx.waker().clone());
                     self.state = State::Done;
@@ -185,10 +310,17 @@ impl Stream for Task {
                         (0..self.element.outgoings().len()).collect(),
                     )))
                 }
-                Ok(Completion::Error) => {
+                Ok(TaskCompletion::Error) => {
                     self.state = State::Errored;
                     Poll::Ready(Some(Action::Complete))
                 }
+                Ok(TaskCompletion::Suspended) | Ok(TaskCompletion::Caught) => {
+                    // the token stays here until the process is modified (or it has been
+                    // taken over by an error boundary event, which cancels this task)
+                    self.state = State::Errored;
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
                 Err(broadcast::error::TryRecvError::Empty) => {
                     self.waker.replace(cx.waker().clone());
                     Poll::Pending
@@ -211,6 +343,37 @@ impl Stream for Task {
     }
 }
 
+/// Stores the result of the script in its result variable, if there's one
+async fn store_result(
+    process: &process::Handle,
+    element: &Element,
+    data_objects: &[Box<dyn DataObject>],
+) {
+    let variable = match element
+        .id
+        .as_deref()
+        .and_then(|id| process.model().result_variable(id))
+    {
+        Some(variable) => variable,
+        None => return,
+    };
+    let value: Box<dyn DataObject> = match data_objects {
+        [data_object] => dyn_clone::clone_box(&**data_object),
+        data_objects => Box::new(data_object::Collection(
+            data_objects
+                .iter()
+                .map(|data_object| dyn_clone::clone_box(&**data_object))
+                .collect(),
+        )),
+    };
+    if let Err(err) = process.set_variable(&variable, value).await {
+        let _ = process.log_broadcast().send(Log::ScriptError {
+            element: element.id.clone(),
+            error: ErrorReport::new(&err),
+        });
+    }
+}
+
 #[cfg(test)]
 #[allow(unused_imports)]
 mod tests {
@@ -250,4 +413,125 @@ mod tests {
 
         model.terminate().await;
     }
+
+    #[cfg(feature = "rhai")]
+    #[bpxe_im::test]
+    async fn time_limit() {
+        use crate::event::ProcessEvent;
+        use crate::process::{Incident, Outcome};
+        use std::time::Duration;
+
+        let definitions = parse(include_str!("test_models/task_script.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_script_engine_factory(model::FnLanguageEngineFactory(move || {
+                use ::rhai::RegisterFn;
+                let mut engine = MultiLanguageEngine::new();
+                let rhai_engine = engine.rhai.engine_mut().unwrap();
+                rhai_engine.register_fn("notify", || {
+                    std::thread::sleep(Duration::from_millis(300));
+                });
+                engine
+            }))
+            .with_activity_time_limit("script", Duration::from_millis(50));
+
+        let model = model.spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some(node),
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+            }) if node == "script" && code == "ActivityTimedOut"
+        ));
+
+        model.terminate().await;
+    }
+
+    #[cfg(feature = "rhai")]
+    #[bpxe_im::test]
+    async fn failure_policy() {
+        use crate::event::ProcessEvent;
+        use crate::process::{FailurePolicy, Incident, Log, Outcome};
+
+        // `notify` is not registered, so the script fails
+        let definitions = parse(include_str!("test_models/task_script.bpmn")).unwrap();
+
+        let model = model::Model::new(definitions.clone())
+            .with_failure_policy("proc1", FailurePolicy::Fail)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some(node),
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+            }) if node == "script" && code == "ScriptError"
+        ));
+        model.terminate().await;
+
+        // activity's policy takes precedence
+        let model = model::Model::new(definitions.clone())
+            .with_failure_policy("proc1", FailurePolicy::Fail)
+            .with_failure_policy("script", FailurePolicy::Skip)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert_eq!(handle.join().await, Outcome::Completed);
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::Done { visits, .. } if visits.get("end") == Some(&1)))
+                .await
+        );
+        model.terminate().await;
+
+        let model = model::Model::new(definitions)
+            .with_failure_policy("script", FailurePolicy::Suspend)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::ActivitySuspended { activity: Some(activity), .. }
+                    if activity == "script")
+                )
+                .await
+        );
+        assert!(handle.modify().skip("script").apply().await.is_ok());
+        assert_eq!(handle.join().await, Outcome::Completed);
+        model.terminate().await;
+    }
+
+    #[cfg(feature = "rhai")]
+    #[bpxe_im::test]
+    async fn result_variable() {
+        use crate::data_object;
+
+        let definitions = parse(include_str!("test_models/task_script_result.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_result_variable("script", "result")
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        let variables = handle.result().await.unwrap();
+        assert!(matches!(
+            variables
+                .get("result")
+                .and_then(|v| v.downcast_ref::<data_object::Container<i64>>()),
+            Some(data_object::Container(42))
+        ));
+        model.terminate().await;
+    }
 }
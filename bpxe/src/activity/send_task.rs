@@ -0,0 +1,207 @@
+//! # Send Task flow node
+//!
+//! Send tasks throw [`ProcessEvent::MessageEvent`] (attributed to the task) once executed and
+//! pass their tokens on right away, just like intermediate message throw events do.
+//!
+//! The message is the one referenced by the task or, if there's none, the input message of its
+//! operation. Input sets of the task make up the payload of the message: an object with input
+//! sets by their identifiers (the unnamed one as `input`), each of them being its only JSON
+//! data object, or an array of its JSON data objects. Data objects of other types are left out.
+use crate::activity::{message_event, Activity, InputSet};
+use crate::bpmn::schema::{Definitions, FlowNodeType, SendTask as Element};
+use crate::event::ProcessEvent;
+use crate::flow_node::{self, Action, FlowNode};
+use crate::process;
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+/// Send Task flow node
+pub struct Task {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    input_sets: Vec<InputSet>,
+    process: Option<process::Handle>,
+}
+
+impl Task {
+    /// Creates new Send Task flow node
+    pub fn new(element: Element) -> Self {
+        Self {
+            element: Arc::new(element),
+            state: State::Initialized,
+            waker: None,
+            input_sets: vec![],
+            process: None,
+        }
+    }
+
+    /// Returns the message event thrown by the task
+    pub fn event(
+        element: &Element,
+        definitions: &Definitions,
+        payload: Option<serde_json::Value>,
+    ) -> ProcessEvent {
+        message_event(
+            element.message_ref.as_ref(),
+            element.operation_ref.as_ref(),
+            definitions,
+            payload,
+        )
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Composes message payload out of input sets
+fn payload(input_sets: Vec<InputSet>) -> Option<serde_json::Value> {
+    if input_sets.is_empty() {
+        return None;
+    }
+    let mut payload = serde_json::Map::new();
+    for (name, input_set) in input_sets {
+        let mut values: Vec<serde_json::Value> = input_set
+            .into_iter()
+            .filter_map(|data_object| data_object.downcast::<serde_json::Value>().ok())
+            .map(|value| *value)
+            .collect();
+        let value = if values.len() == 1 {
+            values.pop().unwrap()
+        } else {
+            serde_json::Value::Array(values)
+        };
+        payload.insert(name.unwrap_or_else(|| "input".into()), value);
+    }
+    Some(serde_json::Value::Object(payload))
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum State {
+    Initialized,
+    Ready,
+    Execute,
+    Done,
+}
+
+impl FlowNode for Task {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::SendTask(state) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::SendTask(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn set_process(&mut self, process: crate::process::Handle) {
+        if let State::Initialized = self.state {
+            self.state = State::Ready;
+            self.wake();
+        }
+        self.process.replace(process);
+    }
+}
+
+impl Activity for Task {
+    fn execute(&mut self) {
+        self.state = State::Execute;
+        self.wake();
+    }
+
+    fn input_sets(&mut self, input_sets: Vec<InputSet>) {
+        self.input_sets = input_sets;
+    }
+}
+
+impl From<Element> for Task {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Task {
+    type Item = Action;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.state {
+            State::Initialized | State::Ready => {
+                self.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Execute => {
+                let process = match self.process.clone() {
+                    Some(process) => process,
+                    None => {
+                        self.waker.replace(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                };
+                self.state = State::Done;
+                // We only need input once, we can drain it
+                let payload = payload(std::mem::take(&mut self.input_sets));
+                let event = Task::event(&self.element, &process.model().definitions(), payload);
+                process.emit_event(self.element.id.clone(), event);
+                self.waker.replace(cx.waker().clone());
+                Poll::Ready(Some(Action::Flow(
+                    (0..self.element.outgoings().len()).collect(),
+                )))
+            }
+            State::Done => {
+                self.state = State::Ready;
+                Poll::Ready(Some(Action::Complete))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bpmn::parse;
+    use crate::event::ProcessEvent;
+    use crate::model;
+    use crate::test::Mailbox;
+    use bpxe_internal_macros as bpxe_im;
+    use serde_json::json;
+
+    #[bpxe_im::test]
+    async fn throws_message() {
+        let definitions = parse(include_str!("test_models/task_send.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        {
+            let data_object = handle.data_object("order").await.unwrap();
+            *data_object.write().await = Box::new(json!({"amount": 42}));
+        }
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::MessageEvent {
+                        message_ref: Some(message_ref),
+                        operation_ref: Some(operation_ref),
+                        payload: Some(payload),
+                    } if message_ref == "order_placed"
+                        && operation_ref == "place_order"
+                        && payload == &json!({"order": {"amount": 42}}))
+                    && e.source.as_deref() == Some("send"))
+                .await
+        );
+        model.terminate().await;
+    }
+}
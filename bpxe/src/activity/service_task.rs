@@ -0,0 +1,749 @@
+//! # Service Task flow node
+//!
+//! Service tasks are executed by handlers registered with the process (see
+//! [`process::Handle::register_service`]) for their topic. The topic is the `implementation`
+//! attribute of the task (unless it's one of the technology identifiers starting with `##`,
+//! such as `##WebService`), or its identifier otherwise.
+//!
+//! The handler receives the input sets of the task: named ones by their names, the unnamed
+//! one as `input`. Variables it returns are set before the task passes its token on.
+//!
+//! Failing handlers (including those that are not registered) are retried according to the
+//! retry policy of the task or its process, if any (see
+//! [`crate::model::Model::with_retry_policy`]). Failures are reported with
+//! [`Log::ServiceError`] and caught by an error boundary event attached to the task, if
+//! one matches, or otherwise handled according to the failure policy of the task or its
+//! process (see [`crate::model::Model::with_failure_policy`]).
+//!
+//! If the model limits concurrency of the task (see
+//! [`crate::model::Model::with_concurrency_limit`]), the handler waits for its turn before
+//! it is called. Each call is abandoned once it exceeds the execution time limit of the
+//! task (see [`crate::model::Model::with_activity_time_limit`]), which fails the process
+//! unless the task or its process has a failure policy.
+use crate::activity::{
+    acquire_permit, handle_failure, input_variables, retry_delay, task_failure_policy,
+    time_limit_exceeded, within_time_limit, Activity, InputSet, TaskCompletion,
+};
+use crate::bpmn::schema::{FlowNodeType, ServiceTask as Element};
+use crate::data_object::{self, DataObject};
+use crate::error::ErrorReport;
+use crate::event::ProcessEvent;
+use crate::flow_node::{self, Action, FlowNode};
+use crate::process::{self, Incident, Log, Variables};
+use crate::sys::{task, time};
+use futures::future::{AbortHandle, Abortable, BoxFuture};
+use futures::stream::Stream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+/// Service handler error
+#[derive(Error, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServiceError {
+    /// No handler is registered for the topic
+    #[error("no service handler registered for topic {0}")]
+    NotRegistered(String),
+    /// Handler has failed
+    #[error("service has failed: {message}")]
+    Failed {
+        /// Error code of the error event raised if the failure fails the process
+        code: Option<String>,
+        message: String,
+    },
+    /// Variables don't match the input of a typed handler (see
+    /// [`process::Handle::register_typed_service`])
+    #[error("service input doesn't match: {0}")]
+    InvalidInput(String),
+    /// Output of a typed handler doesn't serialize into variables (see
+    /// [`process::Handle::register_typed_service`])
+    #[error("service output doesn't match: {0}")]
+    InvalidOutput(String),
+}
+
+/// Result of a service handler: variables to set
+pub type ServiceResult = Result<Variables, ServiceError>;
+
+/// Service handler (see [`process::Handle::register_service`])
+pub type ServiceHandler = Arc<dyn Fn(Variables) -> BoxFuture<'static, ServiceResult> + Send + Sync>;
+
+/// Deserializes variables into the input of a typed handler
+///
+/// Variables are converted into a JSON object (see [`data_object::to_json`]). Input sets
+/// holding a single data object are unwrapped into that object.
+pub(crate) fn typed_input<In>(variables: Variables) -> Result<In, ServiceError>
+where
+    In: DeserializeOwned,
+{
+    let mut input = serde_json::Map::new();
+    for (name, value) in variables {
+        let value = match value.downcast::<data_object::Collection>() {
+            Ok(mut collection) if collection.len() == 1 => collection.0.remove(0),
+            Ok(collection) => collection as Box<dyn DataObject>,
+            Err(value) => value,
+        };
+        let value = data_object::to_json(&*value).ok_or_else(|| {
+            ServiceError::InvalidInput(format!("variable `{}` can't be represented as JSON", name))
+        })?;
+        input.insert(name, value);
+    }
+    serde_json::from_value(serde_json::Value::Object(input))
+        .map_err(|err| ServiceError::InvalidInput(err.to_string()))
+}
+
+/// Serializes the output of a typed handler into variables, one for every field
+pub(crate) fn typed_output<Out>(output: &Out) -> ServiceResult
+where
+    Out: Serialize,
+{
+    match serde_json::to_value(output) {
+        Ok(serde_json::Value::Object(fields)) => Ok(fields
+            .into_iter()
+            .map(|(name, value)| (name, Box::new(value) as Box<dyn DataObject>))
+            .collect()),
+        Ok(serde_json::Value::Null) => Ok(Variables::new()),
+        Ok(value) => Err(ServiceError::InvalidOutput(format!(
+            "expected a struct or a map of variables, got {}",
+            value
+        ))),
+        Err(err) => Err(ServiceError::InvalidOutput(err.to_string())),
+    }
+}
+
+/// Service Task flow node
+pub struct Task {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    notifier: broadcast::Sender<TaskCompletion>,
+    notifier_receiver: broadcast::Receiver<TaskCompletion>,
+    input_sets: Vec<InputSet>,
+    sandboxed: bool,
+    process: Option<process::Handle>,
+    // in-flight work, abandoned if the node is dropped before it completes
+    work: Option<AbortHandle>,
+}
+
+impl Task {
+    /// Creates new Service Task flow node
+    pub fn new(element: Element) -> Self {
+        let (notifier, notifier_receiver) = broadcast::channel(1);
+        Self {
+            element: Arc::new(element),
+            state: State::Initialized,
+            waker: None,
+            notifier,
+            notifier_receiver,
+            input_sets: vec![],
+            sandboxed: false,
+            process: None,
+            work: None,
+        }
+    }
+
+    /// Returns the topic the handler of the task is registered for
+    pub fn topic(element: &Element) -> Option<String> {
+        element
+            .implementation
+            .clone()
+            .filter(|implementation| !implementation.starts_with("##"))
+            .or_else(|| element.id.clone())
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some(work) = self.work.take() {
+            work.abort();
+        }
+    }
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum State {
+    Initialized,
+    Ready,
+    Execute,
+    /// Waiting for the handler to complete
+    Waiting,
+    Errored,
+    Done,
+}
+
+impl FlowNode for Task {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::ServiceTask(state) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::ServiceTask(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn set_process(&mut self, process: crate::process::Handle) {
+        self.sandboxed = process.is_sandboxed();
+        if let State::Initialized = self.state {
+            self.state = State::Ready;
+            self.wake();
+        }
+        self.process.replace(process);
+    }
+}
+
+impl Activity for Task {
+    fn execute(&mut self) {
+        self.state = State::Execute;
+        self.wake();
+    }
+
+    fn input_sets(&mut self, input_sets: Vec<InputSet>) {
+        self.input_sets = input_sets;
+    }
+}
+
+impl From<Element> for Task {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Task {
+    type Item = Action;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.state {
+            State::Initialized | State::Ready | State::Errored => {
+                self.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Execute if self.sandboxed => {
+                // services are not called in a sandbox
+                self.state = State::Waiting;
+                let _ = self.notifier.send(TaskCompletion::Success(None));
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            State::Execute => {
+                self.state = State::Waiting;
+                let process = match self.process.clone() {
+                    Some(process) => process,
+                    None => {
+                        self.waker.replace(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                };
+                let waker = cx.waker().clone();
+                let element = self.element.clone();
+                let notifier = self.notifier.clone();
+                // We only need input once, we can drain it
+                let variables = input_variables(std::mem::take(&mut self.input_sets));
+                let (work, registration) = AbortHandle::new_pair();
+                self.work = Some(work);
+                task::spawn(Abortable::new(
+                    async move {
+                        let topic = Task::topic(&element).unwrap_or_default();
+                        let _permit = acquire_permit(&process, element.id.as_deref()).await;
+                        let mut attempt = 1;
+                        let result = loop {
+                            let call = async {
+                                match process.service(&topic) {
+                                    Some(handler) => handler(variables.clone()).await,
+                                    None => Err(ServiceError::NotRegistered(topic.clone())),
+                                }
+                            };
+                            let result = match within_time_limit(
+                                &process,
+                                element.id.as_deref(),
+                                call,
+                            )
+                            .await
+                            {
+                                Ok(result) => result,
+                                Err(limit) => {
+                                    let completion =
+                                        time_limit_exceeded(&process, element.id.as_ref(), limit)
+                                            .await;
+                                    let _ = notifier.send(completion);
+                                    waker.wake();
+                                    return;
+                                }
+                            };
+                            let retry = match result {
+                                Err(ref err) => retry_delay(
+                                    &process,
+                                    element.id.as_ref(),
+                                    attempt,
+                                    ErrorReport::new(err),
+                                ),
+                                Ok(_) => None,
+                            };
+                            match retry {
+                                Some(delay) => {
+                                    time::sleep(delay).await;
+                                    attempt += 1;
+                                }
+                                None => break result,
+                            }
+                        };
+                        let completion = match result {
+                            Ok(variables) => {
+                                for (id, value) in variables {
+                                    if let Err(err) = process.set_variable(&id, value).await {
+                                        let _ = process.log_broadcast().send(Log::ServiceError {
+                                            element: element.id.clone(),
+                                            error: ErrorReport::new(&err),
+                                        });
+                                    }
+                                }
+                                TaskCompletion::Success(None)
+                            }
+                            Err(err) => {
+                                let _ = process.log_broadcast().send(Log::ServiceError {
+                                    element: element.id.clone(),
+                                    error: ErrorReport::new(&err),
+                                });
+                                let error_code = match err {
+                                    ServiceError::Failed {
+                                        code: Some(ref code),
+                                        ..
+                                    } => code.clone(),
+                                    _ => "ServiceError".into(),
+                                };
+                                let policy = task_failure_policy(&process, element.id.as_deref());
+                                handle_failure(
+                                    &process,
+                                    Incident {
+                                        node: element.id.clone(),
+                                        error: ProcessEvent::ErrorEvent {
+                                            error_ref: None,
+                                            error_code: Some(error_code),
+                                            error_message: Some(err.to_string()),
+                                        },
+                                    },
+                                    policy,
+                                )
+                                .await
+                            }
+                        };
+                        let _ = notifier.send(completion);
+                        waker.wake();
+                    },
+                    registration,
+                ));
+                Poll::Pending
+            }
+            State::Waiting => match self.notifier_receiver.try_recv() {
+                Ok(TaskCompletion::Success(_)) => {
+                    self.waker.replace(cx.waker().clone());
+                    self.state = State::Done;
+                    Poll::Ready(Some(Action::Flow(
+                        (0..self.element.outgoings().len()).collect(),
+                    )))
+                }
+                Ok(TaskCompletion::Error) => {
+                    self.state = State::Errored;
+                    Poll::Ready(Some(Action::Complete))
+                }
+                Ok(TaskCompletion::Suspended) | Ok(TaskCompletion::Caught) => {
+                    // the token stays here until the process is modified (or it has been
+                    // taken over by an error boundary event, which cancels this task)
+                    self.state = State::Errored;
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
+                Err(broadcast::error::TryRecvError::Empty)
+                | Err(broadcast::error::TryRecvError::Lagged(_)) => {
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
+                Err(broadcast::error::TryRecvError::Closed) => Poll::Ready(None),
+            },
+            State::Done => {
+                self.state = State::Ready;
+                Poll::Ready(Some(Action::Complete))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServiceError;
+    use crate::bpmn::parse;
+    use crate::data_object;
+    use crate::event::ProcessEvent;
+    use crate::model;
+    use crate::process::{FailurePolicy, Incident, Log, Outcome, RetryPolicy, Variables};
+    use crate::test::*;
+    use bpxe_internal_macros as bpxe_im;
+    use serde::{Deserialize, Serialize};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[bpxe_im::test]
+    async fn calls_handler() {
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        handle.register_service("charge", |_variables| async {
+            let mut variables = Variables::new();
+            variables.insert("result".into(), Box::new(data_object::Container(42i64)));
+            Ok(variables)
+        });
+        assert!(handle.start().await.is_ok());
+        let variables = handle.result().await.unwrap();
+        assert!(matches!(
+            variables
+                .get("result")
+                .and_then(|v| v.downcast_ref::<data_object::Container<i64>>()),
+            Some(data_object::Container(42))
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn failure() {
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_failure_policy("service", FailurePolicy::Fail)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        handle.register_service("charge", |_variables| async {
+            Err(ServiceError::Failed {
+                code: Some("CardDeclined".into()),
+                message: "card declined".into(),
+            })
+        });
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::ServiceError { element, error }
+                    if element.as_deref() == Some("service") && error.code == "service.failed"))
+                .await
+        );
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some(node),
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+            }) if node == "service" && code == "CardDeclined"
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn boundary_catches_failure() {
+        let definitions = parse(include_str!("test_models/task_service_boundary.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_failure_policy("service", FailurePolicy::Fail)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        handle.register_service("charge", |_variables| async {
+            Err(ServiceError::Failed {
+                code: Some("CardDeclined".into()),
+                message: "card declined".into(),
+            })
+        });
+        assert!(handle.start().await.is_ok());
+        // the boundary event matching the error code wins over the catch-all one before it,
+        // and the failure policy doesn't apply
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                    if visits.contains_key("declined_end") && !visits.contains_key("any_end"))
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn timeout_abandons_handler() {
+        use crate::process::TimeoutAction;
+        use crate::sys::time;
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_task_timeout(
+                "service",
+                Duration::from_millis(50),
+                TimeoutAction::Flow("Flow_end".into()),
+            )
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let finished_ = finished.clone();
+        handle.register_service("charge", move |_variables| {
+            let finished = finished_.clone();
+            async move {
+                time::sleep(Duration::from_millis(200)).await;
+                finished.fetch_add(1, Ordering::SeqCst);
+                Ok(Variables::new())
+            }
+        });
+        assert!(handle.start().await.is_ok());
+        // the task times out after 50ms, well before the handler would finish
+        assert_eq!(
+            timeout_after(Duration::from_millis(150), handle.join())
+                .await
+                .unwrap(),
+            Outcome::Completed
+        );
+        time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn time_limit() {
+        use crate::sys::time;
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_activity_time_limit("service", Duration::from_millis(10))
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        handle.register_service("charge", |_variables| async {
+            time::sleep(Duration::from_secs(60)).await;
+            Ok(Variables::new())
+        });
+        assert!(handle.start().await.is_ok());
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some(node),
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+            }) if node == "service" && code == "ActivityTimedOut"
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn concurrency_limit() {
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_concurrency_limit("service", 0)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_ = calls.clone();
+        handle.register_service("charge", move |_variables| {
+            calls_.fetch_add(1, Ordering::SeqCst);
+            async { Ok(Variables::new()) }
+        });
+        assert!(handle.start().await.is_ok());
+        // the handler never gets its turn
+        assert!(expects_timeout(handle.join()).await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn retries() {
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_retry_policy("service", RetryPolicy::new(3, Duration::from_millis(1)))
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_ = calls.clone();
+        handle.register_service("charge", move |_variables| {
+            let call = calls_.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if call < 2 {
+                    return Err(ServiceError::Failed {
+                        code: None,
+                        message: "unavailable".into(),
+                    });
+                }
+                let mut variables = Variables::new();
+                variables.insert("result".into(), Box::new(data_object::Container(42i64)));
+                Ok(variables)
+            }
+        });
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::AttemptFailed { attempt: 2, retry_in, .. }
+                    if *retry_in == Some(Duration::from_millis(2)))
+                )
+                .await
+        );
+        assert!(handle.result().await.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn retries_exhausted() {
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_retry_policy("service", RetryPolicy::new(1, Duration::from_millis(1)))
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        handle.register_service("charge", |_variables| async {
+            Err(ServiceError::Failed {
+                code: Some("Unavailable".into()),
+                message: "unavailable".into(),
+            })
+        });
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(
+                    e,
+                    Log::AttemptFailed {
+                        attempt: 2,
+                        retry_in: None,
+                        ..
+                    }
+                ))
+                .await
+        );
+        // the process fails rather than the token stalling
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some(node),
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+            }) if node == "service" && code == "Unavailable"
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn typed_handler() {
+        #[derive(Deserialize)]
+        struct Charge {}
+
+        #[derive(Serialize)]
+        struct Receipt {
+            result: i64,
+        }
+
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        handle.register_typed_service("charge", |_charge: Charge| async {
+            Ok(Receipt { result: 42 })
+        });
+        assert!(handle.start().await.is_ok());
+        let variables = handle.result().await.unwrap();
+        assert_eq!(
+            variables
+                .get("result")
+                .and_then(|v| v.downcast_ref::<serde_json::Value>()),
+            Some(&serde_json::json!(42))
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn typed_handler_input() {
+        #[derive(Deserialize)]
+        struct Charge {
+            // input set with a single data object
+            amount: i64,
+            // input set with more than one
+            items: Vec<String>,
+        }
+
+        #[derive(Serialize)]
+        struct Receipt {
+            total: i64,
+        }
+
+        let definitions = parse(include_str!("test_models/task_service_input.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        // charged by the letter
+        handle.register_typed_service("charge", |charge: Charge| async move {
+            Ok(Receipt {
+                total: charge.amount * charge.items.concat().len() as i64,
+            })
+        });
+        assert!(handle
+            .start_with()
+            .variable("amount", Box::new(data_object::Container(10i64)))
+            .variable(
+                "first_item",
+                Box::new(data_object::Container("book".to_string()))
+            )
+            .variable(
+                "second_item",
+                Box::new(data_object::Container("pen".to_string()))
+            )
+            .start()
+            .await
+            .is_ok());
+        let variables = handle.result().await.unwrap();
+        assert_eq!(
+            variables
+                .get("total")
+                .and_then(|v| v.downcast_ref::<serde_json::Value>()),
+            Some(&serde_json::json!(70))
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn typed_handler_mismatch() {
+        #[derive(Deserialize)]
+        struct Charge {
+            #[allow(dead_code)]
+            amount: i64,
+        }
+
+        let definitions = parse(include_str!("test_models/task_service.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_failure_policy("service", FailurePolicy::Fail)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        handle.register_typed_service("charge", |_charge: Charge| async { Ok(()) });
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::ServiceError { error, .. }
+                    if error.code == "service.invalid_input"
+                        && error.message.contains("missing field `amount`")))
+                .await
+        );
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Failed);
+        model.terminate().await;
+    }
+}
@@ -0,0 +1,307 @@
+//! # User Task flow node
+//!
+//! User tasks are performed by people. Once reached by a token, a user task opens a task on the
+//! task list of its model (see [`crate::model::Handle::user_tasks`]) and keeps the token until
+//! the task is completed. Variables the task is completed with are set before the user task
+//! passes its token on.
+//!
+//! In a sandbox (see [`process::Handle::fork`]), user tasks don't open tasks and pass their
+//! tokens on right away.
+use crate::activity::{Activity, InputSet};
+use crate::bpmn::schema::{FlowNodeType, UserTask as Element};
+use crate::error::ErrorReport;
+use crate::flow_node::{self, Action, FlowNode};
+use crate::process::{self, Log};
+use crate::sys::task;
+use crate::tasks::{TaskId, UserTasks};
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use tokio::sync::broadcast;
+
+/// User Task flow node
+pub struct Task {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    notifier: broadcast::Sender<()>,
+    notifier_receiver: broadcast::Receiver<()>,
+    sandboxed: bool,
+    process: Option<process::Handle>,
+    // open task, withdrawn if the node is dropped before it is completed
+    open: Option<(TaskId, UserTasks)>,
+}
+
+impl Task {
+    /// Creates new User Task flow node
+    pub fn new(element: Element) -> Self {
+        let (notifier, notifier_receiver) = broadcast::channel(1);
+        Self {
+            element: Arc::new(element),
+            state: State::Initialized,
+            waker: None,
+            notifier,
+            notifier_receiver,
+            sandboxed: false,
+            process: None,
+            open: None,
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Some((id, user_tasks)) = self.open.take() {
+            user_tasks.withdraw(id);
+        }
+    }
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum State {
+    Initialized,
+    Ready,
+    Execute,
+    /// Waiting for the task to be completed
+    Waiting,
+    Done,
+}
+
+impl FlowNode for Task {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::UserTask(state) => {
+                self.state = state;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::UserTask(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn set_process(&mut self, process: crate::process::Handle) {
+        self.sandboxed = process.is_sandboxed();
+        if let State::Initialized = self.state {
+            self.state = State::Ready;
+            self.wake();
+        }
+        self.process.replace(process);
+    }
+}
+
+impl Activity for Task {
+    fn execute(&mut self) {
+        self.state = State::Execute;
+        self.wake();
+    }
+
+    fn input_sets(&mut self, _input_sets: Vec<InputSet>) {}
+}
+
+impl From<Element> for Task {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Task {
+    type Item = Action;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.state {
+            State::Initialized | State::Ready => {
+                self.waker.replace(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Execute if self.sandboxed => {
+                // nobody performs tasks in a sandbox
+                self.state = State::Waiting;
+                let _ = self.notifier.send(());
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            State::Execute => {
+                let process = match self.process.clone() {
+                    Some(process) => process,
+                    None => {
+                        self.waker.replace(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                };
+                self.state = State::Waiting;
+                let model = process.model();
+                let user_tasks = model.user_tasks();
+                let (id, completion) = user_tasks.open(
+                    process.id(),
+                    process.element().id.clone(),
+                    self.element.id.clone(),
+                    self.element.name.clone(),
+                    self.element.id.as_ref().and_then(|id| model.form(id)),
+                );
+                self.open = Some((id, user_tasks));
+                let waker = cx.waker().clone();
+                let element = self.element.clone();
+                let notifier = self.notifier.clone();
+                task::spawn(async move {
+                    // the task is withdrawn (and the sender dropped) if the node is gone
+                    if let Ok(variables) = completion.await {
+                        for (id, value) in variables {
+                            if let Err(err) = process.set_variable(&id, value).await {
+                                let _ = process.log_broadcast().send(Log::UserTaskError {
+                                    element: element.id.clone(),
+                                    error: ErrorReport::new(&err),
+                                });
+                            }
+                        }
+                        let _ = notifier.send(());
+                        waker.wake();
+                    }
+                });
+                Poll::Pending
+            }
+            State::Waiting => match self.notifier_receiver.try_recv() {
+                Ok(()) => {
+                    self.open = None;
+                    self.waker.replace(cx.waker().clone());
+                    self.state = State::Done;
+                    Poll::Ready(Some(Action::Flow(
+                        (0..self.element.outgoings().len()).collect(),
+                    )))
+                }
+                Err(broadcast::error::TryRecvError::Empty)
+                | Err(broadcast::error::TryRecvError::Lagged(_)) => {
+                    self.waker.replace(cx.waker().clone());
+                    Poll::Pending
+                }
+                Err(broadcast::error::TryRecvError::Closed) => Poll::Ready(None),
+            },
+            State::Done => {
+                self.state = State::Ready;
+                Poll::Ready(Some(Action::Complete))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bpmn::parse;
+    use crate::data_object;
+    use crate::model;
+    use crate::process::Variables;
+    use crate::sys::task;
+    use crate::tasks::{Form, TaskError};
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    async fn complete() {
+        let definitions = parse(include_str!("test_models/task_user.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        let user_tasks = model.user_tasks();
+        while user_tasks.list().is_empty() {
+            task::yield_now().await;
+        }
+        let task = user_tasks.list().pop().unwrap();
+        assert_eq!(task.instance, handle.id());
+        assert_eq!(task.element.as_deref(), Some("approve"));
+        assert_eq!(task.name.as_deref(), Some("Approve"));
+
+        assert!(user_tasks.claim(task.id, "alice").is_ok());
+        assert_eq!(
+            user_tasks.claim(task.id, "bob"),
+            Err(TaskError::AlreadyClaimed {
+                task: task.id,
+                assignee: "alice".into()
+            })
+        );
+        assert_eq!(
+            user_tasks.list().pop().unwrap().assignee.as_deref(),
+            Some("alice")
+        );
+
+        let mut variables = Variables::new();
+        variables.insert("approved".into(), Box::new(data_object::Container(true)));
+        assert!(user_tasks.complete(task.id, variables).is_ok());
+        let variables = handle.result().await.unwrap();
+        assert!(matches!(
+            variables
+                .get("approved")
+                .and_then(|v| v.downcast_ref::<data_object::Container<bool>>()),
+            Some(data_object::Container(true))
+        ));
+        assert!(user_tasks.list().is_empty());
+        assert_eq!(
+            user_tasks.complete(task.id, Variables::new()),
+            Err(TaskError::NotFound(task.id))
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn form() {
+        let definitions = parse(include_str!("test_models/task_user.bpmn")).unwrap();
+        let form = Form::schema(serde_json::json!({
+            "required": ["approved"],
+            "properties": { "approved": { "type": "boolean" } },
+            "additionalProperties": false
+        }));
+        let model = model::Model::new(definitions)
+            .with_form("approve", form.clone())
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        let user_tasks = model.user_tasks();
+        while user_tasks.list().is_empty() {
+            task::yield_now().await;
+        }
+        let task = user_tasks.list().pop().unwrap();
+        assert_eq!(task.form, Some(form));
+
+        assert!(matches!(
+            user_tasks.complete(task.id, Variables::new()),
+            Err(TaskError::InvalidSubmission { .. })
+        ));
+        let mut variables = Variables::new();
+        variables.insert(
+            "approved".into(),
+            Box::new(data_object::Container("yes".to_string())),
+        );
+        assert!(matches!(
+            user_tasks.complete(task.id, variables),
+            Err(TaskError::InvalidSubmission { .. })
+        ));
+        let mut variables = Variables::new();
+        variables.insert("approved".into(), Box::new(data_object::Container(true)));
+        variables.insert("comment".into(), Box::new(data_object::Empty));
+        assert!(matches!(
+            user_tasks.complete(task.id, variables),
+            Err(TaskError::InvalidSubmission { .. })
+        ));
+        // rejected submissions leave the task open
+        assert_eq!(user_tasks.list().len(), 1);
+
+        let mut variables = Variables::new();
+        variables.insert("approved".into(), Box::new(data_object::Container(true)));
+        assert!(user_tasks.complete(task.id, variables).is_ok());
+        assert!(handle.result().await.is_ok());
+        model.terminate().await;
+    }
+}
@@ -4,4 +4,4 @@
 pub use bpxe_bpmn_schema as schema;
 
 mod parser;
-pub use parser::{parse, NormalizationError, ParseError};
+pub use parser::{parse, parse_reader, NormalizationError, ParseError, SyntaxError};
@@ -1,23 +1,76 @@
 use crate::bpmn::schema::Definitions;
-use strong_xml::XmlRead;
+use std::borrow::Cow;
+use std::io::Read;
+use strong_xml::{XmlError, XmlRead};
 use thiserror::Error;
 
 /// Parsing error
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum ParseError {
-    #[error("xml parsing error: {error:?}")]
-    ParsingError { error: strong_xml::XmlError },
-    #[error("xml normalization error: {error:?}")]
-    NormalizationError {
-        #[from]
-        error: NormalizationError,
-    },
+    /// Document can't be read
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    /// Document is not well-formed XML
+    #[error("xml syntax error: {error}")]
+    Syntax { error: SyntaxError },
+    /// Document is well-formed XML, but doesn't conform to BPMN schema
+    #[error("schema violation: {error:?}")]
+    Schema { error: XmlError },
+    /// Document uses a construct that is not supported
+    #[error("unsupported construct: {construct}")]
+    Unsupported { construct: String },
+}
+
+/// XML syntax error, as reported by the parser that has encountered it
+#[derive(Error, Debug)]
+pub enum SyntaxError {
+    /// Reported while reading the document
+    #[error("{0:?}")]
+    Reading(XmlError),
+    /// Reported while normalizing the document
+    #[error("{0:?}")]
+    Normalization(sxd::parser::Error),
+}
+
+impl From<NormalizationError> for ParseError {
+    fn from(error: NormalizationError) -> Self {
+        match error {
+            NormalizationError::ParsingError { error } => ParseError::Syntax {
+                error: SyntaxError::Normalization(error),
+            },
+            NormalizationError::WritingError { error } => ParseError::Io(error),
+            NormalizationError::UnsupportedRoot { name } => ParseError::Unsupported {
+                construct: format!("root element {}", name),
+            },
+        }
+    }
+}
+
+impl From<XmlError> for ParseError {
+    fn from(error: XmlError) -> Self {
+        match error {
+            XmlError::MissingField { .. } | XmlError::FromStr(_) => ParseError::Schema { error },
+            _ => ParseError::Syntax {
+                error: SyntaxError::Reading(error),
+            },
+        }
+    }
 }
 
 /// Parse BPMN XML document.
+///
+/// Malformed input never panics, an error is returned instead.
 pub fn parse(string: &str) -> Result<Definitions, ParseError> {
     let normalized = normalize(string)?;
-    Definitions::from_str(&normalized).map_err(|err| ParseError::ParsingError { error: err })
+    Ok(Definitions::from_str(&normalized)?)
+}
+
+/// Read and parse BPMN XML document.
+pub fn parse_reader<R: Read>(mut reader: R) -> Result<Definitions, ParseError> {
+    let mut string = String::new();
+    reader.read_to_string(&mut string)?;
+    parse(&string)
 }
 
 use sxd_document as sxd;
@@ -29,6 +82,8 @@ pub enum NormalizationError {
     ParsingError { error: sxd::parser::Error },
     #[error("xml writing error: {error:?}")]
     WritingError { error: std::io::Error },
+    #[error("unsupported root element {name}")]
+    UnsupportedRoot { name: String },
 }
 
 const BPMN_NS: &str = "http://www.omg.org/spec/BPMN/20100524/MODEL";
@@ -43,7 +98,13 @@ const BPMN_NS: &str = "http://www.omg.org/spec/BPMN/20100524/MODEL";
 //
 // * Resolve BPMN's namespace (http://www.omg.org/spec/BPMN/20100524/MODEL) and
 //   ensure that `bpmn` is used as a declared prefix for it.
-fn normalize(string: &str) -> Result<String, NormalizationError> {
+//
+// Building and writing out the document is expensive for large documents, so documents
+// that already look this way (see [`is_normalized`]) are returned as is.
+fn normalize(string: &str) -> Result<Cow<'_, str>, NormalizationError> {
+    if is_normalized(string) {
+        return Ok(Cow::Borrowed(string));
+    }
     let package = sxd::parser::parse(string)
         .map_err(|err| NormalizationError::ParsingError { error: err })?;
     let doc = package.as_document();
@@ -54,7 +115,7 @@ fn normalize(string: &str) -> Result<String, NormalizationError> {
         _ => None,
     });
     match top {
-        None => Ok(string.into()),
+        None => Ok(Cow::Borrowed(string)),
         Some(e) => {
             if e.name().local_part() == "definitions" {
                 let ns = e
@@ -67,16 +128,71 @@ fn normalize(string: &str) -> Result<String, NormalizationError> {
                         let mut output = Vec::new();
                         sxd::writer::format_document(&doc, &mut output)
                             .map_err(|err| NormalizationError::WritingError { error: err })?;
-                        return Ok(String::from_utf8_lossy(&output).into_owned());
+                        return Ok(Cow::Owned(String::from_utf8_lossy(&output).into_owned()));
                     }
                     Some(_) => {}
                 }
+            } else {
+                return Err(NormalizationError::UnsupportedRoot {
+                    name: e.name().local_part().to_string(),
+                });
             }
-            Ok(string.into())
+            Ok(Cow::Borrowed(string))
         }
     }
 }
 
+// Checks (in a single pass, without building the document) whether the root element is
+// `bpmn:definitions` declaring `bpmn` as a prefix for BPMN's namespace, and no other prefix
+// (or default namespace) is bound to it anywhere.
+//
+// Anything unusual (including syntax errors, CDATA sections and DTDs) is left to the
+// normalization.
+fn is_normalized(string: &str) -> bool {
+    use xmlparser::{ElementEnd, Token, Tokenizer};
+    let mut root = None;
+    let mut in_root = false;
+    let mut declared = false;
+    for token in Tokenizer::from(string) {
+        match token {
+            Ok(Token::ElementStart { prefix, local, .. }) => {
+                in_root = root.is_none();
+                if in_root {
+                    root = Some((prefix.as_str(), local.as_str()));
+                }
+            }
+            Ok(Token::ElementEnd { end, .. }) => {
+                if matches!(end, ElementEnd::Open | ElementEnd::Empty) {
+                    in_root = false;
+                }
+            }
+            Ok(Token::Attribute {
+                prefix,
+                local,
+                value,
+                ..
+            }) => {
+                let bound = match (prefix.as_str(), local.as_str()) {
+                    ("xmlns", prefix) => prefix,
+                    ("", "xmlns") => "",
+                    _ => continue,
+                };
+                match (bound == "bpmn", value.as_str() == BPMN_NS) {
+                    (true, true) if in_root => declared = true,
+                    (false, false) => {}
+                    _ => return false,
+                }
+            }
+            Ok(Token::Cdata { .. })
+            | Ok(Token::DtdStart { .. })
+            | Ok(Token::EmptyDtd { .. })
+            | Err(_) => return false,
+            Ok(_) => {}
+        }
+    }
+    declared && root == Some(("bpmn", "definitions"))
+}
+
 fn update_prefix(element: &sxd::dom::Element) {
     element.set_preferred_prefix(Some("bpmn"));
     let children = element.children();
@@ -87,3 +203,70 @@ fn update_prefix(element: &sxd::dom::Element) {
 
     element_sub.for_each(update_prefix);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    fn syntax_error() {
+        assert!(matches!(
+            parse("<bpmn:definitions"),
+            Err(ParseError::Syntax { .. })
+        ));
+        assert!(matches!(parse(""), Err(ParseError::Syntax { .. })));
+    }
+
+    #[bpxe_im::test]
+    fn unsupported_root() {
+        assert!(matches!(
+            parse("<process/>"),
+            Err(ParseError::Unsupported { .. })
+        ));
+    }
+
+    #[bpxe_im::test]
+    fn io_error() {
+        assert!(matches!(
+            parse_reader(&[0xffu8, 0xfe][..]),
+            Err(ParseError::Io(_))
+        ));
+    }
+
+    #[bpxe_im::test]
+    fn normalized() {
+        let document = include_str!("../event/test_models/throw_none_event.bpmn");
+        assert!(is_normalized(document));
+        let renamed = document
+            .replace("xmlns:bpmn=", "xmlns:semantic=")
+            .replace("<bpmn:", "<semantic:")
+            .replace("</bpmn:", "</semantic:");
+        assert!(!is_normalized(&renamed));
+        assert!(!is_normalized(&renamed.replace(
+            "xmlns:semantic=",
+            "xmlns:bpmn=\"http://www.omg.org/spec/BPMN/20100524/MODEL\" xmlns:semantic="
+        )));
+        assert_eq!(parse(document).unwrap(), parse(&renamed).unwrap());
+    }
+
+    #[bpxe_im::test]
+    fn garbage() {
+        for input in &[
+            "\0\0\0",
+            "<?xml version=\"1.0\"?>",
+            "<bpmn:definitions xmlns:bpmn=\"http://www.omg.org/spec/BPMN/20100524/MODEL\"><bpmn:process><",
+            "<bpmn:definitions xmlns:bpmn=\"http://www.omg.org/spec/BPMN/20100524/MODEL\"><bpmn:process id=\"p\"><bpmn:startEvent></bpmn:process></bpmn:definitions>",
+        ] {
+            assert!(parse(input).is_err());
+        }
+    }
+
+    #[bpxe_im::test]
+    fn truncated() {
+        let document = include_str!("../process/test_models/default_flow.bpmn");
+        for (end, _) in document.char_indices() {
+            assert!(parse(&document[..end]).is_err());
+        }
+    }
+}
@@ -84,6 +84,39 @@ impl Default for Collection {
 // Support for JSON data structures
 impl DataObject for serde_json::Value {}
 
+/// Converts a data object into JSON
+///
+/// Supports JSON values, empty data objects (as `null`), containers of `bool`, `i64`, `f64`
+/// and `String`, and collections of any of these. Returns `None` for anything else.
+pub fn to_json(data_object: &dyn DataObject) -> Option<serde_json::Value> {
+    if let Some(value) = data_object.downcast_ref::<serde_json::Value>() {
+        return Some(value.clone());
+    }
+    if data_object.is::<Empty>() {
+        return Some(serde_json::Value::Null);
+    }
+    if let Some(collection) = data_object.downcast_ref::<Collection>() {
+        return collection
+            .iter()
+            .map(|item| to_json(&**item))
+            .collect::<Option<Vec<_>>>()
+            .map(serde_json::Value::Array);
+    }
+    if let Some(Container(value)) = data_object.downcast_ref::<Container<bool>>() {
+        return Some((*value).into());
+    }
+    if let Some(Container(value)) = data_object.downcast_ref::<Container<i64>>() {
+        return Some((*value).into());
+    }
+    if let Some(Container(value)) = data_object.downcast_ref::<Container<f64>>() {
+        return serde_json::Number::from_f64(*value).map(serde_json::Value::Number);
+    }
+    if let Some(Container(value)) = data_object.downcast_ref::<Container<String>>() {
+        return Some(value.clone().into());
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,4 +156,18 @@ mod tests {
             Some(Collection(v)) if matches!(v[1].downcast_ref::<Container<u8>>(), Some(Container(1)))
         ));
     }
+
+    #[bpxe_im::test]
+    fn json() {
+        let collection = Collection(vec![
+            Box::new(Container(1i64)),
+            Box::new(Container("a".to_string())),
+            Box::new(Empty),
+        ]);
+        assert_eq!(
+            to_json(&collection),
+            Some(serde_json::json!([1, "a", null]))
+        );
+        assert_eq!(to_json(&Container(1u8)), None);
+    }
 }
@@ -0,0 +1,72 @@
+//! # Decisions
+//!
+//! Business rule tasks delegate their decisions to a [`DecisionEvaluator`] registered with
+//! [`crate::model::Model::with_decision_evaluator`], which can evaluate them locally (for
+//! example, with a decision table) or remotely.
+//!
+//! The decision of a business rule task is the one referenced with
+//! [`crate::model::Model::with_decision_ref`] (standing in for the `decisionRef` extension
+//! attribute, which isn't retained by the parser), or, if there's none, its `implementation`
+//! attribute (unless it's one of the technology identifiers starting with `##`), or its
+//! identifier otherwise.
+//!
+//! Decisions can also be evaluated directly with [`evaluate`], by the same evaluator, without
+//! wrapping them in a process.
+use crate::bpmn::schema::BusinessRuleTask;
+use crate::model;
+use crate::process::Variables;
+use async_trait::async_trait;
+use thiserror::Error;
+
+/// Decision evaluation error
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum DecisionError {
+    /// No decision evaluator is registered with the model
+    #[error("no decision evaluator registered")]
+    NoEvaluator,
+    /// Evaluator doesn't know the decision
+    #[error("unknown decision {0}")]
+    UnknownDecision(String),
+    /// Decision evaluation has failed
+    #[error("decision evaluation has failed: {0}")]
+    Failed(String),
+}
+
+/// Decision evaluator
+#[async_trait]
+pub trait DecisionEvaluator: Send + Sync {
+    /// Evaluates a decision with input variables, returning its output variables
+    ///
+    /// Input variables are input sets of the business rule task: named ones by their
+    /// names, the unnamed one as `input`. Output variables are set before the task passes
+    /// its token on.
+    async fn evaluate(&self, decision: &str, input: Variables) -> Result<Variables, DecisionError>;
+}
+
+/// Returns the decision a business rule task delegates to
+pub fn decision_ref(model: &model::Handle, element: &BusinessRuleTask) -> Option<String> {
+    element
+        .id
+        .as_ref()
+        .and_then(|id| model.decision_ref(id))
+        .or_else(|| {
+            element
+                .implementation
+                .clone()
+                .filter(|implementation| !implementation.starts_with("##"))
+        })
+        .or_else(|| element.id.clone())
+}
+
+/// Evaluates a decision with input variables using the decision evaluator of a model,
+/// returning its output variables
+pub async fn evaluate(
+    model: &model::Handle,
+    decision: &str,
+    input: Variables,
+) -> Result<Variables, DecisionError> {
+    match model.decision_evaluator() {
+        Some(evaluator) => evaluator.evaluate(decision, input).await,
+        None => Err(DecisionError::NoEvaluator),
+    }
+}
@@ -0,0 +1,392 @@
+//! # Errors
+//!
+//! Every public error type implements [`ErrorCode`], a stable machine-readable code that
+//! doesn't change when error messages do, so that API consumers can branch on failures.
+//! [`Error`] brings all of them together, keeping their source chains intact.
+//!
+//! Errors that are reported through process logs (for example, [`Log::ExpressionError`]) are
+//! carried as [`ErrorReport`]s.
+//!
+//! [`Log::ExpressionError`]: crate::process::Log::ExpressionError
+use crate::activity::call_activity::CallActivityError;
+use crate::activity::manual_task::ManualTaskError;
+use crate::activity::service_task::ServiceError;
+use crate::bpmn::{NormalizationError, ParseError};
+use crate::decision::DecisionError;
+use crate::event::ConversionError;
+use crate::flow_node::StateError;
+use crate::journal::{JournalError, ReplayError};
+use crate::language::EvaluationError;
+use crate::model;
+use crate::process::{
+    DataObjectError, ForkError, InspectionError, ModificationError, ResultError, StartError,
+};
+use crate::tasks::TaskError;
+use crate::timer::TimerError;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Stable machine-readable error code
+pub trait ErrorCode {
+    /// Returns error code
+    ///
+    /// Codes are dot-separated, starting with the area of the error (for example,
+    /// `start.no_start_event`).
+    fn code(&self) -> &'static str;
+}
+
+/// Crate-wide error
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Model(#[from] model::Error),
+    #[error(transparent)]
+    Start(#[from] StartError),
+    #[error(transparent)]
+    Result(#[from] ResultError),
+    #[error(transparent)]
+    DataObject(#[from] DataObjectError),
+    #[error(transparent)]
+    Fork(#[from] ForkError),
+    #[error(transparent)]
+    Inspection(#[from] InspectionError),
+    #[error(transparent)]
+    Timer(#[from] TimerError),
+    #[error(transparent)]
+    Evaluation(#[from] EvaluationError),
+    #[error(transparent)]
+    Conversion(#[from] ConversionError),
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error(transparent)]
+    Journal(#[from] JournalError),
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+    #[error(transparent)]
+    Modification(#[from] ModificationError),
+    #[error(transparent)]
+    Service(#[from] ServiceError),
+    #[error(transparent)]
+    Task(#[from] TaskError),
+    #[error(transparent)]
+    Decision(#[from] DecisionError),
+    #[error(transparent)]
+    ManualTask(#[from] ManualTaskError),
+    #[error(transparent)]
+    CallActivity(#[from] CallActivityError),
+}
+
+impl ErrorCode for Error {
+    fn code(&self) -> &'static str {
+        match self {
+            Error::Parse(error) => error.code(),
+            Error::Model(error) => error.code(),
+            Error::Start(error) => error.code(),
+            Error::Result(error) => error.code(),
+            Error::DataObject(error) => error.code(),
+            Error::Fork(error) => error.code(),
+            Error::Inspection(error) => error.code(),
+            Error::Timer(error) => error.code(),
+            Error::Evaluation(error) => error.code(),
+            Error::Conversion(error) => error.code(),
+            Error::State(error) => error.code(),
+            Error::Journal(error) => error.code(),
+            Error::Replay(error) => error.code(),
+            Error::Modification(error) => error.code(),
+            Error::Service(error) => error.code(),
+            Error::Task(error) => error.code(),
+            Error::Decision(error) => error.code(),
+            Error::ManualTask(error) => error.code(),
+            Error::CallActivity(error) => error.code(),
+        }
+    }
+}
+
+/// Serializable report of an error
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorReport {
+    /// Error code (see [`ErrorCode`])
+    pub code: String,
+    /// Error message
+    pub message: String,
+    /// Messages of the chain of errors that have caused this one, outermost first
+    pub sources: Vec<String>,
+}
+
+impl ErrorReport {
+    /// Creates a report of an error
+    pub fn new<E>(error: &E) -> Self
+    where
+        E: std::error::Error + ErrorCode,
+    {
+        let mut sources = vec![];
+        let mut source = error.source();
+        while let Some(error) = source {
+            sources.push(error.to_string());
+            source = error.source();
+        }
+        Self {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            sources,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)
+    }
+}
+
+impl ErrorCode for ParseError {
+    fn code(&self) -> &'static str {
+        match self {
+            ParseError::Io(_) => "parse.io",
+            ParseError::Syntax { .. } => "parse.syntax",
+            ParseError::Schema { .. } => "parse.schema",
+            ParseError::Unsupported { .. } => "parse.unsupported",
+        }
+    }
+}
+
+impl ErrorCode for NormalizationError {
+    fn code(&self) -> &'static str {
+        match self {
+            NormalizationError::ParsingError { .. } => "normalization.parsing",
+            NormalizationError::WritingError { .. } => "normalization.writing",
+            NormalizationError::UnsupportedRoot { .. } => "normalization.unsupported_root",
+        }
+    }
+}
+
+impl ErrorCode for model::Error {
+    fn code(&self) -> &'static str {
+        match self {
+            model::Error::ResponseRecvError { .. } => "model.response_not_received",
+            model::Error::UnknownProcess(_) => "model.unknown_process",
+        }
+    }
+}
+
+impl ErrorCode for StartError {
+    fn code(&self) -> &'static str {
+        match self {
+            StartError::NoStartEvent => "start.no_start_event",
+            StartError::NotReceived => "start.response_not_received",
+            StartError::UnknownDataObject(_) => "start.unknown_data_object",
+            StartError::InvalidDefinition { .. } => "start.invalid_definition",
+            StartError::AmbiguousStartEvents { .. } => "start.ambiguous_start_events",
+            StartError::AlreadyRunning => "start.already_running",
+            StartError::UnknownStartEvent(_) => "start.unknown_start_event",
+            StartError::Overloaded => "start.overloaded",
+            StartError::StartBefore(_) => "start.invalid_start_before",
+        }
+    }
+}
+
+impl ErrorCode for ResultError {
+    fn code(&self) -> &'static str {
+        match self {
+            ResultError::NotCompleted(_) => "result.not_completed",
+        }
+    }
+}
+
+impl ErrorCode for DataObjectError {
+    fn code(&self) -> &'static str {
+        match self {
+            DataObjectError::NotFound => "data_object.not_found",
+            DataObjectError::NotReceived => "data_object.response_not_received",
+        }
+    }
+}
+
+impl ErrorCode for ForkError {
+    fn code(&self) -> &'static str {
+        match self {
+            ForkError::NotReceived => "fork.response_not_received",
+            ForkError::State(_) => "fork.state",
+        }
+    }
+}
+
+impl ErrorCode for InspectionError {
+    fn code(&self) -> &'static str {
+        match self {
+            InspectionError::NotReceived => "inspection.response_not_received",
+        }
+    }
+}
+
+impl ErrorCode for TimerError {
+    fn code(&self) -> &'static str {
+        match self {
+            TimerError::InvalidDateTime(_) => "timer.invalid_date_time",
+            TimerError::InvalidDuration(_) => "timer.invalid_duration",
+            TimerError::InvalidCycle(_) => "timer.invalid_cycle",
+            TimerError::InvalidCron(_) => "timer.invalid_cron",
+            TimerError::InvalidTimezone(_) => "timer.invalid_timezone",
+            TimerError::UnresolvedPlaceholder(_) => "timer.unresolved_placeholder",
+            TimerError::NotFound(_) => "timer.not_found",
+            TimerError::NotLiteral => "timer.not_literal",
+            TimerError::Unspecified => "timer.unspecified",
+        }
+    }
+}
+
+impl ErrorCode for EvaluationError {
+    fn code(&self) -> &'static str {
+        match self {
+            EvaluationError::UnsupportedLanguage { .. } => "evaluation.unsupported_language",
+            EvaluationError::EvaluationError { .. } => "evaluation.failed",
+            EvaluationError::ResultTypeError { .. } => "evaluation.result_type_mismatch",
+            EvaluationError::Empty => "evaluation.empty",
+            EvaluationError::ExecutionError(_) => "evaluation.execution",
+        }
+    }
+}
+
+impl ErrorCode for ConversionError {
+    fn code(&self) -> &'static str {
+        match self {
+            ConversionError::Impossible { .. } => "conversion.impossible",
+            ConversionError::NotImplemented { .. } => "conversion.not_implemented",
+        }
+    }
+}
+
+impl ErrorCode for StateError {
+    fn code(&self) -> &'static str {
+        match self {
+            StateError::InvalidVariant => "state.invalid_variant",
+        }
+    }
+}
+
+impl ErrorCode for JournalError {
+    fn code(&self) -> &'static str {
+        match self {
+            JournalError::Unrepresentable(_) => "journal.unrepresentable",
+            JournalError::Start(error) => error.code(),
+            JournalError::DataObject(error) => error.code(),
+            JournalError::Modification(error) => error.code(),
+            JournalError::Task(error) => error.code(),
+            JournalError::ManualTask(error) => error.code(),
+        }
+    }
+}
+
+impl ErrorCode for ReplayError {
+    fn code(&self) -> &'static str {
+        match self {
+            ReplayError::Start(error) => error.code(),
+            ReplayError::DataObject(error) => error.code(),
+            ReplayError::Modification(error) => error.code(),
+            ReplayError::Task(error) => error.code(),
+            ReplayError::ManualTask(error) => error.code(),
+            ReplayError::NoOpenTask(_) => "replay.no_open_task",
+        }
+    }
+}
+
+impl ErrorCode for ModificationError {
+    fn code(&self) -> &'static str {
+        match self {
+            ModificationError::NotRunning => "modification.not_running",
+            ModificationError::UnknownFlowNode(_) => "modification.unknown_flow_node",
+            ModificationError::UnknownSequenceFlow(_) => "modification.unknown_sequence_flow",
+            ModificationError::NoIncoming(_) => "modification.no_incoming",
+            ModificationError::NoActiveToken(_) => "modification.no_active_token",
+            ModificationError::NotAnActivity(_) => "modification.not_an_activity",
+            ModificationError::UnknownDataObject(_) => "modification.unknown_data_object",
+            ModificationError::Overloaded => "modification.overloaded",
+            ModificationError::NotReceived => "modification.response_not_received",
+        }
+    }
+}
+
+impl ErrorCode for ServiceError {
+    fn code(&self) -> &'static str {
+        match self {
+            ServiceError::NotRegistered(_) => "service.not_registered",
+            ServiceError::Failed { .. } => "service.failed",
+            ServiceError::InvalidInput(_) => "service.invalid_input",
+            ServiceError::InvalidOutput(_) => "service.invalid_output",
+        }
+    }
+}
+
+impl ErrorCode for DecisionError {
+    fn code(&self) -> &'static str {
+        match self {
+            DecisionError::NoEvaluator => "decision.no_evaluator",
+            DecisionError::UnknownDecision(_) => "decision.unknown",
+            DecisionError::Failed(_) => "decision.failed",
+        }
+    }
+}
+
+impl ErrorCode for ManualTaskError {
+    fn code(&self) -> &'static str {
+        match self {
+            ManualTaskError::NotWaiting(_) => "manual_task.not_waiting",
+        }
+    }
+}
+
+impl ErrorCode for CallActivityError {
+    fn code(&self) -> &'static str {
+        match self {
+            CallActivityError::NoCalledElement => "call_activity.no_called_element",
+            CallActivityError::UnknownProcess(_) => "call_activity.unknown_process",
+            CallActivityError::Start(_) => "call_activity.start",
+            CallActivityError::Failed(_) => "call_activity.failed",
+            CallActivityError::NotCompleted(_) => "call_activity.not_completed",
+        }
+    }
+}
+
+impl ErrorCode for TaskError {
+    fn code(&self) -> &'static str {
+        match self {
+            TaskError::NotFound(_) => "task.not_found",
+            TaskError::AlreadyClaimed { .. } => "task.already_claimed",
+            TaskError::InvalidSubmission { .. } => "task.invalid_submission",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    fn codes() {
+        let error: Error = StartError::NoStartEvent.into();
+        assert_eq!(error.code(), "start.no_start_event");
+        assert_eq!(error.to_string(), StartError::NoStartEvent.to_string());
+
+        let error: Error = ReplayError::Start(StartError::AlreadyRunning).into();
+        assert_eq!(error.code(), "start.already_running");
+    }
+
+    #[bpxe_im::test]
+    fn report() {
+        let error: Error = ReplayError::Start(StartError::AlreadyRunning).into();
+        let report = ErrorReport::new(&error);
+        assert_eq!(report.code, "start.already_running");
+        assert_eq!(
+            report.message,
+            "can't start process: process instance is already running"
+        );
+        assert_eq!(
+            report.sources,
+            vec!["process instance is already running".to_string()]
+        );
+    }
+}
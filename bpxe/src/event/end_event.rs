@@ -1,20 +1,20 @@
 //! # End Event flow node
-use crate::bpmn::schema::{EndEvent as Element, FlowNodeType};
+use crate::bpmn::schema::{Cast, EndEvent as Element, EventDefinitionType, FlowNodeType};
 use crate::event::ProcessEvent;
 use crate::flow_node::{self, Action, FlowNode, IncomingIndex};
+use crate::process;
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
-use tokio::sync::broadcast;
 
 /// End Event flow node
 pub struct EndEvent {
     element: Arc<Element>,
     state: State,
     waker: Option<Waker>,
-    event_broadcaster: Option<broadcast::Sender<ProcessEvent>>,
+    process: Option<process::Handle>,
 }
 
 impl EndEvent {
@@ -24,7 +24,7 @@ impl EndEvent {
             element: Arc::new(element),
             state: State::Ready,
             waker: None,
-            event_broadcaster: None,
+            process: None,
         }
     }
 }
@@ -66,7 +66,7 @@ impl FlowNode for EndEvent {
     }
 
     fn set_process(&mut self, process: crate::process::Handle) {
-        self.event_broadcaster.replace(process.event_broadcast());
+        self.process.replace(process);
     }
 }
 
@@ -86,8 +86,22 @@ impl Stream for EndEvent {
             }
             State::Complete => {
                 self.state = State::Done;
-                if let Some(event_broadcaster) = self.event_broadcaster.as_ref() {
-                    let _ = event_broadcaster.send(ProcessEvent::End);
+                if let Some(process) = self.process.as_ref() {
+                    let definitions = process.model().definitions();
+                    for event_definition in &self.element.event_definitions {
+                        if let Some(definition) = Cast::<dyn EventDefinitionType>::cast(
+                            event_definition.clone().into_inner().as_ref(),
+                        ) {
+                            use std::convert::TryFrom;
+                            if let Ok(event) = ProcessEvent::try_from(definition) {
+                                process.emit_event(
+                                    self.element.id.clone(),
+                                    event.with_payload(&definitions),
+                                );
+                            }
+                        }
+                    }
+                    process.emit_event(self.element.id.clone(), ProcessEvent::End);
                 }
                 Poll::Ready(Some(Action::Complete))
             }
@@ -116,7 +130,71 @@ mod tests {
 
         assert!(handle.start().await.is_ok());
 
-        assert!(mailbox.receive(|e| matches!(e, ProcessEvent::End)).await);
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::End))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn uncaught_error_fails_process() {
+        use crate::process::{Incident, Outcome};
+        let definitions = parse(include_str!("test_models/uncaught_error_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert_eq!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some("end".into()),
+                error: ProcessEvent::ErrorEvent {
+                    error_ref: Some("failure".into()),
+                    error_code: Some("E42".into()),
+                    error_message: Some("Something went wrong".into()),
+                },
+            })
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn error_not_caught_by_idle_catch_event() {
+        use crate::process::Outcome;
+        let definitions = parse(include_str!("test_models/idle_catch_error_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+
+        // `catch` matches the error but no token ever reaches it
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert_eq!(
+            handle.incident().await.and_then(|incident| incident.node),
+            Some("end".into())
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn error_caught_by_waiting_catch_event() {
+        use crate::process::Outcome;
+        let definitions =
+            parse(include_str!("test_models/waiting_catch_error_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+
+        assert_eq!(handle.join().await, Outcome::Completed);
+        assert!(handle.incident().await.is_none());
 
         model.terminate().await;
     }
@@ -1,15 +1,31 @@
 //! # Intermediate Catch Event flow node
+//!
+//! Catch event with multiple event definitions flows as soon as any of them is caught, unless
+//! it is marked as `parallelMultiple`, in which case all of them have to be caught.
+//!
+//! Timer event definitions are armed when the event is reached and fire on behalf of the
+//! event (see [`crate::timer`]); cycles fire at their first upcoming repetition.
+//!
+//! Payload of a caught error event is captured into data objects through the event's data
+//! output associations: data output named `errorCode` receives the error code and the one
+//! named `errorMessage` receives the error message.
 use crate::bpmn::schema::{
     Cast, EventDefinitionType, FlowNodeType, IntermediateCatchEvent as Element,
 };
-use crate::event::ProcessEvent;
+use crate::error::ErrorReport;
+use crate::event::{self, Envelope, ProcessEvent};
 use crate::flow_node::{self, Action, FlowNode, IncomingIndex};
-use crate::process;
-use crate::sys::task;
+use crate::process::{self, Log};
+use crate::sys::{task, time};
+use crate::timer::{self, Due, Schedule, TimerId};
+use futures::future::BoxFuture;
 use futures::stream::Stream;
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
 use tokio::sync::{broadcast, mpsc};
 
@@ -17,10 +33,17 @@ use tokio::sync::{broadcast, mpsc};
 pub struct IntermediateCatchEvent {
     element: Arc<Element>,
     state: State,
-    event_receivers: Vec<broadcast::Receiver<ProcessEvent>>,
+    event_receivers: Vec<broadcast::Receiver<Envelope>>,
     process: Option<process::Handle>,
     waker_sender: mpsc::Sender<Waker>,
     waker_receiver: Option<mpsc::Receiver<Waker>>,
+    capture: Option<BoxFuture<'static, ()>>,
+    // indices of event definitions caught so far (for parallel multiple events)
+    caught_definitions: HashSet<usize>,
+    caught_events: Vec<ProcessEvent>,
+    // armed timers only fire if the generation hasn't changed since
+    timer_generation: Arc<AtomicUsize>,
+    armed_timers: Arc<Mutex<Vec<TimerId>>>,
 }
 
 impl IntermediateCatchEvent {
@@ -34,9 +57,143 @@ impl IntermediateCatchEvent {
             process: None,
             waker_sender,
             waker_receiver: Some(waker_receiver),
+            capture: None,
+            caught_definitions: HashSet::new(),
+            caught_events: vec![],
+            timer_generation: Arc::new(AtomicUsize::new(0)),
+            armed_timers: Arc::new(Mutex::new(vec![])),
         }
     }
 
+    /// Records messages and signals this event is waiting for
+    fn subscribe(&self) {
+        if let Some(process) = self.process.as_ref() {
+            let definitions = process.model().definitions();
+            process.subscription_registry().subscribe(
+                self.element.id.clone(),
+                event::events(&self.element.event_definitions, &definitions),
+                &definitions,
+            );
+        }
+    }
+
+    /// Arms timers of timer event definitions
+    ///
+    /// Once due, a timer emits its timer event on behalf of this event. Previously armed
+    /// timers are disarmed.
+    fn arm_timers(&self) {
+        self.disarm_timers();
+        let process = match self.process.as_ref() {
+            Some(process) => process,
+            None => return,
+        };
+        let generation = self.timer_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let definitions = process.model().definitions();
+        for event in event::events(&self.element.event_definitions, &definitions) {
+            if let ProcessEvent::TimerEvent { .. } = event {
+                let process = process.clone();
+                let id = self.element.id.clone();
+                let timer_generation = self.timer_generation.clone();
+                let armed_timers = self.armed_timers.clone();
+                task::spawn(async move {
+                    let timezone = process.model().default_timezone();
+                    let schedule = match timer::resolve(&event, &process)
+                        .await
+                        .and_then(|resolved| Schedule::from_event_in(&resolved, timezone))
+                    {
+                        Ok(schedule) => schedule,
+                        Err(err) => {
+                            let _ = process.log_broadcast().send(Log::TimerError {
+                                element: id,
+                                error: ErrorReport::new(&err),
+                            });
+                            return;
+                        }
+                    };
+                    let now = time::now();
+                    let due = match (&schedule, process.model().business_calendar()) {
+                        (Schedule::Duration(duration), Some(calendar)) => {
+                            Some(Due::At(calendar.add(now, duration)))
+                        }
+                        _ => schedule.due(now),
+                    };
+                    if let Some(due) = due {
+                        let repetition = schedule.next(now, now).map(|(n, _)| n).unwrap_or(0);
+                        let timer = process.timer_registry().arm(id.clone(), due, repetition);
+                        {
+                            let mut armed_timers = armed_timers.lock().unwrap();
+                            if timer_generation.load(Ordering::SeqCst) != generation {
+                                // disarmed while resolving
+                                return;
+                            }
+                            armed_timers.push(timer.id());
+                        }
+                        if timer.fired().await
+                            && timer_generation.load(Ordering::SeqCst) == generation
+                        {
+                            process.emit_event(id, event);
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    /// Disarms all armed timers
+    fn disarm_timers(&self) {
+        self.timer_generation.fetch_add(1, Ordering::SeqCst);
+        let mut armed_timers = self.armed_timers.lock().unwrap();
+        if let Some(process) = self.process.as_ref() {
+            for id in armed_timers.drain(..) {
+                process.timer_registry().cancel(id);
+            }
+        }
+    }
+
+    /// Prepares capturing of caught events' payload into data objects
+    fn capture_payload(&self, events: &[ProcessEvent]) -> Option<BoxFuture<'static, ()>> {
+        let process = self.process.clone()?;
+        let (error_code, error_message) = events.iter().find_map(|event| match event {
+            ProcessEvent::ErrorEvent {
+                error_code,
+                error_message,
+                ..
+            } => Some((error_code.clone(), error_message.clone())),
+            _ => None,
+        })?;
+        let mut assignments = vec![];
+        for association in &self.element.data_output_associations {
+            for source_ref in &association.source_refs {
+                let value = self
+                    .element
+                    .data_outputs
+                    .iter()
+                    .find(|data_output| data_output.id.as_ref() == Some(source_ref))
+                    .and_then(|data_output| match data_output.name.as_deref() {
+                        Some("errorCode") => error_code.clone(),
+                        Some("errorMessage") => error_message.clone(),
+                        _ => None,
+                    });
+                if let Some(value) = value {
+                    assignments.push((association.target_ref.clone(), value));
+                }
+            }
+        }
+        if assignments.is_empty() {
+            return None;
+        }
+        Some(
+            async move {
+                for (target, value) in assignments {
+                    let _ = process
+                        .set_variable(&target, Box::new(serde_json::Value::String(value)))
+                        .await;
+                }
+            }
+            .boxed(),
+        )
+    }
+
     /// Wakes IntermediateCatchEvent if there's an event available
     fn wake_on_event(&self, waker: Waker) {
         let waker_sender = self.waker_sender.clone();
@@ -53,6 +210,7 @@ impl IntermediateCatchEvent {
 pub enum State {
     Initialized,
     Ready,
+    Capture,
     Complete,
     Done,
 }
@@ -62,6 +220,14 @@ impl FlowNode for IntermediateCatchEvent {
         match state {
             flow_node::State::IntermediateCatchEvent(state) => {
                 self.state = state;
+                // restored into a ready state, need to be able to receive events
+                if let (State::Ready, true, Some(process)) =
+                    (&self.state, self.event_receivers.is_empty(), &self.process)
+                {
+                    self.event_receivers = vec![process.event_receiver()];
+                    self.subscribe();
+                    self.arm_timers();
+                }
                 Ok(())
             }
             _ => Err(flow_node::StateError::InvalidVariant),
@@ -116,6 +282,21 @@ impl FlowNode for IntermediateCatchEvent {
                 // safe to unwrap here because we checked it above with `is_some`
                 self.event_receivers = vec![self.process.as_ref().unwrap().event_receiver()];
             };
+            self.subscribe();
+            self.arm_timers();
+        }
+    }
+}
+
+// cancelled events (for example, ones that lost the race after an event-based gateway)
+// shouldn't leave their timers and subscriptions behind
+impl Drop for IntermediateCatchEvent {
+    fn drop(&mut self) {
+        self.disarm_timers();
+        if let Some(process) = self.process.as_ref() {
+            process
+                .subscription_registry()
+                .unsubscribe(&self.element.id);
         }
     }
 }
@@ -136,36 +317,52 @@ impl Stream for IntermediateCatchEvent {
             }
             State::Ready => {
                 enum Control {
-                    Ready(Vec<ProcessEvent>),
+                    // event and indices of event definitions it matched
+                    Caught(ProcessEvent, Vec<usize>),
                     Done,
                 }
                 // FIXME: cloning this is not perfect, but it was a quick way
                 // to get around mutable + immutable borrowing error
                 let event_definitions = self.element.event_definitions.clone();
                 let no_events = event_definitions.is_empty();
+                let definitions = self
+                    .process
+                    .as_ref()
+                    .map(|process| process.model().definitions());
+                let id = self.element.id.clone();
                 let next = self.event_receivers.iter_mut().find_map(|receiver| {
-                    let res = receiver.try_recv();
+                    let res = receiver
+                        .try_recv()
+                        .map(|envelope| (envelope.event, envelope.source));
                     #[allow(unreachable_patterns)]
                     match res {
-                        Ok(e @ ProcessEvent::NoneEvent) => {
+                        Ok((e @ ProcessEvent::NoneEvent, _)) => {
                             if no_events {
-                                Some(Control::Ready(vec![e]))
+                                Some(Control::Caught(e, vec![]))
                             } else {
                                 None
                             }
                         }
-                        Ok(e) => {
+                        Ok((e, source)) => {
+                            // timers are only fired by this event itself
+                            if matches!(e, ProcessEvent::TimerEvent { .. }) && source != id {
+                                return None;
+                            }
                             // attempt matching events
-                            let events: Vec<ProcessEvent> = event_definitions
+                            let indices: Vec<usize> = event_definitions
                                 .iter()
-                                .filter_map(|event_definition| {
+                                .enumerate()
+                                .filter_map(|(index, event_definition)| {
                                     if let Some(definition) = Cast::<dyn EventDefinitionType>::cast(
                                         event_definition.clone().into_inner().as_ref(),
                                     ) {
                                         use std::convert::TryFrom;
-                                        if let Ok(event) = ProcessEvent::try_from(definition) {
-                                            if event == e {
-                                                Some(e.clone())
+                                        if let Ok(mut event) = ProcessEvent::try_from(definition) {
+                                            if let Some(definitions) = &definitions {
+                                                event = event.with_payload(definitions);
+                                            }
+                                            if event.matches(&e) {
+                                                Some(index)
                                             } else {
                                                 None
                                             }
@@ -177,10 +374,10 @@ impl Stream for IntermediateCatchEvent {
                                     }
                                 })
                                 .collect();
-                            if events.is_empty() {
+                            if indices.is_empty() {
                                 None
                             } else {
-                                Some(Control::Ready(events))
+                                Some(Control::Caught(e, indices))
                             }
                         }
                         // If the channel is empty, continue trying
@@ -192,11 +389,21 @@ impl Stream for IntermediateCatchEvent {
                     }
                 });
                 match next {
-                    Some(Control::Ready(_)) => {
-                        self.state = State::Complete;
-                        Poll::Ready(Some(Action::Flow(
-                            (0..self.element.outgoings().len()).collect(),
-                        )))
+                    Some(Control::Caught(event, indices)) => {
+                        self.caught_definitions.extend(indices);
+                        self.caught_events.push(event);
+                        if self.element.parallel_multiple == Some(true)
+                            && self.caught_definitions.len() < event_definitions.len()
+                        {
+                            // there may be more events available already
+                            cx.waker().wake_by_ref();
+                            return Poll::Pending;
+                        }
+                        self.caught_definitions.clear();
+                        let events = std::mem::take(&mut self.caught_events);
+                        self.capture = self.capture_payload(&events);
+                        self.state = State::Capture;
+                        self.poll_next(cx)
                     }
                     Some(Control::Done) => Poll::Ready(None),
                     None => {
@@ -205,6 +412,24 @@ impl Stream for IntermediateCatchEvent {
                     }
                 }
             }
+            State::Capture => {
+                if let Some(capture) = self.capture.as_mut() {
+                    if capture.poll_unpin(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+                self.capture = None;
+                self.disarm_timers();
+                if let Some(process) = self.process.as_ref() {
+                    process
+                        .subscription_registry()
+                        .unsubscribe(&self.element.id);
+                }
+                self.state = State::Complete;
+                Poll::Ready(Some(Action::Flow(
+                    (0..self.element.outgoings().len()).collect(),
+                )))
+            }
             State::Complete => {
                 self.state = State::Done;
                 Poll::Ready(Some(Action::Complete))
@@ -223,6 +448,7 @@ mod tests {
     use crate::event::ProcessEvent;
     use crate::model;
     use crate::process::Log;
+    use crate::sys::time;
     use crate::test::*;
     use bpxe_internal_macros as bpxe_im;
 
@@ -241,11 +467,11 @@ mod tests {
             .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
             .await);
 
-        let _ = handle.event_broadcast().send(ProcessEvent::NoneEvent);
+        handle.send_event(ProcessEvent::NoneEvent);
 
         assert!(
             mailbox
-                .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "signal"))
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "signal"))
                 .await
         );
 
@@ -267,15 +493,365 @@ mod tests {
             .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
             .await);
 
-        let _ = handle.event_broadcast().send(ProcessEvent::SignalEvent {
+        handle.send_event(ProcessEvent::SignalEvent {
             signal_ref: Some("signal".into()),
         });
 
         assert!(
             mailbox
-                .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn catch_conditional_event() {
+        use crate::bpmn::schema::{
+            ConditionalEventDefinition, DocumentElementContainer, IntermediateCatchEvent,
+        };
+        use std::convert::TryFrom;
+        let definitions = parse(include_str!("test_models/catch_conditional_event.bpmn")).unwrap();
+        let event = definitions
+            .find_by_id("catch")
+            .and_then(|e| e.downcast_ref::<IntermediateCatchEvent>())
+            .and_then(|e| e.event_definitions.first().cloned())
+            .and_then(|e| {
+                e.into_inner()
+                    .downcast_ref::<ConditionalEventDefinition>()
+                    .cloned()
+            })
+            .and_then(|e| ProcessEvent::try_from(e).ok())
+            .unwrap();
+        assert!(matches!(event, ProcessEvent::ConditionalEvent { .. }));
+
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        handle.send_event(event);
+
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(feature = "rhai")]
+    async fn catch_conditional_event_on_variable_change() {
+        use crate::data_object::Container;
+        let definitions = parse(include_str!(
+            "test_models/catch_conditional_variable_event.bpmn"
+        ))
+        .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        // condition is not satisfied yet
+        assert!(handle
+            .set_variable("ready", Box::new(Container(0i32)))
+            .await
+            .is_ok());
+        assert!(expects_timeout(mailbox.receive(
+            |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report")
+        ))
+        .await
+        .is_ok());
+
+        assert!(handle
+            .set_variable("ready", Box::new(Container(1i32)))
+            .await
+            .is_ok());
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn catch_conditional_modified_variable_event() {
+        use crate::data_object::Container;
+        let definitions = parse(include_str!(
+            "test_models/catch_conditional_variable_event.bpmn"
+        ))
+        .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        // variables set through a modification are seen by conditions, too
+        assert!(handle
+            .modify()
+            .variable("ready", Box::new(Container(1i32)))
+            .apply()
+            .await
+            .is_ok());
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn catch_error_event() {
+        let definitions = parse(include_str!("test_models/catch_error_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        handle.send_event(ProcessEvent::ErrorEvent {
+            error_ref: Some("failure".into()),
+            error_code: Some("E1".into()),
+            error_message: Some("Caught".into()),
+        });
+
+        // error end event resolves the payload of the error it throws
+        assert!(mailbox
+            .receive(|e| matches!(&e.event, ProcessEvent::ErrorEvent { error_code, error_message, .. }
+                if e.source.as_deref() == Some("end")
+                    && error_code.as_deref() == Some("E42")
+                    && error_message.as_deref() == Some("Something went wrong")))
+            .await);
+
+        assert_variable_eq(&handle, "code", serde_json::json!("E1")).await;
+        assert_variable_eq(&handle, "message", serde_json::json!("Caught")).await;
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn catch_parallel_multiple_event() {
+        let definitions = parse(include_str!(
+            "test_models/catch_parallel_multiple_event.bpmn"
+        ))
+        .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal1".into()),
+        });
+
+        // Only one of the signals has been caught
+        assert!(expects_timeout(mailbox.receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))).await.is_ok());
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal2".into()),
+        });
+
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn catch_timer_event() {
+        let definitions = parse(include_str!("test_models/catch_timer_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::TimerEvent { .. })
+                    && e.source.as_deref() == Some("catch"))
+                .await
+        );
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn catch_timer_variable_event() {
+        let definitions =
+            parse(include_str!("test_models/catch_timer_variable_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+
+        let sla = handle.data_object("sla").await.unwrap();
+        *sla.write().await = Box::new(serde_json::json!("0.1S"));
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn catch_timer_variable_event_out_of_range() {
+        let definitions =
+            parse(include_str!("test_models/catch_timer_variable_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        // substituted duration is validated like a literal one
+        let sla = handle.data_object("sla").await.unwrap();
+        *sla.write().await = Box::new(serde_json::json!("99999999999999999999S"));
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::TimerError { element, error }
+                if element.as_deref() == Some("catch") && error.code == "timer.invalid_duration"))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn subscriptions() {
+        use crate::event::Subscription;
+        let definitions = parse(include_str!("test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        let mut subscriptions = handle.subscriptions();
+        for _ in 0..100 {
+            if !subscriptions.is_empty() {
+                break;
+            }
+            time::sleep(std::time::Duration::from_millis(10)).await;
+            subscriptions = handle.subscriptions();
+        }
+        assert_eq!(
+            subscriptions,
+            vec![Subscription {
+                node: Some("catch".into()),
+                event: ProcessEvent::SignalEvent {
+                    signal_ref: Some("signal".into()),
+                },
+                name: Some("signal".into()),
+            }]
+        );
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal".into()),
+        });
+
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+        assert!(handle.subscriptions().is_empty());
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn fire_pending_timer() {
+        let definitions = parse(include_str!("test_models/catch_long_timer_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        let mut timers = handle.timers();
+        for _ in 0..100 {
+            if !timers.is_empty() {
+                break;
+            }
+            time::sleep(std::time::Duration::from_millis(10)).await;
+            timers = handle.timers();
+        }
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].node.as_deref(), Some("catch"));
+        assert_eq!(timers[0].repetition, 0);
+
+        assert!(handle.fire_timer(timers[0].id).is_ok());
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
                 .await
         );
+        assert!(handle.timers().is_empty());
 
         model.terminate().await;
     }
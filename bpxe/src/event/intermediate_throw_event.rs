@@ -1,22 +1,26 @@
 //! # Intermediate Throw Event flow node
+//!
+//! Intermediate throw event without event definitions serves as a milestone marker:
+//! it emits [`ProcessEvent::NoneEvent`] (attributed to the event) and invokes model's
+//! milestone listener, if one is registered (see [`crate::model::Model::with_milestone_listener`]).
 use crate::bpmn::schema::{
     Cast, EventDefinitionType, FlowNodeType, IntermediateThrowEvent as Element,
 };
 use crate::event::ProcessEvent;
 use crate::flow_node::{self, Action, FlowNode, IncomingIndex};
+use crate::process;
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
-use tokio::sync::broadcast;
 
 /// Intermediate Throw Event flow node
 pub struct IntermediateThrowEvent {
     element: Arc<Element>,
     state: State,
     waker: Option<Waker>,
-    event_broadcaster: Option<broadcast::Sender<ProcessEvent>>,
+    process: Option<process::Handle>,
 }
 
 impl IntermediateThrowEvent {
@@ -26,7 +30,7 @@ impl IntermediateThrowEvent {
             element: Arc::new(element),
             state: State::Ready,
             waker: None,
-            event_broadcaster: None,
+            process: None,
         }
     }
 }
@@ -68,7 +72,7 @@ impl FlowNode for IntermediateThrowEvent {
     }
 
     fn set_process(&mut self, process: crate::process::Handle) {
-        self.event_broadcaster.replace(process.event_broadcast());
+        self.process.replace(process);
     }
 }
 
@@ -88,17 +92,24 @@ impl Stream for IntermediateThrowEvent {
             }
             State::Complete => {
                 self.state = State::Done;
-                if let Some(event_broadcaster) = self.event_broadcaster.as_ref() {
+                if let Some(process) = self.process.as_ref() {
                     if self.element.event_definitions.is_empty() {
-                        let _ = event_broadcaster.send(ProcessEvent::NoneEvent);
+                        process.emit_event(self.element.id.clone(), ProcessEvent::NoneEvent);
+                        if let Some(listener) = process.model().milestone_listener() {
+                            listener(process, self.element.id.as_deref().unwrap_or_default());
+                        }
                     } else {
+                        let definitions = process.model().definitions();
                         for event_definition in &self.element.event_definitions {
                             if let Some(definition) = Cast::<dyn EventDefinitionType>::cast(
                                 event_definition.clone().into_inner().as_ref(),
                             ) {
                                 use std::convert::TryFrom;
                                 if let Ok(event) = ProcessEvent::try_from(definition) {
-                                    let _ = event_broadcaster.send(event);
+                                    process.emit_event(
+                                        self.element.id.clone(),
+                                        event.with_payload(&definitions),
+                                    );
                                 }
                             }
                         }
@@ -137,13 +148,60 @@ mod tests {
 
         assert!(
             mailbox
-                .receive(|e| matches!(e, ProcessEvent::NoneEvent))
+                .receive(|e| matches!(&e.event, ProcessEvent::NoneEvent))
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn thrown_event_attribution() {
+        let definitions = parse(include_str!("test_models/throw_none_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        let id = handle.id();
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::NoneEvent)
+                    && e.source.as_ref().map(|s| s.as_str()) == Some("throw")
+                    && e.instance == id)
+                .await
+        );
+        // Start event was sent from outside
+        assert!(
+            mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::Start) && e.source.is_none())
                 .await
         );
 
         model.terminate().await;
     }
 
+    #[bpxe_im::test]
+    async fn milestone_listener() {
+        let definitions = parse(include_str!("test_models/throw_none_event.bpmn")).unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let model = model::Model::new(definitions)
+            .with_milestone_listener(move |_, id| {
+                let _ = sender.send(id.to_string());
+            })
+            .spawn()
+            .await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+
+        assert_eq!(receiver.recv().await, Some("throw".to_string()));
+
+        model.terminate().await;
+    }
+
     #[bpxe_im::test]
     async fn throw_signal_event() {
         let definitions = parse(include_str!("test_models/throw_signal_event.bpmn")).unwrap();
@@ -156,7 +214,7 @@ mod tests {
 
         assert!(
             mailbox
-                .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "sig1"))
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "sig1"))
                 .await
         );
 
@@ -9,9 +9,21 @@ pub mod intermediate_catch_event;
 pub use intermediate_catch_event::IntermediateCatchEvent;
 
 use crate::bpmn::schema::*;
+use crate::process::InstanceId;
+use derive_more::Deref;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use thiserror::Error;
+use tokio::sync::broadcast;
 
-#[derive(Clone, Debug, PartialEq)]
+/// Process event
+///
+/// Events are serialized as objects tagged with their variant name (`type`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 #[non_exhaustive]
 pub enum ProcessEvent {
     /// Process has started
@@ -32,6 +44,9 @@ pub enum ProcessEvent {
     MessageEvent {
         message_ref: Option<String>,
         operation_ref: Option<String>,
+        /// Message content (for example, input of a send task)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payload: Option<serde_json::Value>,
     },
     /// Escalation Event
     EscalationEvent { escalation_ref: Option<String> },
@@ -41,15 +56,374 @@ pub enum ProcessEvent {
         target: Option<String>,
     },
     /// Error Event
-    ErrorEvent { error_ref: Option<String> },
+    ErrorEvent {
+        error_ref: Option<String>,
+        /// Error code (`errorCode` of the referenced error)
+        error_code: Option<String>,
+        /// Error message (`name` of the referenced error)
+        error_message: Option<String>,
+    },
+    /// Conditional Event
+    ConditionalEvent { condition: Expr },
+    /// Timer Event
+    TimerEvent {
+        time_date: Option<Expr>,
+        time_duration: Option<Expr>,
+        time_cycle: Option<Expr>,
+    },
+}
+
+/// Kind of [`ProcessEvent`], disregarding its payload
+///
+/// Used to subscribe to events of a particular kind only
+/// (see [`crate::process::Handle::subscribe`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// [`ProcessEvent::Start`]
+    Start,
+    /// [`ProcessEvent::End`]
+    End,
+    /// [`ProcessEvent::NoneEvent`]
+    NoneEvent,
+    /// [`ProcessEvent::SignalEvent`]
+    SignalEvent,
+    /// [`ProcessEvent::CancelEvent`]
+    CancelEvent,
+    /// [`ProcessEvent::TerminateEvent`]
+    TerminateEvent,
+    /// [`ProcessEvent::CompensationEvent`]
+    CompensationEvent,
+    /// [`ProcessEvent::MessageEvent`]
+    MessageEvent,
+    /// [`ProcessEvent::EscalationEvent`]
+    EscalationEvent,
+    /// [`ProcessEvent::LinkEvent`]
+    LinkEvent,
+    /// [`ProcessEvent::ErrorEvent`]
+    ErrorEvent,
+    /// [`ProcessEvent::ConditionalEvent`]
+    ConditionalEvent,
+    /// [`ProcessEvent::TimerEvent`]
+    TimerEvent,
+}
+
+impl ProcessEvent {
+    /// Returns event's kind
+    pub fn kind(&self) -> EventKind {
+        match self {
+            ProcessEvent::Start => EventKind::Start,
+            ProcessEvent::End => EventKind::End,
+            ProcessEvent::NoneEvent => EventKind::NoneEvent,
+            ProcessEvent::SignalEvent { .. } => EventKind::SignalEvent,
+            ProcessEvent::CancelEvent => EventKind::CancelEvent,
+            ProcessEvent::TerminateEvent => EventKind::TerminateEvent,
+            ProcessEvent::CompensationEvent { .. } => EventKind::CompensationEvent,
+            ProcessEvent::MessageEvent { .. } => EventKind::MessageEvent,
+            ProcessEvent::EscalationEvent { .. } => EventKind::EscalationEvent,
+            ProcessEvent::LinkEvent { .. } => EventKind::LinkEvent,
+            ProcessEvent::ErrorEvent { .. } => EventKind::ErrorEvent,
+            ProcessEvent::ConditionalEvent { .. } => EventKind::ConditionalEvent,
+            ProcessEvent::TimerEvent { .. } => EventKind::TimerEvent,
+        }
+    }
+}
+
+impl ProcessEvent {
+    /// Returns `true` if `thrown` event should be caught by a catcher of this event
+    ///
+    /// Unlike equality, this disregards payloads carried by thrown events.
+    ///
+    /// Error catchers without an error reference catch all errors. Otherwise, error catchers
+    /// catch errors with the same error reference or, when both carry one, a matching error
+    /// code, even if the references differ. Error codes may contain `*` wildcards (for
+    /// example, `E4*` catches `E42`).
+    pub fn matches(&self, thrown: &ProcessEvent) -> bool {
+        match (self, thrown) {
+            (
+                ProcessEvent::ErrorEvent {
+                    error_ref: None, ..
+                },
+                ProcessEvent::ErrorEvent { .. },
+            ) => true,
+            (
+                ProcessEvent::ErrorEvent {
+                    error_ref,
+                    error_code,
+                    ..
+                },
+                ProcessEvent::ErrorEvent {
+                    error_ref: thrown_error_ref,
+                    error_code: thrown_error_code,
+                    ..
+                },
+            ) => {
+                error_ref == thrown_error_ref
+                    || matches!((error_code, thrown_error_code),
+                        (Some(pattern), Some(code)) if wildcard_match(pattern, code))
+            }
+            (
+                ProcessEvent::MessageEvent {
+                    message_ref,
+                    operation_ref,
+                    ..
+                },
+                ProcessEvent::MessageEvent {
+                    message_ref: thrown_message_ref,
+                    operation_ref: thrown_operation_ref,
+                    ..
+                },
+            ) => message_ref == thrown_message_ref && operation_ref == thrown_operation_ref,
+            _ => self == thrown,
+        }
+    }
+
+    /// Returns how specifically an error catcher matches a thrown error, if it does (see
+    /// [`ProcessEvent::matches`]), lower being more specific
+    ///
+    /// Catchers with the same error reference come first, then those matching the error
+    /// code, then those catching all errors.
+    pub(crate) fn error_match_rank(&self, thrown: &ProcessEvent) -> Option<u8> {
+        match (self, thrown) {
+            (
+                ProcessEvent::ErrorEvent {
+                    error_ref: Some(error_ref),
+                    ..
+                },
+                ProcessEvent::ErrorEvent {
+                    error_ref: Some(thrown_error_ref),
+                    ..
+                },
+            ) if error_ref == thrown_error_ref => Some(0),
+            (
+                ProcessEvent::ErrorEvent {
+                    error_ref: Some(_), ..
+                },
+                ProcessEvent::ErrorEvent { .. },
+            ) if self.matches(thrown) => Some(1),
+            (
+                ProcessEvent::ErrorEvent {
+                    error_ref: None, ..
+                },
+                ProcessEvent::ErrorEvent { .. },
+            ) => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Resolves event's payload from referenced root elements
+    pub(crate) fn with_payload(self, definitions: &Definitions) -> Self {
+        match self {
+            ProcessEvent::ErrorEvent {
+                error_ref: Some(error_ref),
+                error_code: None,
+                error_message: None,
+            } => {
+                let error = definitions
+                    .find_by_id(&error_ref)
+                    .and_then(|e| e.downcast_ref::<crate::bpmn::schema::Error>());
+                ProcessEvent::ErrorEvent {
+                    error_code: error.and_then(|e| e.error_code.clone()),
+                    error_message: error.and_then(|e| e.name.clone()),
+                    error_ref: Some(error_ref),
+                }
+            }
+            event => event,
+        }
+    }
+}
+
+/// Converts event definitions into process events with their payloads resolved
+///
+/// Definitions that can't be converted are skipped.
+pub(crate) fn events<'a>(
+    event_definitions: &'a [EventDefinition],
+    definitions: &'a Definitions,
+) -> impl Iterator<Item = ProcessEvent> + 'a {
+    event_definitions
+        .iter()
+        .filter_map(move |event_definition| {
+            Cast::<dyn EventDefinitionType>::cast(event_definition.clone().into_inner().as_ref())
+                .and_then(|definition| ProcessEvent::try_from(definition).ok())
+                .map(|event| event.with_payload(definitions))
+        })
+}
+
+/// Matches `text` against `pattern` where `*` matches any (possibly empty) sequence
+/// of characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // there's always at least one part
+    let first = parts.next().unwrap();
+    if !text.starts_with(first) {
+        return false;
+    }
+    let mut rest = &text[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // no wildcards
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(index) => rest = &rest[index + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+/// Process event envelope
+///
+/// Attributes [`ProcessEvent`] to its emitter
+#[derive(Clone, Debug, PartialEq, Deref, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Event
+    #[deref]
+    pub event: ProcessEvent,
+    /// Identifier of the flow node that has emitted the event (`None` if it was sent
+    /// from outside of the process)
+    pub source: Option<String>,
+    /// Process instance the event belongs to
+    pub instance: InstanceId,
+    /// Time of emission
+    pub timestamp: SystemTime,
+}
+
+impl Envelope {
+    /// Creates a new envelope timestamped with current time
+    pub fn new(event: ProcessEvent, source: Option<String>, instance: InstanceId) -> Self {
+        Self {
+            event,
+            source,
+            instance,
+            timestamp: crate::sys::time::now(),
+        }
+    }
+}
+
+/// Per-kind event channels
+///
+/// Every dispatched envelope is forwarded only to the channel of its kind, so subscribers
+/// of one kind don't lag behind because of events of other kinds. Channels are created on
+/// first subscription and dropped once they have no receivers left.
+#[derive(Clone, Default)]
+pub(crate) struct Demultiplexer {
+    channels: Arc<Mutex<HashMap<EventKind, broadcast::Sender<Envelope>>>>,
+}
+
+impl Demultiplexer {
+    /// Returns a receiver of events of `kind`
+    pub(crate) fn subscribe(&self, kind: EventKind) -> broadcast::Receiver<Envelope> {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(kind)
+            .or_insert_with(|| broadcast::channel(128).0)
+            .subscribe()
+    }
+
+    /// Forwards an envelope to the subscribers of its kind
+    pub(crate) fn dispatch(&self, envelope: &Envelope) {
+        let mut channels = self.channels.lock().unwrap();
+        let kind = envelope.event.kind();
+        if let Some(sender) = channels.get(&kind) {
+            if sender.send(envelope.clone()).is_err() {
+                // nobody is listening anymore
+                channels.remove(&kind);
+            }
+        }
+    }
+}
+
+/// Message or signal a flow node is waiting for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    /// Waiting flow node
+    pub node: Option<String>,
+    /// Awaited event
+    pub event: ProcessEvent,
+    /// Name of the awaited message or signal, if known
+    pub name: Option<String>,
+}
+
+/// Registry of message and signal subscriptions of a process instance
+#[derive(Clone, Default)]
+pub(crate) struct Subscriptions {
+    nodes: Arc<Mutex<HashMap<Option<String>, Vec<Subscription>>>>,
+}
+
+impl Subscriptions {
+    /// Records message and signal events a flow node is waiting for
+    ///
+    /// Replaces previously recorded subscriptions of the node, other events are ignored.
+    pub(crate) fn subscribe<I>(&self, node: Option<String>, events: I, definitions: &Definitions)
+    where
+        I: IntoIterator<Item = ProcessEvent>,
+    {
+        let subscriptions: Vec<_> = events
+            .into_iter()
+            .filter_map(|event| {
+                let name = match &event {
+                    ProcessEvent::MessageEvent { message_ref, .. } => message_ref
+                        .as_ref()
+                        .and_then(|id| definitions.find_by_id(id))
+                        .and_then(|e| e.downcast_ref::<Message>())
+                        .and_then(|message| message.name.clone()),
+                    ProcessEvent::SignalEvent { signal_ref } => signal_ref
+                        .as_ref()
+                        .and_then(|id| definitions.find_by_id(id))
+                        .and_then(|e| e.downcast_ref::<Signal>())
+                        .and_then(|signal| signal.name.clone()),
+                    _ => return None,
+                };
+                Some(Subscription {
+                    node: node.clone(),
+                    event,
+                    name,
+                })
+            })
+            .collect();
+        let mut nodes = self.nodes.lock().unwrap();
+        if subscriptions.is_empty() {
+            nodes.remove(&node);
+        } else {
+            nodes.insert(node, subscriptions);
+        }
+    }
+
+    /// Removes subscriptions of a flow node
+    pub(crate) fn unsubscribe(&self, node: &Option<String>) {
+        self.nodes.lock().unwrap().remove(node);
+    }
+
+    /// Returns all subscriptions, ordered by flow node
+    pub(crate) fn list(&self) -> Vec<Subscription> {
+        let nodes = self.nodes.lock().unwrap();
+        let mut subscriptions: Vec<_> = nodes.values().flatten().cloned().collect();
+        subscriptions.sort_by(|a, b| a.node.cmp(&b.node));
+        subscriptions
+    }
 }
 
 /// Event conversion error
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum ConversionError {
     /// Event can't be converted
-    Impossible,
+    #[error("can't convert {definition:?} into a process event: {reason}")]
+    Impossible {
+        /// Event definition type that failed to convert
+        definition: Element,
+        /// Reason of the failure
+        reason: String,
+    },
     /// Event can be converted, but this hasn't been implemented yet
-    NotImplemented,
+    #[error("conversion of {definition:?} into a process event is not implemented")]
+    NotImplemented {
+        /// Event definition type that failed to convert
+        definition: Element,
+    },
 }
 
 impl TryFrom<CancelEventDefinition> for ProcessEvent {
@@ -90,6 +464,7 @@ impl TryFrom<MessageEventDefinition> for ProcessEvent {
         Ok(ProcessEvent::MessageEvent {
             message_ref: event_definition.message_ref,
             operation_ref: event_definition.operation_ref,
+            payload: None,
         })
     }
 }
@@ -118,27 +493,38 @@ impl TryFrom<ErrorEventDefinition> for ProcessEvent {
     fn try_from(event_definition: ErrorEventDefinition) -> Result<Self, Self::Error> {
         Ok(ProcessEvent::ErrorEvent {
             error_ref: event_definition.error_ref,
+            error_code: None,
+            error_message: None,
         })
     }
 }
 
 impl TryFrom<ConditionalEventDefinition> for ProcessEvent {
     type Error = ConversionError;
-    fn try_from(_event_definition: ConditionalEventDefinition) -> Result<Self, Self::Error> {
-        Err(ConversionError::Impossible)
+    fn try_from(event_definition: ConditionalEventDefinition) -> Result<Self, Self::Error> {
+        Ok(ProcessEvent::ConditionalEvent {
+            condition: event_definition.condition.0,
+        })
     }
 }
 
 impl TryFrom<TimerEventDefinition> for ProcessEvent {
     type Error = ConversionError;
-    fn try_from(_event_definition: TimerEventDefinition) -> Result<Self, Self::Error> {
-        Err(ConversionError::Impossible)
-    }
-}
-
-impl<T> From<Box<T>> for ConversionError {
-    fn from(_: Box<T>) -> Self {
-        Self::NotImplemented
+    fn try_from(event_definition: TimerEventDefinition) -> Result<Self, Self::Error> {
+        if event_definition.time_date.is_none()
+            && event_definition.time_duration.is_none()
+            && event_definition.time_cycle.is_none()
+        {
+            return Err(ConversionError::Impossible {
+                definition: Element::TimerEventDefinition,
+                reason: "neither time date, duration nor cycle is specified".into(),
+            });
+        }
+        Ok(ProcessEvent::TimerEvent {
+            time_date: event_definition.time_date.map(|e| e.0),
+            time_duration: event_definition.time_duration.map(|e| e.0),
+            time_cycle: event_definition.time_cycle.map(|e| e.0),
+        })
     }
 }
 
@@ -176,6 +562,81 @@ impl TryFrom<&dyn EventDefinitionType> for ProcessEvent {
             return ProcessEvent::try_from(e.clone());
         }
 
-        Err(ConversionError::NotImplemented)
+        Err(ConversionError::NotImplemented {
+            definition: event_definition.element(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    fn timer_event_conversion() {
+        let duration = Expr::FormalExpression(FormalExpression {
+            content: Some("PT5M".into()),
+            ..Default::default()
+        });
+        let definition = TimerEventDefinition {
+            time_duration: Some(TimerEventDefinitionTimeDuration(duration.clone())),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ProcessEvent::try_from(definition),
+            Ok(ProcessEvent::TimerEvent {
+                time_date: None,
+                time_duration: Some(ref e),
+                time_cycle: None,
+            }) if e == &duration
+        ));
+    }
+
+    #[bpxe_im::test]
+    fn error_matching() {
+        let thrown = ProcessEvent::ErrorEvent {
+            error_ref: Some("failure".into()),
+            error_code: Some("E42".into()),
+            error_message: None,
+        };
+        let catcher =
+            |error_ref: Option<&str>, error_code: Option<&str>| ProcessEvent::ErrorEvent {
+                error_ref: error_ref.map(String::from),
+                error_code: error_code.map(String::from),
+                error_message: None,
+            };
+        assert!(catcher(Some("failure"), None).matches(&thrown));
+        assert!(catcher(None, None).matches(&thrown));
+        assert!(catcher(Some("other"), Some("E4*")).matches(&thrown));
+        assert!(catcher(Some("other"), Some("*2")).matches(&thrown));
+        assert!(catcher(Some("other"), Some("E42")).matches(&thrown));
+        assert!(!catcher(Some("other"), Some("E43")).matches(&thrown));
+        assert!(!catcher(Some("other"), Some("E4")).matches(&thrown));
+        assert!(!catcher(Some("other"), None).matches(&thrown));
+        assert!(!catcher(Some("other"), Some("E5*")).matches(&thrown));
+        assert!(!catcher(None, None).matches(&ProcessEvent::NoneEvent));
+
+        assert_eq!(
+            catcher(Some("failure"), Some("E5*")).error_match_rank(&thrown),
+            Some(0)
+        );
+        assert_eq!(
+            catcher(Some("other"), Some("E4*")).error_match_rank(&thrown),
+            Some(1)
+        );
+        assert_eq!(catcher(None, None).error_match_rank(&thrown), Some(2));
+        assert_eq!(catcher(Some("other"), None).error_match_rank(&thrown), None);
+    }
+
+    #[bpxe_im::test]
+    fn empty_timer_event_conversion() {
+        assert!(matches!(
+            ProcessEvent::try_from(TimerEventDefinition::default()),
+            Err(ConversionError::Impossible {
+                definition: Element::TimerEventDefinition,
+                ..
+            })
+        ));
     }
 }
@@ -1,6 +1,6 @@
 //! # Start Event flow node
 use crate::bpmn::schema::{FlowNodeType, StartEvent as Element};
-use crate::event::ProcessEvent;
+use crate::event::{Envelope, ProcessEvent};
 use crate::flow_node::{self, Action, FlowNode};
 use crate::sys::task;
 use futures::stream::Stream;
@@ -14,7 +14,7 @@ use tokio::sync::{broadcast, mpsc};
 pub struct StartEvent {
     element: Arc<Element>,
     state: State,
-    event_receivers: Vec<broadcast::Receiver<ProcessEvent>>,
+    event_receivers: Vec<broadcast::Receiver<Envelope>>,
     waker_sender: mpsc::Sender<Waker>,
     waker_receiver: Option<mpsc::Receiver<Waker>>,
 }
@@ -117,7 +117,7 @@ impl Stream for StartEvent {
                     Done,
                 }
                 let next = self.event_receivers.iter_mut().find_map(|receiver| {
-                    let res = receiver.try_recv();
+                    let res = receiver.try_recv().map(|envelope| envelope.event);
                     #[allow(unreachable_patterns)]
                     match res {
                         Ok(ProcessEvent::Start) => Some(Control::Ready),
@@ -1,9 +1,10 @@
 //! # Flow Node
 use crate::activity;
 use crate::bpmn::schema::{
-    ActivityType, DocumentElement, Element, EndEvent, EventBasedGateway, ExclusiveGateway,
-    FlowNodeType, InclusiveGateway, IntermediateCatchEvent, IntermediateThrowEvent,
-    ParallelGateway, ScriptTask, SequenceFlow, StartEvent,
+    ActivityType, BusinessRuleTask, CallActivity, ComplexGateway, DocumentElement, Element,
+    EndEvent, EventBasedGateway, ExclusiveGateway, FlowNodeType, InclusiveGateway,
+    IntermediateCatchEvent, IntermediateThrowEvent, ManualTask, ParallelGateway, ReceiveTask,
+    ScriptTask, SendTask, SequenceFlow, ServiceTask, StartEvent, UserTask,
 };
 use crate::event::{end_event, intermediate_catch_event, intermediate_throw_event, start_event};
 use crate::gateway;
@@ -33,12 +34,20 @@ pub enum State {
     ExclusiveGateway(gateway::exclusive::State),
     InclusiveGateway(gateway::inclusive::State),
     EventBasedGateway(gateway::event_based::State),
+    ComplexGateway(gateway::complex::State),
+    BusinessRuleTask(activity::business_rule_task::State),
+    CallActivity(activity::call_activity::State),
+    ManualTask(activity::manual_task::State),
+    ReceiveTask(activity::receive_task::State),
     ScriptTask(activity::script_task::State),
+    SendTask(activity::send_task::State),
+    ServiceTask(activity::service_task::State),
+    UserTask(activity::user_task::State),
     ActivityState(activity::State),
 }
 
 /// State handling errors
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
 pub enum StateError {
     /// Invalid state variant. A different variant was expected.
     #[error("invalid state variant")]
@@ -154,6 +163,28 @@ pub trait FlowNode: Stream<Item = Action> + Send + Unpin {
     #[allow(unused_variables)]
     fn tokens(&mut self, count: usize) {}
 
+    /// Reports, for every incoming sequence flow, whether tokens can still arrive through it
+    ///
+    /// Tokens can arrive through a sequence flow as long as any flow node it can be reached
+    /// from (without passing through this flow node) holds tokens. This is only reported for
+    /// flow nodes with more than one incoming sequence flow, every time it changes, before the
+    /// flow node is polled. Useful for joins that only wait for tokens that can still arrive.
+    ///
+    /// Default implementation does nothing.
+    #[allow(unused_variables)]
+    fn upstream_tokens(&mut self, incomings: &[bool]) {}
+
+    /// Returns the number of tokens the flow node still holds after passing tokens on
+    ///
+    /// Useful for flow nodes that activate more than once for tokens arriving together
+    /// (for example, gateways that merge or join some incoming tokens while others wait for
+    /// a subsequent activation). The process doesn't complete while these tokens wait.
+    ///
+    /// Default implementation returns zero.
+    fn waiting_tokens(&self) -> usize {
+        0
+    }
+
     /// Returns a flow element
     fn element(&self) -> Box<dyn FlowNodeType>;
 }
@@ -177,7 +208,25 @@ pub(crate) fn new(element: Box<dyn DocumentElement>) -> Option<Box<dyn FlowNode>
         Element::EventBasedGateway => {
             make::<EventBasedGateway, gateway::event_based::Gateway>(element)
         }
+        Element::ComplexGateway => make::<ComplexGateway, gateway::complex::Gateway>(element),
+        Element::BusinessRuleTask => {
+            make_activity::<BusinessRuleTask, activity::business_rule_task::Task>(element)
+        }
+        Element::CallActivity => {
+            make_activity::<CallActivity, activity::call_activity::Task>(element)
+        }
+        Element::ManualTask => make_activity::<ManualTask, activity::manual_task::Task>(element),
+        Element::ReceiveTask => match element.downcast_ref::<ReceiveTask>() {
+            // entry points aren't executed as activities, they start with the process
+            Some(receive_task) if activity::receive_task::instantiates(receive_task) => {
+                make::<ReceiveTask, activity::receive_task::Task>(element)
+            }
+            _ => make_activity::<ReceiveTask, activity::receive_task::Task>(element),
+        },
         Element::ScriptTask => make_activity::<ScriptTask, activity::script_task::Task>(element),
+        Element::SendTask => make_activity::<SendTask, activity::send_task::Task>(element),
+        Element::ServiceTask => make_activity::<ServiceTask, activity::service_task::Task>(element),
+        Element::UserTask => make_activity::<UserTask, activity::user_task::Task>(element),
         _ => None,
     }
 }
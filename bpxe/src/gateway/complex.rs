@@ -0,0 +1,441 @@
+//! # Complex Gateway
+//!
+//! Join fires once its `activationCondition` holds. The condition is evaluated with the
+//! expression engine every time the number of incoming flows that have delivered a token
+//! changes, with that number available to it as `activationCount` (for example,
+//! `activationCount.unveil() >= 2` fires after two of the incoming flows). Without a
+//! condition, the gateway fires on every token.
+//!
+//! Once fired, the gateway waits for the incoming flows that haven't contributed to the
+//! activation and consumes their tokens without firing again, until every one of them has
+//! delivered a token or no more tokens can arrive through it. Tokens that arrive on flows that
+//! have already delivered one wait for the next activation.
+//!
+//! On the diverging side, every outgoing flow whose condition holds is taken, and the default
+//! flow is taken if none do.
+use crate::bpmn::schema::{
+    ComplexGateway as Element, ComplexGatewayActivationCondition, Expr, FlowNodeType, SequenceFlow,
+};
+use crate::data_object;
+use crate::error::ErrorReport;
+use crate::flow_node::{self, Action, FlowNode, IncomingIndex, OutgoingIndex};
+use crate::language::{
+    Engine as _, EngineContext as _, EngineContextProvider as _, MultiLanguageEngine,
+};
+use crate::process::{self, Log};
+use futures::future::BoxFuture;
+use futures::stream::Stream;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use smallvec::{smallvec, SmallVec};
+use std::iter;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+/// Complex Gateway flow node
+pub struct Gateway {
+    element: Arc<Element>,
+    state: State,
+    waker: Option<Waker>,
+    process: Option<process::Handle>,
+    engine: Arc<MultiLanguageEngine>,
+    evaluation: Option<BoxFuture<'static, bool>>,
+}
+
+impl Gateway {
+    /// Creates new Complex Gateway flow node
+    pub fn new(element: Element) -> Self {
+        let element = Arc::new(element);
+        let number_of_incomings = element.incomings.len();
+        Self {
+            element,
+            state: State {
+                incoming_tokens: smallvec![0; number_of_incomings],
+                upstream_tokens: smallvec![false; number_of_incomings],
+                case: StateCase::Waiting { evaluated: None },
+            },
+            waker: None,
+            process: None,
+            engine: Arc::new(Default::default()),
+            evaluation: None,
+        }
+    }
+
+    /// Starts evaluating the activation condition
+    fn evaluate(&self, activation_count: usize) -> BoxFuture<'static, bool> {
+        let expr = match self.element.activation_condition {
+            Some(ComplexGatewayActivationCondition(Expr::FormalExpression(ref expr))) => {
+                expr.clone()
+            }
+            _ => return futures::future::ready(true).boxed(),
+        };
+        let engine = self.engine.clone();
+        let process = self.process.clone();
+        let id = self.element.id.clone();
+        async move {
+            let mut context = engine.new_context();
+            context.set(
+                "activationCount",
+                Box::new(data_object::Container(activation_count as i64)),
+            );
+            let evaluation_started = instant::Instant::now();
+            let result = engine.eval::<bool>(&expr, &mut context).await;
+            if let Some(process) = process.as_ref() {
+                process.count_execution_time(evaluation_started.elapsed());
+            }
+            match result {
+                Ok(result) => result,
+                Err(err) => {
+                    if let Some(process) = process {
+                        let _ = process.log_broadcast().send(Log::ExpressionError {
+                            element: id,
+                            error: ErrorReport::new(&err),
+                        });
+                    }
+                    false
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Node state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct State {
+    /// Number of tokens waiting on every incoming flow
+    incoming_tokens: SmallVec<[usize; flow_node::SMALL_INCOMING]>,
+    /// Whether tokens can still arrive through every incoming flow
+    upstream_tokens: SmallVec<[bool; flow_node::SMALL_INCOMING]>,
+    case: StateCase,
+}
+
+impl State {
+    /// Returns the number of incoming flows that have delivered a token
+    fn activation_count(&self) -> usize {
+        self.incoming_tokens
+            .iter()
+            .filter(|tokens| **tokens > 0)
+            .count()
+    }
+}
+
+type ProbingResult = (OutgoingIndex, bool, bool);
+
+/// Node state case
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StateCase {
+    /// Waiting for the activation condition to hold
+    Waiting {
+        /// Activation count the condition has been evaluated with last
+        evaluated: Option<usize>,
+    },
+    /// Evaluating the activation condition
+    Evaluating { activation_count: usize },
+    AwaitingProbing {
+        probed: SmallVec<[ProbingResult; flow_node::SMALL_OUTGOING]>,
+        activated: SmallVec<[bool; flow_node::SMALL_INCOMING]>,
+    },
+    Done {
+        selected_outgoing: SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]>,
+        activated: SmallVec<[bool; flow_node::SMALL_INCOMING]>,
+    },
+    /// Consuming tokens of incoming flows that haven't contributed to the activation
+    Resetting {
+        activated: SmallVec<[bool; flow_node::SMALL_INCOMING]>,
+        consumed: bool,
+    },
+}
+
+impl FlowNode for Gateway {
+    fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
+        match state {
+            flow_node::State::ComplexGateway(state) => {
+                self.state = state;
+                // evaluation in progress is not a part of the state, start over
+                if let StateCase::Evaluating { .. } = self.state.case {
+                    self.state.case = StateCase::Waiting { evaluated: None };
+                }
+                self.evaluation = None;
+                Ok(())
+            }
+            _ => Err(flow_node::StateError::InvalidVariant),
+        }
+    }
+
+    fn set_process(&mut self, process: process::Handle) {
+        let mut engine = process.model().expression_engine_factory().create();
+        if let Some(ref default_expression_language) =
+            process.model().definitions().expression_language
+        {
+            engine.set_default_namespace(default_expression_language);
+        }
+        self.engine = Arc::new(engine);
+        self.process = Some(process);
+    }
+
+    fn get_state(&mut self) -> flow_node::State {
+        flow_node::State::ComplexGateway(self.state.clone())
+    }
+
+    fn element(&self) -> Box<dyn FlowNodeType> {
+        Box::new(self.element.as_ref().clone())
+    }
+
+    fn incoming(&mut self, index: IncomingIndex) {
+        if let Some(tokens) = self.state.incoming_tokens.get_mut(index) {
+            *tokens += 1;
+        }
+        self.wake();
+    }
+
+    fn upstream_tokens(&mut self, incomings: &[bool]) {
+        self.state.upstream_tokens = incomings
+            .iter()
+            .copied()
+            .chain(iter::repeat(false))
+            .take(self.state.incoming_tokens.len())
+            .collect();
+        self.wake();
+    }
+
+    fn sequence_flow(
+        &mut self,
+        output: OutgoingIndex,
+        sequence_flow: &SequenceFlow,
+        condition_result: bool,
+    ) {
+        if let StateCase::AwaitingProbing {
+            ref mut probed,
+            ref mut activated,
+        } = self.state.case
+        {
+            probed.push((
+                output,
+                sequence_flow.id == self.element.default,
+                condition_result,
+            ));
+            if probed.len() < self.element.outgoings().len() {
+                return;
+            }
+            let mut selected_outgoing: SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]> =
+                probed
+                    .iter()
+                    .filter(|(_, default, result)| !*default && *result)
+                    .map(|(index, _, _)| *index)
+                    .collect();
+            if selected_outgoing.is_empty() {
+                selected_outgoing.extend(
+                    probed
+                        .iter()
+                        .filter(|(_, default, result)| *default && *result)
+                        .map(|(index, _, _)| *index),
+                );
+            }
+            if selected_outgoing.is_empty() {
+                // "If a default path is not specified and the Process is executed such that
+                // none of the conditional Expressions evaluates to true, a runtime exception
+                // occurs"
+                if let Some(process) = self.process.as_ref() {
+                    let _ = process.log_broadcast().send(Log::NoDefaultPath {
+                        node: Box::new(self.element.as_ref().clone()),
+                    });
+                }
+                return;
+            }
+            self.state.case = StateCase::Done {
+                selected_outgoing,
+                activated: std::mem::take(activated),
+            };
+            self.wake();
+        }
+    }
+}
+
+impl From<Element> for Gateway {
+    fn from(element: Element) -> Self {
+        Self::new(element)
+    }
+}
+
+impl Stream for Gateway {
+    type Item = Action;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match this.state.case {
+                StateCase::Waiting { evaluated } => {
+                    let activation_count = this.state.activation_count();
+                    if activation_count == 0 || evaluated == Some(activation_count) {
+                        this.waker.replace(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    this.evaluation = Some(this.evaluate(activation_count));
+                    this.state.case = StateCase::Evaluating { activation_count };
+                }
+                StateCase::Evaluating { activation_count } => {
+                    if this.evaluation.is_none() {
+                        this.evaluation = Some(this.evaluate(activation_count));
+                    }
+                    // safe to unwrap here because we made sure it's there above
+                    let activate = match this.evaluation.as_mut().unwrap().poll_unpin(cx) {
+                        Poll::Ready(activate) => activate,
+                        Poll::Pending => {
+                            this.waker.replace(cx.waker().clone());
+                            return Poll::Pending;
+                        }
+                    };
+                    this.evaluation = None;
+                    if !activate {
+                        this.state.case = StateCase::Waiting {
+                            evaluated: Some(activation_count),
+                        };
+                        continue;
+                    }
+                    let activated = this
+                        .state
+                        .incoming_tokens
+                        .iter_mut()
+                        .map(|tokens| {
+                            if *tokens > 0 {
+                                *tokens -= 1;
+                                true
+                            } else {
+                                false
+                            }
+                        })
+                        .collect();
+                    this.state.case = StateCase::AwaitingProbing {
+                        probed: smallvec![],
+                        activated,
+                    };
+                    return Poll::Ready(Some(Action::ProbeOutgoingSequenceFlows(
+                        (0..this.element.outgoings().len()).collect(),
+                    )));
+                }
+                StateCase::AwaitingProbing { .. } => {
+                    this.waker.replace(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                StateCase::Done {
+                    ref selected_outgoing,
+                    ref activated,
+                } => {
+                    let action = Action::Flow(selected_outgoing.clone());
+                    this.state.case = StateCase::Resetting {
+                        activated: activated.clone(),
+                        consumed: false,
+                    };
+                    cx.waker().wake_by_ref();
+                    return Poll::Ready(Some(action));
+                }
+                StateCase::Resetting {
+                    ref mut activated,
+                    ref mut consumed,
+                } => {
+                    let State {
+                        ref mut incoming_tokens,
+                        ref upstream_tokens,
+                        ..
+                    } = this.state;
+                    for (tokens, activated) in incoming_tokens.iter_mut().zip(activated.iter_mut())
+                    {
+                        if !*activated && *tokens > 0 {
+                            *tokens -= 1;
+                            *activated = true;
+                            *consumed = true;
+                        }
+                    }
+                    let reset = activated
+                        .iter()
+                        .zip(upstream_tokens.iter())
+                        .all(|(activated, upstream)| *activated || !upstream);
+                    if !reset {
+                        this.waker.replace(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    let complete =
+                        *consumed && this.state.incoming_tokens.iter().all(|tokens| *tokens == 0);
+                    this.state.case = StateCase::Waiting { evaluated: None };
+                    if complete {
+                        // tokens consumed while resetting are not passed on
+                        return Poll::Ready(Some(Action::Complete));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bpmn::parse;
+    use crate::bpmn::schema::*;
+    use crate::event::ProcessEvent;
+    use crate::model;
+    use crate::process::{Log, Outcome};
+    use crate::test::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    #[cfg(any(feature = "rhai"))]
+    async fn two_of_three() {
+        let definitions = parse(include_str!("test_models/complex_two_of_three.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        let end_reached = |e: &Log| {
+            if let Log::FlowNodeCompleted { node } = e {
+                matches!(node.downcast_ref::<EndEvent>(),
+                Some(end_event) if end_event.id().as_ref().unwrap() == "end")
+            } else {
+                false
+            }
+        };
+
+        for catch in &["first_catch", "second_catch", "third_catch"] {
+            assert!(log_mailbox
+                .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_deref() == Some(catch)))
+                .await);
+        }
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("first".into()),
+        });
+
+        // one of three isn't enough
+        assert!(expects_timeout(log_mailbox.receive(end_reached))
+            .await
+            .is_ok());
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("second".into()),
+        });
+
+        // but two are
+        assert!(log_mailbox.receive(end_reached).await);
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("third".into()),
+        });
+
+        // and the third one is consumed without firing again
+        assert_eq!(handle.join().await, Outcome::Completed);
+        assert!(expects_timeout(log_mailbox.receive(end_reached))
+            .await
+            .is_ok());
+
+        model.terminate().await;
+    }
+}
@@ -129,9 +129,11 @@ mod tests {
     use crate::bpmn::schema::*;
     use crate::event::ProcessEvent;
     use crate::model;
-    use crate::process::Log;
+    use crate::process::{Log, Outcome};
+    use crate::sys::time;
     use crate::test::*;
     use bpxe_internal_macros as bpxe_im;
+    use std::time::Duration;
 
     #[bpxe_im::test]
     async fn event() {
@@ -155,14 +157,14 @@ mod tests {
             .await);
 
         // trigger f2sig
-        let _ = handle.event_broadcast().send(ProcessEvent::SignalEvent {
+        handle.send_event(ProcessEvent::SignalEvent {
             signal_ref: Some("f2sig".into()),
         });
 
         // should throw f2report signal
         assert!(
             mailbox
-                .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2report"))
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2report"))
                 .await
         );
 
@@ -179,17 +181,48 @@ mod tests {
         );
 
         // trigger f1sig
-        let _ = handle.event_broadcast().send(ProcessEvent::SignalEvent {
+        handle.send_event(ProcessEvent::SignalEvent {
             signal_ref: Some("f1sig".into()),
         });
 
         // should not throw f1report signal because the path was already chosen
         assert!(
             expects_timeout( mailbox
-                .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1report")))
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1report")))
             .await.is_ok()
         );
 
         model.terminate().await;
     }
+
+    #[bpxe_im::test]
+    async fn cancels_other_events() {
+        let definitions = parse(include_str!("test_models/event_reply_or_timeout.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "reply"))
+            .await);
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "timeout"))
+            .await);
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("reply_signal".into()),
+        });
+
+        // the timeout no longer holds a token, so the process completes
+        assert_eq!(handle.join().await, Outcome::Completed);
+        // and leaves no timers or subscriptions behind (once the timer task notices)
+        time::sleep(Duration::from_millis(50)).await;
+        assert!(handle.timers().is_empty());
+        assert!(handle.subscriptions().is_empty());
+
+        model.terminate().await;
+    }
 }
@@ -23,26 +23,112 @@ impl Gateway {
         let element = Arc::new(element);
         Self {
             element,
-            state: State::Ready,
+            state: State {
+                waiting: 0,
+                case: StateCase::Ready,
+            },
             waker: None,
             process: None,
         }
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct State {
+    /// Number of tokens waiting for an activation
+    waiting: usize,
+    case: StateCase,
+}
+
 /// Node state
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum State {
+pub enum StateCase {
     Ready,
-    Probing,
+    Probing {
+        /// Outgoing sequence flows that haven't been probed yet, in order of probing
+        pending: SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]>,
+    },
     AwaitingProbing {
-        probed: SmallVec<[(OutgoingIndex, bool); flow_node::SMALL_OUTGOING]>,
+        pending: SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]>,
     },
     Done {
         selected_outgoing: OutgoingIndex,
     },
 }
 
+impl Gateway {
+    /// Returns outgoing sequence flows (excluding the default one) in order of probing
+    fn probing_order(&self) -> SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]> {
+        let priority = self
+            .process
+            .as_ref()
+            .zip(self.element.id.as_ref())
+            .and_then(|(process, id)| process.model().gateway_priority(id));
+        super::probing_order(
+            self.element.outgoings(),
+            self.element.default.as_ref(),
+            priority.as_ref(),
+        )
+    }
+
+    /// Proceeds with the next sequence flow to probe or, if there's none left,
+    /// with the default path
+    fn next(&mut self, pending: SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]>) {
+        if !pending.is_empty() {
+            self.state.case = StateCase::Probing { pending };
+            self.wake();
+            return;
+        }
+        // nothing worked, proceed with the default path, if there's one
+        if let Some(outgoing) = self.element.default.as_ref().and_then(|default| {
+            self.element
+                .outgoings()
+                .iter()
+                .position(|outgoing| outgoing == default)
+        }) {
+            self.select(outgoing);
+            return;
+        }
+        self.state.case = StateCase::AwaitingProbing { pending };
+        // ..otherwise, according to the specification:
+        // "If a default path is not specified and the Process is executed such that none of the conditional Expressions
+        // evaluates to true, a runtime exception occurs"
+        let exception = process::Log::NoDefaultPath {
+            node: Box::new(self.element.as_ref().clone()),
+        };
+        let _ = self
+            .process
+            .clone()
+            .ok_or_else(|| tokio::sync::broadcast::error::SendError(exception.clone()))
+            .and_then(|process| process.log_broadcast().send(exception));
+    }
+
+    fn select(&mut self, outgoing: OutgoingIndex) {
+        let order = self.probing_order();
+        let pending = match self.state.case {
+            StateCase::AwaitingProbing { ref pending } => pending.len(),
+            _ => 0,
+        };
+        super::report_decision(
+            self.process.as_ref(),
+            &self.element.id,
+            self.element.outgoings(),
+            &order[..order.len().saturating_sub(pending)],
+            &[outgoing],
+        );
+        self.state.case = StateCase::Done {
+            selected_outgoing: outgoing,
+        };
+        self.wake();
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
 impl FlowNode for Gateway {
     fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
         match state {
@@ -67,66 +153,31 @@ impl FlowNode for Gateway {
     }
 
     fn incoming(&mut self, _index: IncomingIndex) {
-        if let State::Ready = self.state {
-            self.state = State::Probing;
-            if let Some(waker) = self.waker.take() {
-                waker.wake();
-            }
-        }
+        // tokens arriving through any incoming flow are merged, each of them activates the
+        // gateway separately
+        self.state.waiting += 1;
+        self.wake();
+    }
+
+    fn waiting_tokens(&self) -> usize {
+        self.state.waiting
     }
 
     fn sequence_flow(
         &mut self,
         outgoing: OutgoingIndex,
-        sequence_flow: &SequenceFlow,
+        _sequence_flow: &SequenceFlow,
         condition_result: bool,
     ) {
-        if let State::AwaitingProbing { ref mut probed } = self.state {
-            probed.push((outgoing, condition_result));
+        if let StateCase::AwaitingProbing { ref pending } = self.state.case {
             // If sequence flow has resolved to `true`, proceed with it
-            // (as long as it is not a default path)
-            if condition_result && self.element.default == sequence_flow.id {
-                self.state = State::Done {
-                    selected_outgoing: outgoing,
-                };
-
-                if let Some(waker) = self.waker.take() {
-                    waker.wake();
-                }
-
+            // without probing the rest
+            if condition_result {
+                self.select(outgoing);
                 return;
             }
-            // if we've probed everything
-            if probed.len() == self.element.outgoings().len() {
-                // and if there was something successful then this means it was a default path
-                if let Some(outgoing) =
-                    probed.iter().find_map(
-                        |(result_index, result)| if *result { Some(result_index) } else { None },
-                    )
-                {
-                    // therefore, we can proceed with it
-                    self.state = State::Done {
-                        selected_outgoing: *outgoing,
-                    };
-
-                    if let Some(waker) = self.waker.take() {
-                        waker.wake();
-                    }
-                    return;
-                }
-                // if we've probed everything and nothing worked (there's no default)
-                // ..according to the specification:
-                // "If a default path is not specified and the Process is executed such that none of the conditional Expressions
-                // evaluates to true, a runtime exception occurs"
-                let exception = process::Log::NoDefaultPath {
-                    node: Box::new(self.element.as_ref().clone()),
-                };
-                let _ = self
-                    .process
-                    .clone()
-                    .ok_or_else(|| tokio::sync::broadcast::error::SendError(exception.clone()))
-                    .and_then(|process| process.log_broadcast().send(exception));
-            }
+            let pending = pending.clone();
+            self.next(pending);
         }
     }
 }
@@ -140,23 +191,35 @@ impl From<Element> for Gateway {
 impl Stream for Gateway {
     type Item = Action;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.state {
-            State::Ready | State::AwaitingProbing { .. } => {
+        if let StateCase::Ready = self.state.case {
+            if self.state.waiting > 0 {
+                self.state.waiting -= 1;
+                let pending = self.probing_order();
+                self.next(pending);
+            }
+        }
+        match self.state.case {
+            StateCase::Ready | StateCase::AwaitingProbing { .. } => {
                 self.waker.replace(cx.waker().clone());
                 Poll::Pending
             }
-            State::Probing => {
-                self.state = State::AwaitingProbing {
-                    probed: smallvec![],
-                };
-                Poll::Ready(Some(Action::ProbeOutgoingSequenceFlows(
-                    (0..self.element().outgoings().len()).collect(),
-                )))
+            StateCase::Probing { ref pending } => {
+                // sequence flows are probed one by one, in order
+                let mut pending = pending.clone();
+                let outgoing = pending.remove(0);
+                self.state.case = StateCase::AwaitingProbing { pending };
+                Poll::Ready(Some(Action::ProbeOutgoingSequenceFlows(smallvec![
+                    outgoing
+                ])))
             }
-            State::Done {
+            StateCase::Done {
                 selected_outgoing: index,
             } => {
-                self.state = State::Ready;
+                self.state.case = StateCase::Ready;
+                if self.state.waiting > 0 {
+                    // next token can be activated already
+                    cx.waker().wake_by_ref();
+                }
                 Poll::Ready(Some(Action::Flow(smallvec![index])))
             }
         }
@@ -170,7 +233,7 @@ mod tests {
     use crate::bpmn::schema::*;
     use crate::event::ProcessEvent;
     use crate::model;
-    use crate::process::Log;
+    use crate::process::{Log, Outcome};
     use crate::test::*;
     use bpxe_internal_macros as bpxe_im;
 
@@ -201,14 +264,14 @@ mod tests {
         // f2's signal should be thrown
         assert!(
             mailbox
-            .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2sig"))
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2sig"))
             .await
         );
 
         // but f1's should not
         assert!(expects_timeout(
                 mailbox.receive(
-                    |e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig")
+                    |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig")
                 )
         )
             .await
@@ -244,14 +307,43 @@ mod tests {
         // f0's signal should be thrown
         assert!(
             mailbox
-            .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f0sig"))
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f0sig"))
             .await
         );
 
         // but f1's or f2's should not
         assert!(expects_timeout(
                 mailbox.receive(
-                    |e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig" || signal_ref.as_ref().unwrap() == "f2sig")
+                    |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig" || signal_ref.as_ref().unwrap() == "f2sig")
+                )
+        )
+            .await
+            .is_ok());
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(any(feature = "rhai"))]
+    async fn default_comes_last() {
+        // default path is listed first, but a successful condition takes precedence
+        let definitions = parse(include_str!("test_models/exclusive_default_first.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(
+            mailbox
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig"))
+            .await
+        );
+
+        assert!(expects_timeout(
+                mailbox.receive(
+                    |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f0sig")
                 )
         )
             .await
@@ -281,4 +373,59 @@ mod tests {
 
         model.terminate().await;
     }
+
+    #[bpxe_im::test]
+    #[cfg(any(feature = "rhai"))]
+    async fn priority() {
+        let definitions = parse(include_str!("test_models/exclusive_choose_one.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_gateway_priority("excl", crate::gateway::Priority::new(vec!["Flow_0k9b80i"]))
+            .spawn()
+            .await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        // the first condition in priority order holds, so nothing else is evaluated
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::GatewayDecision { gateway, evaluated, taken }
+                    if gateway.as_deref() == Some("excl")
+                        && evaluated == &["Flow_0k9b80i"]
+                        && taken == &["Flow_0k9b80i"])
+                )
+                .await
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(any(feature = "rhai"))]
+    async fn mixed() {
+        let definitions = parse(include_str!("test_models/exclusive_mixed.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        // every token arriving at the gateway is routed separately
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                    if visits.get("mixed") == Some(&2)
+                        && visits.get("x") == Some(&2)
+                        && !visits.contains_key("y"))
+                )
+                .await
+        );
+
+        model.terminate().await;
+    }
 }
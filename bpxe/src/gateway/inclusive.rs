@@ -1,10 +1,16 @@
 //! # Inclusive Gateway
+//!
+//! Join fires once a token has arrived and no more tokens can arrive through the incoming
+//! flows that haven't delivered one yet (that is, no flow node these flows can be reached
+//! from holds tokens). Tokens that arrive on an incoming flow ahead of others are counted and
+//! wait for subsequent activations.
 use crate::bpmn::schema::{FlowNodeType, InclusiveGateway as Element, SequenceFlow};
 use crate::flow_node::{self, Action, FlowNode, IncomingIndex, OutgoingIndex};
 use crate::process;
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use smallvec::{smallvec, SmallVec};
+use std::iter;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
@@ -21,11 +27,12 @@ impl Gateway {
     /// Creates new Inclusive Gateway flow node
     pub fn new(element: Element) -> Self {
         let element = Arc::new(element);
+        let number_of_incomings = element.incomings.len();
         Self {
             element,
             state: State {
-                tokens: None,
-                incoming: 0,
+                incoming_tokens: smallvec![0; number_of_incomings],
+                upstream_tokens: smallvec![false; number_of_incomings],
                 case: StateCase::Ready,
             },
             waker: None,
@@ -36,26 +43,110 @@ impl Gateway {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct State {
-    tokens: Option<usize>,
-    incoming: usize,
+    /// Number of tokens waiting on every incoming flow
+    incoming_tokens: SmallVec<[usize; flow_node::SMALL_INCOMING]>,
+    /// Whether tokens can still arrive through every incoming flow
+    upstream_tokens: SmallVec<[bool; flow_node::SMALL_INCOMING]>,
     case: StateCase,
 }
 
-type ProbingResult = (OutgoingIndex, bool, bool);
+impl State {
+    /// Consumes a token from every incoming flow that has one, if the join can fire
+    fn join(&mut self) -> bool {
+        let can_fire = self.incoming_tokens.iter().any(|tokens| *tokens > 0)
+            && self
+                .incoming_tokens
+                .iter()
+                .zip(self.upstream_tokens.iter())
+                .all(|(tokens, upstream)| *tokens > 0 || !upstream);
+        if can_fire {
+            for tokens in self.incoming_tokens.iter_mut() {
+                *tokens = tokens.saturating_sub(1);
+            }
+        }
+        can_fire
+    }
+}
+
+type Indices = SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]>;
 
 /// Node state
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StateCase {
     Ready,
-    Probing,
+    Probing {
+        /// Probed outgoing sequence flows and their condition results, in order of probing
+        probed: SmallVec<[(OutgoingIndex, bool); flow_node::SMALL_OUTGOING]>,
+        /// Outgoing sequence flows that haven't been probed yet, in order of probing
+        pending: Indices,
+    },
     AwaitingProbing {
-        probed: SmallVec<[ProbingResult; flow_node::SMALL_OUTGOING]>,
+        probed: SmallVec<[(OutgoingIndex, bool); flow_node::SMALL_OUTGOING]>,
+        pending: Indices,
+        /// Number of probing results yet to be reported
+        awaiting: usize,
     },
     Done {
-        selected_outgoing: SmallVec<[OutgoingIndex; flow_node::SMALL_OUTGOING]>,
+        selected_outgoing: Indices,
     },
 }
 
+impl Gateway {
+    fn priority(&self) -> Option<super::Priority> {
+        self.process
+            .as_ref()
+            .zip(self.element.id.as_ref())
+            .and_then(|(process, id)| process.model().gateway_priority(id))
+    }
+
+    /// Takes successful sequence flows once everything has been probed
+    fn decide(&mut self, probed: &[(OutgoingIndex, bool)]) {
+        let evaluated: Indices = probed.iter().map(|(index, _)| *index).collect();
+        let mut successful: Indices = probed
+            .iter()
+            .filter(|(_, result)| *result)
+            .map(|(index, _)| *index)
+            .collect();
+        // if nothing was successful, proceed with the default path, if there's one
+        if successful.is_empty() {
+            successful.extend(self.element.default.as_ref().and_then(|default| {
+                self.element
+                    .outgoings()
+                    .iter()
+                    .position(|outgoing| outgoing == default)
+            }));
+        }
+        if successful.is_empty() {
+            // if we've probed everything and nothing worked (there's no default)
+            // ..according to the specification:
+            // "If a default path is not specified and the Process is executed such that none of the conditional Expressions
+            // evaluates to true, a runtime exception occurs"
+            let exception = process::Log::NoDefaultPath {
+                node: Box::new(self.element.as_ref().clone()),
+            };
+            let _ = self
+                .process
+                .clone()
+                .ok_or_else(|| tokio::sync::broadcast::error::SendError(exception.clone()))
+                .and_then(|process| process.log_broadcast().send(exception));
+            return;
+        }
+        super::report_decision(
+            self.process.as_ref(),
+            &self.element.id,
+            self.element.outgoings(),
+            &evaluated,
+            &successful,
+        );
+        self.state.case = StateCase::Done {
+            selected_outgoing: successful,
+        };
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
 impl FlowNode for Gateway {
     fn set_state(&mut self, state: flow_node::State) -> Result<(), flow_node::StateError> {
         match state {
@@ -79,101 +170,71 @@ impl FlowNode for Gateway {
         Box::new(self.element.as_ref().clone())
     }
 
-    fn incoming(&mut self, _index: IncomingIndex) {
-        self.state.incoming += 1;
-        if let StateCase::Ready = self.state.case {
-            self.state.case = StateCase::Probing;
+    fn incoming(&mut self, index: IncomingIndex) {
+        if let Some(tokens) = self.state.incoming_tokens.get_mut(index) {
+            *tokens += 1;
         }
         if let Some(waker) = self.waker.take() {
             waker.wake();
         }
     }
 
+    fn upstream_tokens(&mut self, incomings: &[bool]) {
+        self.state.upstream_tokens = incomings
+            .iter()
+            .copied()
+            .chain(iter::repeat(false))
+            .take(self.state.incoming_tokens.len())
+            .collect();
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn waiting_tokens(&self) -> usize {
+        self.state.incoming_tokens.iter().sum()
+    }
+
     fn sequence_flow(
         &mut self,
-        output: OutgoingIndex,
-        sequence_flow: &SequenceFlow,
+        outgoing: OutgoingIndex,
+        _sequence_flow: &SequenceFlow,
         condition_result: bool,
     ) {
-        if let StateCase::AwaitingProbing { ref mut probed } = self.state.case {
-            probed.push((
-                output,
-                sequence_flow.id == self.element.default,
-                condition_result,
-            ));
-            // if we've probed everything
-            if probed.len() == self.element.outgoings().len() {
-                let successful: SmallVec<[ProbingResult; flow_node::SMALL_OUTGOING]> =
-                    std::mem::replace(probed, smallvec![])
-                        .into_iter()
-                        .filter(|(_, _, result)| *result)
-                        .collect();
-                // and if there were successful flows
-                if !successful.is_empty() {
-                    let successful_non_default: SmallVec<
-                        [&ProbingResult; flow_node::SMALL_OUTGOING],
-                    > = successful
-                        .iter()
-                        .filter(|(_, default, _)| !(*default as bool))
-                        .collect();
-                    // and there were some that were not default
-                    if !successful_non_default.is_empty() {
-                        // then flow
-                        self.state.case = StateCase::Done {
-                            selected_outgoing: successful_non_default
-                                .into_iter()
-                                .map(|(index, _, _)| *index)
-                                .collect(),
-                        };
-
-                        if let Some(waker) = self.waker.take() {
-                            waker.wake();
-                        }
-                        return;
-                    }
-                    // otherwise, if there was a default path
-                    let default_path =
-                        successful.iter().find_map(
-                            |(index, default, _)| {
-                                if *default {
-                                    Some(*index)
-                                } else {
-                                    None
-                                }
-                            },
-                        );
-                    if let Some(index) = default_path {
-                        // then flow
-                        self.state.case = StateCase::Done {
-                            selected_outgoing: smallvec![index],
-                        };
-
-                        if let Some(waker) = self.waker.take() {
-                            waker.wake();
-                        }
-                        return;
-                    }
-                }
-
-                // if we've probed everything and nothing worked (there's no default)
-                // ..according to the specification:
-                // "If a default path is not specified and the Process is executed such that none of the conditional Expressions
-                // evaluates to true, a runtime exception occurs"
-                let exception = process::Log::NoDefaultPath {
-                    node: Box::new(self.element.as_ref().clone()),
+        let first_match = self
+            .priority()
+            .map(|priority| priority.first_match)
+            .unwrap_or_default();
+        if let StateCase::AwaitingProbing {
+            ref mut probed,
+            ref mut pending,
+            ref mut awaiting,
+        } = self.state.case
+        {
+            probed.push((outgoing, condition_result));
+            *awaiting = awaiting.saturating_sub(1);
+            if condition_result && first_match {
+                let probed = probed.clone();
+                self.decide(&probed);
+                return;
+            }
+            if *awaiting > 0 {
+                return;
+            }
+            if !pending.is_empty() {
+                self.state.case = StateCase::Probing {
+                    probed: std::mem::take(probed),
+                    pending: std::mem::take(pending),
                 };
-                let _ = self
-                    .process
-                    .clone()
-                    .ok_or_else(|| tokio::sync::broadcast::error::SendError(exception.clone()))
-                    .and_then(|process| process.log_broadcast().send(exception));
+                if let Some(waker) = self.waker.take() {
+                    waker.wake();
+                }
+                return;
             }
+            let probed = probed.clone();
+            self.decide(&probed);
         }
     }
-
-    fn tokens(&mut self, count: usize) {
-        self.state.tokens.replace(count);
-    }
 }
 
 impl From<Element> for Gateway {
@@ -185,34 +246,61 @@ impl From<Element> for Gateway {
 impl Stream for Gateway {
     type Item = Action;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let StateCase::Ready = self.state.case {
+            if self.state.join() {
+                let priority = self.priority();
+                let pending = super::probing_order(
+                    self.element.outgoings(),
+                    self.element.default.as_ref(),
+                    priority.as_ref(),
+                );
+                if pending.is_empty() {
+                    // there's nothing to probe
+                    self.decide(&[]);
+                } else {
+                    self.state.case = StateCase::Probing {
+                        probed: smallvec![],
+                        pending,
+                    };
+                }
+            }
+        }
         match self.state.case {
             StateCase::Ready | StateCase::AwaitingProbing { .. } => {
                 self.waker.replace(cx.waker().clone());
                 Poll::Pending
             }
-            StateCase::Probing => {
+            StateCase::Probing {
+                ref mut probed,
+                ref mut pending,
+            } => {
+                let probed = std::mem::take(probed);
+                let mut pending = std::mem::take(pending);
+                let first_match = self
+                    .priority()
+                    .map(|priority| priority.first_match)
+                    .unwrap_or_default();
+                // when stopping at the first match, sequence flows are probed one by one
+                let probing: Indices = if first_match {
+                    pending.drain(..1).collect()
+                } else {
+                    pending.drain(..).collect()
+                };
                 self.state.case = StateCase::AwaitingProbing {
-                    probed: smallvec![],
+                    probed,
+                    pending,
+                    awaiting: probing.len(),
                 };
-                Poll::Ready(Some(Action::ProbeOutgoingSequenceFlows(
-                    (0..self.element().outgoings().len()).collect(),
-                )))
+                Poll::Ready(Some(Action::ProbeOutgoingSequenceFlows(probing)))
             }
             StateCase::Done {
                 ref selected_outgoing,
             } => {
-                if let Some(tokens) = self.state.tokens.as_ref().take() {
-                    if *tokens >= self.state.incoming {
-                        // if enough tokens, flow
-                        let result = Poll::Ready(Some(Action::Flow(selected_outgoing.clone())));
-                        self.state.case = StateCase::Ready;
-                        return result;
-                    }
-                }
-                // if no token count, or not enough tokens, keep waiting
-                self.waker.replace(cx.waker().clone());
+                let result = Poll::Ready(Some(Action::Flow(selected_outgoing.clone())));
                 self.state.case = StateCase::Ready;
-                Poll::Pending
+                // tokens waiting for subsequent activations may be able to fire already
+                cx.waker().wake_by_ref();
+                result
             }
         }
     }
@@ -256,21 +344,21 @@ mod tests {
         // f1's signal should be thrown
         assert!(
             mailbox
-            .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig"))
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig"))
             .await
         );
 
         // f2's signal should be thrown
         assert!(
             mailbox
-            .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2sig"))
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2sig"))
             .await
         );
 
         // but f3's should not
         assert!(expects_timeout(
                 mailbox.receive(
-                    |e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f3sig")
+                    |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f3sig")
                 )
         )
             .await
@@ -306,14 +394,14 @@ mod tests {
         // f0's signal should be thrown
         assert!(
             mailbox
-            .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f0sig"))
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f0sig"))
             .await
         );
 
         // but f1's or f2's should not
         assert!(expects_timeout(
                 mailbox.receive(
-                    |e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig" || signal_ref.as_ref().unwrap() == "f2sig")
+                    |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig" || signal_ref.as_ref().unwrap() == "f2sig")
                 )
         )
             .await
@@ -372,14 +460,108 @@ mod tests {
         // f2's signal should be thrown
         assert!(
             mailbox
-            .receive(|e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2sig"))
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2sig"))
             .await
         );
 
         // but f1's should not (because it was false)
         assert!(expects_timeout(
                 mailbox.receive(
-                    |e| matches!(e, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig")
+                    |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig")
+                )
+        )
+            .await
+            .is_ok());
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn join_waits_for_reachable_tokens() {
+        let definitions = parse(include_str!("test_models/inclusive_or_join.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        let end_reached = |e: &Log| {
+            if let Log::FlowNodeCompleted { node } = e {
+                matches!(node.downcast_ref::<EndEvent>(),
+                Some(end_event) if end_event.id().as_ref().unwrap() == "end")
+            } else {
+                false
+            }
+        };
+
+        // one branch has reached the join
+        assert!(
+            mailbox
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "thrown"))
+            .await
+        );
+
+        // but the other one can still deliver its token
+        assert!(expects_timeout(log_mailbox.receive(end_reached))
+            .await
+            .is_ok());
+
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("go".into()),
+        });
+
+        // so the join fires once it has arrived
+        assert!(log_mailbox.receive(end_reached).await);
+
+        // and only once
+        assert!(expects_timeout(log_mailbox.receive(end_reached))
+            .await
+            .is_ok());
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(any(feature = "rhai"))]
+    async fn first_match() {
+        let definitions = parse(include_str!("test_models/inclusive_fork.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_gateway_priority(
+                "incl",
+                crate::gateway::Priority::new(vec!["Flow_0ld9ay2"]).first_match(),
+            )
+            .spawn()
+            .await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::GatewayDecision { gateway, evaluated, taken }
+                    if gateway.as_deref() == Some("incl")
+                        && evaluated == &["Flow_0ld9ay2"]
+                        && taken == &["Flow_0ld9ay2"])
+                )
+                .await
+        );
+
+        // only f2's flow is taken, even though f1's condition holds too
+        assert!(
+            mailbox
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f2sig"))
+            .await
+        );
+
+        assert!(expects_timeout(
+                mailbox.receive(
+                    |e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "f1sig")
                 )
         )
             .await
@@ -1,4 +1,8 @@
 //! # Gateways
+use crate::flow_node::{OutgoingIndex, SMALL_OUTGOING};
+use crate::process;
+use smallvec::SmallVec;
+
 pub mod parallel;
 pub use parallel::Gateway as Parallel;
 pub mod exclusive;
@@ -7,3 +11,84 @@ pub mod inclusive;
 pub use inclusive::Gateway as Inclusive;
 pub mod event_based;
 pub use event_based::Gateway as EventBased;
+pub mod complex;
+pub use complex::Gateway as Complex;
+
+/// Order in which an exclusive or inclusive gateway evaluates conditions of its outgoing
+/// sequence flows (see [`crate::model::Model::with_gateway_priority`])
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Priority {
+    /// Outgoing sequence flows evaluated first, in this order (the rest follow in document
+    /// order, the default flow is always considered last)
+    pub order: Vec<String>,
+    /// Whether an inclusive gateway stops evaluating conditions after the first one that holds
+    /// (taking only that sequence flow)
+    ///
+    /// Exclusive gateways always do.
+    pub first_match: bool,
+}
+
+impl Priority {
+    /// Creates a new priority with sequence flows evaluated first
+    pub fn new<I, S>(order: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            order: order.into_iter().map(Into::into).collect(),
+            first_match: false,
+        }
+    }
+
+    /// Returns priority updated to stop evaluating after the first condition that holds
+    pub fn first_match(self) -> Self {
+        Self {
+            first_match: true,
+            ..self
+        }
+    }
+}
+
+/// Returns outgoing sequence flows (excluding the default one) in the order their conditions
+/// are evaluated
+pub(crate) fn probing_order(
+    outgoings: &[String],
+    default: Option<&String>,
+    priority: Option<&Priority>,
+) -> SmallVec<[OutgoingIndex; SMALL_OUTGOING]> {
+    let mut order: SmallVec<[OutgoingIndex; SMALL_OUTGOING]> = SmallVec::new();
+    let prioritized = priority
+        .into_iter()
+        .flat_map(|priority| priority.order.iter())
+        .filter_map(|flow| outgoings.iter().position(|outgoing| outgoing == flow));
+    for index in prioritized.chain(0..outgoings.len()) {
+        if !order.contains(&index) && Some(&outgoings[index]) != default {
+            order.push(index);
+        }
+    }
+    order
+}
+
+/// Reports sequence flows a gateway has decided to take with [`process::Log::GatewayDecision`]
+pub(crate) fn report_decision(
+    process: Option<&process::Handle>,
+    gateway: &Option<String>,
+    outgoings: &[String],
+    evaluated: &[OutgoingIndex],
+    taken: &[OutgoingIndex],
+) {
+    if let Some(process) = process {
+        let flows = |indices: &[OutgoingIndex]| {
+            indices
+                .iter()
+                .filter_map(|index| outgoings.get(*index).cloned())
+                .collect()
+        };
+        let _ = process.log_broadcast().send(process::Log::GatewayDecision {
+            gateway: gateway.clone(),
+            evaluated: flows(evaluated),
+            taken: flows(taken),
+        });
+    }
+}
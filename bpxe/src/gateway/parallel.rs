@@ -1,10 +1,21 @@
 //! # Parallel Gateway
-use crate::bpmn::schema::{FlowNodeType, ParallelGateway as Element};
+//!
+//! Join fires once every incoming flow has delivered a token. Tokens that arrive on an
+//! incoming flow ahead of others are counted and wait for subsequent activations.
+//!
+//! If the model has a [join diagnostics threshold](crate::model::Model::with_join_diagnostics_threshold),
+//! a join that has received some, but not all, of its incoming flows for longer than that
+//! is reported with [`Log::StuckJoin`].
+use crate::bpmn::schema::{FlowElement, FlowNodeType, ParallelGateway as Element, Process};
 use crate::flow_node::{self, Action, FlowNode, IncomingIndex};
+use crate::process::{self, Log};
+use crate::sys::{task, time};
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use smallvec::{smallvec, SmallVec};
+use std::collections::HashSet;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker};
 
@@ -13,6 +24,9 @@ pub struct Gateway {
     element: Arc<Element>,
     state: State,
     waker: Option<Waker>,
+    process: Option<process::Handle>,
+    // incremented every time the join completes
+    generation: Arc<AtomicUsize>,
 }
 
 impl Gateway {
@@ -23,20 +37,117 @@ impl Gateway {
         Self {
             element,
             state: State::Ready {
-                incoming_completed: smallvec![false; number_of_incomings],
+                incoming_tokens: smallvec![0; number_of_incomings],
             },
             waker: None,
+            process: None,
+            generation: Arc::new(AtomicUsize::new(0)),
         }
     }
+
+    /// Reports the join if it still waits for the same set of incoming flows once the join
+    /// diagnostics threshold has passed
+    fn watch(&self) {
+        let process = match self.process.clone() {
+            Some(process) => process,
+            None => return,
+        };
+        let threshold = match process.model().join_diagnostics_threshold() {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        let element = self.element.clone();
+        let generation = self.generation.clone();
+        let current = generation.load(Ordering::SeqCst);
+        task::spawn(async move {
+            time::sleep(threshold).await;
+            if generation.load(Ordering::SeqCst) != current {
+                return;
+            }
+            let inspection = match process.inspect().await {
+                Ok(inspection) => inspection,
+                // process is over
+                Err(_) => return,
+            };
+            let incoming_completed: Vec<bool> = match element
+                .id
+                .as_ref()
+                .and_then(|id| inspection.flow_nodes.get(id))
+                .map(|node| &node.state)
+            {
+                Some(flow_node::State::ParallelGateway(State::Ready { incoming_tokens })) => {
+                    incoming_tokens.iter().map(|tokens| *tokens > 0).collect()
+                }
+                _ => return,
+            };
+            if generation.load(Ordering::SeqCst) != current || incoming_completed.iter().all(|c| *c)
+            {
+                return;
+            }
+            let missing: Vec<String> = element
+                .incomings
+                .iter()
+                .zip(incoming_completed)
+                .filter(|(_, completed)| !completed)
+                .map(|(flow, _)| flow.clone())
+                .collect();
+            let upstream = upstream(&process.element(), &missing, &inspection);
+            let _ = process.log_broadcast().send(Log::StuckJoin {
+                gateway: element.id.clone(),
+                missing,
+                upstream,
+            });
+        });
+    }
+}
+
+/// Returns flow nodes holding tokens that can reach any of the given sequence flows
+fn upstream(process: &Process, flows: &[String], inspection: &process::Inspection) -> Vec<String> {
+    let seq_flows: Vec<_> = process
+        .flow_elements
+        .iter()
+        .filter_map(|e| match e {
+            FlowElement::SequenceFlow(seq_flow) => Some(seq_flow),
+            _ => None,
+        })
+        .collect();
+    let mut queue: Vec<String> = seq_flows
+        .iter()
+        .filter(|seq_flow| matches!(seq_flow.id, Some(ref id) if flows.contains(id)))
+        .map(|seq_flow| seq_flow.source_ref.clone())
+        .collect();
+    let mut reachable = HashSet::new();
+    while let Some(node) = queue.pop() {
+        if reachable.insert(node.clone()) {
+            queue.extend(
+                seq_flows
+                    .iter()
+                    .filter(|seq_flow| seq_flow.target_ref == node)
+                    .map(|seq_flow| seq_flow.source_ref.clone()),
+            );
+        }
+    }
+    let mut upstream: Vec<String> = reachable
+        .into_iter()
+        .filter(|id| {
+            inspection
+                .flow_nodes
+                .get(id)
+                .map(|node| node.active_tokens > 0)
+                .unwrap_or(false)
+        })
+        .collect();
+    upstream.sort();
+    upstream
 }
 
 /// Node state
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum State {
     Ready {
-        incoming_completed: SmallVec<[bool; flow_node::SMALL_INCOMING]>,
+        /// Number of tokens waiting on every incoming flow
+        incoming_tokens: SmallVec<[usize; flow_node::SMALL_INCOMING]>,
     },
-    Complete,
 }
 
 impl FlowNode for Gateway {
@@ -50,6 +161,10 @@ impl FlowNode for Gateway {
         }
     }
 
+    fn set_process(&mut self, process: process::Handle) {
+        self.process = Some(process);
+    }
+
     fn get_state(&mut self) -> flow_node::State {
         flow_node::State::ParallelGateway(self.state.clone())
     }
@@ -59,14 +174,19 @@ impl FlowNode for Gateway {
     }
 
     fn incoming(&mut self, index: IncomingIndex) {
-        if let State::Ready {
-            ref mut incoming_completed,
-        } = self.state
-        {
-            incoming_completed[index] = true;
-            if let Some(waker) = self.waker.take() {
-                waker.wake();
-            }
+        let State::Ready {
+            ref mut incoming_tokens,
+        } = self.state;
+        if index >= incoming_tokens.len() {
+            return;
+        }
+        let first = incoming_tokens.iter().all(|tokens| *tokens == 0);
+        incoming_tokens[index] += 1;
+        if first && !incoming_tokens.iter().all(|tokens| *tokens > 0) {
+            self.watch();
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
         }
     }
 }
@@ -80,26 +200,21 @@ impl From<Element> for Gateway {
 impl Stream for Gateway {
     type Item = Action;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.state {
-            State::Ready {
-                ref incoming_completed,
-            } => {
-                if incoming_completed.iter().all(|c| *c) {
-                    self.state = State::Complete;
-                    Poll::Ready(Some(Action::Flow(
-                        (0..self.element().outgoings().len()).collect(),
-                    )))
-                } else {
-                    self.waker.replace(cx.waker().clone());
-                    Poll::Pending
-                }
-            }
-            State::Complete => {
-                self.state = State::Ready {
-                    incoming_completed: smallvec![false; self.element().incomings().len()],
-                };
-                Poll::Pending
+        let State::Ready {
+            ref mut incoming_tokens,
+        } = self.state;
+        if incoming_tokens.iter().all(|tokens| *tokens > 0) {
+            // every incoming flow gives up one token
+            for tokens in incoming_tokens.iter_mut() {
+                *tokens -= 1;
             }
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            Poll::Ready(Some(Action::Flow(
+                (0..self.element.outgoings().len()).collect(),
+            )))
+        } else {
+            self.waker.replace(cx.waker().clone());
+            Poll::Pending
         }
     }
 }
@@ -127,7 +242,7 @@ mod tests {
             // NoneEvent should be thrown twice
             assert!(
                 mailbox
-                    .receive(|e| matches!(e, ProcessEvent::NoneEvent))
+                    .receive(|e| matches!(&e.event, ProcessEvent::NoneEvent))
                     .await
             );
         }
@@ -149,7 +264,7 @@ mod tests {
             // NoneEvent should be thrown three times
             assert!(
                 mailbox
-                    .receive(|e| matches!(e, ProcessEvent::NoneEvent))
+                    .receive(|e| matches!(&e.event, ProcessEvent::NoneEvent))
                     .await
             );
         }
@@ -157,6 +272,38 @@ mod tests {
         model.terminate().await;
     }
 
+    #[bpxe_im::test]
+    async fn join_counts_tokens() {
+        use super::Gateway;
+        use crate::bpmn::schema::ParallelGateway;
+        use crate::flow_node::{Action, FlowNode};
+        use futures::StreamExt;
+        use std::task::Poll;
+
+        let mut gateway = Gateway::new(ParallelGateway {
+            id: Some("join".into()),
+            incomings: vec!["a".into(), "b".into()],
+            outgoings: vec!["c".into()],
+            ..Default::default()
+        });
+        gateway.incoming(0);
+        gateway.incoming(0);
+        assert!(matches!(futures::poll!(gateway.next()), Poll::Pending));
+        gateway.incoming(1);
+        assert!(matches!(
+            futures::poll!(gateway.next()),
+            Poll::Ready(Some(Action::Flow(_)))
+        ));
+        // the second token on the first flow is still waiting for its pair
+        assert!(matches!(futures::poll!(gateway.next()), Poll::Pending));
+        gateway.incoming(1);
+        assert!(matches!(
+            futures::poll!(gateway.next()),
+            Poll::Ready(Some(Action::Flow(_)))
+        ));
+        assert!(matches!(futures::poll!(gateway.next()), Poll::Pending));
+    }
+
     #[bpxe_im::test]
     async fn not_enough_to_join() {
         let definitions =
@@ -173,7 +320,7 @@ mod tests {
             // NoneEvent should be thrown twice (forking)
             assert!(
                 mailbox
-                    .receive(|e| matches!(e, ProcessEvent::NoneEvent))
+                    .receive(|e| matches!(&e.event, ProcessEvent::NoneEvent))
                     .await
             );
         }
@@ -188,11 +335,40 @@ mod tests {
 
         // but third NoneEvent should not happen as the join didn't occur
         assert!(
-            expects_timeout(mailbox.receive(|e| matches!(e, ProcessEvent::NoneEvent)))
+            expects_timeout(mailbox.receive(|e| matches!(&e.event, ProcessEvent::NoneEvent)))
                 .await
                 .is_ok()
         );
 
         model.terminate().await;
     }
+
+    #[bpxe_im::test]
+    async fn stuck_join() {
+        let definitions =
+            parse(include_str!("test_models/parallel_not_enough_to_join.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_join_diagnostics_threshold(std::time::Duration::from_millis(50))
+            .spawn()
+            .await;
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+
+        assert!(handle.start().await.is_ok());
+
+        // dead_end never flows, so the join keeps waiting for it
+        assert!(
+            log_mailbox
+                .receive(
+                    |log| matches!(log, Log::StuckJoin { gateway, missing, upstream }
+                        if gateway.as_deref() == Some("join")
+                        && missing == &vec!["Flow_07ilkci".to_string()]
+                        && upstream == &vec!["dead_end".to_string()])
+                )
+                .await
+        );
+
+        model.terminate().await;
+    }
 }
@@ -0,0 +1,389 @@
+//! # History export
+//!
+//! Logs of all process instances of a model (see [`model::Handle::instance_logs`]) make up its
+//! execution history. [`Exporter`] delivers them, one [`Record`] at a time, to a
+//! [`HistorySink`] of choice, such as [`JsonLines`].
+//!
+//! A record the sink has failed to export is retried until it succeeds, and every record
+//! carries its position in the history so that sinks can recognize records they have already
+//! seen. Records are queued in memory while the sink is busy.
+//!
+//! Delivery is not guaranteed, though, as logs travel through bounded buses before they reach
+//! the exporter. Losses are reported rather than silent:
+//!
+//! * If the exporter falls behind the model, positions of the records it has missed are
+//!   skipped and the next record tells how many there were ([`Record::missed`]).
+//! * If a process instance falls behind forwarding its own logs to the model, a record
+//!   of [`Log::LogsMissed`] tells how many of its logs were dropped.
+use crate::model::{self, InstanceLog};
+use crate::process::{InstanceId, Log};
+use crate::sys::task::{self, JoinHandle};
+use crate::sys::time;
+use async_trait::async_trait;
+use futures::io::{AsyncWrite, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// History record
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Position of the record in the history, starting from 1
+    pub position: u64,
+    /// Number of records missed right before this one (their positions are skipped)
+    #[serde(default)]
+    pub missed: u64,
+    /// Process instance the log belongs to
+    pub instance: InstanceId,
+    /// Log
+    pub log: Log,
+}
+
+/// Destination of exported history
+#[async_trait]
+pub trait HistorySink: Send {
+    /// Export error
+    type Error: std::error::Error + Send;
+
+    /// Exports a record
+    ///
+    /// If an error is returned, the same record will be exported again.
+    async fn export(&mut self, record: &Record) -> Result<(), Self::Error>;
+}
+
+/// Sink that writes records as JSON, one per line
+///
+/// If writing a line fails part way, exporting the same record again writes only the rest
+/// of it.
+pub struct JsonLines<W> {
+    writer: W,
+    // position of the record being written and the rest of its line
+    pending: Option<(u64, Vec<u8>)>,
+}
+
+impl<W> JsonLines<W>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    /// Creates a sink writing into `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: None,
+        }
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[async_trait]
+impl<W> HistorySink for JsonLines<W>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    type Error = std::io::Error;
+
+    async fn export(&mut self, record: &Record) -> Result<(), Self::Error> {
+        let line = match self.pending.take() {
+            Some((position, rest)) if position == record.position => rest,
+            _ => {
+                let mut line = serde_json::to_vec(record)?;
+                line.push(b'\n');
+                line
+            }
+        };
+        let (_, rest) = self.pending.insert((record.position, line));
+        while !rest.is_empty() {
+            let written = self.writer.write(rest).await?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            rest.drain(..written);
+        }
+        self.writer.flush().await?;
+        self.pending = None;
+        Ok(())
+    }
+}
+
+/// History exporter
+pub struct Exporter<S> {
+    sink: S,
+    retry_interval: Duration,
+    position: u64,
+}
+
+impl<S> Exporter<S>
+where
+    S: HistorySink + 'static,
+{
+    /// Creates an exporter into `sink`
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            retry_interval: Duration::from_secs(1),
+            position: 0,
+        }
+    }
+
+    /// Consumes exporter and returns it updated with an interval between attempts to export
+    /// a record that has failed
+    ///
+    /// Default is one second.
+    pub fn with_retry_interval(self, retry_interval: Duration) -> Self {
+        Self {
+            retry_interval,
+            ..self
+        }
+    }
+
+    /// Consumes exporter and returns it updated to continue numbering records after
+    /// `position` (for example, the last position known to the sink)
+    pub fn with_position(self, position: u64) -> Self {
+        Self { position, ..self }
+    }
+
+    /// Starts exporting history of a model
+    ///
+    /// Only logs produced after this call are exported.
+    pub fn spawn(self, model: &model::Handle) -> ExporterHandle<S> {
+        let mut logs = model.instance_log_receiver();
+        // logs, or the number of logs missed
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Result<InstanceLog, u64>>();
+        let (stop, mut stopped) = oneshot::channel::<()>();
+        task::spawn(async move {
+            loop {
+                tokio::select! {
+                    log = logs.recv() => {
+                        let log = match log {
+                            Ok(log) => Ok(log),
+                            Err(broadcast::error::RecvError::Lagged(missed)) => Err(missed),
+                            Err(broadcast::error::RecvError::Closed) => return,
+                        };
+                        if sender.send(log).is_err() {
+                            return;
+                        }
+                    }
+                    _ = &mut stopped => return,
+                }
+            }
+        });
+        let position = Arc::new(AtomicU64::new(self.position));
+        let exported = position.clone();
+        let Self {
+            mut sink,
+            retry_interval,
+            position: mut next,
+        } = self;
+        let join_handle = task::spawn(async move {
+            let mut missed = 0;
+            while let Some(log) = receiver.recv().await {
+                let InstanceLog { instance, log } = match log {
+                    Ok(log) => log,
+                    Err(count) => {
+                        missed += count;
+                        next += count;
+                        continue;
+                    }
+                };
+                next += 1;
+                let record = Record {
+                    position: next,
+                    missed: std::mem::take(&mut missed),
+                    instance,
+                    log,
+                };
+                while sink.export(&record).await.is_err() {
+                    time::sleep(retry_interval).await;
+                }
+                exported.store(next, Ordering::SeqCst);
+            }
+            sink
+        });
+        ExporterHandle {
+            position,
+            stop: Some(stop),
+            join_handle,
+        }
+    }
+}
+
+/// Control handle for a running history exporter
+pub struct ExporterHandle<S> {
+    position: Arc<AtomicU64>,
+    stop: Option<oneshot::Sender<()>>,
+    join_handle: JoinHandle<S>,
+}
+
+impl<S> ExporterHandle<S> {
+    /// Returns position of the last exported record
+    pub fn position(&self) -> u64 {
+        self.position.load(Ordering::SeqCst)
+    }
+
+    /// Stops receiving logs, waits until all records received so far are exported and
+    /// returns the sink
+    pub async fn stop(mut self) -> Option<S> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        self.join_handle.await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpmn::parse;
+    use crate::model::Model;
+    use crate::process::Outcome;
+    use bpxe_internal_macros as bpxe_im;
+
+    struct Flaky {
+        failures: usize,
+        records: Vec<Record>,
+    }
+
+    #[async_trait]
+    impl HistorySink for Flaky {
+        type Error = std::io::Error;
+
+        async fn export(&mut self, record: &Record) -> Result<(), Self::Error> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "unavailable",
+                ));
+            }
+            self.records.push(record.clone());
+            Ok(())
+        }
+    }
+
+    #[bpxe_im::test]
+    async fn export() {
+        let definitions = parse(include_str!("process/test_models/data_object.bpmn")).unwrap();
+        let model = Model::new(definitions).spawn().await;
+        let exporter = Exporter::new(Flaky {
+            failures: 2,
+            records: vec![],
+        })
+        .with_retry_interval(Duration::from_millis(10))
+        .with_position(100)
+        .spawn(&model);
+
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        assert_eq!(handle.join().await, Outcome::Completed);
+        // let the last logs reach the exporter
+        time::sleep(Duration::from_millis(100)).await;
+
+        let position = exporter.position();
+        let sink = exporter.stop().await.unwrap();
+        assert_eq!(position, 100 + sink.records.len() as u64);
+        assert!(sink
+            .records
+            .iter()
+            .enumerate()
+            .all(|(i, record)| record.position == 101 + i as u64));
+        assert!(sink
+            .records
+            .iter()
+            .all(|record| record.instance == handle.id()));
+        assert!(matches!(
+            sink.records.last().unwrap().log,
+            Log::Done {
+                outcome: Outcome::Completed,
+                ..
+            }
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn json_lines() {
+        let mut sink = JsonLines::new(vec![]);
+        let record = Record {
+            position: 1,
+            missed: 0,
+            instance: InstanceId(0),
+            log: Log::TokenInjected {
+                node: "task".into(),
+            },
+        };
+        assert!(sink.export(&record).await.is_ok());
+        assert!(sink.export(&record).await.is_ok());
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let parsed: Record = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed.position, 1);
+        assert!(matches!(parsed.log, Log::TokenInjected { node } if node == "task"));
+    }
+
+    // Writer that accepts a few bytes at a time and fails once along the way
+    struct Choppy {
+        written: Vec<u8>,
+        failed: bool,
+    }
+
+    impl AsyncWrite for Choppy {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            if !self.written.is_empty() && !self.failed {
+                self.failed = true;
+                return std::task::Poll::Ready(Err(std::io::ErrorKind::Interrupted.into()));
+            }
+            let len = buf.len().min(8);
+            self.written.extend_from_slice(&buf[..len]);
+            std::task::Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[bpxe_im::test]
+    async fn json_lines_partial_write() {
+        let mut sink = JsonLines::new(Choppy {
+            written: vec![],
+            failed: false,
+        });
+        let record = Record {
+            position: 1,
+            missed: 0,
+            instance: InstanceId(0),
+            log: Log::TokenInjected {
+                node: "task".into(),
+            },
+        };
+        assert!(sink.export(&record).await.is_err());
+        // the retry finishes the line instead of starting over
+        assert!(sink.export(&record).await.is_ok());
+        let output = String::from_utf8(sink.into_inner().written).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let parsed: Record = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed.position, 1);
+    }
+}
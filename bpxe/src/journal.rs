@@ -0,0 +1,693 @@
+//! # Journal
+//!
+//! Journal records external stimuli sent to a process so that they can later be replayed, step
+//! by step, into a fresh process. Since replay can be stopped at any entry, this allows
+//! inspecting the state of the process at any point of its history.
+//!
+//! Stimuli are recorded when they go through the journal rather than the process handle:
+//! starts (with everything the [`StartBuilder`] carries), events, variables set from outside,
+//! modifications, completions of user and manual tasks and results of service handlers
+//! registered through [`Journal::register_service`]. Variables are recorded as JSON (see
+//! [`data_object::to_json`]) and replayed as JSON values; stimuli carrying variables that
+//! can't be represented this way are rejected without being sent.
+//!
+//! Replay registers its own handlers for the topics of recorded service results, which
+//! return these results instead of calling the services again, in the order the original
+//! calls have completed in. User task completions are replayed into the oldest open task of
+//! the same user task.
+use crate::activity::manual_task::ManualTaskError;
+use crate::activity::service_task::{ServiceError, ServiceResult};
+use crate::data_object::{self, DataObject};
+use crate::event::ProcessEvent;
+use crate::process::{
+    self, DataObjectError, Modification, ModificationBuilder, ModificationError, Priority,
+    StartBuilder, StartError, StartRequest, Variables,
+};
+use crate::tasks::{TaskError, TaskId};
+use futures::future::Future;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+/// Variables as recorded in a journal
+pub type JsonVariables = HashMap<String, serde_json::Value>;
+
+/// Journal entry
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Entry {
+    /// Process start was requested
+    Start(StartEntry),
+    /// Event was sent to the process
+    Event(ProcessEvent),
+    /// Variable was set
+    SetVariable(String, serde_json::Value),
+    /// Process instance was modified
+    Modify(Vec<Change>),
+    /// User task was completed
+    UserTaskCompleted {
+        /// Identifier of the user task
+        element: Option<String>,
+        variables: JsonVariables,
+    },
+    /// Manual task was completed
+    ManualTaskCompleted(String),
+    /// Service handler has completed
+    ServiceCompleted {
+        topic: String,
+        result: Result<JsonVariables, ServiceError>,
+    },
+}
+
+/// Recorded process start (see [`StartRequest`])
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StartEntry {
+    pub variables: JsonVariables,
+    pub business_key: Option<String>,
+    pub priority: Option<Priority>,
+    pub start_event: Option<String>,
+    pub start_before: Option<String>,
+}
+
+/// Recorded modification (see [`Modification`])
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Change {
+    Cancel(String),
+    StartBefore(String),
+    TakeSequenceFlow(String),
+    SetVariable(String, serde_json::Value),
+    Skip(String),
+}
+
+/// Journal error
+#[derive(Error, Debug, PartialEq)]
+pub enum JournalError {
+    /// Variable can't be represented as JSON, so it can't be recorded
+    #[error("variable {0} can't be represented as JSON")]
+    Unrepresentable(String),
+    /// Process failed to start
+    #[error("can't start process: {0}")]
+    Start(#[from] StartError),
+    /// Variable failed to be set
+    #[error("can't set variable: {0}")]
+    DataObject(#[from] DataObjectError),
+    /// Modification failed
+    #[error("can't modify process: {0}")]
+    Modification(#[from] ModificationError),
+    /// User task failed to be completed
+    #[error("can't complete user task: {0}")]
+    Task(#[from] TaskError),
+    /// Manual task failed to be completed
+    #[error("can't complete manual task: {0}")]
+    ManualTask(#[from] ManualTaskError),
+}
+
+/// Journal of process stimuli
+///
+/// Clones share their entries, so that a journal can be recorded into from handlers of
+/// services.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "Entries", into = "Entries")]
+pub struct Journal {
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+// serialized form of a journal
+#[derive(Serialize, Deserialize)]
+struct Entries {
+    entries: Vec<Entry>,
+}
+
+impl From<Entries> for Journal {
+    fn from(entries: Entries) -> Self {
+        entries.entries.into()
+    }
+}
+
+impl From<Journal> for Entries {
+    fn from(journal: Journal) -> Self {
+        Self {
+            entries: journal.entries(),
+        }
+    }
+}
+
+fn json_variables(variables: &Variables) -> Result<JsonVariables, JournalError> {
+    variables
+        .iter()
+        .map(|(id, value)| Ok((id.clone(), json_value(id, &**value)?)))
+        .collect()
+}
+
+fn json_value(id: &str, value: &dyn DataObject) -> Result<serde_json::Value, JournalError> {
+    data_object::to_json(value).ok_or_else(|| JournalError::Unrepresentable(id.to_string()))
+}
+
+fn from_json_variables(variables: JsonVariables) -> Variables {
+    variables
+        .into_iter()
+        .map(|(id, value)| (id, Box::new(value) as Box<dyn DataObject>))
+        .collect()
+}
+
+impl Journal {
+    /// Creates an empty journal
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all recorded entries
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Records an entry
+    pub fn record(&self, entry: Entry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    /// Records an entry before sending the stimulus it describes, retracting it if the
+    /// stimulus fails
+    ///
+    /// The entry has to precede whatever the process does in response, such as completions
+    /// of services it calls.
+    async fn journaled<F, E>(&self, entry: Entry, stimulus: F) -> Result<(), JournalError>
+    where
+        F: Future<Output = Result<(), E>>,
+        JournalError: From<E>,
+    {
+        self.record(entry.clone());
+        let result = stimulus.await;
+        if result.is_err() {
+            let mut entries = self.entries.lock().unwrap();
+            // entries equal to this one are interchangeable, so the latest one is retracted
+            if let Some(position) = entries.iter().rposition(|e| e == &entry) {
+                entries.remove(position);
+            }
+        }
+        result.map_err(JournalError::from)
+    }
+
+    /// Requests process start and records it
+    ///
+    /// Only successful start requests are recorded.
+    pub async fn start(&self, process: &process::Handle) -> Result<(), JournalError> {
+        self.start_with(process.start_with()).await
+    }
+
+    /// Starts the process with a start builder (see [`process::Handle::start_with`]) and
+    /// records it along with variables, business key, priority, start event and flow node
+    /// the process is started before
+    ///
+    /// Only successful start requests are recorded.
+    pub async fn start_with(&self, builder: StartBuilder<'_>) -> Result<(), JournalError> {
+        let request = &builder.request;
+        let entry = Entry::Start(StartEntry {
+            variables: json_variables(&request.variables)?,
+            business_key: request.business_key.clone(),
+            priority: request.priority,
+            start_event: request.start_event.clone(),
+            start_before: request.start_before.clone(),
+        });
+        self.journaled(entry, builder.start()).await
+    }
+
+    /// Sends an event to the process and records it
+    pub fn send(&self, process: &process::Handle, event: ProcessEvent) {
+        self.record(Entry::Event(event.clone()));
+        process.send_event(event);
+    }
+
+    /// Sets a variable (see [`process::Handle::set_variable`]) and records it
+    pub async fn set_variable(
+        &self,
+        process: &process::Handle,
+        id: &str,
+        value: Box<dyn DataObject>,
+    ) -> Result<(), JournalError> {
+        let entry = Entry::SetVariable(id.to_string(), json_value(id, &*value)?);
+        self.journaled(entry, process.set_variable(id, value)).await
+    }
+
+    /// Applies modifications (see [`process::Handle::modify`]) and records them
+    ///
+    /// Only modifications that have been applied are recorded.
+    pub async fn modify(&self, builder: ModificationBuilder<'_>) -> Result<(), JournalError> {
+        let changes = builder
+            .modifications
+            .iter()
+            .map(|modification| {
+                Ok(match modification {
+                    Modification::Cancel(node) => Change::Cancel(node.clone()),
+                    Modification::StartBefore(node) => Change::StartBefore(node.clone()),
+                    Modification::TakeSequenceFlow(seq_flow) => {
+                        Change::TakeSequenceFlow(seq_flow.clone())
+                    }
+                    Modification::SetVariable(id, value) => {
+                        Change::SetVariable(id.clone(), json_value(id, &**value)?)
+                    }
+                    Modification::Skip(activity) => Change::Skip(activity.clone()),
+                })
+            })
+            .collect::<Result<Vec<_>, JournalError>>()?;
+        self.journaled(Entry::Modify(changes), builder.apply())
+            .await
+    }
+
+    /// Completes a user task of the process (see [`crate::tasks::UserTasks::complete`]) and
+    /// records it
+    pub async fn complete_user_task(
+        &self,
+        process: &process::Handle,
+        id: TaskId,
+        variables: Variables,
+    ) -> Result<(), JournalError> {
+        let user_tasks = process.model().user_tasks();
+        let task = user_tasks
+            .list()
+            .into_iter()
+            .find(|task| task.id == id && task.instance == process.id())
+            .ok_or(TaskError::NotFound(id))?;
+        let entry = Entry::UserTaskCompleted {
+            element: task.element,
+            variables: json_variables(&variables)?,
+        };
+        self.journaled(entry, async { user_tasks.complete(id, variables) })
+            .await
+    }
+
+    /// Completes a manual task (see [`process::Handle::complete_manual_task`]) and records it
+    pub async fn complete_manual_task(
+        &self,
+        process: &process::Handle,
+        node: &str,
+    ) -> Result<(), JournalError> {
+        let entry = Entry::ManualTaskCompleted(node.to_string());
+        self.journaled(entry, async { process.complete_manual_task(node) })
+            .await
+    }
+
+    /// Registers a handler of service tasks (see [`process::Handle::register_service`]),
+    /// recording its results
+    ///
+    /// Results holding variables that can't be represented as JSON are turned into
+    /// [`ServiceError::InvalidOutput`], so that the process sees what is recorded.
+    pub fn register_service<S, F, Fut>(&self, process: &process::Handle, topic: S, handler: F)
+    where
+        S: Into<String>,
+        F: Fn(Variables) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ServiceResult> + Send + 'static,
+    {
+        let topic = topic.into();
+        let journal = self.clone();
+        process.register_service(topic.clone(), move |variables| {
+            let result = handler(variables);
+            let journal = journal.clone();
+            let topic = topic.clone();
+            async move {
+                let (result, recorded) = match result.await {
+                    Ok(variables) => match json_variables(&variables) {
+                        Ok(recorded) => (Ok(variables), Ok(recorded)),
+                        Err(err) => {
+                            let err = ServiceError::InvalidOutput(err.to_string());
+                            (Err(err.clone()), Err(err))
+                        }
+                    },
+                    Err(err) => (Err(err.clone()), Err(err)),
+                };
+                journal.record(Entry::ServiceCompleted {
+                    topic,
+                    result: recorded,
+                });
+                result
+            }
+        });
+    }
+}
+
+impl From<Vec<Entry>> for Journal {
+    fn from(entries: Vec<Entry>) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(entries)),
+        }
+    }
+}
+
+/// Replay error
+#[derive(Error, Debug, PartialEq)]
+pub enum ReplayError {
+    /// Process failed to start
+    #[error("can't start process: {0}")]
+    Start(#[from] StartError),
+    /// Variable failed to be set
+    #[error("can't set variable: {0}")]
+    DataObject(#[from] DataObjectError),
+    /// Modification failed
+    #[error("can't modify process: {0}")]
+    Modification(#[from] ModificationError),
+    /// User task failed to be completed
+    #[error("can't complete user task: {0}")]
+    Task(#[from] TaskError),
+    /// Manual task failed to be completed
+    #[error("can't complete manual task: {0}")]
+    ManualTask(#[from] ManualTaskError),
+    /// No task of the user task is open to be completed
+    #[error("no open task of user task {0:?}")]
+    NoOpenTask(Option<String>),
+}
+
+/// Step-by-step journal replay
+pub struct Replay {
+    entries: Vec<Entry>,
+    position: usize,
+    process: process::Handle,
+    // recorded results of services, by topic
+    services: HashMap<String, mpsc::UnboundedSender<ServiceResult>>,
+}
+
+/// Registers a handler that returns replayed results of a service instead of calling it
+fn replay_service(process: &process::Handle, topic: &str) -> mpsc::UnboundedSender<ServiceResult> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+    let topic = topic.to_string();
+    process.register_service(topic.clone(), move |_| {
+        let receiver = receiver.clone();
+        let topic = topic.clone();
+        async move {
+            match receiver.lock().await.recv().await {
+                Some(result) => result,
+                // the replay is over, no more results will come
+                None => Err(ServiceError::NotRegistered(topic)),
+            }
+        }
+    });
+    sender
+}
+
+impl Replay {
+    /// Creates a new replay of `journal` into `process`
+    ///
+    /// `process` is expected to be freshly spawned and not started. Handlers of services
+    /// with recorded results are registered with it, replacing those registered before.
+    pub fn new(journal: Journal, process: process::Handle) -> Self {
+        let entries = journal.entries();
+        let mut services = HashMap::new();
+        for entry in &entries {
+            if let Entry::ServiceCompleted { topic, .. } = entry {
+                if !services.contains_key(topic) {
+                    services.insert(topic.clone(), replay_service(&process, topic));
+                }
+            }
+        }
+        Self {
+            entries,
+            position: 0,
+            process,
+            services,
+        }
+    }
+
+    /// Returns the number of entries replayed so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the next entry to be replayed, if any
+    pub fn peek(&self) -> Option<&Entry> {
+        self.entries.get(self.position)
+    }
+
+    /// Returns `true` if all entries have been replayed
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.entries.len()
+    }
+
+    /// Returns the handle of the process being replayed into
+    pub fn process(&self) -> &process::Handle {
+        &self.process
+    }
+
+    /// Replays the next entry
+    ///
+    /// Waits for the process to settle before returning, so that the next entry isn't
+    /// replayed while the process is still reacting to this one.
+    ///
+    /// Returns `Ok(false)` if there were no entries left.
+    pub async fn step(&mut self) -> Result<bool, ReplayError> {
+        match self.entries.get(self.position).cloned() {
+            None => Ok(false),
+            Some(entry) => {
+                self.position += 1;
+                self.replay(entry).await?;
+                self.process.settled().await;
+                Ok(true)
+            }
+        }
+    }
+
+    async fn replay(&self, entry: Entry) -> Result<(), ReplayError> {
+        match entry {
+            Entry::Start(start) => {
+                let request = StartRequest {
+                    variables: from_json_variables(start.variables),
+                    business_key: start.business_key,
+                    priority: start.priority,
+                    start_event: start.start_event,
+                    start_before: start.start_before,
+                };
+                self.process.start_request(request).start().await?;
+            }
+            Entry::Event(event) => self.process.send_event(event),
+            Entry::SetVariable(id, value) => {
+                self.process.set_variable(&id, Box::new(value)).await?;
+            }
+            Entry::Modify(changes) => {
+                let mut builder = self.process.modify();
+                for change in changes {
+                    builder = match change {
+                        Change::Cancel(node) => builder.cancel(node),
+                        Change::StartBefore(node) => builder.start_before(node),
+                        Change::TakeSequenceFlow(seq_flow) => builder.take_sequence_flow(seq_flow),
+                        Change::SetVariable(id, value) => builder.variable(id, Box::new(value)),
+                        Change::Skip(activity) => builder.skip(activity),
+                    };
+                }
+                builder.apply().await?;
+            }
+            Entry::UserTaskCompleted { element, variables } => {
+                let user_tasks = self.process.model().user_tasks();
+                // tasks are listed in the order they were opened
+                let task = user_tasks
+                    .list()
+                    .into_iter()
+                    .find(|task| task.instance == self.process.id() && task.element == element);
+                match task {
+                    Some(task) => user_tasks.complete(task.id, from_json_variables(variables))?,
+                    None => return Err(ReplayError::NoOpenTask(element)),
+                }
+            }
+            Entry::ManualTaskCompleted(node) => self.process.complete_manual_task(&node)?,
+            Entry::ServiceCompleted { topic, result } => {
+                if let Some(service) = self.services.get(&topic) {
+                    let _ = service.send(result.map(from_json_variables));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replays entries until `position` entries have been replayed (or the journal is exhausted)
+    pub async fn replay_until(&mut self, position: usize) -> Result<(), ReplayError> {
+        while self.position < position && self.step().await? {}
+        Ok(())
+    }
+
+    /// Replays all remaining entries
+    pub async fn replay_all(&mut self) -> Result<(), ReplayError> {
+        while self.step().await? {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpmn::parse;
+    use crate::data_object::Container;
+    use crate::model;
+    use crate::process::Log;
+    use crate::sys::task;
+    use crate::test::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    async fn replay_step_by_step() {
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+
+        // Record
+        let model = model::Model::new(definitions.clone()).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        let journal = Journal::new();
+        assert!(journal.start(&handle).await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+        journal.send(
+            &handle,
+            ProcessEvent::SignalEvent {
+                signal_ref: Some("signal".into()),
+            },
+        );
+        model.terminate().await;
+
+        assert_eq!(journal.entries().len(), 2);
+
+        // Replay into a fresh model
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        let mut replay = Replay::new(journal, handle);
+
+        assert_eq!(replay.peek(), Some(&Entry::Start(StartEntry::default())));
+        assert_eq!(replay.step().await, Ok(true));
+        // the step is over only once the process has reacted to it
+        assert_eq!(
+            replay.process().inspect().await.unwrap().flow_nodes["catch"].active_tokens,
+            1
+        );
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        // Stopped before the signal, nothing should be reported
+        assert!(expects_timeout(mailbox.receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))).await.is_ok());
+
+        assert!(replay.replay_all().await.is_ok());
+        assert!(replay.is_finished());
+        assert!(mailbox
+            .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+            .await);
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn replay_start_and_service_results() {
+        let definitions = parse(include_str!("activity/test_models/task_service.bpmn")).unwrap();
+
+        // Record
+        let model = model::Model::new(definitions.clone()).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let journal = Journal::new();
+        journal.register_service(&handle, "charge", |_| async {
+            let mut variables = Variables::new();
+            variables.insert("result".into(), Box::new(Container(42i64)));
+            Ok(variables)
+        });
+        assert!(journal
+            .start_with(handle.start_with().business_key("order-1"))
+            .await
+            .is_ok());
+        assert!(timeout(handle.result()).await.unwrap().is_ok());
+        model.terminate().await;
+
+        let mut result = JsonVariables::new();
+        result.insert("result".into(), 42.into());
+        assert_eq!(
+            journal.entries(),
+            vec![
+                Entry::Start(StartEntry {
+                    business_key: Some("order-1".into()),
+                    ..Default::default()
+                }),
+                Entry::ServiceCompleted {
+                    topic: "charge".into(),
+                    result: Ok(result),
+                }
+            ]
+        );
+
+        // Replay into a fresh model, through serialization
+        let journal: Journal =
+            serde_json::from_str(&serde_json::to_string(&journal).unwrap()).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut replay = Replay::new(journal, handle.clone());
+        assert_eq!(replay.replay_until(1).await, Ok(()));
+        assert_eq!(handle.business_key().as_deref(), Some("order-1"));
+        // the service is waiting for its recorded result
+        assert!(expects_timeout(handle.result()).await.is_ok());
+
+        assert_eq!(replay.replay_all().await, Ok(()));
+        let variables = timeout(handle.result()).await.unwrap().unwrap();
+        assert_eq!(
+            variables
+                .get("result")
+                .and_then(|v| v.downcast_ref::<serde_json::Value>()),
+            Some(&serde_json::Value::from(42))
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn replay_user_task() {
+        let definitions = parse(include_str!("activity/test_models/task_user.bpmn")).unwrap();
+
+        // Record
+        let model = model::Model::new(definitions.clone()).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let journal = Journal::new();
+        assert!(journal.start(&handle).await.is_ok());
+        let user_tasks = model.user_tasks();
+        while user_tasks.list().is_empty() {
+            task::yield_now().await;
+        }
+        let task = user_tasks.list().pop().unwrap();
+
+        // variables that can't be recorded are rejected before anything is sent
+        assert_eq!(
+            journal
+                .set_variable(&handle, "approved", Box::new(Container(1u8)))
+                .await,
+            Err(JournalError::Unrepresentable("approved".into()))
+        );
+        assert_eq!(journal.entries().len(), 1);
+
+        let mut variables = Variables::new();
+        variables.insert("approved".into(), Box::new(Container(true)));
+        assert!(journal
+            .complete_user_task(&handle, task.id, variables)
+            .await
+            .is_ok());
+        assert!(timeout(handle.result()).await.unwrap().is_ok());
+        model.terminate().await;
+
+        let mut approved = JsonVariables::new();
+        approved.insert("approved".into(), true.into());
+        assert_eq!(
+            journal.entries()[1],
+            Entry::UserTaskCompleted {
+                element: Some("approve".into()),
+                variables: approved,
+            }
+        );
+
+        // Replay into a fresh model
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut replay = Replay::new(journal, handle.clone());
+        assert_eq!(replay.replay_all().await, Ok(()));
+        let variables = timeout(handle.result()).await.unwrap().unwrap();
+        assert_eq!(
+            variables
+                .get("approved")
+                .and_then(|v| v.downcast_ref::<serde_json::Value>()),
+            Some(&serde_json::Value::Bool(true))
+        );
+        assert!(model.user_tasks().list().is_empty());
+        model.terminate().await;
+    }
+}
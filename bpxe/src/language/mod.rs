@@ -75,13 +75,20 @@ pub enum EvaluationError {
     #[error("empty expression or script")]
     Empty,
     #[error("execution error {0:?}")]
-    ExecutionError(ExecutionError),
+    ExecutionError(#[source] ExecutionError),
 }
 
-#[derive(Debug)]
+/// Execution error
+#[derive(Error, Debug)]
 pub enum ExecutionError {
+    /// Evaluation has been cancelled
+    #[error("evaluation has been cancelled")]
     Cancelled,
+    /// Evaluation has panicked
+    #[error("evaluation has panicked")]
     Panicked,
+    /// Evaluation has failed for an unknown reason
+    #[error("evaluation has failed for an unknown reason")]
     UnknownCause,
 }
 
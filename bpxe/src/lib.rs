@@ -15,12 +15,20 @@
 pub mod activity;
 pub mod bpmn;
 pub mod data_object;
+pub mod decision;
+pub mod error;
 pub mod event;
 pub mod flow_node;
 pub mod gateway;
+pub mod history;
+pub mod journal;
 pub mod language;
 pub mod model;
 pub mod process;
+pub mod simulation;
+pub mod tasks;
+pub mod timer;
+pub mod validation;
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 pub mod wasm;
 
@@ -30,8 +38,8 @@ pub(crate) mod sys;
 #[cfg(all(test, target_arch = "wasm32", target_os = "unknown"))]
 wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
-#[cfg(test)]
-mod test;
+#[cfg(any(test, feature = "testing"))]
+pub mod test;
 
 #[allow(unused_imports)]
 pub(crate) use wasm_rs_dbg::dbg;
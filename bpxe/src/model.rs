@@ -2,16 +2,28 @@
 //!
 //! Model is a central entrypoint to BPMN execution. It contains all definitions of a BPMN document
 //! and orchestrates process instantiation and execution.
-use crate::bpmn::schema::{Definitions, RootElement};
+use crate::bpmn::schema::{Definitions, Process, RootElement};
+use crate::data_object::DataObject;
+use crate::decision::DecisionEvaluator;
+use crate::event::Envelope;
+use crate::gateway;
 use crate::language::MultiLanguageEngine;
 use crate::process;
+use crate::tasks;
+use crate::timer::{Calendar, Timezone};
+use crate::validation;
 use factory::Factory;
 use futures::future::join_all;
+use futures::stream::Stream;
+use serde::{Deserialize, Serialize};
 
 use crate::sys::task::{self, JoinHandle};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, OwnedSemaphorePermit, Semaphore};
 
 /// Model error
 #[derive(Error, Debug)]
@@ -22,6 +34,9 @@ pub enum Error {
         #[from]
         error: oneshot::error::RecvError,
     },
+    /// There is no process with this identifier in the model
+    #[error("unknown process {0}")]
+    UnknownProcess(String),
 }
 
 /// Script engine factory
@@ -68,6 +83,12 @@ where
 {
 }
 
+/// Milestone listener
+///
+/// Invoked with the process and the identifier of the reached intermediate throw event
+/// whenever an intermediate throw event without event definitions is reached.
+pub type MilestoneListener = Arc<dyn Fn(&process::Handle, &str) + Send + Sync>;
+
 /// Model is a container for a BPMN document
 pub struct Model<ScriptEngine, ExpressionEngine>
 where
@@ -78,26 +99,267 @@ where
     processes: Vec<process::Handle>,
     script_engine_factory: Option<ScriptEngine>,
     expression_engine_factory: Option<ExpressionEngine>,
+    milestone_listener: Option<MilestoneListener>,
+    business_calendar: Option<Arc<dyn Calendar>>,
+    default_timezone: Timezone,
+    activity_time_limits: Arc<HashMap<String, Duration>>,
+    concurrency_limits: Arc<HashMap<String, Arc<Semaphore>>>,
+    failure_policies: Arc<HashMap<String, process::FailurePolicy>>,
+    retry_policies: Arc<HashMap<String, process::RetryPolicy>>,
+    task_timeouts: Arc<HashMap<String, (Duration, process::TimeoutAction)>>,
+    gateway_priorities: Arc<HashMap<String, gateway::Priority>>,
+    result_variables: Arc<HashMap<String, String>>,
+    decision_refs: Arc<HashMap<String, String>>,
+    decision_evaluator: Option<Arc<dyn DecisionEvaluator>>,
+    forms: Arc<HashMap<String, tasks::Form>>,
+    budget: process::Budget,
+    max_active_instances: Option<Arc<Semaphore>>,
+    admission: Admission,
+    drop_policy: DropPolicy,
+    join_diagnostics_threshold: Option<Duration>,
+    execution_listeners: process::ExecutionListeners,
+    action_middleware: Arc<Vec<Arc<dyn process::ActionMiddleware>>>,
+}
+
+/// What to do with process starts once a limit of active instances is reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// Wait until enough active instances are over
+    Queue,
+    /// Fail with [`process::StartError::Overloaded`]
+    Reject,
+}
+
+impl Default for Admission {
+    fn default() -> Self {
+        Admission::Queue
+    }
 }
 
 /// Control handle for a running model
 #[derive(Clone)]
 pub struct Handle {
     definitions: Arc<Definitions>,
+    // process => its definition, shared by instances spawned on request
+    process_definitions: Arc<HashMap<String, Arc<Process>>>,
     sender: mpsc::Sender<Request>,
     log_broadcast: broadcast::Sender<Log>,
+    instance_event_bus: broadcast::Sender<Envelope>,
+    instance_log_bus: broadcast::Sender<InstanceLog>,
     script_engine_factory: Arc<Box<dyn LanguageEngineFactory>>,
     expression_engine_factory: Arc<Box<dyn LanguageEngineFactory>>,
+    milestone_listener: Option<MilestoneListener>,
+    business_calendar: Option<Arc<dyn Calendar>>,
+    default_timezone: Timezone,
+    activity_time_limits: Arc<HashMap<String, Duration>>,
+    concurrency_limits: Arc<HashMap<String, Arc<Semaphore>>>,
+    failure_policies: Arc<HashMap<String, process::FailurePolicy>>,
+    retry_policies: Arc<HashMap<String, process::RetryPolicy>>,
+    task_timeouts: Arc<HashMap<String, (Duration, process::TimeoutAction)>>,
+    gateway_priorities: Arc<HashMap<String, gateway::Priority>>,
+    result_variables: Arc<HashMap<String, String>>,
+    decision_refs: Arc<HashMap<String, String>>,
+    decision_evaluator: Option<Arc<dyn DecisionEvaluator>>,
+    forms: Arc<HashMap<String, tasks::Form>>,
+    budget: process::Budget,
+    max_active_instances: Option<Arc<Semaphore>>,
+    admission: Admission,
+    join_diagnostics_threshold: Option<Duration>,
+    execution_listeners: process::ExecutionListeners,
+    action_middleware: Arc<Vec<Arc<dyn process::ActionMiddleware>>>,
+    queued_starts: Arc<AtomicUsize>,
+    priorities: Arc<process::priority::Registry>,
+    diagnostics: Arc<Vec<validation::Diagnostic>>,
+    user_tasks: tasks::UserTasks,
+    // `None` for handles used by the model internally
+    drop_guard: Option<Arc<DropGuard>>,
+    timer_pause: Arc<watch::Sender<bool>>,
+    timers_paused: watch::Receiver<bool>,
+    clock: Arc<watch::Sender<()>>,
+    clock_adjustments: watch::Receiver<()>,
 }
 
 /// Model events
 #[derive(Clone, Debug)]
 pub enum Log {}
 
+/// Process log attributed to its process instance (see [`Handle::instance_logs`])
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstanceLog {
+    /// Process instance the log belongs to
+    pub instance: process::InstanceId,
+    /// Log
+    pub log: process::Log,
+}
+
 enum Request {
     JoinHandle(JoinHandle<()>),
     Terminate(oneshot::Sender<Option<JoinHandle<()>>>),
     Processes(oneshot::Sender<Vec<process::Handle>>),
+    Adopt(Vec<process::Handle>),
+    Instance(
+        process::InstanceId,
+        oneshot::Sender<Option<process::Handle>>,
+    ),
+    Instances(oneshot::Sender<Vec<process::Handle>>),
+    Terminated(process::InstanceId),
+    Abort,
+}
+
+/// Variable predicate of an [`InstanceQuery`]
+pub type VariablePredicate = Arc<dyn Fn(&dyn DataObject) -> bool + Send + Sync>;
+
+/// Process instance query (see [`Handle::query_instances`])
+///
+/// Instance matches the query if it satisfies all of its filters.
+#[derive(Default, Clone)]
+pub struct InstanceQuery {
+    business_key: Option<String>,
+    process: Option<String>,
+    state: Option<process::InstanceState>,
+    started_after: Option<SystemTime>,
+    started_before: Option<SystemTime>,
+    variables: Vec<(String, VariablePredicate)>,
+}
+
+impl InstanceQuery {
+    /// Creates a query that matches all instances
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches instances with a given business key
+    pub fn business_key<S>(self, business_key: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            business_key: Some(business_key.into()),
+            ..self
+        }
+    }
+
+    /// Matches instances of a process with a given identifier
+    pub fn process<S>(self, process: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            process: Some(process.into()),
+            ..self
+        }
+    }
+
+    /// Matches instances in a given state
+    pub fn state(self, state: process::InstanceState) -> Self {
+        Self {
+            state: Some(state),
+            ..self
+        }
+    }
+
+    /// Matches instances started at or after a given time
+    pub fn started_after(self, time: SystemTime) -> Self {
+        Self {
+            started_after: Some(time),
+            ..self
+        }
+    }
+
+    /// Matches instances started before a given time
+    pub fn started_before(self, time: SystemTime) -> Self {
+        Self {
+            started_before: Some(time),
+            ..self
+        }
+    }
+
+    /// Matches instances whose variable (data object) satisfies a predicate
+    pub fn variable<S, F>(mut self, id: S, predicate: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&dyn DataObject) -> bool + Send + Sync + 'static,
+    {
+        self.variables.push((id.into(), Arc::new(predicate)));
+        self
+    }
+
+    async fn matches(&self, instance: &process::Handle) -> bool {
+        if self.business_key.is_some() && instance.business_key() != self.business_key {
+            return false;
+        }
+        if self.process.is_some() && instance.element().id != self.process {
+            return false;
+        }
+        if matches!(self.state, Some(state) if state != instance.state()) {
+            return false;
+        }
+        if self.started_after.is_some() || self.started_before.is_some() {
+            match instance.started_at() {
+                Some(started_at) => {
+                    if matches!(self.started_after, Some(time) if started_at < time)
+                        || matches!(self.started_before, Some(time) if started_at >= time)
+                    {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+        for (id, predicate) in self.variables.iter() {
+            match instance.data_object(id).await {
+                Ok(container) => {
+                    if !predicate(&**container.read().await) {
+                        return false;
+                    }
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+/// What happens to a running model once all of its handles are dropped
+///
+/// Only handles obtained from [`Model::spawn`] (and their clones) count: handles returned by
+/// [`process::Handle::model`] for processes spawned by the model don't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Model and its process instances keep running
+    Continue,
+    /// Process instances are terminated (reaching [`process::Outcome::Terminated`])
+    Terminate,
+    /// Process instances are stopped immediately, without reaching any terminal outcome
+    /// (so they are observed as [`process::Outcome::Cancelled`])
+    Abort,
+}
+
+impl Default for DropPolicy {
+    fn default() -> Self {
+        DropPolicy::Continue
+    }
+}
+
+struct DropGuard {
+    sender: mpsc::Sender<Request>,
+    policy: DropPolicy,
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        let request = match self.policy {
+            DropPolicy::Continue => return,
+            DropPolicy::Terminate => Request::Terminate(oneshot::channel().0),
+            DropPolicy::Abort => Request::Abort,
+        };
+        if let Err(mpsc::error::TrySendError::Full(request)) = self.sender.try_send(request) {
+            let sender = self.sender.clone();
+            task::spawn(async move {
+                let _ = sender.send(request).await;
+            });
+        }
+    }
 }
 
 impl Model<DefaultLanguageEngineFactory, DefaultLanguageEngineFactory> {
@@ -110,6 +372,26 @@ impl Model<DefaultLanguageEngineFactory, DefaultLanguageEngineFactory> {
             processes: vec![],
             script_engine_factory: Some(DefaultLanguageEngineFactory),
             expression_engine_factory: Some(DefaultLanguageEngineFactory),
+            milestone_listener: None,
+            business_calendar: None,
+            default_timezone: Timezone::UTC,
+            activity_time_limits: Default::default(),
+            concurrency_limits: Default::default(),
+            failure_policies: Default::default(),
+            retry_policies: Default::default(),
+            task_timeouts: Default::default(),
+            gateway_priorities: Default::default(),
+            result_variables: Default::default(),
+            decision_refs: Default::default(),
+            decision_evaluator: None,
+            forms: Default::default(),
+            budget: Default::default(),
+            max_active_instances: None,
+            admission: Admission::default(),
+            drop_policy: DropPolicy::default(),
+            join_diagnostics_threshold: None,
+            execution_listeners: Default::default(),
+            action_middleware: Default::default(),
         }
     }
 }
@@ -132,6 +414,26 @@ where
             expression_engine_factory: self.expression_engine_factory,
             definitions: self.definitions,
             processes: self.processes,
+            milestone_listener: self.milestone_listener,
+            business_calendar: self.business_calendar,
+            default_timezone: self.default_timezone,
+            activity_time_limits: self.activity_time_limits,
+            concurrency_limits: self.concurrency_limits,
+            failure_policies: self.failure_policies,
+            retry_policies: self.retry_policies,
+            task_timeouts: self.task_timeouts,
+            gateway_priorities: self.gateway_priorities,
+            result_variables: self.result_variables,
+            decision_refs: self.decision_refs,
+            decision_evaluator: self.decision_evaluator,
+            forms: self.forms,
+            budget: self.budget,
+            max_active_instances: self.max_active_instances,
+            admission: self.admission,
+            drop_policy: self.drop_policy,
+            join_diagnostics_threshold: self.join_diagnostics_threshold,
+            execution_listeners: self.execution_listeners,
+            action_middleware: self.action_middleware,
         }
     }
 
@@ -148,27 +450,371 @@ where
             expression_engine_factory: Some(expression_engine_factory),
             definitions: self.definitions,
             processes: self.processes,
+            milestone_listener: self.milestone_listener,
+            business_calendar: self.business_calendar,
+            default_timezone: self.default_timezone,
+            activity_time_limits: self.activity_time_limits,
+            concurrency_limits: self.concurrency_limits,
+            failure_policies: self.failure_policies,
+            retry_policies: self.retry_policies,
+            task_timeouts: self.task_timeouts,
+            gateway_priorities: self.gateway_priorities,
+            result_variables: self.result_variables,
+            decision_refs: self.decision_refs,
+            decision_evaluator: self.decision_evaluator,
+            forms: self.forms,
+            budget: self.budget,
+            max_active_instances: self.max_active_instances,
+            admission: self.admission,
+            drop_policy: self.drop_policy,
+            join_diagnostics_threshold: self.join_diagnostics_threshold,
+            execution_listeners: self.execution_listeners,
+            action_middleware: self.action_middleware,
         }
     }
 
+    /// Consumes model and returns it updated with a milestone listener
+    pub fn with_milestone_listener<F>(self, listener: F) -> Self
+    where
+        F: Fn(&process::Handle, &str) + Send + Sync + 'static,
+    {
+        Self {
+            milestone_listener: Some(Arc::new(listener)),
+            ..self
+        }
+    }
+
+    /// Consumes model and returns it updated with a business calendar
+    ///
+    /// Once set, duration timers are measured using this calendar.
+    pub fn with_business_calendar<C>(self, calendar: C) -> Self
+    where
+        C: Calendar + 'static,
+    {
+        Self {
+            business_calendar: Some(Arc::new(calendar)),
+            ..self
+        }
+    }
+
+    /// Consumes model and returns it updated with a default timezone
+    ///
+    /// Default timezone applies to timer dates and times without a timezone designator
+    /// and to calendar arithmetic in timer cycles.
+    pub fn with_default_timezone(self, timezone: Timezone) -> Self {
+        Self {
+            default_timezone: timezone,
+            ..self
+        }
+    }
+
+    /// Consumes model and returns it updated with an execution time limit for an activity
+    ///
+    /// Once an activity with this identifier runs longer than `limit`, its execution is
+    /// abandoned and the process fails with an incident (unless the activity or its process
+    /// has a failure policy). This applies to script, service and business rule tasks and to
+    /// call activities.
+    pub fn with_activity_time_limit<S>(mut self, activity: S, limit: Duration) -> Self
+    where
+        S: Into<String>,
+    {
+        Arc::make_mut(&mut self.activity_time_limits).insert(activity.into(), limit);
+        self
+    }
+
+    /// Consumes model and returns it updated with a concurrency limit for a process or
+    /// an activity
+    ///
+    /// No more than `limit` instances of the process (or executions of the activity, across
+    /// all process instances) with this identifier run simultaneously. The rest wait for
+    /// their turn in order: process starts wait in [`process::Handle::start`], activities
+    /// (script, service and business rule tasks and call activities) wait before executing.
+    pub fn with_concurrency_limit<S>(mut self, id: S, limit: usize) -> Self
+    where
+        S: Into<String>,
+    {
+        Arc::make_mut(&mut self.concurrency_limits)
+            .insert(id.into(), Arc::new(Semaphore::new(limit)));
+        self
+    }
+
+    /// Consumes model and returns it updated with a failure policy for a process or an activity
+    ///
+    /// The policy applies when an activity fails (for example, its script returns an error) and
+    /// no handler for the failure is modeled. Policy of an activity takes precedence over policy
+    /// of its process. Without a policy, activities that time out fail the process (see
+    /// [`Model::with_activity_time_limit`]) and activities that fail otherwise end their path.
+    pub fn with_failure_policy<S>(mut self, id: S, policy: process::FailurePolicy) -> Self
+    where
+        S: Into<String>,
+    {
+        Arc::make_mut(&mut self.failure_policies).insert(id.into(), policy);
+        self
+    }
+
+    /// Consumes model and returns it updated with a retry policy for a process or a service or
+    /// script task
+    ///
+    /// Failing tasks are retried with an exponential backoff (see [`process::RetryPolicy`]),
+    /// and each failed attempt is reported with [`process::Log::AttemptFailed`]. Policy of a
+    /// task takes precedence over policy of its process. Once retries are exhausted, the
+    /// failure policy applies, failing the process with an incident if there's none (see
+    /// [`Model::with_failure_policy`]).
+    pub fn with_retry_policy<S>(mut self, id: S, policy: process::RetryPolicy) -> Self
+    where
+        S: Into<String>,
+    {
+        Arc::make_mut(&mut self.retry_policies).insert(id.into(), policy);
+        self
+    }
+
+    /// Consumes model and returns it updated with a timeout for a task
+    ///
+    /// Once the task has held a token longer than `timeout`, the scheduler reports it with
+    /// [`process::Log::FlowNodeTimedOut`] and acts according to `action`: either the task is
+    /// cancelled (abandoning its in-flight work) and a fallback sequence flow is taken, or a
+    /// `TaskTimedOut` error is thrown. The error can be caught by a catch event of the process,
+    /// otherwise it's handled according to the failure policy of the task (see
+    /// [`Model::with_failure_policy`]), failing the process by default.
+    ///
+    /// Fallback sequence flows that don't leave the task are reported by [`Model::validate`],
+    /// and fail the process instance once the timeout elapses.
+    ///
+    /// Unlike [`Model::with_activity_time_limit`], which limits script evaluation, the timeout
+    /// applies to any task, including those waiting for messages or people.
+    pub fn with_task_timeout<S>(
+        mut self,
+        task: S,
+        timeout: Duration,
+        action: process::TimeoutAction,
+    ) -> Self
+    where
+        S: Into<String>,
+    {
+        Arc::make_mut(&mut self.task_timeouts).insert(task.into(), (timeout, action));
+        self
+    }
+
+    /// Consumes model and returns it updated with the order in which an exclusive or inclusive
+    /// gateway evaluates conditions of its outgoing sequence flows
+    ///
+    /// Without a priority, conditions are evaluated in document order. Exclusive gateways stop
+    /// at the first condition that holds, inclusive gateways only do with a `first_match`
+    /// priority (see [`gateway::Priority`]). Every decision is reported with
+    /// [`process::Log::GatewayDecision`].
+    pub fn with_gateway_priority<S>(mut self, gateway: S, priority: gateway::Priority) -> Self
+    where
+        S: Into<String>,
+    {
+        Arc::make_mut(&mut self.gateway_priorities).insert(gateway.into(), priority);
+        self
+    }
+
+    /// Consumes model and returns it updated with a variable (data object) the result of a
+    /// script task is stored in
+    ///
+    /// The result is stored before the task passes its token on: a single data object as it
+    /// is, several of them as a [`crate::data_object::Collection`].
+    pub fn with_result_variable<S, V>(mut self, task: S, variable: V) -> Self
+    where
+        S: Into<String>,
+        V: Into<String>,
+    {
+        Arc::make_mut(&mut self.result_variables).insert(task.into(), variable.into());
+        self
+    }
+
+    /// Consumes model and returns it updated with an evaluator of decisions of business rule
+    /// tasks (see [`crate::decision`])
+    pub fn with_decision_evaluator<E>(self, evaluator: E) -> Self
+    where
+        E: DecisionEvaluator + 'static,
+    {
+        Self {
+            decision_evaluator: Some(Arc::new(evaluator)),
+            ..self
+        }
+    }
+
+    /// Consumes model and returns it updated with the decision a business rule task
+    /// delegates to
+    pub fn with_decision_ref<S, D>(mut self, task: S, decision: D) -> Self
+    where
+        S: Into<String>,
+        D: Into<String>,
+    {
+        Arc::make_mut(&mut self.decision_refs).insert(task.into(), decision.into());
+        self
+    }
+
+    /// Consumes model and returns it updated with the form of a user task (see
+    /// [`tasks::Form`])
+    pub fn with_form<S>(mut self, task: S, form: tasks::Form) -> Self
+    where
+        S: Into<String>,
+    {
+        Arc::make_mut(&mut self.forms).insert(task.into(), form);
+        self
+    }
+
+    /// Consumes model and returns it updated with a limit of simultaneously active process
+    /// instances across the whole model
+    pub fn with_max_active_instances(self, limit: usize) -> Self {
+        Self {
+            max_active_instances: Some(Arc::new(Semaphore::new(limit))),
+            ..self
+        }
+    }
+
+    /// Consumes model and returns it updated with an admission policy for process starts
+    /// beyond limits of active instances (see [`Model::with_max_active_instances`] and
+    /// [`Model::with_concurrency_limit`])
+    ///
+    /// By default, such starts are queued.
+    pub fn with_admission(self, admission: Admission) -> Self {
+        Self { admission, ..self }
+    }
+
+    /// Consumes model and returns it updated with a policy applied once all model handles
+    /// are dropped
+    ///
+    /// By default, the model keeps running.
+    pub fn with_drop_policy(self, drop_policy: DropPolicy) -> Self {
+        Self {
+            drop_policy,
+            ..self
+        }
+    }
+
+    /// Consumes model and returns it updated with a threshold after which joins that keep
+    /// waiting for some of their incoming flows are reported with
+    /// [`process::Log::StuckJoin`]
+    ///
+    /// By default, no such reports are made.
+    pub fn with_join_diagnostics_threshold(self, threshold: Duration) -> Self {
+        Self {
+            join_diagnostics_threshold: Some(threshold),
+            ..self
+        }
+    }
+
+    /// Consumes model and returns it updated with an execution listener
+    ///
+    /// Listeners are notified in the order they have been added.
+    pub fn with_execution_listener<L>(mut self, scope: process::ListenerScope, listener: L) -> Self
+    where
+        L: process::ExecutionListener + 'static,
+    {
+        Arc::make_mut(&mut self.execution_listeners).push((scope, Arc::new(listener)));
+        self
+    }
+
+    /// Consumes model and returns it updated with action middleware
+    ///
+    /// Actions pass through middleware in the order it has been added.
+    pub fn with_action_middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: process::ActionMiddleware + 'static,
+    {
+        Arc::make_mut(&mut self.action_middleware).push(Arc::new(middleware));
+        self
+    }
+
+    /// Validates process definitions of the model
+    ///
+    /// Diagnostics found are also available through [`Handle::diagnostics`] once the model
+    /// is spawned.
+    pub fn validate(&self) -> Vec<validation::Diagnostic> {
+        let mut diagnostics = validation::validate(&self.definitions);
+        // timeouts are reported in the order of their tasks
+        let mut timeout_flows: Vec<_> = self
+            .task_timeouts
+            .iter()
+            .filter_map(|(task, (_, action))| match action {
+                process::TimeoutAction::Flow(seq_flow) => Some((task, seq_flow)),
+                process::TimeoutAction::Error => None,
+            })
+            .collect();
+        timeout_flows.sort();
+        diagnostics.extend(validation::validate_timeout_flows(
+            &self.definitions,
+            timeout_flows.into_iter(),
+        ));
+        diagnostics
+    }
+
+    /// Consumes model and returns it updated with a resource budget for every process
+    /// instance
+    pub fn with_budget(self, budget: process::Budget) -> Self {
+        Self { budget, ..self }
+    }
+
     /// Spawns model operation task
     pub async fn spawn(mut self) -> Handle {
         let (sender, receiver) = mpsc::channel(1);
         let (log_broadcast, _) = broadcast::channel(128);
         let log_sender = log_broadcast.clone();
+        let (instance_event_bus, _) = broadcast::channel(1024);
+        let (instance_log_bus, _) = broadcast::channel(1024);
+        let (timer_pause, timers_paused) = watch::channel(false);
+        let (clock, clock_adjustments) = watch::channel(());
+        let diagnostics = Arc::new(self.validate());
+        let process_definitions = self
+            .definitions
+            .root_elements
+            .iter()
+            .filter_map(|e| match e {
+                RootElement::Process(def) => Some((def.id.clone()?, Arc::new(def.clone()))),
+                _ => None,
+            })
+            .collect();
         let handle = Handle {
             definitions: self.definitions.clone(),
+            process_definitions: Arc::new(process_definitions),
             sender: sender.clone(),
             log_broadcast,
+            instance_event_bus,
+            instance_log_bus,
             // these unwraps should be ok because we don't use `None` for `script_engine_factory`
             // and `script_expression_factory` anywhere
             script_engine_factory: Arc::new(Box::new(self.script_engine_factory.take().unwrap())),
             expression_engine_factory: Arc::new(Box::new(
                 self.expression_engine_factory.take().unwrap(),
             )),
+            milestone_listener: self.milestone_listener.clone(),
+            business_calendar: self.business_calendar.clone(),
+            default_timezone: self.default_timezone,
+            activity_time_limits: self.activity_time_limits.clone(),
+            concurrency_limits: self.concurrency_limits.clone(),
+            failure_policies: self.failure_policies.clone(),
+            retry_policies: self.retry_policies.clone(),
+            task_timeouts: self.task_timeouts.clone(),
+            gateway_priorities: self.gateway_priorities.clone(),
+            result_variables: self.result_variables.clone(),
+            decision_refs: self.decision_refs.clone(),
+            decision_evaluator: self.decision_evaluator.clone(),
+            forms: self.forms.clone(),
+            budget: self.budget,
+            max_active_instances: self.max_active_instances.clone(),
+            admission: self.admission,
+            join_diagnostics_threshold: self.join_diagnostics_threshold,
+            execution_listeners: self.execution_listeners.clone(),
+            action_middleware: self.action_middleware.clone(),
+            queued_starts: Default::default(),
+            priorities: Default::default(),
+            diagnostics,
+            user_tasks: Default::default(),
+            drop_guard: Some(Arc::new(DropGuard {
+                sender: sender.clone(),
+                policy: self.drop_policy,
+            })),
+            timer_pause: Arc::new(timer_pause),
+            timers_paused,
+            clock: Arc::new(clock),
+            clock_adjustments,
         };
 
-        let handle_clone = handle.clone();
+        let handle_clone = handle.internal();
 
         let join_handle =
             task::spawn(async move { self.runner(receiver, handle_clone, log_sender).await });
@@ -201,13 +847,21 @@ where
         )
         .await;
 
+        // Instances spawned on request (terminated along with the model), kept until they
+        // reach their terminal outcome
+        let mut instances: HashMap<process::InstanceId, process::Handle> = HashMap::new();
+
         // Process requests until termination
         loop {
             let next = receiver.recv().await;
             match next {
                 Some(Request::JoinHandle(handle)) => join_handle = Some(handle),
                 Some(Request::Terminate(sender)) => {
-                    for process in self.processes.drain(..) {
+                    for process in self
+                        .processes
+                        .drain(..)
+                        .chain(instances.drain().map(|(_, process)| process))
+                    {
                         let _ = process.terminate().await;
                     }
                     let _ = sender.send(join_handle.take());
@@ -216,6 +870,53 @@ where
                 Some(Request::Processes(sender)) => {
                     let _ = sender.send(self.processes.clone());
                 }
+                Some(Request::Adopt(processes)) => {
+                    for process in processes {
+                        let id = process.id();
+                        if instances.contains_key(&id)
+                            || self.processes.iter().any(|p| p.id() == id)
+                        {
+                            continue;
+                        }
+                        let terminated = process.terminated();
+                        let sender = handle.sender.clone();
+                        task::spawn(async move {
+                            terminated.await;
+                            let _ = sender.send(Request::Terminated(id)).await;
+                        });
+                        instances.insert(id, process);
+                    }
+                }
+                Some(Request::Terminated(id)) => {
+                    instances.remove(&id);
+                }
+                Some(Request::Abort) => {
+                    for process in self
+                        .processes
+                        .drain(..)
+                        .chain(instances.drain().map(|(_, process)| process))
+                    {
+                        process.abort().await;
+                    }
+                    return;
+                }
+                Some(Request::Instances(sender)) => {
+                    let _ = sender.send(
+                        self.processes
+                            .iter()
+                            .chain(instances.values())
+                            .cloned()
+                            .collect(),
+                    );
+                }
+                Some(Request::Instance(id, sender)) => {
+                    let _ = sender.send(
+                        instances
+                            .get(&id)
+                            .or_else(|| self.processes.iter().find(|process| process.id() == id))
+                            .cloned(),
+                    );
+                }
                 None => {}
             }
         }
@@ -242,6 +943,41 @@ impl Handle {
         self.log_broadcast.subscribe()
     }
 
+    /// Returns a stream of events of all process instances of the model
+    ///
+    /// Includes instances spawned after the stream has been created, but not sandboxes
+    /// (see [`process::Handle::fork`]). If the consumer lags behind, events it has missed are
+    /// skipped.
+    pub fn instance_events(&self) -> impl Stream<Item = Envelope> + Send + 'static {
+        process::broadcast_stream(self.instance_event_bus.subscribe())
+    }
+
+    /// Returns a stream of logs of all process instances of the model
+    ///
+    /// Includes instances spawned after the stream has been created, but not sandboxes
+    /// (see [`process::Handle::fork`]). If the consumer lags behind, logs it has missed are
+    /// skipped. Logs an instance couldn't forward to the stream in time are reported with
+    /// [`process::Log::LogsMissed`].
+    pub fn instance_logs(&self) -> impl Stream<Item = InstanceLog> + Send + 'static {
+        process::broadcast_stream(self.instance_log_bus.subscribe())
+    }
+
+    /// Returns a receiver of logs of all process instances of the model that reports
+    /// how many logs it has missed when it lags behind
+    pub(crate) fn instance_log_receiver(&self) -> broadcast::Receiver<InstanceLog> {
+        self.instance_log_bus.subscribe()
+    }
+
+    /// Returns senders of events and logs of all process instances
+    pub(crate) fn instance_buses(
+        &self,
+    ) -> (broadcast::Sender<Envelope>, broadcast::Sender<InstanceLog>) {
+        (
+            self.instance_event_bus.clone(),
+            self.instance_log_bus.clone(),
+        )
+    }
+
     /// Asynchronously returns all processes
     pub async fn processes(&self) -> Result<Vec<process::Handle>, Error> {
         let (sender, receiver) = oneshot::channel();
@@ -249,6 +985,122 @@ impl Handle {
         Ok(receiver.await?)
     }
 
+    /// Asynchronously returns the process with a given identifier, if any
+    pub async fn process_by_id(&self, id: &str) -> Result<Option<process::Handle>, Error> {
+        Ok(self
+            .processes()
+            .await?
+            .into_iter()
+            .find(|process| process.element().id.as_deref() == Some(id)))
+    }
+
+    /// Asynchronously returns the first process (in the order of definitions) with a given
+    /// name, if any
+    pub async fn process_by_name(&self, name: &str) -> Result<Option<process::Handle>, Error> {
+        Ok(self
+            .processes()
+            .await?
+            .into_iter()
+            .find(|process| process.element().name.as_deref() == Some(name)))
+    }
+
+    /// Asynchronously returns a process instance known to the model, if any
+    ///
+    /// Model knows about its processes (see [`Handle::processes`]), as well as instances
+    /// spawned with [`Handle::start_batch`] and detached instances (see
+    /// [`process::Handle::detach`]) until they reach their terminal outcome.
+    pub async fn instance(
+        &self,
+        id: process::InstanceId,
+    ) -> Result<Option<process::Handle>, Error> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.sender.send(Request::Instance(id, sender)).await;
+        Ok(receiver.await?)
+    }
+
+    /// Asynchronously returns process instances known to the model (see [`Handle::instance`])
+    /// that match a query
+    ///
+    /// Instances are searched in memory.
+    pub async fn query_instances(
+        &self,
+        query: &InstanceQuery,
+    ) -> Result<Vec<process::Handle>, Error> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.sender.send(Request::Instances(sender)).await;
+        let mut instances = vec![];
+        for instance in receiver.await? {
+            if query.matches(&instance).await {
+                instances.push(instance);
+            }
+        }
+        Ok(instances)
+    }
+
+    /// Asynchronously returns open user tasks (see [`Handle::user_tasks`]) that match
+    /// a query, sorted and paginated as the query requests
+    ///
+    /// Tasks are searched in memory.
+    pub async fn query_tasks(&self, query: &tasks::TaskQuery) -> Vec<tasks::UserTask> {
+        let mut found = vec![];
+        for task in self.user_tasks.list() {
+            if query.matches(self, &task).await {
+                found.push(task);
+            }
+        }
+        query.page(found)
+    }
+
+    /// Returns a handle for internal use that doesn't count towards the drop policy
+    pub(crate) fn internal(&self) -> Self {
+        Self {
+            drop_guard: None,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the definition of a process of the model
+    pub(crate) fn process_definition(&self, process: &str) -> Option<Arc<Process>> {
+        self.process_definitions.get(process).cloned()
+    }
+
+    /// Hands a process instance over to the model
+    pub(crate) async fn adopt(&self, process: process::Handle) {
+        let _ = self.sender.send(Request::Adopt(vec![process])).await;
+    }
+
+    /// Spawns and starts a batch of instances of a process
+    ///
+    /// All instances share the parsed process definition. Returns start results in the order
+    /// of `requests`. Instances are terminated along with the model, but they are not
+    /// included in [`Handle::processes`].
+    pub async fn start_batch(
+        &self,
+        process: &str,
+        requests: Vec<process::StartRequest>,
+    ) -> Result<Vec<Result<process::Handle, process::StartError>>, Error> {
+        let element = self
+            .process_definition(process)
+            .ok_or_else(|| Error::UnknownProcess(process.to_string()))?;
+        let handles = join_all(
+            requests
+                .iter()
+                .map(|_| process::Process::shared(element.clone(), self.internal()).spawn()),
+        )
+        .await;
+        let _ = self.sender.send(Request::Adopt(handles.clone())).await;
+        Ok(join_all(
+            handles
+                .into_iter()
+                .zip(requests)
+                .map(|(handle, request)| async move {
+                    let result = handle.start_request(request).start().await;
+                    result.map(|_| handle)
+                }),
+        )
+        .await)
+    }
+
     /// Returns model's script engine factory
     pub fn script_engine_factory(&self) -> Arc<Box<dyn LanguageEngineFactory>> {
         self.script_engine_factory.clone()
@@ -258,6 +1110,197 @@ impl Handle {
     pub fn expression_engine_factory(&self) -> Arc<Box<dyn LanguageEngineFactory>> {
         self.expression_engine_factory.clone()
     }
+
+    /// Returns model's milestone listener, if any
+    pub fn milestone_listener(&self) -> Option<MilestoneListener> {
+        self.milestone_listener.clone()
+    }
+
+    /// Returns model's execution listeners
+    pub fn execution_listeners(&self) -> process::ExecutionListeners {
+        self.execution_listeners.clone()
+    }
+
+    /// Returns model's action middleware
+    pub fn action_middleware(&self) -> Arc<Vec<Arc<dyn process::ActionMiddleware>>> {
+        self.action_middleware.clone()
+    }
+
+    /// Pauses timers of all process instances
+    ///
+    /// While paused, timers don't fire. Timers that become due during the pause fire
+    /// (once) when timers are resumed.
+    pub fn pause_timers(&self) {
+        let _ = self.timer_pause.send(true);
+    }
+
+    /// Resumes timers paused with [`Handle::pause_timers`]
+    pub fn resume_timers(&self) {
+        let _ = self.timer_pause.send(false);
+    }
+
+    /// Returns `true` if timers are paused
+    pub fn timers_paused(&self) -> bool {
+        *self.timers_paused.borrow()
+    }
+
+    /// Returns a receiver of timer pause state changes
+    pub(crate) fn timer_pause(&self) -> watch::Receiver<bool> {
+        self.timers_paused.clone()
+    }
+
+    /// Reports that the system clock has been adjusted
+    ///
+    /// Timers due at a point in time re-check it against the wall clock, so that those that
+    /// have become due because the clock has been set forward fire right away. Timers never
+    /// fire early because of a clock set back, whether this is reported or not.
+    pub fn clock_adjusted(&self) {
+        let _ = self.clock.send(());
+    }
+
+    /// Returns a receiver of clock adjustments
+    pub(crate) fn clock_adjustments(&self) -> watch::Receiver<()> {
+        self.clock_adjustments.clone()
+    }
+
+    /// Returns model's business calendar, if any
+    pub fn business_calendar(&self) -> Option<Arc<dyn Calendar>> {
+        self.business_calendar.clone()
+    }
+
+    /// Returns model's default timezone
+    pub fn default_timezone(&self) -> Timezone {
+        self.default_timezone
+    }
+
+    /// Returns execution time limit of an activity, if any
+    pub fn activity_time_limit(&self, activity: &str) -> Option<Duration> {
+        self.activity_time_limits.get(activity).copied()
+    }
+
+    /// Returns resource budget of every process instance
+    pub fn budget(&self) -> process::Budget {
+        self.budget
+    }
+
+    /// Returns task list of user tasks of process instances of the model
+    pub fn user_tasks(&self) -> tasks::UserTasks {
+        self.user_tasks.clone()
+    }
+
+    /// Returns diagnostics found by validating process definitions when the model was
+    /// spawned (see [`Model::validate`])
+    pub fn diagnostics(&self) -> &[validation::Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns threshold after which waiting joins are reported, if any
+    pub fn join_diagnostics_threshold(&self) -> Option<Duration> {
+        self.join_diagnostics_threshold
+    }
+
+    /// Returns number of running process instances with a priority
+    pub fn running_instances(&self, priority: process::Priority) -> usize {
+        self.priorities.running(priority)
+    }
+
+    /// Returns registry of running process instances by priority
+    pub(crate) fn priorities(&self) -> Arc<process::priority::Registry> {
+        self.priorities.clone()
+    }
+
+    /// Returns number of process starts waiting to be admitted
+    pub fn queued_starts(&self) -> usize {
+        self.queued_starts.load(Ordering::SeqCst)
+    }
+
+    /// Admits a start of the process, honoring limits of active instances
+    ///
+    /// Returns permits to be held for as long as the instance is active.
+    pub(crate) async fn admit(
+        &self,
+        process: Option<&str>,
+    ) -> Result<Vec<OwnedSemaphorePermit>, process::StartError> {
+        // per-process limit goes first, so that waiting for it doesn't hold a model-wide permit
+        let semaphores: Vec<_> = process
+            .and_then(|id| self.concurrency_limit(id))
+            .into_iter()
+            .chain(self.max_active_instances.clone())
+            .collect();
+        let mut permits = vec![];
+        match self.admission {
+            Admission::Reject => {
+                for semaphore in semaphores {
+                    permits.push(
+                        semaphore
+                            .try_acquire_owned()
+                            .map_err(|_| process::StartError::Overloaded)?,
+                    );
+                }
+            }
+            Admission::Queue => {
+                self.queued_starts.fetch_add(1, Ordering::SeqCst);
+                for semaphore in semaphores {
+                    match semaphore.acquire_owned().await {
+                        Ok(permit) => permits.push(permit),
+                        Err(_) => {
+                            self.queued_starts.fetch_sub(1, Ordering::SeqCst);
+                            return Err(process::StartError::NotReceived);
+                        }
+                    }
+                }
+                self.queued_starts.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        Ok(permits)
+    }
+
+    /// Returns failure policy of a process or an activity, if any
+    pub fn failure_policy(&self, id: &str) -> Option<process::FailurePolicy> {
+        self.failure_policies.get(id).copied()
+    }
+
+    /// Returns retry policy of a process or a task, if any
+    pub fn retry_policy(&self, id: &str) -> Option<process::RetryPolicy> {
+        self.retry_policies.get(id).copied()
+    }
+
+    /// Returns timeout of a task and what happens when it's exceeded, if any
+    pub fn task_timeout(&self, task: &str) -> Option<(Duration, process::TimeoutAction)> {
+        self.task_timeouts.get(task).cloned()
+    }
+
+    /// Returns the order in which a gateway evaluates conditions of its outgoing sequence
+    /// flows, if any
+    pub fn gateway_priority(&self, gateway: &str) -> Option<gateway::Priority> {
+        self.gateway_priorities.get(gateway).cloned()
+    }
+
+    /// Returns the variable the result of a script task is stored in, if any
+    pub fn result_variable(&self, task: &str) -> Option<String> {
+        self.result_variables.get(task).cloned()
+    }
+
+    /// Returns the decision a business rule task delegates to, if one has been set with
+    /// [`Model::with_decision_ref`]
+    pub fn decision_ref(&self, task: &str) -> Option<String> {
+        self.decision_refs.get(task).cloned()
+    }
+
+    /// Returns model's decision evaluator, if any
+    pub fn decision_evaluator(&self) -> Option<Arc<dyn DecisionEvaluator>> {
+        self.decision_evaluator.clone()
+    }
+
+    /// Returns the form of a user task, if one has been set with [`Model::with_form`]
+    pub fn form(&self, task: &str) -> Option<tasks::Form> {
+        self.forms.get(task).cloned()
+    }
+
+    /// Returns semaphore limiting concurrency of a process or an activity, if any
+    pub(crate) fn concurrency_limit(&self, id: &str) -> Option<Arc<Semaphore>> {
+        self.concurrency_limits.get(id).cloned()
+    }
 }
 
 #[cfg(test)]
@@ -274,6 +1317,313 @@ mod tests {
         handle.terminate().await;
     }
 
+    #[bpxe_im::test]
+    async fn start_batch() {
+        let definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![StartEvent {
+                    id: Some("start".into()),
+                    ..Default::default()
+                }
+                .into()],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+        let model = Model::new(definitions).spawn().await;
+        assert!(matches!(
+            model.start_batch("proc2", vec![]).await,
+            Err(Error::UnknownProcess(id)) if id == "proc2"
+        ));
+
+        let requests = (0..3)
+            .map(|i| process::StartRequest {
+                business_key: Some(format!("batch-{}", i)),
+                ..Default::default()
+            })
+            .collect();
+        let instances: Vec<_> = model
+            .start_batch("proc1", requests)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(instances.len(), 3);
+        for (i, instance) in instances.iter().enumerate() {
+            assert_eq!(instance.business_key(), Some(format!("batch-{}", i)));
+            assert_eq!(instance.join().await, process::Outcome::Completed);
+        }
+        // batch instances are not model's processes
+        assert_eq!(model.processes().await.unwrap().len(), 1);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn query_tasks() {
+        use crate::bpmn::parse;
+        use crate::data_object::Container;
+        use tasks::{TaskOrder, TaskQuery};
+        let definitions = parse(include_str!("activity/test_models/task_user.bpmn")).unwrap();
+        let model = Model::new(definitions).spawn().await;
+
+        let requests = (1..=3)
+            .map(|i| {
+                let mut variables = process::Variables::new();
+                variables.insert("approved".into(), Box::new(Container(i)) as _);
+                process::StartRequest {
+                    variables,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let instances: Vec<_> = model
+            .start_batch("proc1", requests)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|instance| instance.unwrap())
+            .collect();
+        let user_tasks = model.user_tasks();
+        assert!(crate::test::timeout(async {
+            while user_tasks.list().len() < 3 {
+                task::yield_now().await;
+            }
+        })
+        .await
+        .is_ok());
+        let opened = user_tasks.list();
+        assert!(user_tasks.claim(opened[0].id, "bob").is_ok());
+        assert!(user_tasks.claim(opened[2].id, "alice").is_ok());
+
+        let found = model.query_tasks(&TaskQuery::new().assignee("bob")).await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, opened[0].id);
+
+        let found = model.query_tasks(&TaskQuery::new().unassigned()).await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, opened[1].id);
+
+        let found = model
+            .query_tasks(&TaskQuery::new().variable("approved", |v| {
+                matches!(v.downcast_ref::<Container<i32>>(), Some(Container(3)))
+            }))
+            .await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].instance, instances[2].id());
+
+        let found = model
+            .query_tasks(
+                &TaskQuery::new()
+                    .process("proc1")
+                    .element("approve")
+                    .instance(opened[1].instance),
+            )
+            .await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, opened[1].id);
+        assert!(model
+            .query_tasks(&TaskQuery::new().process("proc2"))
+            .await
+            .is_empty());
+
+        // unclaimed first, then alice and bob, reversed
+        let found = model
+            .query_tasks(&TaskQuery::new().order_by(TaskOrder::Assignee).descending())
+            .await;
+        let ids: Vec<_> = found.iter().map(|task| task.id).collect();
+        assert_eq!(ids, vec![opened[0].id, opened[2].id, opened[1].id]);
+
+        let found = model
+            .query_tasks(&TaskQuery::new().descending().offset(1).limit(1))
+            .await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, opened[1].id);
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn query_instances() {
+        use crate::bpmn::parse;
+        use crate::data_object::Container;
+        use process::InstanceState;
+        let definitions = parse(include_str!("activity/test_models/task_user.bpmn")).unwrap();
+        let model = Model::new(definitions).spawn().await;
+
+        let requests = (1..=2)
+            .map(|i| {
+                let mut variables = process::Variables::new();
+                variables.insert("approved".into(), Box::new(Container(i)) as _);
+                process::StartRequest {
+                    business_key: Some(format!("order-{}", i)),
+                    variables,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        for instance in model.start_batch("proc1", requests).await.unwrap() {
+            assert!(instance.is_ok());
+        }
+
+        let found = model
+            .query_instances(&InstanceQuery::new().business_key("order-2"))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].business_key(), Some("order-2".into()));
+
+        let found = model
+            .query_instances(&InstanceQuery::new().variable("approved", |v| {
+                matches!(v.downcast_ref::<Container<i32>>(), Some(Container(1)))
+            }))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].business_key(), Some("order-1".into()));
+
+        // model's own process hasn't been started
+        let found = model
+            .query_instances(&InstanceQuery::new().state(InstanceState::NotStarted))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].business_key().is_none());
+
+        let found = model
+            .query_instances(
+                &InstanceQuery::new()
+                    .process("proc1")
+                    .state(InstanceState::Running)
+                    .started_before(SystemTime::now() + Duration::from_secs(60)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+
+        // finished instances are no longer kept by the model
+        let id = found[0].id();
+        found[0].clone().terminate().await;
+        assert!(crate::test::timeout(async {
+            while model.instance(id).await.unwrap().is_some() {
+                task::yield_now().await;
+            }
+        })
+        .await
+        .is_ok());
+        assert_eq!(
+            model
+                .query_instances(&InstanceQuery::new().process("proc1"))
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn instance_logs() {
+        use futures::StreamExt;
+        let definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![StartEvent {
+                    id: Some("start".into()),
+                    ..Default::default()
+                }
+                .into()],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+        let model = Model::new(definitions).spawn().await;
+        let logs = model.instance_logs();
+        futures::pin_mut!(logs);
+        let mut instances: Vec<_> = model
+            .start_batch("proc1", vec![Default::default(), Default::default()])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|instance| instance.unwrap().id())
+            .collect();
+
+        let mut done = vec![];
+        while done.len() < instances.len() {
+            if let Some(InstanceLog {
+                instance,
+                log: process::Log::Done { .. },
+            }) = logs.next().await
+            {
+                done.push(instance);
+            }
+        }
+        done.sort();
+        instances.sort();
+        assert_eq!(done, instances);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn instance_forwarding_ends() {
+        let definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![StartEvent {
+                    id: Some("start".into()),
+                    ..Default::default()
+                }
+                .into()],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+        let model = Model::new(definitions).spawn().await;
+        let instance = model
+            .start_batch("proc1", vec![Default::default()])
+            .await
+            .unwrap()
+            .pop()
+            .unwrap()
+            .unwrap();
+        let logs = instance.log_broadcast();
+        assert_eq!(instance.join().await, process::Outcome::Completed);
+        // the forwarder to model-wide buses lets go of the instance
+        assert!(crate::test::timeout(async {
+            while logs.receiver_count() > 0 {
+                task::yield_now().await;
+            }
+        })
+        .await
+        .is_ok());
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn drop_policy() {
+        use crate::bpmn::parse;
+        for (policy, outcome) in vec![
+            (DropPolicy::Terminate, process::Outcome::Terminated),
+            (DropPolicy::Abort, process::Outcome::Cancelled),
+        ] {
+            let definitions =
+                parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+            let model = Model::new(definitions)
+                .with_drop_policy(policy)
+                .spawn()
+                .await;
+            let handle = model.processes().await.unwrap().pop().unwrap();
+            assert!(handle.start().await.is_ok());
+            drop(model);
+            assert_eq!(handle.join().await, outcome);
+        }
+    }
+
     #[bpxe_im::test]
     async fn list_processes() {
         let definitions = Definitions {
@@ -307,4 +1657,34 @@ mod tests {
 
         assert_eq!(process_names, vec!["proc1", "proc2"]);
     }
+
+    #[bpxe_im::test]
+    async fn find_processes() {
+        let definitions = Definitions {
+            root_elements: vec![
+                Process {
+                    id: Some("proc1".into()),
+                    name: Some("Onboarding".into()),
+                    ..Default::default()
+                }
+                .into(),
+                Process {
+                    id: Some("proc2".into()),
+                    name: Some("Offboarding".into()),
+                    ..Default::default()
+                }
+                .into(),
+            ],
+            ..Default::default()
+        };
+
+        let handle = Model::new(definitions).spawn().await;
+        let process = handle.process_by_id("proc2").await.unwrap().unwrap();
+        assert_eq!(process.element().name.as_deref(), Some("Offboarding"));
+        let process = handle.process_by_name("Onboarding").await.unwrap().unwrap();
+        assert_eq!(process.element().id.as_deref(), Some("proc1"));
+        assert!(handle.process_by_id("proc3").await.unwrap().is_none());
+        assert!(handle.process_by_name("proc1").await.unwrap().is_none());
+        handle.terminate().await;
+    }
 }
@@ -1,15 +1,38 @@
 //! # Process
+use crate::activity::manual_task::ManualTaskError;
+use crate::activity::service_task::{self, ServiceError, ServiceHandler, ServiceResult};
 use crate::bpmn::schema::{FlowNodeType, Process as Element};
 use crate::data_object::DataObject;
-use crate::event::ProcessEvent as Event;
+use crate::error::ErrorReport;
+use crate::event::{
+    Demultiplexer, Envelope, EventKind, ProcessEvent as Event, Subscription, Subscriptions,
+};
 use crate::flow_node;
 use crate::model;
 use crate::sys::task::{self, JoinHandle};
-use serde::Serialize;
-use std::sync::Arc;
+use crate::sys::time;
+use crate::timer::{PendingTimer, TimerError, TimerId, Timers};
+use futures::future::{Future, FutureExt};
+use futures::stream::{self, Stream};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
-use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
 
+#[cfg(feature = "invariants")]
+pub mod invariants;
+pub mod listener;
+pub use listener::{Execution, ExecutionListener, ExecutionListeners, ListenerScope};
+pub mod middleware;
+pub use middleware::ActionMiddleware;
+pub mod modification;
+pub mod priority;
+pub use modification::{Modification, ModificationBuilder, ModificationError};
+pub use priority::Priority;
 mod scheduler;
 use scheduler::Scheduler;
 
@@ -22,22 +45,263 @@ pub enum StartError {
     /// Response has not been received
     #[error("response has not been received")]
     NotReceived,
+    /// Initial variable doesn't correspond to any data object
+    #[error("unknown data object {0}")]
+    UnknownDataObject(String),
+    /// Process definition is invalid
+    #[error("invalid process definition: {reason}")]
+    InvalidDefinition { reason: String },
+    /// There are multiple start events, all of them triggered by specific events, so it's
+    /// not clear which one should be started
+    #[error("ambiguous start events: {start_events:?}")]
+    AmbiguousStartEvents { start_events: Vec<String> },
+    /// Process instance has already been started (every process runs as a single instance)
+    #[error("process instance is already running")]
+    AlreadyRunning,
+    /// Requested start event doesn't exist
+    #[error("unknown start event {0}")]
+    UnknownStartEvent(String),
+    /// Limit of active instances has been reached (see [`model::Admission::Reject`])
+    #[error("too many active instances")]
+    Overloaded,
+    /// Process can't be started before the requested flow node (see [`StartBuilder::before`])
+    #[error("can't start before the requested flow node")]
+    StartBefore(#[source] ModificationError),
+}
+
+/// Process instance identifier
+///
+/// Unique within the running program
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct InstanceId(pub u64);
+
+impl InstanceId {
+    fn next() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        Self(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl std::fmt::Display for InstanceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Weak process handle (see [`Handle::detach`])
+///
+/// Refers to a process instance by its identifier, without holding on to it.
+#[derive(Clone)]
+pub struct WeakHandle {
+    id: InstanceId,
+    model: model::Handle,
+}
+
+impl WeakHandle {
+    /// Returns process instance identifier
+    pub fn id(&self) -> InstanceId {
+        self.id
+    }
+
+    /// Asynchronously returns a handle of the process instance, if the model still knows
+    /// about it
+    pub async fn upgrade(&self) -> Option<Handle> {
+        self.model.instance(self.id).await.ok().flatten()
+    }
 }
 
 /// Process container
 pub struct Process {
     element: Arc<Element>,
     model: model::Handle,
+    id: Option<InstanceId>,
 }
 
 /// Control handle for a running process
+///
+/// Process instance doesn't depend on its handles to keep running: it runs until it is
+/// terminated (see [`Handle::terminate`]) even if all handles are dropped. To be able to
+/// retrieve the instance later, detach it (see [`Handle::detach`]).
 #[derive(Clone)]
 pub struct Handle {
+    id: InstanceId,
     model: model::Handle,
     element: Arc<Element>,
     sender: mpsc::Sender<Request>,
     log_broadcast: broadcast::Sender<Log>,
-    event_broadcast: broadcast::Sender<Event>,
+    event_broadcast: broadcast::Sender<Envelope>,
+    event_demultiplexer: Demultiplexer,
+    timers: Timers,
+    subscriptions: Subscriptions,
+    usage: Arc<UsageCounters>,
+    business_key: Arc<Mutex<Option<String>>>,
+    priority: Arc<Mutex<Priority>>,
+    started_at: Arc<Mutex<Option<SystemTime>>>,
+    // set once a start has been claimed, so that concurrent starts don't wait for admission
+    starting: Arc<AtomicBool>,
+    termination: watch::Receiver<Option<Termination>>,
+    sandboxed: bool,
+    // topic => service handler
+    services: Arc<Mutex<HashMap<String, ServiceHandler>>>,
+    // manual task => completions of its waiting tokens, in order of arrival
+    manual_tasks: Arc<Mutex<HashMap<String, VecDeque<oneshot::Sender<()>>>>>,
+}
+
+/// Terminal outcome of a process instance
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    /// All tokens have been consumed
+    Completed,
+    /// Process has been terminated
+    Terminated,
+    /// Process has been stopped without reaching any other outcome
+    Cancelled,
+    /// An error has been thrown and nothing was there to catch it (see [`Handle::incident`])
+    Failed,
+}
+
+/// State of a process instance (see [`Handle::state`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceState {
+    /// Process instance hasn't been started yet
+    NotStarted,
+    /// Process instance has been started and hasn't reached its terminal outcome yet
+    Running,
+    /// Process instance has reached its terminal outcome
+    Finished(Outcome),
+}
+
+/// Incident that has failed a process instance
+#[derive(Clone, Debug, PartialEq)]
+pub struct Incident {
+    /// Identifier of the flow node that has thrown the error
+    pub node: Option<String>,
+    /// Uncaught error event
+    pub error: Event,
+}
+
+/// What to do when an activity fails with no modeled handler (see
+/// [`model::Model::with_failure_policy`])
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailurePolicy {
+    /// Fail the process instance with an incident
+    Fail,
+    /// Keep the token at the activity, reporting the incident with
+    /// [`Log::ActivitySuspended`]
+    ///
+    /// The process instance won't complete until the activity is skipped or cancelled (see
+    /// [`Handle::modify`]), while its other paths carry on.
+    Suspend,
+    /// Continue as if the activity has completed without output
+    Skip,
+}
+
+/// How failing service and script tasks are retried (see [`model::Model::with_retry_policy`])
+///
+/// The first retry waits for `backoff`, and every next one waits `multiplier` times longer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Maximum number of retries (attempts after the first one)
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub backoff: Duration,
+    /// Factor the delay grows by with every retry
+    pub multiplier: u32,
+}
+
+impl RetryPolicy {
+    /// Creates a retry policy doubling its delay with every retry
+    pub fn new(max_retries: u32, backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            backoff,
+            multiplier: 2,
+        }
+    }
+
+    /// Returns the delay before a retry following a failed attempt (counting from 1), or
+    /// `None` if retries are exhausted
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt > self.max_retries {
+            return None;
+        }
+        let factor = self.multiplier.saturating_pow(attempt.saturating_sub(1));
+        Some(self.backoff.saturating_mul(factor))
+    }
+}
+
+/// What happens to a task that exceeds its timeout (see [`model::Model::with_task_timeout`])
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeoutAction {
+    /// Emit an error event on behalf of the task and fail the process with an incident
+    Error,
+    /// Take a fallback sequence flow (by its identifier)
+    Flow(String),
+}
+
+/// Resource budget of a process instance (see [`model::Model::with_budget`])
+///
+/// Once any part of the budget is exceeded, the instance stops making progress and fails
+/// with an incident.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Budget {
+    /// Maximum number of scheduler steps
+    pub steps: Option<u64>,
+    /// Maximum total time spent evaluating scripts and expressions
+    pub execution_time: Option<Duration>,
+}
+
+impl Budget {
+    /// Returns `true` if `usage` exceeds the budget
+    pub fn is_exceeded_by(&self, usage: &Usage) -> bool {
+        matches!(self.steps, Some(steps) if usage.steps > steps)
+            || matches!(self.execution_time, Some(time) if usage.execution_time > time)
+    }
+}
+
+/// Resources used by a process instance (see [`Handle::usage`])
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Number of scheduler steps taken
+    pub steps: u64,
+    /// Total time spent evaluating scripts and expressions
+    pub execution_time: Duration,
+}
+
+#[derive(Default)]
+struct UsageCounters {
+    steps: AtomicU64,
+    // nanoseconds
+    execution_time: AtomicU64,
+}
+
+/// Process variables (data object id => data object)
+pub type Variables = HashMap<String, Box<dyn DataObject>>;
+
+/// Terminal state of a process instance
+#[derive(Clone)]
+pub(crate) struct Termination {
+    pub(crate) outcome: Outcome,
+    pub(crate) variables: Arc<Variables>,
+    pub(crate) incident: Option<Incident>,
+}
+
+/// Outcome of [`Handle::start_and_wait`]
+pub enum ProcessOutcome {
+    /// Process has completed with these final variables
+    Completed(Variables),
+    /// Process has reached a different terminal outcome
+    NotCompleted(Outcome),
+    /// Process hasn't reached any terminal outcome in time
+    TimedOut,
+}
+
+/// Process result error
+#[derive(Error, Debug, PartialEq)]
+pub enum ResultError {
+    /// Process has not completed
+    #[error("process has not completed ({0:?})")]
+    NotCompleted(Outcome),
 }
 
 /// Data object container
@@ -52,50 +316,428 @@ pub enum DataObjectError {
     NotReceived,
 }
 
+/// Process fork error
+#[derive(Error, Debug, PartialEq)]
+pub enum ForkError {
+    /// Response has not been received
+    #[error("response has not been received")]
+    NotReceived,
+    /// State of a flow node can't be restored in the fork
+    #[error("state can't be restored: {0}")]
+    State(#[from] flow_node::StateError),
+}
+
+/// Process inspection error
+#[derive(Error, Debug, PartialEq)]
+pub enum InspectionError {
+    /// Response has not been received
+    #[error("response has not been received")]
+    NotReceived,
+}
+
+/// Flow node inspection
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowNodeInspection {
+    /// Flow node state
+    pub state: flow_node::State,
+    /// Number of tokens flow node has received
+    pub tokens: usize,
+    /// Number of tokens flow node is currently holding
+    pub active_tokens: usize,
+}
+
+/// Process inspection
+///
+/// Provides operational tooling with a point-in-time view of the process: its flow nodes'
+/// states and tokens, as well as its data objects (variables).
+pub struct Inspection {
+    /// Flow node id => flow node inspection
+    pub flow_nodes: HashMap<String, FlowNodeInspection>,
+    /// Data object id => data object
+    pub data_objects: Variables,
+}
+
+impl From<Snapshot> for Inspection {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            flow_nodes: snapshot
+                .flow_nodes
+                .into_iter()
+                .map(|(id, flow_node)| {
+                    (
+                        id,
+                        FlowNodeInspection {
+                            state: flow_node.state,
+                            tokens: flow_node.tokens,
+                            active_tokens: flow_node.active_tokens,
+                        },
+                    )
+                })
+                .collect(),
+            data_objects: snapshot.data_objects,
+        }
+    }
+}
+
+/// Snapshot of flow node state
+pub(crate) struct FlowNodeSnapshot {
+    pub(crate) state: flow_node::State,
+    pub(crate) tokens: usize,
+    pub(crate) active_tokens: usize,
+}
+
+/// Snapshot of process state
+pub(crate) struct Snapshot {
+    /// Flow node id => flow node snapshot
+    pub(crate) flow_nodes: HashMap<String, FlowNodeSnapshot>,
+    /// Data object id => data object
+    pub(crate) data_objects: Variables,
+}
+
 pub(crate) enum Request {
     JoinHandle(JoinHandle<()>),
     Terminate(oneshot::Sender<Option<JoinHandle<()>>>),
-    Start(oneshot::Sender<Result<(), StartError>>),
+    Abort,
+    Start(StartOptions, oneshot::Sender<Result<(), StartError>>),
     DataObject(
         String,
         oneshot::Sender<Result<DataObjectContainer, DataObjectError>>,
     ),
+    Snapshot(oneshot::Sender<Snapshot>),
+    TokensUpstream(String, oneshot::Sender<Option<Vec<usize>>>),
+    Incident(Incident),
+    // (node, visit) of a task whose timeout has elapsed
+    TaskTimedOut(String, usize),
+    // error an activity has failed with, to be caught by its boundary events
+    BoundaryError(String, Event, oneshot::Sender<bool>),
+    VariablesChanged,
+    PriorityChanged,
+    // answered once no flow node is ready to make progress
+    Settled(oneshot::Sender<()>),
+    Modify(
+        Vec<Modification>,
+        oneshot::Sender<Result<(), ModificationError>>,
+    ),
+    #[cfg(feature = "unsafe-ops")]
+    Inject(
+        String,
+        Variables,
+        oneshot::Sender<Result<(), ModificationError>>,
+    ),
+}
+
+/// Scheduler-side options of a process start
+#[derive(Default)]
+pub(crate) struct StartOptions {
+    pub(crate) start_event: Option<String>,
+    pub(crate) start_before: Option<String>,
+    // applied only once the start has been accepted
+    pub(crate) variables: Variables,
+    pub(crate) priority: Option<Priority>,
+}
+
+/// Process start request
+#[derive(Default)]
+pub struct StartRequest {
+    /// Initial values of variables (data objects)
+    pub variables: Variables,
+    /// Business key of the process instance (see [`Handle::business_key`])
+    pub business_key: Option<String>,
+    /// Priority of the process instance (see [`Handle::priority`])
+    pub priority: Option<Priority>,
+    /// Start event to start the process with (see [`StartBuilder::start_event`])
+    pub start_event: Option<String>,
+    /// Flow node to start the process before (see [`StartBuilder::before`])
+    pub start_before: Option<String>,
+}
+
+/// Process start builder (see [`Handle::start_with`])
+#[must_use = "process is only started with StartBuilder::start"]
+pub struct StartBuilder<'a> {
+    handle: &'a Handle,
+    pub(crate) request: StartRequest,
+}
+
+impl<'a> StartBuilder<'a> {
+    /// Sets initial value of a variable (data object)
+    pub fn variable<S>(mut self, id: S, value: Box<dyn DataObject>) -> Self
+    where
+        S: Into<String>,
+    {
+        self.request.variables.insert(id.into(), value);
+        self
+    }
+
+    /// Sets initial values of variables (data objects)
+    pub fn variables(mut self, variables: Variables) -> Self {
+        self.request.variables.extend(variables);
+        self
+    }
+
+    /// Sets business key of the process instance (see [`Handle::business_key`])
+    pub fn business_key<S>(mut self, business_key: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.request.business_key = Some(business_key.into());
+        self
+    }
+
+    /// Sets priority of the process instance (see [`Handle::priority`])
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.request.priority = Some(priority);
+        self
+    }
+
+    /// Selects the start event to start the process with
+    ///
+    /// Only the selected start event flows, which is necessary when there are multiple start
+    /// events, all of them triggered by specific events.
+    pub fn start_event<S>(mut self, start_event: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.request.start_event = Some(start_event.into());
+        self
+    }
+
+    /// Starts the process before a flow node, skipping everything that precedes it
+    ///
+    /// Instead of a start event, the flow node receives a token through its first incoming
+    /// sequence flow. This is useful for reproducing issues or re-running the failed tail of
+    /// a long process with the variables it had at that point.
+    pub fn before<S>(mut self, node: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.request.start_before = Some(node.into());
+        self
+    }
+
+    /// Starts the process
+    ///
+    /// If the model limits active instances (see [`model::Model::with_concurrency_limit`] and
+    /// [`model::Model::with_max_active_instances`]), waits until the process can run or fails
+    /// with [`StartError::Overloaded`], depending on model's admission policy.
+    pub async fn start(self) -> Result<(), StartError> {
+        let handle = self.handle;
+        let request = self.request;
+        // an instance that is (or is being) started must not wait for, or be rejected by,
+        // admission, as it wouldn't be started anyway
+        if handle.state() != InstanceState::NotStarted
+            || handle.starting.swap(true, Ordering::SeqCst)
+        {
+            return Err(StartError::AlreadyRunning);
+        }
+        let permits = match handle.model.admit(handle.element.id.as_deref()).await {
+            Ok(permits) => permits,
+            Err(err) => {
+                handle.starting.store(false, Ordering::SeqCst);
+                return Err(err);
+            }
+        };
+        let (sender, receiver) = oneshot::channel();
+        let options = StartOptions {
+            start_event: request.start_event,
+            start_before: request.start_before,
+            variables: request.variables,
+            priority: request.priority,
+        };
+        let _ = handle.sender.send(Request::Start(options, sender)).await;
+        let result = if let Ok(result) = receiver.await {
+            result
+        } else {
+            Err(StartError::NotReceived)
+        };
+        if result.is_err() {
+            handle.starting.store(false, Ordering::SeqCst);
+        } else {
+            handle.started_at.lock().unwrap().replace(time::now());
+            if let Some(business_key) = request.business_key {
+                handle.business_key.lock().unwrap().replace(business_key);
+            }
+            if !permits.is_empty() {
+                // hold the permits until the process is over
+                let handle = handle.clone();
+                task::spawn(async move {
+                    handle.join().await;
+                    drop(permits);
+                });
+            }
+        }
+        result
+    }
 }
 
 /// Process events
-#[derive(Clone, Debug, Serialize)]
+///
+/// Logs are serialized as objects tagged with their variant name (`type`), with flow nodes
+/// tagged the same way.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[non_exhaustive]
 pub enum Log {
     /// Flow node has received an incoming flow (activated for each incoming flow)
     FlowNodeIncoming {
-        #[serde(serialize_with = "crate::serde::serialize_flow_node")]
+        #[serde(
+            serialize_with = "crate::serde::serialize_flow_node",
+            deserialize_with = "crate::serde::deserialize_flow_node"
+        )]
         node: Box<dyn FlowNodeType>,
         incoming_index: flow_node::IncomingIndex,
     },
     /// Flow node execution has been completed
     FlowNodeCompleted {
-        #[serde(serialize_with = "crate::serde::serialize_flow_node")]
+        #[serde(
+            serialize_with = "crate::serde::serialize_flow_node",
+            deserialize_with = "crate::serde::deserialize_flow_node"
+        )]
         node: Box<dyn FlowNodeType>,
     },
     #[cfg(test)]
     /// Flow node report of tokens (for testing)
     FlowNodeTokens {
-        #[serde(serialize_with = "crate::serde::serialize_flow_node")]
+        #[serde(
+            serialize_with = "crate::serde::serialize_flow_node",
+            deserialize_with = "crate::serde::deserialize_flow_node"
+        )]
         node: Box<dyn FlowNodeType>,
         count: usize,
     },
     /// No default path is available for a node
     NoDefaultPath {
-        #[serde(serialize_with = "crate::serde::serialize_flow_node")]
+        #[serde(
+            serialize_with = "crate::serde::serialize_flow_node",
+            deserialize_with = "crate::serde::deserialize_flow_node"
+        )]
         node: Box<dyn FlowNodeType>,
     },
     /// Expression evaluation error
-    ExpressionError { error: String },
+    ExpressionError {
+        /// Identifier of the element the expression belongs to
+        element: Option<String>,
+        error: ErrorReport,
+    },
     /// Script evaluation error
-    ScriptError { error: String },
-    /// There are no more flow nodes to schedule, ever
-    Done,
+    ScriptError {
+        /// Identifier of the element the script belongs to
+        element: Option<String>,
+        error: ErrorReport,
+    },
+    /// Service task handler error
+    ServiceError {
+        /// Identifier of the service task
+        element: Option<String>,
+        error: ErrorReport,
+    },
+    /// Business rule task decision error
+    DecisionError {
+        /// Identifier of the business rule task
+        element: Option<String>,
+        error: ErrorReport,
+    },
+    /// Call activity error
+    CallActivityError {
+        /// Identifier of the call activity
+        element: Option<String>,
+        error: ErrorReport,
+    },
+    /// User task completion error
+    UserTaskError {
+        /// Identifier of the user task
+        element: Option<String>,
+        error: ErrorReport,
+    },
+    /// Timer definition error
+    TimerError {
+        /// Identifier of the event the timer belongs to
+        element: Option<String>,
+        error: ErrorReport,
+    },
+    /// Attempt of a task with a retry policy has failed (see [`RetryPolicy`])
+    AttemptFailed {
+        /// Identifier of the task
+        element: Option<String>,
+        /// Number of the failed attempt, counting from 1
+        attempt: u32,
+        error: ErrorReport,
+        /// Delay before the next attempt, or `None` if retries are exhausted
+        retry_in: Option<Duration>,
+    },
+    /// Flow node has held its token longer than its timeout and has been cancelled (see
+    /// [`model::Model::with_task_timeout`])
+    FlowNodeTimedOut { node: String, timeout: Duration },
+    /// Activity has exceeded its execution time limit
+    ActivityTimedOut {
+        activity: Option<String>,
+        limit: Duration,
+    },
+    /// Activity has failed and holds its token until modified (see [`FailurePolicy::Suspend`])
+    ActivitySuspended {
+        activity: Option<String>,
+        /// Error the activity has failed with
+        error: Event,
+    },
+    /// Process instance has exceeded its resource budget
+    BudgetExceeded { budget: Budget, usage: Usage },
+    /// Token has been placed at a flow node bypassing the model (see `Handle::inject_token`)
+    TokenInjected { node: String },
+    /// Process instance has been modified by an operator (see [`Handle::modify`])
+    Modified {
+        /// Flow nodes whose tokens have been cancelled
+        cancelled: Vec<String>,
+        /// Flow nodes that have received new tokens
+        started: Vec<String>,
+        /// Variables that have been set
+        variables: Vec<String>,
+    },
+    /// Activity has been completed by an operator without executing it (see
+    /// [`ModificationBuilder::skip`])
+    ActivitySkipped {
+        activity: String,
+        /// All variables set by the same modification
+        variables: Vec<String>,
+    },
+    /// Join has been waiting for some of its incoming flows for longer than
+    /// [`model::Handle::join_diagnostics_threshold`]
+    StuckJoin {
+        /// Joining gateway
+        gateway: Option<String>,
+        /// Incoming sequence flows that haven't been taken yet
+        missing: Vec<String>,
+        /// Flow nodes currently holding tokens that can still reach the missing flows
+        upstream: Vec<String>,
+    },
+    /// Exclusive or inclusive gateway has decided which outgoing sequence flows to take
+    GatewayDecision {
+        gateway: Option<String>,
+        /// Sequence flows whose conditions have been evaluated, in order of evaluation
+        evaluated: Vec<String>,
+        /// Sequence flows taken
+        taken: Vec<String>,
+    },
+    #[cfg(feature = "invariants")]
+    /// Runtime invariant violation (see [`invariants`])
+    InvariantViolation { error: String },
+    /// Logs of the process instance have been dropped before reaching the model-wide bus
+    /// (see [`model::Handle::instance_logs`]), because it couldn't keep up
+    ///
+    /// Only appears in the model-wide bus.
+    LogsMissed {
+        /// Number of logs dropped
+        count: u64,
+    },
+    /// Process has reached its terminal outcome (see [`Handle::join`])
+    Done {
+        /// Terminal outcome
+        outcome: Outcome,
+        /// Total number of tokens processed
+        tokens: usize,
+        /// Wall-clock duration since process start
+        duration: Duration,
+        /// Number of times each flow node has been entered (flow node id => count)
+        visits: HashMap<String, usize>,
+    },
 }
 
 impl Process {
@@ -104,32 +746,104 @@ impl Process {
         Self {
             element: Arc::new(element),
             model,
+            id: None,
+        }
+    }
+
+    /// Creates a new process container sharing process definition with other containers
+    pub(crate) fn shared(element: Arc<Element>, model: model::Handle) -> Self {
+        Self {
+            element,
+            model,
+            id: None,
+        }
+    }
+
+    /// Consumes process container and returns it updated with a requested instance identifier
+    ///
+    /// By default, instances are assigned identifiers that are unique within the running
+    /// program. If an identifier is requested, ensuring its uniqueness is up to the caller.
+    pub fn with_instance_id(self, id: InstanceId) -> Self {
+        Self {
+            id: Some(id),
+            ..self
         }
     }
 
     /// Spawns process task
     pub async fn spawn(self) -> Handle {
+        let (handle, scheduler) = self.instance(false);
+        Self::launch(handle, scheduler).await
+    }
+
+    /// Spawns process task of a sandbox whose state is restored from a snapshot (see
+    /// [`Handle::fork`])
+    async fn spawn_sandbox(self, snapshot: Snapshot) -> Result<Handle, flow_node::StateError> {
+        let (handle, mut scheduler) = self.instance(true);
+        scheduler.restore(snapshot)?;
+        Ok(Self::launch(handle, scheduler).await)
+    }
+
+    /// Creates a handle of a new process instance along with its scheduler
+    fn instance(self, sandboxed: bool) -> (Handle, Scheduler) {
         let (sender, receiver) = mpsc::channel(1);
         let (log_broadcast, _) = broadcast::channel(128);
         let (event_broadcast, _) = broadcast::channel(128);
+        let (termination_sender, termination) = watch::channel(None);
         let element = self.element.clone();
         let handle = Handle {
+            id: self.id.unwrap_or_else(InstanceId::next),
             sender: sender.clone(),
             model: self.model.clone(),
             log_broadcast,
             event_broadcast,
+            event_demultiplexer: Demultiplexer::default(),
+            timers: Timers::new(self.model.timer_pause(), self.model.clock_adjustments()),
+            subscriptions: Subscriptions::default(),
+            usage: Default::default(),
+            business_key: Default::default(),
+            priority: Default::default(),
+            started_at: Default::default(),
+            starting: Default::default(),
+            termination,
             element,
+            sandboxed,
+            services: Default::default(),
+            manual_tasks: Default::default(),
         };
 
-        let scheduler = Scheduler::new(receiver, handle.clone());
+        let scheduler = Scheduler::new(receiver, handle.clone(), termination_sender);
+        (handle, scheduler)
+    }
+
+    /// Runs the scheduler of a process instance
+    async fn launch(handle: Handle, scheduler: Scheduler) -> Handle {
+        if !handle.sandboxed {
+            handle.forward_to_model();
+        }
+
         let join_handle = task::spawn(async move { scheduler.run().await });
 
-        let _ = sender.send(Request::JoinHandle(join_handle)).await;
+        let _ = handle.sender.send(Request::JoinHandle(join_handle)).await;
         handle
     }
 }
 
 impl Handle {
+    /// Hands the process instance over to the model and returns a weak handle to it
+    ///
+    /// Detached instance keeps running and can be retrieved later by its identifier (see
+    /// [`model::Handle::instance`]). It is terminated along with the model.
+    pub async fn detach(self) -> WeakHandle {
+        let weak = WeakHandle {
+            id: self.id,
+            model: self.model.clone(),
+        };
+        let model = self.model.clone();
+        model.adopt(self).await;
+        weak
+    }
+
     /// Request and wait for model execution termination
     pub async fn terminate(self) {
         let (sender, receiver) = oneshot::channel();
@@ -139,15 +853,360 @@ impl Handle {
         }
     }
 
+    /// Stops the process immediately, without reaching any terminal outcome
+    pub(crate) async fn abort(&self) {
+        let _ = self.sender.send(Request::Abort).await;
+    }
+
     /// Request explicit process start
+    ///
+    /// If the model limits active instances, the start is subject to model's admission policy
+    /// (see [`StartBuilder::start`]).
+    ///
+    /// This is a shorthand for `handle.start_with().start()`.
     pub async fn start(&self) -> Result<(), StartError> {
+        self.start_with().start().await
+    }
+
+    /// Returns a builder to start the process with initial variables, a business key or
+    /// a specific start event
+    pub fn start_with(&self) -> StartBuilder<'_> {
+        self.start_request(StartRequest::default())
+    }
+
+    /// Returns a builder to start the process according to a request
+    pub fn start_request(&self, request: StartRequest) -> StartBuilder<'_> {
+        StartBuilder {
+            handle: self,
+            request,
+        }
+    }
+
+    /// Returns a builder to modify the running process instance (see [`modification`])
+    pub fn modify(&self) -> ModificationBuilder<'_> {
+        ModificationBuilder {
+            handle: self,
+            modifications: vec![],
+        }
+    }
+
+    /// Places a token at an arbitrary flow node, setting variables first
+    ///
+    /// Unlike [`Handle::modify`], this works on process instances that haven't been started
+    /// yet (which are then started without going through their start events), so that deep
+    /// regions of a model can be tested in isolation or an instance can be recovered by hand.
+    /// The token comes through the first incoming sequence flow of the flow node. Neither
+    /// the token nor the variables are placed if either the flow node or any variable
+    /// doesn't exist.
+    ///
+    /// Starting an instance this way goes through admission and takes concurrency permits
+    /// just like [`StartBuilder::start`].
+    ///
+    /// Nothing prevents the token from breaking the semantics of the model, which is why this
+    /// is only available with the `unsafe-ops` feature.
+    #[cfg(feature = "unsafe-ops")]
+    pub async fn inject_token(
+        &self,
+        node: &str,
+        variables: Variables,
+    ) -> Result<(), ModificationError> {
+        // only the first start of an instance is admitted
+        let starting = self.state() == InstanceState::NotStarted
+            && !self.starting.swap(true, Ordering::SeqCst);
+        let permits = if starting {
+            match self.model.admit(self.element.id.as_deref()).await {
+                Ok(permits) => permits,
+                Err(err) => {
+                    self.starting.store(false, Ordering::SeqCst);
+                    return Err(match err {
+                        StartError::Overloaded => ModificationError::Overloaded,
+                        _ => ModificationError::NotReceived,
+                    });
+                }
+            }
+        } else {
+            vec![]
+        };
         let (sender, receiver) = oneshot::channel();
-        let _ = self.sender.send(Request::Start(sender)).await;
-        if let Ok(result) = receiver.await {
-            result
+        let _ = self
+            .sender
+            .send(Request::Inject(node.to_owned(), variables, sender))
+            .await;
+        let result = receiver
+            .await
+            .unwrap_or(Err(ModificationError::NotReceived));
+        if starting {
+            if result.is_err() {
+                self.starting.store(false, Ordering::SeqCst);
+            } else {
+                self.started_at.lock().unwrap().replace(time::now());
+                if !permits.is_empty() {
+                    // hold the permits until the process is over
+                    let handle = self.clone();
+                    task::spawn(async move {
+                        handle.join().await;
+                        drop(permits);
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns business key of the process instance, if it has been started with one
+    pub fn business_key(&self) -> Option<String> {
+        self.business_key.lock().unwrap().clone()
+    }
+
+    /// Returns priority of the process instance
+    ///
+    /// Running instances of lower priority give way to running instances of higher priority
+    /// (see [`priority`]).
+    pub fn priority(&self) -> Priority {
+        *self.priority.lock().unwrap()
+    }
+
+    /// Changes priority of the process instance
+    pub async fn set_priority(&self, priority: Priority) {
+        *self.priority.lock().unwrap() = priority;
+        let _ = self.sender.send(Request::PriorityChanged).await;
+    }
+
+    /// Returns when the process instance has been started (with [`Handle::start`] or
+    /// [`StartBuilder::start`]), if it has
+    pub fn started_at(&self) -> Option<SystemTime> {
+        *self.started_at.lock().unwrap()
+    }
+
+    /// Returns current state of the process instance
+    pub fn state(&self) -> InstanceState {
+        if let Some(ref termination) = *self.termination.borrow() {
+            InstanceState::Finished(termination.outcome)
+        } else if self.started_at().is_some() {
+            InstanceState::Running
         } else {
-            Err(StartError::NotReceived)
+            InstanceState::NotStarted
+        }
+    }
+
+    /// Sets initial variables, starts the process and waits for it to reach its terminal
+    /// outcome (for no longer than `timeout`, if specified)
+    pub async fn start_and_wait(
+        &self,
+        variables: Variables,
+        timeout: Option<Duration>,
+    ) -> Result<ProcessOutcome, StartError> {
+        self.start_with().variables(variables).start().await?;
+        let termination = match timeout {
+            Some(duration) => match time::timeout(duration, self.termination()).await {
+                Ok(termination) => termination,
+                Err(_) => return Ok(ProcessOutcome::TimedOut),
+            },
+            None => self.termination().await,
+        };
+        Ok(match termination.outcome {
+            Outcome::Completed => ProcessOutcome::Completed(termination.variables.as_ref().clone()),
+            outcome => ProcessOutcome::NotCompleted(outcome),
+        })
+    }
+
+    /// Waits for the process to reach its terminal outcome
+    ///
+    /// Process is considered completed once it has been started and none of its flow nodes
+    /// hold any tokens.
+    pub async fn join(&self) -> Outcome {
+        self.termination().await.outcome
+    }
+
+    /// Waits for the process to complete and returns its final variables
+    ///
+    /// Returns [`ResultError::NotCompleted`] if the process has reached any other outcome.
+    pub async fn result(&self) -> Result<Variables, ResultError> {
+        let termination = self.termination().await;
+        match termination.outcome {
+            Outcome::Completed => Ok(termination.variables.as_ref().clone()),
+            outcome => Err(ResultError::NotCompleted(outcome)),
+        }
+    }
+
+    /// Waits for the process to reach its terminal outcome and returns the incident
+    /// that has failed it, if any
+    pub async fn incident(&self) -> Option<Incident> {
+        self.termination().await.incident
+    }
+
+    async fn termination(&self) -> Termination {
+        let mut termination = self.termination.clone();
+        loop {
+            if let Some(result) = termination.borrow().clone() {
+                return result;
+            }
+            if termination.changed().await.is_err() {
+                return termination.borrow().clone().unwrap_or(Termination {
+                    outcome: Outcome::Cancelled,
+                    variables: Default::default(),
+                    incident: None,
+                });
+            }
+        }
+    }
+
+    /// Returns a future that resolves once the process instance has reached its terminal
+    /// outcome (or its scheduler is gone), without holding on to the instance
+    pub(crate) fn terminated(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut termination = self.termination.clone();
+        async move {
+            while termination.borrow().is_none() {
+                if termination.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Forks the process into a detached sandbox process
+    ///
+    /// The fork starts with a copy of the full state of this process (flow nodes and data
+    /// objects), but has its own event and log broadcasts, so anything done to it doesn't affect
+    /// this process. Side-effecting flow nodes (such as script tasks) are stubbed in the
+    /// sandbox: they complete without doing any actual work.
+    ///
+    /// This is useful for "what-if" analysis.
+    pub async fn fork(&self) -> Result<Handle, ForkError> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.sender.send(Request::Snapshot(sender)).await;
+        let snapshot = receiver.await.map_err(|_| ForkError::NotReceived)?;
+        let process = Process {
+            element: self.element.clone(),
+            model: self.model.clone(),
+            id: None,
+        };
+        let fork = process.spawn_sandbox(snapshot).await?;
+        // the fork is as started as this process, so it doesn't go through admission
+        *fork.started_at.lock().unwrap() = self.started_at();
+        fork.starting
+            .store(self.starting.load(Ordering::SeqCst), Ordering::SeqCst);
+        *fork.business_key.lock().unwrap() = self.business_key();
+        fork.set_priority(self.priority()).await;
+        Ok(fork)
+    }
+
+    /// Waits until none of the flow nodes of the process is ready to make progress
+    pub(crate) async fn settled(&self) {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.sender.send(Request::Settled(sender)).await;
+        let _ = receiver.await;
+    }
+
+    /// Inspects process state
+    pub async fn inspect(&self) -> Result<Inspection, InspectionError> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.sender.send(Request::Snapshot(sender)).await;
+        receiver
+            .await
+            .map(Inspection::from)
+            .map_err(|_| InspectionError::NotReceived)
+    }
+
+    /// Returns, for every incoming sequence flow of a flow node, the number of tokens that can
+    /// still arrive through it
+    ///
+    /// These are tokens held by flow nodes the sequence flow can be reached from (without
+    /// passing through the flow node itself), which is what joins such as inclusive gateways
+    /// wait for. Returns `None` if the flow node is not found (or the process is over).
+    pub async fn tokens_upstream_of(&self, node: &str) -> Option<Vec<usize>> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self
+            .sender
+            .send(Request::TokensUpstream(node.to_string(), sender))
+            .await;
+        receiver.await.ok().flatten()
+    }
+
+    /// Returns process instance identifier
+    pub fn id(&self) -> InstanceId {
+        self.id
+    }
+
+    /// Registers a handler of service tasks with a topic (see [`crate::activity::service_task`])
+    ///
+    /// The handler receives variables of the task and returns variables to set. Service task
+    /// waits for its handler to complete before passing its token on. Registering another
+    /// handler with the same topic replaces the previous one.
+    pub fn register_service<S, F, Fut>(&self, topic: S, handler: F)
+    where
+        S: Into<String>,
+        F: Fn(Variables) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ServiceResult> + Send + 'static,
+    {
+        let handler: ServiceHandler = Arc::new(move |variables| handler(variables).boxed());
+        self.services.lock().unwrap().insert(topic.into(), handler);
+    }
+
+    /// Registers a typed handler of service tasks with a topic (see
+    /// [`Handle::register_service`])
+    ///
+    /// Variables of the task are deserialized into the handler's input, and fields of its
+    /// output are set as variables (holding JSON values). Variables that don't match the input
+    /// and outputs that don't serialize into a struct or a map fail the task with
+    /// [`ServiceError::InvalidInput`] and [`ServiceError::InvalidOutput`], respectively.
+    pub fn register_typed_service<In, Out, S, F, Fut>(&self, topic: S, handler: F)
+    where
+        In: DeserializeOwned + Send + 'static,
+        Out: Serialize + 'static,
+        S: Into<String>,
+        F: Fn(In) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Out, ServiceError>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.register_service(topic, move |variables| {
+            let input = service_task::typed_input::<In>(variables);
+            let handler = handler.clone();
+            async move {
+                let output = handler(input?).await?;
+                service_task::typed_output(&output)
+            }
+        });
+    }
+
+    /// Returns a handler of service tasks registered with a topic, if any
+    pub(crate) fn service(&self, topic: &str) -> Option<ServiceHandler> {
+        self.services.lock().unwrap().get(topic).cloned()
+    }
+
+    /// Completes a manual task (see [`crate::activity::manual_task`])
+    ///
+    /// If the manual task holds more than one token, the one that has been waiting the
+    /// longest is passed on.
+    pub fn complete_manual_task(&self, node: &str) -> Result<(), ManualTaskError> {
+        let mut manual_tasks = self.manual_tasks.lock().unwrap();
+        if let Some(waiting) = manual_tasks.get_mut(node) {
+            // tokens that are gone (for example, cancelled) are skipped
+            while let Some(completion) = waiting.pop_front() {
+                if completion.send(()).is_ok() {
+                    return Ok(());
+                }
+            }
+            manual_tasks.remove(node);
         }
+        Err(ManualTaskError::NotWaiting(node.to_string()))
+    }
+
+    /// Returns a receiver that is notified once a token waiting at a manual task is completed
+    pub(crate) fn await_manual_task(&self, node: String) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.manual_tasks
+            .lock()
+            .unwrap()
+            .entry(node)
+            .or_default()
+            .push_back(sender);
+        receiver
+    }
+
+    /// Returns `true` if this process is a sandbox (see [`Handle::fork`])
+    pub fn is_sandboxed(&self) -> bool {
+        self.sandboxed
     }
 
     /// Returns model handle
@@ -160,6 +1219,13 @@ impl Handle {
         self.log_broadcast.subscribe()
     }
 
+    /// Returns a stream of logs
+    ///
+    /// If the consumer lags behind, logs it has missed are skipped.
+    pub fn logs(&self) -> impl Stream<Item = Log> + Send + 'static {
+        broadcast_stream(self.log_receiver())
+    }
+
     /// Returns log broadcaster
     pub fn log_broadcast(&self) -> broadcast::Sender<Log> {
         self.log_broadcast.clone()
@@ -171,16 +1237,83 @@ impl Handle {
     }
 
     /// Returns event receiver
-    pub fn event_receiver(&self) -> broadcast::Receiver<Event> {
+    pub fn event_receiver(&self) -> broadcast::Receiver<Envelope> {
         self.event_broadcast.subscribe()
     }
 
+    /// Returns a receiver of events of a particular kind only
+    ///
+    /// Only events sent through [`Handle::send_event`] (or emitted by flow nodes)
+    /// are delivered to such receivers.
+    pub fn subscribe(&self, kind: EventKind) -> broadcast::Receiver<Envelope> {
+        self.event_demultiplexer.subscribe(kind)
+    }
+
+    /// Returns a stream of events
+    ///
+    /// If the consumer lags behind, events it has missed are skipped.
+    pub fn events(&self) -> impl Stream<Item = Envelope> + Send + 'static {
+        broadcast_stream(self.event_receiver())
+    }
+
+    /// Returns a stream of events of a particular kind only (see [`Handle::subscribe`])
+    ///
+    /// If the consumer lags behind, events it has missed are skipped.
+    pub fn events_of(&self, kind: EventKind) -> impl Stream<Item = Envelope> + Send + 'static {
+        broadcast_stream(self.subscribe(kind))
+    }
+
     /// Returns event broadcaster
-    pub fn event_broadcast(&self) -> broadcast::Sender<Event> {
+    pub fn event_broadcast(&self) -> broadcast::Sender<Envelope> {
         self.event_broadcast.clone()
     }
 
-    /// Returns a data object container
+    /// Sends an event to the process from outside
+    pub fn send_event(&self, event: Event) {
+        self.broadcast_event(Envelope::new(event, None, self.id));
+    }
+
+    /// Emits an event on behalf of a flow node
+    pub(crate) fn emit_event(&self, source: Option<String>, event: Event) {
+        self.broadcast_event(Envelope::new(event, source, self.id));
+    }
+
+    fn broadcast_event(&self, envelope: Envelope) {
+        self.event_demultiplexer.dispatch(&envelope);
+        let _ = self.event_broadcast.send(envelope);
+    }
+
+    /// Sets a variable (data object)
+    ///
+    /// Conditions of conditional events the process is waiting for are re-evaluated, and
+    /// those that are satisfied are triggered.
+    pub async fn set_variable(
+        &self,
+        id: &str,
+        value: Box<dyn DataObject>,
+    ) -> Result<(), DataObjectError> {
+        self.update_variable(id, |data_object| *data_object = value)
+            .await
+    }
+
+    /// Updates a variable (data object) in place
+    ///
+    /// This is the write path every variable change goes through, so that conditions
+    /// of conditional events are re-evaluated no matter what changed the variable.
+    pub(crate) async fn update_variable<F>(
+        &self,
+        id: &str,
+        update: F,
+    ) -> Result<(), DataObjectError>
+    where
+        F: FnOnce(&mut Box<dyn DataObject>),
+    {
+        update(&mut *self.data_object(id).await?.write().await);
+        let _ = self.sender.send(Request::VariablesChanged).await;
+        Ok(())
+    }
+
+    /// Returns a data object container
     pub async fn data_object(&self, id: &str) -> Result<DataObjectContainer, DataObjectError> {
         let (sender, receiver) = oneshot::channel();
         let _ = self
@@ -193,16 +1326,191 @@ impl Handle {
             Err(DataObjectError::NotReceived)
         }
     }
+
+    /// Returns pending timers, earliest first
+    pub fn timers(&self) -> Vec<PendingTimer> {
+        self.timers.list()
+    }
+
+    /// Reschedules a pending timer to be due at `due`
+    pub fn reschedule_timer(&self, id: TimerId, due: SystemTime) -> Result<(), TimerError> {
+        self.timers.reschedule(id, due)
+    }
+
+    /// Fires a pending timer immediately
+    pub fn fire_timer(&self, id: TimerId) -> Result<(), TimerError> {
+        self.timers.fire(id)
+    }
+
+    /// Returns messages and signals flow nodes are currently waiting for
+    pub fn subscriptions(&self) -> Vec<Subscription> {
+        self.subscriptions.list()
+    }
+
+    /// Returns registry of message and signal subscriptions
+    pub(crate) fn subscription_registry(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    /// Returns registry of pending timers
+    pub(crate) fn timer_registry(&self) -> &Timers {
+        &self.timers
+    }
+
+    /// Returns resources used by the process instance so far
+    pub fn usage(&self) -> Usage {
+        Usage {
+            steps: self.usage.steps.load(Ordering::Relaxed),
+            execution_time: Duration::from_nanos(self.usage.execution_time.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Accounts for a scheduler step
+    pub(crate) fn count_step(&self) {
+        self.usage.steps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for time spent evaluating a script or an expression
+    pub(crate) fn count_execution_time(&self, duration: Duration) {
+        self.usage
+            .execution_time
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Forwards events and logs of the process instance to model-wide buses
+    /// (see [`model::Handle::instance_events`]) until the instance terminates
+    fn forward_to_model(&self) {
+        let (event_bus, log_bus) = self.model.instance_buses();
+        let mut events = self.event_receiver();
+        let mut logs = self.log_receiver();
+        let instance = self.id;
+        let terminated = self.terminated();
+        task::spawn(async move {
+            tokio::pin!(terminated);
+            loop {
+                tokio::select! {
+                    _ = &mut terminated => {
+                        // forward whatever has been sent before termination
+                        loop {
+                            match events.try_recv() {
+                                Ok(envelope) => {
+                                    let _ = event_bus.send(envelope);
+                                }
+                                Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+                                Err(_) => break,
+                            }
+                        }
+                        loop {
+                            let log = match logs.try_recv() {
+                                Ok(log) => log,
+                                Err(broadcast::error::TryRecvError::Lagged(count)) => {
+                                    Log::LogsMissed { count }
+                                }
+                                Err(_) => break,
+                            };
+                            let _ = log_bus.send(model::InstanceLog { instance, log });
+                        }
+                        return;
+                    }
+                    event = events.recv() => match event {
+                        Ok(envelope) => {
+                            let _ = event_bus.send(envelope);
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                    log = logs.recv() => {
+                        // dropped logs are reported, so that consumers can tell
+                        let log = match log {
+                            Ok(log) => log,
+                            Err(broadcast::error::RecvError::Lagged(count)) => {
+                                Log::LogsMissed { count }
+                            }
+                            Err(broadcast::error::RecvError::Closed) => return,
+                        };
+                        let _ = log_bus.send(model::InstanceLog { instance, log });
+                    }
+                }
+            }
+        });
+    }
+
+    /// Returns failure policy of an activity of the process, if any
+    ///
+    /// Policy of the activity takes precedence over policy of the process.
+    pub fn failure_policy(&self, activity: Option<&str>) -> Option<FailurePolicy> {
+        let model = self.model();
+        activity
+            .and_then(|id| model.failure_policy(id))
+            .or_else(|| {
+                self.element()
+                    .id
+                    .as_deref()
+                    .and_then(|id| model.failure_policy(id))
+            })
+    }
+
+    /// Returns retry policy of a task of the process, if any
+    ///
+    /// Policy of the task takes precedence over policy of the process.
+    pub fn retry_policy(&self, task: Option<&str>) -> Option<RetryPolicy> {
+        let model = self.model();
+        task.and_then(|id| model.retry_policy(id)).or_else(|| {
+            self.element()
+                .id
+                .as_deref()
+                .and_then(|id| model.retry_policy(id))
+        })
+    }
+
+    /// Fails the process with an incident
+    pub(crate) async fn raise_incident(&self, incident: Incident) {
+        let _ = self.sender.send(Request::Incident(incident)).await;
+    }
+
+    /// Passes the token of an activity that has failed with an error on to an error boundary
+    /// event attached to the activity, returning false if none of them catches the error
+    pub(crate) async fn catch_on_boundary(&self, activity: &str, error: Event) -> bool {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self
+            .sender
+            .send(Request::BoundaryError(activity.to_string(), error, sender))
+            .await;
+        receiver.await.unwrap_or(false)
+    }
+}
+
+// Turns a broadcast receiver into a stream that skips missed items
+pub(crate) fn broadcast_stream<T>(
+    receiver: broadcast::Receiver<T>,
+) -> impl Stream<Item = T> + Send + 'static
+where
+    T: Clone + Send + 'static,
+{
+    stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(item) => return Some((item, receiver)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Log, StartError};
+    use super::{
+        Execution, ExecutionListener, Incident, ListenerScope, Log, Outcome, ProcessOutcome,
+        ResultError, StartError, TimeoutAction, Variables,
+    };
     use crate::bpmn::parse;
     use crate::bpmn::schema::*;
+    use crate::flow_node;
     use crate::model;
     use crate::test::*;
     use bpxe_internal_macros as bpxe_im;
+    use std::sync::{Arc, Mutex};
 
     #[bpxe_im::test]
     async fn no_start_event() {
@@ -255,6 +1563,174 @@ mod tests {
         model.terminate().await;
     }
 
+    #[bpxe_im::test]
+    async fn already_running() {
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        assert_eq!(handle.start().await, Err(StartError::AlreadyRunning));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn ambiguous_start_events() {
+        let definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![
+                    StartEvent {
+                        id: Some("start1".into()),
+                        event_definitions: vec![SignalEventDefinition::default().into()],
+                        ..Default::default()
+                    }
+                    .into(),
+                    StartEvent {
+                        id: Some("start2".into()),
+                        event_definitions: vec![MessageEventDefinition::default().into()],
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert_eq!(
+            handle.start().await,
+            Err(StartError::AmbiguousStartEvents {
+                start_events: vec!["start1".into(), "start2".into()]
+            })
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn start_with() {
+        let definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![
+                    StartEvent {
+                        id: Some("start1".into()),
+                        event_definitions: vec![SignalEventDefinition::default().into()],
+                        ..Default::default()
+                    }
+                    .into(),
+                    StartEvent {
+                        id: Some("start2".into()),
+                        event_definitions: vec![MessageEventDefinition::default().into()],
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.log_receiver());
+        assert_eq!(
+            handle.start_with().start_event("start3").start().await,
+            Err(StartError::UnknownStartEvent("start3".into()))
+        );
+        assert!(handle
+            .start_with()
+            .start_event("start2")
+            .business_key("order-1")
+            .start()
+            .await
+            .is_ok());
+        assert_eq!(handle.business_key().as_deref(), Some("order-1"));
+        assert!(
+            mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                    if visits.get("start2") == Some(&1) && !visits.contains_key("start1"))
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn invalid_definition() {
+        let definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![
+                    StartEvent {
+                        id: Some("start".into()),
+                        outgoings: vec!["s1".into()],
+                        ..Default::default()
+                    }
+                    .into(),
+                    SequenceFlow {
+                        id: Some("s1".into()),
+                        source_ref: "start".into(),
+                        target_ref: "nowhere".into(),
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(matches!(
+            handle.start().await,
+            Err(StartError::InvalidDefinition { .. })
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn compensation_activity_in_flow() {
+        let mut definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![
+                    StartEvent {
+                        id: Some("start".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                    ScriptTask {
+                        id: Some("compensation".into()),
+                        is_for_compensation: Some(true),
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+        definitions
+            .find_by_id_mut("proc1")
+            .unwrap()
+            .downcast_mut::<Process>()
+            .unwrap()
+            .establish_sequence_flow("start", "compensation", "s1", None::<FormalExpression>)
+            .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(matches!(
+            handle.start().await,
+            Err(StartError::InvalidDefinition { reason }) if reason.contains("compensation")
+        ));
+        model.terminate().await;
+    }
+
     #[bpxe_im::test]
     async fn multiple_start_events() {
         let definitions = Definitions {
@@ -364,40 +1840,1040 @@ mod tests {
         model.terminate().await;
     }
 
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl ExecutionListener for Recorder {
+        async fn on_enter(&self, execution: &Execution<'_>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("enter {}", execution.element));
+        }
+
+        async fn on_leave(&self, execution: &Execution<'_>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("leave {}", execution.element));
+        }
+
+        async fn on_take(&self, execution: &Execution<'_>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("take {}", execution.element));
+        }
+    }
+
     #[bpxe_im::test]
-    async fn data_object() {
-        use crate::data_object;
+    async fn execution_listeners() {
+        let definitions = parse(include_str!("event/test_models/throw_none_event.bpmn")).unwrap();
+        let all = Arc::new(Mutex::new(vec![]));
+        let throw = Arc::new(Mutex::new(vec![]));
+        let other = Arc::new(Mutex::new(vec![]));
+        let model = model::Model::new(definitions)
+            .with_execution_listener(
+                ListenerScope::Process("proc1".into()),
+                Recorder(all.clone()),
+            )
+            .with_execution_listener(
+                ListenerScope::Element("throw".into()),
+                Recorder(throw.clone()),
+            )
+            .with_execution_listener(
+                ListenerScope::Process("proc2".into()),
+                Recorder(other.clone()),
+            )
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Completed);
+        assert_eq!(
+            *all.lock().unwrap(),
+            vec![
+                "enter start",
+                "leave start",
+                "take Flow_1sib8ni",
+                "enter throw",
+                "leave throw",
+                "take Flow_15ao09k",
+                "enter end",
+                "leave end"
+            ]
+        );
+        assert_eq!(*throw.lock().unwrap(), vec!["enter throw", "leave throw"]);
+        assert!(other.lock().unwrap().is_empty());
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn action_middleware() {
+        let definitions = parse(include_str!("gateway/test_models/parallel_fork.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_action_middleware(|_: &super::Handle, node: &str, action: flow_node::Action| {
+                match action {
+                    // only the first path is enabled
+                    flow_node::Action::Flow(mut indices) if node == "fork" => {
+                        indices.retain(|index| *index == 0);
+                        Some(flow_node::Action::Flow(indices))
+                    }
+                    action => Some(action),
+                }
+            })
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                if visits.get("f1") == Some(&1) && visits.get("f2").is_none())
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn join_completed() {
+        let definitions = parse(include_str!("event/test_models/throw_none_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        assert_eq!(timeout(handle.join()).await.unwrap(), Outcome::Completed);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn done_summary() {
+        let definitions = parse(include_str!("event/test_models/throw_none_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, tokens: 3, visits, .. }
+                if ["start", "throw", "end"].iter().all(|id| visits.get(*id) == Some(&1)))
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn join_terminated() {
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        // Waiting for the signal, so it's not complete
+        assert!(expects_timeout(handle.join()).await.is_ok());
+
+        let join = handle.clone();
+        model.terminate().await;
+        assert_eq!(timeout(join.join()).await.unwrap(), Outcome::Terminated);
+    }
+
+    #[bpxe_im::test]
+    async fn result() {
         use serde_json::json;
         let definitions = parse(include_str!("process/test_models/data_object.bpmn")).unwrap();
         let model = model::Model::new(definitions).spawn().await;
         let handle = model.processes().await.unwrap().pop().unwrap();
-        let data_object = handle.data_object("data_object").await.unwrap();
-        let read = data_object.read().await;
-        // It should be empty
-        assert!(read.downcast_ref::<data_object::Empty>().is_some());
-        drop(read);
-        let mut write = data_object.write().await;
-        // Write something into it
-        *write = Box::new(json!({"test": "passed"}));
-        drop(write);
-        // Get it again
-        let data_object = handle.data_object("data_object").await.unwrap();
-        let read = data_object.read().await;
-        // It should have the value
-        assert_eq!(
-            read.downcast_ref::<serde_json::Value>().unwrap(),
-            &json!({"test": "passed"})
-        );
-        // Since `data_object` is actual a data object reference to `DataObject`, let's check the
-        // actual object.
         let data_object = handle.data_object("DataObject").await.unwrap();
-        let read = data_object.read().await;
-        // It should have the value
+        *data_object.write().await = Box::new(json!({"test": "passed"}));
+        assert!(handle.start().await.is_ok());
+        let variables = timeout(handle.result()).await.unwrap().unwrap();
         assert_eq!(
-            read.downcast_ref::<serde_json::Value>().unwrap(),
-            &json!({"test": "passed"})
+            variables
+                .get("DataObject")
+                .and_then(|v| v.downcast_ref::<serde_json::Value>()),
+            Some(&json!({"test": "passed"}))
         );
-
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn result_terminated() {
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        let result = handle.clone();
+        model.terminate().await;
+        assert!(matches!(
+            timeout(result.result()).await.unwrap(),
+            Err(ResultError::NotCompleted(Outcome::Terminated))
+        ));
+    }
+
+    #[bpxe_im::test]
+    async fn start_and_wait() {
+        use serde_json::json;
+        use std::time::Duration;
+        let definitions = parse(include_str!("process/test_models/data_object.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+
+        let mut variables = Variables::new();
+        variables.insert("unknown".into(), Box::new(json!(1)));
+        assert!(matches!(
+            handle.start_and_wait(variables, None).await,
+            Err(StartError::UnknownDataObject(id)) if id == "unknown"
+        ));
+
+        let mut variables = Variables::new();
+        variables.insert("DataObject".into(), Box::new(json!({"test": "passed"})));
+        match handle
+            .start_and_wait(variables, Some(Duration::from_secs(1)))
+            .await
+        {
+            Ok(ProcessOutcome::Completed(variables)) => assert_eq!(
+                variables
+                    .get("DataObject")
+                    .and_then(|v| v.downcast_ref::<serde_json::Value>()),
+                Some(&json!({"test": "passed"}))
+            ),
+            _ => panic!("process should have completed"),
+        }
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn rejected_start_keeps_variables() {
+        use super::Priority;
+        use serde_json::json;
+        let definitions = parse(include_str!("process/test_models/data_object.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert_eq!(
+            handle
+                .start_with()
+                .variable("DataObject", Box::new(json!(1)))
+                .priority(Priority::High)
+                .start_event("unknown")
+                .start()
+                .await,
+            Err(StartError::UnknownStartEvent("unknown".into()))
+        );
+        let container = handle.data_object("DataObject").await.unwrap();
+        assert!(container
+            .read()
+            .await
+            .downcast_ref::<serde_json::Value>()
+            .is_none());
+        assert_eq!(handle.priority(), Priority::default());
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn start_and_wait_timeout() {
+        use std::time::Duration;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(matches!(
+            handle
+                .start_and_wait(Variables::new(), Some(Duration::from_millis(50)))
+                .await,
+            Ok(ProcessOutcome::TimedOut)
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn fork() {
+        use super::InstanceState;
+        use crate::event::ProcessEvent;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut mailbox = Mailbox::new(handle.event_receiver());
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        let fork = handle.fork().await.unwrap();
+        assert!(fork.is_sandboxed());
+        assert!(!handle.is_sandboxed());
+        assert_eq!(fork.state(), InstanceState::Running);
+        assert_eq!(fork.started_at(), handle.started_at());
+        assert_eq!(fork.start().await, Err(StartError::AlreadyRunning));
+        let mut fork_mailbox = Mailbox::new(fork.event_receiver());
+
+        // Signal the fork only
+        fork.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal".into()),
+        });
+
+        assert!(
+            fork_mailbox
+                .receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+                .await
+        );
+
+        // The original process is not affected
+        assert!(expects_timeout(
+            mailbox.receive(|e| matches!(&e.event, ProcessEvent::SignalEvent { signal_ref } if signal_ref.as_ref().unwrap() == "report"))
+        )
+        .await
+        .is_ok());
+
+        fork.terminate().await;
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn fork_invalid_state() {
+        use crate::flow_node::StateError;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let _ = handle.sender.send(super::Request::Snapshot(sender)).await;
+        let mut snapshot = receiver.await.unwrap();
+        // start event can't be restored from the state of a catch event
+        let catch = snapshot.flow_nodes.remove("catch").unwrap();
+        snapshot.flow_nodes.insert("start".into(), catch);
+        let process = super::Process {
+            element: handle.element(),
+            model: handle.model(),
+            id: None,
+        };
+        assert!(matches!(
+            process.spawn_sandbox(snapshot).await,
+            Err(StateError::InvalidVariant)
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn inspect() {
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        let inspection = handle.inspect().await.unwrap();
+        let catch = inspection.flow_nodes.get("catch").unwrap();
+        assert_eq!(catch.tokens, 1);
+        assert_eq!(catch.active_tokens, 1);
+        assert!(matches!(
+            catch.state,
+            crate::flow_node::State::IntermediateCatchEvent(_)
+        ));
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn data_object() {
+        use crate::data_object;
+        use serde_json::json;
+        let definitions = parse(include_str!("process/test_models/data_object.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let data_object = handle.data_object("data_object").await.unwrap();
+        let read = data_object.read().await;
+        // It should be empty
+        assert!(read.downcast_ref::<data_object::Empty>().is_some());
+        drop(read);
+        let mut write = data_object.write().await;
+        // Write something into it
+        *write = Box::new(json!({"test": "passed"}));
+        drop(write);
+        // Get it again
+        let data_object = handle.data_object("data_object").await.unwrap();
+        let read = data_object.read().await;
+        // It should have the value
+        assert_eq!(
+            read.downcast_ref::<serde_json::Value>().unwrap(),
+            &json!({"test": "passed"})
+        );
+        // Since `data_object` is actual a data object reference to `DataObject`, let's check the
+        // actual object.
+        let data_object = handle.data_object("DataObject").await.unwrap();
+        let read = data_object.read().await;
+        // It should have the value
+        assert_eq!(
+            read.downcast_ref::<serde_json::Value>().unwrap(),
+            &json!({"test": "passed"})
+        );
+
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn subscribe() {
+        use crate::event::{EventKind, ProcessEvent};
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut signals = handle.subscribe(EventKind::SignalEvent);
+        assert!(handle.start().await.is_ok());
+
+        handle.send_event(ProcessEvent::NoneEvent);
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal".into()),
+        });
+
+        let envelope = signals.recv().await.unwrap();
+        assert_eq!(
+            envelope.event,
+            ProcessEvent::SignalEvent {
+                signal_ref: Some("signal".into())
+            }
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn concurrency_limit() {
+        use crate::event::ProcessEvent;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_concurrency_limit("proc1", 1)
+            .spawn()
+            .await;
+        let first = model.processes().await.unwrap().pop().unwrap();
+        let second = super::Process::new((*first.element()).clone(), model.clone())
+            .spawn()
+            .await;
+        assert!(first.start().await.is_ok());
+
+        // the second instance waits for the first one to complete
+        let start = second.start();
+        futures::pin_mut!(start);
+        assert!(expects_timeout(&mut start).await.is_ok());
+        assert_eq!(model.queued_starts(), 1);
+
+        first.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal".into()),
+        });
+        assert!(start.await.is_ok());
+
+        second.terminate().await;
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn streams() {
+        use crate::event::{EventKind, ProcessEvent};
+        use futures::StreamExt;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let signals = handle.events_of(EventKind::SignalEvent);
+        futures::pin_mut!(signals);
+        let logs = handle.logs();
+        futures::pin_mut!(logs);
+        assert!(handle.start().await.is_ok());
+        while let Some(log) = logs.next().await {
+            if matches!(log, Log::FlowNodeIncoming { ref node, .. } if node.id().as_deref() == Some("catch"))
+            {
+                break;
+            }
+        }
+
+        handle.send_event(ProcessEvent::NoneEvent);
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal".into()),
+        });
+        assert_eq!(
+            signals.next().await.unwrap().event,
+            ProcessEvent::SignalEvent {
+                signal_ref: Some("signal".into())
+            }
+        );
+        let mut done = logs.filter(|log| futures::future::ready(matches!(log, Log::Done { .. })));
+        assert!(matches!(
+            done.next().await,
+            Some(Log::Done {
+                outcome: Outcome::Completed,
+                ..
+            })
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    fn serialization() {
+        use crate::event::{Envelope, ProcessEvent};
+        let log = Log::FlowNodeIncoming {
+            node: Box::new(StartEvent {
+                id: Some("start".into()),
+                ..Default::default()
+            }),
+            incoming_index: 0,
+        };
+        let json = serde_json::to_value(&log).unwrap();
+        assert_eq!(json["type"], "FlowNodeIncoming");
+        assert_eq!(json["node"]["type"], "StartEvent");
+        assert!(matches!(
+            serde_json::from_value::<Log>(json).unwrap(),
+            Log::FlowNodeIncoming { node, incoming_index: 0 }
+                if node.downcast_ref::<StartEvent>().and_then(|e| e.id.as_deref()) == Some("start")
+        ));
+
+        let envelope = Envelope::new(
+            ProcessEvent::SignalEvent {
+                signal_ref: Some("signal".into()),
+            },
+            Some("throw".into()),
+            super::InstanceId(1),
+        );
+        let json = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(json["event"]["type"], "SignalEvent");
+        assert_eq!(serde_json::from_value::<Envelope>(json).unwrap(), envelope);
+    }
+
+    #[bpxe_im::test]
+    async fn detach() {
+        use crate::event::ProcessEvent;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let instance = super::Process::new((*handle.element()).clone(), model.clone())
+            .spawn()
+            .await;
+        let id = instance.id();
+        assert!(model.instance(id).await.unwrap().is_none());
+        let mut log_mailbox = Mailbox::new(instance.log_receiver());
+        assert!(instance.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+
+        let weak = instance.detach().await;
+        assert_eq!(weak.id(), id);
+        let instance = weak.upgrade().await.unwrap();
+        assert_eq!(instance.id(), id);
+        drop(instance);
+
+        let instance = model.instance(id).await.unwrap().unwrap();
+        instance.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal".into()),
+        });
+        assert_eq!(instance.join().await, Outcome::Completed);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn priority() {
+        use super::Priority;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let first = model.processes().await.unwrap().pop().unwrap();
+        let second = super::Process::new((*first.element()).clone(), model.clone())
+            .spawn()
+            .await;
+        assert_eq!(first.priority(), Priority::Normal);
+        assert!(first
+            .start_with()
+            .priority(Priority::High)
+            .start()
+            .await
+            .is_ok());
+        assert!(second
+            .start_with()
+            .priority(Priority::Low)
+            .start()
+            .await
+            .is_ok());
+        let running = |counts: [usize; 3]| {
+            let model = model.clone();
+            async move {
+                while [Priority::Low, Priority::Normal, Priority::High]
+                    .iter()
+                    .map(|priority| model.running_instances(*priority))
+                    .ne(counts.iter().copied())
+                {
+                    crate::sys::task::yield_now().await;
+                }
+            }
+        };
+        assert!(timeout(running([1, 0, 1])).await.is_ok());
+
+        second.set_priority(Priority::Normal).await;
+        assert_eq!(second.priority(), Priority::Normal);
+        assert!(timeout(running([0, 1, 1])).await.is_ok());
+
+        second.terminate().await;
+        assert!(timeout(running([0, 0, 1])).await.is_ok());
+        model.terminate().await;
+        assert!(timeout(running([0, 0, 0])).await.is_ok());
+    }
+
+    #[bpxe_im::test]
+    async fn admission_rejected() {
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_max_active_instances(1)
+            .with_admission(model::Admission::Reject)
+            .spawn()
+            .await;
+        let first = model.processes().await.unwrap().pop().unwrap();
+        let second = super::Process::new((*first.element()).clone(), model.clone())
+            .spawn()
+            .await;
+        assert!(first.start().await.is_ok());
+        assert_eq!(second.start().await, Err(StartError::Overloaded));
+        assert_eq!(model.queued_starts(), 0);
+        // the running instance isn't subject to admission
+        assert_eq!(first.start().await, Err(StartError::AlreadyRunning));
+
+        second.terminate().await;
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn admission_queued_already_running() {
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_max_active_instances(1)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        // doesn't wait for the running instance to end
+        assert_eq!(
+            timeout(handle.start()).await.unwrap(),
+            Err(StartError::AlreadyRunning)
+        );
+        assert_eq!(model.queued_starts(), 0);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn step_budget() {
+        use crate::event::ProcessEvent;
+        let definitions = parse(include_str!("event/test_models/catch_signal_event.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_budget(super::Budget {
+                steps: Some(2),
+                ..Default::default()
+            })
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "catch"))
+            .await);
+        handle.send_event(ProcessEvent::SignalEvent {
+            signal_ref: Some("signal".into()),
+        });
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::BudgetExceeded { .. }))
+                .await
+        );
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert!(handle.usage().steps > 2);
+        assert!(matches!(
+            handle.incident().await,
+            Some(super::Incident {
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+                ..
+            }) if code == "BudgetExceeded"
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn modify() {
+        use super::ModificationError;
+        let definitions = parse(include_str!(
+            "gateway/test_models/parallel_not_enough_to_join.bpmn"
+        ))
+        .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert_eq!(
+            handle.modify().cancel("dead_end").apply().await,
+            Err(ModificationError::NotRunning)
+        );
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "dead_end"))
+            .await);
+        assert_eq!(
+            handle.modify().cancel("f3").apply().await,
+            Err(ModificationError::NoActiveToken("f3".into()))
+        );
+        assert_eq!(
+            handle
+                .modify()
+                .cancel("dead_end")
+                .take_sequence_flow("unknown")
+                .apply()
+                .await,
+            Err(ModificationError::UnknownSequenceFlow("unknown".into()))
+        );
+        // nothing has been applied
+        assert_eq!(
+            handle.inspect().await.unwrap().flow_nodes["dead_end"].active_tokens,
+            1
+        );
+        // dead_end never flows, so its token is moved to the join
+        assert!(handle
+            .modify()
+            .cancel("dead_end")
+            .take_sequence_flow("Flow_07ilkci")
+            .apply()
+            .await
+            .is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::Modified { cancelled, started, .. }
+                if cancelled == &vec!["dead_end".to_string()] && started == &vec!["join".to_string()]))
+            .await);
+        assert_eq!(handle.join().await, Outcome::Completed);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(feature = "unsafe-ops")]
+    async fn inject_token() {
+        use super::{InstanceState, ModificationError};
+        use serde_json::json;
+        let definitions = parse(include_str!(
+            "gateway/test_models/parallel_not_enough_to_join.bpmn"
+        ))
+        .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert_eq!(
+            handle.inject_token("start", Variables::new()).await,
+            Err(ModificationError::NoIncoming("start".into()))
+        );
+        let mut variables = Variables::new();
+        variables.insert("unknown".into(), Box::new(json!(1)));
+        assert_eq!(
+            handle.inject_token("f3", variables).await,
+            Err(ModificationError::UnknownDataObject("unknown".into()))
+        );
+        assert_eq!(handle.state(), InstanceState::NotStarted);
+        // skip straight past the join
+        assert!(handle.inject_token("f3", Variables::new()).await.is_ok());
+        assert!(handle.started_at().is_some());
+        assert_eq!(handle.start().await, Err(StartError::AlreadyRunning));
+        // start event hasn't flowed
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                if visits.contains_key("f3") && !visits.contains_key("start"))
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn start_before() {
+        use super::ModificationError;
+        let definitions = parse(include_str!(
+            "gateway/test_models/parallel_not_enough_to_join.bpmn"
+        ))
+        .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert_eq!(
+            handle.start_with().before("unknown").start().await,
+            Err(StartError::StartBefore(ModificationError::UnknownFlowNode(
+                "unknown".into()
+            )))
+        );
+        assert!(handle.start_with().before("f3").start().await.is_ok());
+        assert_eq!(handle.start().await, Err(StartError::AlreadyRunning));
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                if visits.contains_key("f3") && !visits.contains_key("join"))
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(feature = "rhai")]
+    async fn skip() {
+        use super::ModificationError;
+        let definitions = parse(include_str!("activity/test_models/task_script.bpmn")).unwrap();
+        // the script never gets to run
+        let model = model::Model::new(definitions)
+            .with_concurrency_limit("script", 0)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_ref().unwrap() == "script"))
+            .await);
+        assert_eq!(
+            handle.modify().skip("start").apply().await,
+            Err(ModificationError::NotAnActivity("start".into()))
+        );
+        // tokens are gone after the first modification
+        assert_eq!(
+            handle
+                .modify()
+                .cancel("script")
+                .skip("script")
+                .apply()
+                .await,
+            Err(ModificationError::NoActiveToken("script".into()))
+        );
+        assert_eq!(
+            handle.modify().skip("script").skip("script").apply().await,
+            Err(ModificationError::NoActiveToken("script".into()))
+        );
+        assert!(handle.modify().skip("script").apply().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::ActivitySkipped { activity, .. } if activity == "script")
+                )
+                .await
+        );
+        assert_eq!(handle.join().await, Outcome::Completed);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(feature = "rhai")]
+    async fn skip_default_flow() {
+        let definitions = parse(include_str!("process/test_models/default_flow.bpmn")).unwrap();
+        // neither script gets to run
+        let model = model::Model::new(definitions)
+            .with_concurrency_limit("with_default", 0)
+            .with_concurrency_limit("without_default", 0)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_deref() == Some("with_default")))
+            .await);
+        assert!(handle.modify().skip("with_default").apply().await.is_ok());
+
+        // skipping takes the default flow
+        assert!(log_mailbox
+            .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. } if node.id().as_deref() == Some("without_default")))
+            .await);
+        assert!(handle
+            .modify()
+            .skip("without_default")
+            .apply()
+            .await
+            .is_ok());
+
+        // and reports the lack of one
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::NoDefaultPath { node }
+                if node.id().as_deref() == Some("without_default")))
+                .await
+        );
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                if !visits.contains_key("conditional_end") && !visits.contains_key("dead_end"))
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    #[cfg(any(feature = "rhai"))]
+    async fn default_flow() {
+        let definitions = parse(include_str!("process/test_models/default_flow.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+
+        // the task without a default flow has nowhere to go
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::NoDefaultPath { node }
+                if node.id().as_deref() == Some("without_default")))
+                .await
+        );
+
+        // but the one with a default flow took it
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                if visits.get("without_default") == Some(&1)
+                    && !visits.contains_key("conditional_end")
+                    && !visits.contains_key("dead_end"))
+                )
+                .await
+        );
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn tokens_upstream_of() {
+        let definitions =
+            parse(include_str!("gateway/test_models/inclusive_or_join.bpmn")).unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+
+        // one branch has reached the join
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::FlowNodeIncoming { node, .. }
+                if node.id().as_deref() == Some("join")))
+                .await
+        );
+
+        // and the catch event still holds the token of the other one
+        assert_eq!(handle.tokens_upstream_of("join").await, Some(vec![1, 0]));
+        assert_eq!(handle.tokens_upstream_of("unknown").await, None);
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn task_timeout_error() {
+        use crate::event::ProcessEvent;
+        use std::time::Duration;
+
+        let definitions = parse(include_str!("process/test_models/task_timeout.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_task_timeout("sign", Duration::from_millis(50), TimeoutAction::Error)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        assert!(
+            log_mailbox
+                .receive(|e| matches!(e, Log::FlowNodeTimedOut { node, .. } if node == "sign"))
+                .await
+        );
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                node: Some(node),
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+            }) if node == "sign" && code == "TaskTimedOut"
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn task_timeout_flow() {
+        use std::time::Duration;
+
+        let definitions = parse(include_str!("process/test_models/task_timeout.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_task_timeout(
+                "sign",
+                Duration::from_millis(50),
+                TimeoutAction::Flow("Flow_escalate".into()),
+            )
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut log_mailbox = Mailbox::new(handle.log_receiver());
+        assert!(handle.start().await.is_ok());
+        // the task is abandoned for the fallback flow
+        assert!(
+            log_mailbox
+                .receive(
+                    |e| matches!(e, Log::Done { outcome: Outcome::Completed, visits, .. }
+                if visits.get("escalated") == Some(&1) && !visits.contains_key("end"))
+                )
+                .await
+        );
+        assert!(handle.complete_manual_task("sign").is_err());
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn task_timeout_error_skipped() {
+        use super::FailurePolicy;
+        use std::time::Duration;
+
+        let definitions = parse(include_str!("process/test_models/task_timeout.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_task_timeout("sign", Duration::from_millis(50), TimeoutAction::Error)
+            .with_failure_policy("sign", FailurePolicy::Skip)
+            .spawn()
+            .await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        // the error is left to the failure policy of the task
+        assert_eq!(handle.join().await, Outcome::Completed);
+        assert!(handle.incident().await.is_none());
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn task_timeout_invalid_flow() {
+        use crate::event::ProcessEvent;
+        use crate::validation::Diagnostic;
+        use std::time::Duration;
+
+        let definitions = parse(include_str!("process/test_models/task_timeout.bpmn")).unwrap();
+        let model = model::Model::new(definitions)
+            .with_task_timeout(
+                "sign",
+                Duration::from_millis(50),
+                TimeoutAction::Flow("Flow_start".into()),
+            )
+            .spawn()
+            .await;
+        assert_eq!(
+            model.diagnostics(),
+            &[Diagnostic::InvalidTimeoutFlow {
+                task: "sign".into(),
+                sequence_flow: "Flow_start".into(),
+            }]
+        );
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        assert!(handle.start().await.is_ok());
+        assert_eq!(handle.join().await, Outcome::Failed);
+        assert!(matches!(
+            handle.incident().await,
+            Some(Incident {
+                error: ProcessEvent::ErrorEvent { error_code: Some(code), .. },
+                ..
+            }) if code == "InvalidTimeoutFlow"
+        ));
         model.terminate().await;
     }
 }
@@ -0,0 +1,243 @@
+//! Runtime invariant checking
+//!
+//! Enabled with `invariants` feature. Violations are reported through [`Log::InvariantViolation`]
+//! and are meant to catch engine bugs and misbehaving custom flow nodes early.
+//!
+//! [`Log::InvariantViolation`]: super::Log::InvariantViolation
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+/// Invariant violation
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// Number of tokens created (and restored) doesn't match the number of tokens consumed
+    /// and active, as reported by the scheduler
+    #[error("tokens are not conserved: {created} created, {consumed} consumed, {active} active")]
+    TokenConservation {
+        created: usize,
+        consumed: usize,
+        active: usize,
+    },
+    /// Scheduler has delivered a different number of tokens than it has counted
+    #[error("{counted} tokens counted, but {delivered} delivered")]
+    UncountedTokens { counted: usize, delivered: usize },
+    /// Flow node that has completed has flowed or completed again without receiving a
+    /// token in between
+    #[error("flow node {node} has been re-entered after completion")]
+    CompletedReentered { node: String },
+    /// Flow node has produced a flow without holding any tokens (re-entered without an incoming)
+    #[error("flow node {node} has produced a flow without holding a token")]
+    FlowWithoutToken { node: String },
+    /// Flow node has produced a flow to an outgoing it doesn't have
+    #[error("flow node {node} has no outgoing #{index}")]
+    InvalidOutgoing { node: String, index: usize },
+    /// Incoming has been delivered through a sequence flow that doesn't originate at the
+    /// flow node that produced the flow
+    #[error("sequence flow {seq_flow} doesn't originate at flow node {node}")]
+    UnmatchedIncoming { seq_flow: String, node: String },
+    /// Sequence flow's target is not scheduled
+    #[error("sequence flow {seq_flow} targets flow node {node} that is not scheduled")]
+    LostToken { seq_flow: String, node: String },
+}
+
+/// Token accounting
+///
+/// Keeps its own account of tokens from what is delivered and consumed, and checks it
+/// against the scheduler's counts.
+#[derive(Default)]
+pub(crate) struct Checker {
+    // tokens active when restored from a snapshot
+    restored: usize,
+    created: usize,
+    consumed: usize,
+    // flow node => active tokens
+    active: HashMap<String, usize>,
+    // flow nodes that have completed and haven't received a token since
+    completed: HashSet<String>,
+}
+
+impl Checker {
+    /// Records tokens active in a flow node restored from a snapshot
+    pub(crate) fn restore(&mut self, node: &str, active: usize) {
+        self.restored += active;
+        *self.active.entry(node.to_string()).or_insert(0) += active;
+    }
+
+    /// Records a token delivered to a flow node
+    pub(crate) fn incoming(&mut self, node: &str) {
+        self.created += 1;
+        *self.active.entry(node.to_string()).or_insert(0) += 1;
+        self.completed.remove(node);
+    }
+
+    /// Records a flow produced by a flow node, consuming its tokens except for those it
+    /// keeps waiting for a subsequent flow
+    ///
+    /// Flow nodes without incomings (such as start events) produce flows without tokens.
+    pub(crate) fn flow(
+        &mut self,
+        node: &str,
+        has_incomings: bool,
+        waiting: usize,
+    ) -> Result<(), Violation> {
+        if self.completed.contains(node) {
+            return Err(Violation::CompletedReentered {
+                node: node.to_string(),
+            });
+        }
+        let active = self.active.get(node).copied().unwrap_or(0);
+        let consumed = active.saturating_sub(waiting);
+        self.consumed += consumed;
+        self.active.insert(node.to_string(), active - consumed);
+        if active == 0 && has_incomings {
+            Err(Violation::FlowWithoutToken {
+                node: node.to_string(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records flow node's completion, consuming its tokens
+    pub(crate) fn complete(&mut self, node: &str) -> Result<(), Violation> {
+        self.consume(node);
+        if self.completed.insert(node.to_string()) {
+            Ok(())
+        } else {
+            Err(Violation::CompletedReentered {
+                node: node.to_string(),
+            })
+        }
+    }
+
+    /// Records flow node's cancellation, consuming its tokens and replacing it with a
+    /// flow node in its initial state
+    pub(crate) fn cancel(&mut self, node: &str) {
+        self.consume(node);
+        self.completed.remove(node);
+    }
+
+    fn consume(&mut self, node: &str) {
+        self.consumed += self.active.remove(node).unwrap_or(0);
+    }
+
+    /// Checks token conservation against the number of tokens the scheduler has created
+    /// and the number of active tokens its flow nodes hold
+    pub(crate) fn check(&self, created: usize, active: usize) -> Result<(), Violation> {
+        if created != self.created {
+            return Err(Violation::UncountedTokens {
+                counted: created,
+                delivered: self.created,
+            });
+        }
+        if self.restored + created == self.consumed + active {
+            Ok(())
+        } else {
+            Err(Violation::TokenConservation {
+                created: self.restored + created,
+                consumed: self.consumed,
+                active,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    fn conservation() {
+        let mut checker = Checker::default();
+        checker.incoming("start");
+        assert!(checker.flow("start", false, 0).is_ok());
+        checker.incoming("join");
+        checker.incoming("join");
+        assert!(checker.check(3, 2).is_ok());
+        assert!(checker.flow("join", true, 0).is_ok());
+        checker.incoming("end");
+        assert!(checker.complete("end").is_ok());
+        assert!(checker.check(4, 0).is_ok());
+        assert_eq!(checker.consumed, 4);
+    }
+
+    #[bpxe_im::test]
+    fn lost_token() {
+        let mut checker = Checker::default();
+        checker.incoming("task");
+        // scheduler still counts a token the task has passed on
+        assert!(checker.flow("task", true, 0).is_ok());
+        assert_eq!(
+            checker.check(1, 1),
+            Err(Violation::TokenConservation {
+                created: 1,
+                consumed: 1,
+                active: 1,
+            })
+        );
+        assert_eq!(
+            checker.check(2, 0),
+            Err(Violation::UncountedTokens {
+                counted: 2,
+                delivered: 1,
+            })
+        );
+    }
+
+    #[bpxe_im::test]
+    fn waiting_tokens() {
+        let mut checker = Checker::default();
+        checker.incoming("gateway");
+        checker.incoming("gateway");
+        // flows once per token
+        assert!(checker.flow("gateway", true, 1).is_ok());
+        assert!(checker.check(2, 1).is_ok());
+        assert!(checker.flow("gateway", true, 0).is_ok());
+        assert!(checker.check(2, 0).is_ok());
+    }
+
+    #[bpxe_im::test]
+    fn restored() {
+        let mut checker = Checker::default();
+        checker.restore("task", 1);
+        assert!(checker.check(0, 1).is_ok());
+        assert!(checker.flow("task", true, 0).is_ok());
+        assert!(checker.check(0, 0).is_ok());
+    }
+
+    #[bpxe_im::test]
+    fn flow_without_token() {
+        let mut checker = Checker::default();
+        checker.incoming("task");
+        assert!(checker.flow("task", true, 0).is_ok());
+        assert_eq!(
+            checker.flow("task", true, 0),
+            Err(Violation::FlowWithoutToken {
+                node: "task".to_string()
+            })
+        );
+    }
+
+    #[bpxe_im::test]
+    fn completed_reentered() {
+        let mut checker = Checker::default();
+        checker.incoming("task");
+        assert!(checker.complete("task").is_ok());
+        assert_eq!(
+            checker.flow("task", true, 0),
+            Err(Violation::CompletedReentered {
+                node: "task".to_string()
+            })
+        );
+        assert_eq!(
+            checker.complete("task"),
+            Err(Violation::CompletedReentered {
+                node: "task".to_string()
+            })
+        );
+        // a new token makes it legal again
+        checker.incoming("task");
+        assert!(checker.complete("task").is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+//! # Execution listeners
+//!
+//! Execution listeners are notified as tokens move through process instances: when a flow
+//! node is entered or left and when a sequence flow is taken. They are registered with
+//! [`crate::model::Model::with_execution_listener`] for the whole model, a single process
+//! definition or a single element.
+//!
+//! Listeners are awaited by the process instance before it proceeds, so they can perform
+//! side effects (such as auditing) in order with the execution, but should keep them short
+//! and must not wait for the process instance itself (for example, with
+//! [`Handle::data_object`]). Tokens moved by [`Handle::modify`] are not reported.
+use super::{Handle, Variables};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Execution point a listener is notified about
+pub struct Execution<'a> {
+    /// Process instance
+    pub process: &'a Handle,
+    /// Identifier of the flow node or sequence flow
+    pub element: &'a str,
+    /// Number of tokens the flow node has received so far (for sequence flows, its source
+    /// flow node)
+    pub tokens: usize,
+    /// Copy of process instance variables (data objects)
+    pub variables: &'a Variables,
+}
+
+/// Execution listener
+///
+/// All callbacks do nothing by default.
+#[async_trait]
+pub trait ExecutionListener: Send + Sync {
+    /// Flow node has received a token
+    ///
+    /// Flow nodes without incoming sequence flows (such as start events) are entered right
+    /// before they are left.
+    async fn on_enter(&self, _execution: &Execution<'_>) {}
+
+    /// Flow node has passed its tokens on, or consumed them
+    async fn on_leave(&self, _execution: &Execution<'_>) {}
+
+    /// Sequence flow has been taken
+    async fn on_take(&self, _execution: &Execution<'_>) {}
+}
+
+/// Elements an execution listener is notified about
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenerScope {
+    /// All elements of all processes of the model
+    Model,
+    /// All elements of the process with this identifier
+    Process(String),
+    /// Flow node or sequence flow with this identifier
+    Element(String),
+}
+
+impl ListenerScope {
+    /// Returns `true` if the scope covers an element of a process
+    pub fn covers(&self, process: Option<&str>, element: &str) -> bool {
+        match self {
+            ListenerScope::Model => true,
+            ListenerScope::Process(id) => process == Some(id.as_str()),
+            ListenerScope::Element(id) => id == element,
+        }
+    }
+}
+
+/// Registered execution listeners
+pub type ExecutionListeners = Arc<Vec<(ListenerScope, Arc<dyn ExecutionListener>)>>;
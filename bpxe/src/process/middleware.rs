@@ -0,0 +1,40 @@
+//! # Action middleware
+//!
+//! Every action a flow node yields (see [`flow_node::Action`]) passes through action middleware
+//! registered with [`crate::model::Model::with_action_middleware`] before the process instance
+//! acts on it. Middleware can observe actions, replace them (for example, flow through fewer
+//! outgoing sequence flows when a feature is disabled) or veto them.
+//!
+//! Unlike [`flow_node::FlowNode::handle_outgoing_action`], middleware applies to all flow nodes
+//! of all processes of the model, after their predecessors have handled the action.
+use super::Handle;
+use crate::flow_node;
+
+/// Action middleware
+pub trait ActionMiddleware: Send + Sync {
+    /// Maps an action of a flow node to a new action (or inaction)
+    ///
+    /// Returning `None` will mean that the action has to be dropped (so the tokens stay where
+    /// they are), returning `Some(action)` will replace the original action with the returned
+    /// one in the flow.
+    fn handle_action(
+        &self,
+        process: &Handle,
+        node: &str,
+        action: flow_node::Action,
+    ) -> Option<flow_node::Action>;
+}
+
+impl<F> ActionMiddleware for F
+where
+    F: Fn(&Handle, &str, flow_node::Action) -> Option<flow_node::Action> + Send + Sync,
+{
+    fn handle_action(
+        &self,
+        process: &Handle,
+        node: &str,
+        action: flow_node::Action,
+    ) -> Option<flow_node::Action> {
+        self(process, node, action)
+    }
+}
@@ -0,0 +1,147 @@
+//! # Process instance modification
+//!
+//! Operators can repair instances that got stuck because of data or model errors by moving
+//! their tokens around: cancelling tokens held by some flow nodes, creating new ones elsewhere
+//! and adjusting variables along the way (see [`Handle::modify`]).
+//!
+//! Activities that wait or keep failing (for example, because of a permanently broken
+//! integration) can also be skipped, as if they have completed.
+//!
+//! Modifications requested together are applied all at once and only if all of them are
+//! applicable, each one checked against the tokens left by the ones before it. Variables
+//! are set first; everything else is applied in the order it has been added.
+use super::{Handle, Request};
+use crate::data_object::DataObject;
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+/// Single change of a running process instance
+pub enum Modification {
+    /// Cancels tokens held by a flow node, returning it to its initial state
+    Cancel(String),
+    /// Creates a token before a flow node, as if it came through its first incoming
+    /// sequence flow
+    StartBefore(String),
+    /// Creates a token on a sequence flow, delivering it to its target flow node
+    TakeSequenceFlow(String),
+    /// Sets a variable (data object)
+    SetVariable(String, Box<dyn DataObject>),
+    /// Completes an activity holding a token without executing it, flowing to its
+    /// outgoing sequence flows
+    Skip(String),
+}
+
+/// Process instance modification error
+#[derive(Error, Debug, PartialEq)]
+pub enum ModificationError {
+    /// Process instance hasn't been started or is already over
+    #[error("process instance is not running")]
+    NotRunning,
+    /// Flow node doesn't exist (or is not supported)
+    #[error("unknown flow node {0}")]
+    UnknownFlowNode(String),
+    /// Sequence flow doesn't exist
+    #[error("unknown sequence flow {0}")]
+    UnknownSequenceFlow(String),
+    /// Flow node has no incoming sequence flows to create a token on
+    #[error("flow node {0} has no incoming sequence flows")]
+    NoIncoming(String),
+    /// Flow node holds no tokens to cancel
+    #[error("flow node {0} holds no tokens")]
+    NoActiveToken(String),
+    /// Flow node is not an activity
+    #[error("flow node {0} is not an activity")]
+    NotAnActivity(String),
+    /// Variable doesn't correspond to any data object
+    #[error("unknown data object {0}")]
+    UnknownDataObject(String),
+    /// Process instance would have been started, but the limit of active instances has
+    /// been reached (see [`model::Admission::Reject`])
+    ///
+    /// [`model::Admission::Reject`]: crate::model::Admission::Reject
+    #[error("too many active instances")]
+    Overloaded,
+    /// Response has not been received
+    #[error("response has not been received")]
+    NotReceived,
+}
+
+/// Process instance modification builder (see [`Handle::modify`])
+#[must_use = "modification is only applied with ModificationBuilder::apply"]
+pub struct ModificationBuilder<'a> {
+    pub(super) handle: &'a Handle,
+    pub(crate) modifications: Vec<Modification>,
+}
+
+impl<'a> ModificationBuilder<'a> {
+    /// Cancels tokens held by a flow node
+    pub fn cancel<S>(mut self, node: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.modifications.push(Modification::Cancel(node.into()));
+        self
+    }
+
+    /// Creates a token before a flow node
+    pub fn start_before<S>(mut self, node: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.modifications
+            .push(Modification::StartBefore(node.into()));
+        self
+    }
+
+    /// Creates a token on a sequence flow
+    ///
+    /// Unlike [`ModificationBuilder::start_before`], this allows to choose which incoming
+    /// sequence flow of a join the token comes through.
+    pub fn take_sequence_flow<S>(mut self, seq_flow: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.modifications
+            .push(Modification::TakeSequenceFlow(seq_flow.into()));
+        self
+    }
+
+    /// Sets a variable (data object)
+    pub fn variable<S>(mut self, id: S, value: Box<dyn DataObject>) -> Self
+    where
+        S: Into<String>,
+    {
+        self.modifications
+            .push(Modification::SetVariable(id.into(), value));
+        self
+    }
+
+    /// Completes an activity without executing it
+    ///
+    /// Output variables set with [`ModificationBuilder::variable`] in the same modification
+    /// are set before the activity is skipped, so that conditions of outgoing sequence flows
+    /// can see them. Execution that is already under way is not interrupted, but it no longer
+    /// affects the flow.
+    pub fn skip<S>(mut self, activity: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.modifications.push(Modification::Skip(activity.into()));
+        self
+    }
+
+    /// Applies all modifications
+    ///
+    /// If any of them is not applicable, none are applied.
+    pub async fn apply(self) -> Result<(), ModificationError> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self
+            .handle
+            .sender
+            .send(Request::Modify(self.modifications, sender))
+            .await;
+        receiver
+            .await
+            .unwrap_or(Err(ModificationError::NotReceived))
+    }
+}
@@ -0,0 +1,184 @@
+//! # Instance priority
+//!
+//! Every process instance has a priority (see [`super::Handle::set_priority`] and
+//! [`super::StartBuilder::priority`]). While instances of higher priority are busy taking steps,
+//! instances of lower priority wait before their own steps, so that large batches of
+//! low-priority instances don't starve interactive ones sharing the same engine. Waiting
+//! instances are woken up as soon as no instance of higher priority is busy, or once instances
+//! of higher priority have taken a number of steps, so that they still get a share of the
+//! engine under sustained load. Without busy instances of higher priority around, priority
+//! costs nothing.
+//!
+//! Priority doesn't affect the order in which queued process starts are admitted (see
+//! [`crate::model::Model::with_max_active_instances`]).
+use crate::sys::time;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Process instance priority
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    /// Background work, such as batch runs
+    Low,
+    /// Default priority
+    Normal,
+    /// Interactive work
+    High,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+/// Number of steps instances of higher priority take for every step of a waiting instance
+const SHARE: usize = 8;
+
+/// Longest time an instance waits before its step
+///
+/// Instances of higher priority might be busy waiting for something only an instance of
+/// lower priority can do.
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// Numbers of running and busy process instances by priority
+#[derive(Default)]
+pub(crate) struct Registry {
+    running: [AtomicUsize; 3],
+    busy: [AtomicUsize; 3],
+    steps: [AtomicUsize; 3],
+    stepped: Notify,
+}
+
+impl Registry {
+    /// Returns number of running process instances with a priority
+    pub(crate) fn running(&self, priority: Priority) -> usize {
+        self.running[priority as usize].load(Ordering::SeqCst)
+    }
+
+    fn busy_above(&self, priority: Priority) -> usize {
+        self.busy[priority as usize + 1..]
+            .iter()
+            .map(|busy| busy.load(Ordering::SeqCst))
+            .sum()
+    }
+
+    fn steps_above(&self, priority: Priority) -> usize {
+        self.steps[priority as usize + 1..]
+            .iter()
+            .fold(0, |steps, count| {
+                steps.wrapping_add(count.load(Ordering::SeqCst))
+            })
+    }
+}
+
+/// Registration of a running process instance, withdrawn once dropped
+pub(crate) struct Registration {
+    registry: Arc<Registry>,
+    priority: Option<Priority>,
+    // priority the instance is busy with, if it's taking a step
+    busy: Option<Priority>,
+}
+
+impl Registration {
+    pub(crate) fn new(registry: Arc<Registry>) -> Self {
+        Self {
+            registry,
+            priority: None,
+            busy: None,
+        }
+    }
+
+    /// Updates priority the instance is registered with (`None` if it's not running)
+    pub(crate) fn update(&mut self, priority: Option<Priority>) {
+        if self.priority == priority {
+            return;
+        }
+        if let Some(previous) = self.priority {
+            self.registry.running[previous as usize].fetch_sub(1, Ordering::SeqCst);
+        }
+        if let Some(priority) = priority {
+            self.registry.running[priority as usize].fetch_add(1, Ordering::SeqCst);
+        }
+        self.priority = priority;
+    }
+
+    /// Marks the instance busy with a step, after giving way to busy instances of higher
+    /// priority
+    ///
+    /// The step ends with [`Registration::stepped`].
+    pub(crate) async fn give_way(&mut self) {
+        self.stepped();
+        let priority = match self.priority {
+            Some(priority) => priority,
+            None => return,
+        };
+        self.registry.busy[priority as usize].fetch_add(1, Ordering::SeqCst);
+        self.busy = Some(priority);
+        let registry = &self.registry;
+        let steps = registry.steps_above(priority);
+        let _ = time::timeout(MAX_WAIT, async {
+            loop {
+                // obtained before checking, so that steps ending in between aren't missed
+                let stepped = registry.stepped.notified();
+                if registry.busy_above(priority) == 0
+                    || registry.steps_above(priority).wrapping_sub(steps) >= SHARE
+                {
+                    return;
+                }
+                stepped.await;
+            }
+        })
+        .await;
+    }
+
+    /// Ends the step the instance has been busy with, if any
+    pub(crate) fn stepped(&mut self) {
+        if let Some(priority) = self.busy.take() {
+            self.registry.busy[priority as usize].fetch_sub(1, Ordering::SeqCst);
+            self.registry.steps[priority as usize].fetch_add(1, Ordering::SeqCst);
+            self.registry.stepped.notify_waiters();
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.stepped();
+        self.update(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+    use futures::FutureExt;
+
+    #[bpxe_im::test]
+    async fn give_way() {
+        let registry = Arc::new(Registry::default());
+        let mut high = Registration::new(registry.clone());
+        high.update(Some(Priority::High));
+        let mut low = Registration::new(registry.clone());
+        low.update(Some(Priority::Low));
+
+        high.give_way().await;
+        {
+            let waiting = low.give_way();
+            futures::pin_mut!(waiting);
+            // waits while the instance of higher priority is busy
+            assert!((&mut waiting).now_or_never().is_none());
+            high.stepped();
+            assert!(waiting.now_or_never().is_some());
+        }
+        low.stepped();
+
+        // instances of lower priority don't hold back those of higher priority
+        low.give_way().await;
+        assert!(high.give_way().now_or_never().is_some());
+    }
+}
@@ -1,29 +1,47 @@
 //! Process scheduler
 //!
 //! This is where the magic happens
-use super::{DataObjectContainer, DataObjectError, Handle, Log, Request, StartError};
+use super::{
+    DataObjectContainer, DataObjectError, Execution, FailurePolicy, FlowNodeSnapshot, Handle,
+    Incident, Log, Modification, ModificationError, Outcome, Request, Snapshot, StartError,
+    StartOptions, Termination, TimeoutAction, Variables,
+};
+use crate::activity::receive_task;
 use crate::bpmn::schema::{
-    self, DocumentElementContainer, Element as E, Expr, FormalExpression, Process, ProcessType,
+    self, DocumentElementContainer, Expr, FlowElement, FormalExpression, Process, ProcessType,
     SequenceFlow, SequenceFlowConditionExpression,
 };
 use crate::data_object::{self, DataObject};
-use crate::event::ProcessEvent as Event;
+use crate::error::ErrorReport;
+use crate::event::{self, ProcessEvent as Event};
 use crate::flow_node;
-use crate::language::{Engine as _, EngineContextProvider, MultiLanguageEngine};
+use crate::language::{
+    Engine as _, EngineContext as _, EngineContextProvider, MultiLanguageEngine,
+};
 use crate::sys::task;
+use crate::sys::time;
 use derive_more::{Deref, DerefMut};
+use futures::future::FutureExt;
 use futures::stream::{Stream, StreamExt};
-use std::collections::HashMap;
+use smallvec::SmallVec;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::SystemTime;
 use streamunordered::{StreamUnordered, StreamYield};
-use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
 
 pub(crate) struct Scheduler {
     receiver: mpsc::Receiver<Request>,
     process: Handle,
     flow_nodes: StreamUnordered<FlowNode>,
+    // flow node => token
+    flow_nodes_by_id: HashMap<String, usize>,
+    // have active tokens changed since upstream tokens were last reported to joins?
+    tokens_changed: bool,
+    // event-based gateway target => other targets of the same gateway
+    alternatives: HashMap<String, Vec<String>>,
     // sequence flow => (token, index)
     flow_nodes_outgoing: HashMap<String, (usize, usize)>,
     // sequence flow => (token, index)
@@ -32,12 +50,30 @@ pub(crate) struct Scheduler {
     element: Arc<Process>,
     log_broadcast: broadcast::Sender<Log>,
     data_objects: HashMap<String, DataObjectContainer>,
+    // has the process been started?
+    started: bool,
+    outcome: Option<Outcome>,
+    // uncaught error that has failed the process
+    incident: Option<Incident>,
+    // when has the process been started (or restored)?
+    started_at: SystemTime,
+    // total number of tokens processed
+    tokens: usize,
+    // flow node => number of times it has been entered
+    visits: HashMap<String, usize>,
+    // has the process exceeded its budget?
+    exhausted: bool,
+    termination_sender: watch::Sender<Option<Termination>>,
+    // registration with the priority of the instance while it's running
+    priority: super::priority::Registration,
+    // waiting for no flow node to be ready to make progress
+    settle_waiters: Vec<oneshot::Sender<()>>,
+    #[cfg(feature = "invariants")]
+    invariants: super::invariants::Checker,
 }
 
-// FIXME: We're using this structure to be able to find flow nodes by their identifier
-// in `FuturesUnordered` (`Scheduler.flow_nodes`). It's a linear search and is probably
-// fine when there's a small number of flow nodes, but should it become large, this approach
-// should probably be rethought.
+// Flow nodes are found in `StreamUnordered` (`Scheduler.flow_nodes`) by their tokens,
+// which are indexed by identifiers in `Scheduler.flow_nodes_by_id`
 #[derive(Deref, DerefMut)]
 #[deref(forward)]
 #[deref_mut(forward)]
@@ -51,6 +87,18 @@ struct FlowNode {
     #[deref(ignore)]
     #[deref_mut(ignore)]
     tokens: usize,
+    // tokens delivered to the flow node that it hasn't passed on (or consumed) yet
+    #[deref(ignore)]
+    #[deref_mut(ignore)]
+    active_tokens: usize,
+    // flow nodes every incoming flow can be reached from (only if there's more than one)
+    #[deref(ignore)]
+    #[deref_mut(ignore)]
+    upstream: Vec<Vec<String>>,
+    // whether tokens can still arrive through every incoming flow, as last reported
+    #[deref(ignore)]
+    #[deref_mut(ignore)]
+    upstream_tokens: SmallVec<[bool; flow_node::SMALL_INCOMING]>,
 }
 
 impl Stream for FlowNode {
@@ -61,6 +109,13 @@ impl Stream for FlowNode {
     }
 }
 
+/// Execution point listeners are notified about
+enum Notification {
+    Enter,
+    Leave,
+    Take,
+}
+
 /// Internal flow node scheduler control
 enum Control {
     // Continue with this action
@@ -70,8 +125,13 @@ enum Control {
 }
 
 impl Scheduler {
-    pub(crate) fn new(receiver: mpsc::Receiver<Request>, process: Handle) -> Self {
+    pub(crate) fn new(
+        receiver: mpsc::Receiver<Request>,
+        process: Handle,
+        termination_sender: watch::Sender<Option<Termination>>,
+    ) -> Self {
         let mut flow_nodes = StreamUnordered::new();
+        let mut flow_nodes_by_id = HashMap::new();
         let mut flow_nodes_outgoing = HashMap::new();
         let mut flow_nodes_incoming = HashMap::new();
 
@@ -84,20 +144,26 @@ impl Scheduler {
                 flow_node::new(e).map(|mut flow_node| {
                     flow_node.set_process(process.clone());
                     let e = flow_node.element();
+                    // FIXME: decide what should we do with flow nodes that don't have ID.
+                    // They can't be connected with other nodes (there's no way to refer to
+                    // them), but they can still be operational in a single flow node operation
+                    // (even though this might be a degenerative case)
+                    let id = e.id().as_ref().unwrap_or(&"".to_string()).to_string();
                     FlowNode {
-                        // FIXME: decide what should we do with flow nodes that don't have ID.
-                        // They can't be connected with other nodes (there's no way to refer to
-                        // them), but they can still be operational in a single flow node operation
-                        // (even though this might be a degenerative case)
-                        id: e.id().as_ref().unwrap_or(&"".to_string()).to_string(),
+                        upstream: upstream(&process.element(), &id, e.incomings()),
+                        upstream_tokens: SmallVec::new(),
+                        id,
                         node: flow_node,
                         tokens: 0,
+                        active_tokens: 0,
                     }
                 })
             })
         {
             let element = flow_node.element();
+            let id = flow_node.id.clone();
             let token = flow_nodes.insert(flow_node);
+            flow_nodes_by_id.insert(id, token);
             for (index, outgoing) in element.outgoings().iter().enumerate() {
                 flow_nodes_outgoing.insert(outgoing.to_owned(), (token, index));
             }
@@ -163,45 +229,146 @@ impl Scheduler {
         }
 
         let element = process.element();
+        let alternatives = alternatives(&element);
         let log_broadcast = process.log_broadcast();
+        let priority = super::priority::Registration::new(process.model().priorities());
 
         Self {
             receiver,
             process,
             flow_nodes,
+            flow_nodes_by_id,
+            tokens_changed: true,
+            alternatives,
             flow_nodes_outgoing,
             flow_nodes_incoming,
             expression_evaluator,
             element,
             log_broadcast,
             data_objects,
+            started: false,
+            outcome: None,
+            incident: None,
+            started_at: time::now(),
+            tokens: 0,
+            visits: HashMap::new(),
+            exhausted: false,
+            termination_sender,
+            priority,
+            settle_waiters: vec![],
+            #[cfg(feature = "invariants")]
+            invariants: Default::default(),
         }
     }
 
+    /// Restores state of flow nodes and data objects from a snapshot
+    ///
+    /// Must be called before the scheduler is run.
+    pub(crate) fn restore(&mut self, snapshot: Snapshot) -> Result<(), flow_node::StateError> {
+        for flow_node in self.flow_nodes.iter_mut() {
+            if let Some(snapshot) = snapshot.flow_nodes.get(&flow_node.id) {
+                flow_node.node.set_state(snapshot.state.clone())?;
+                flow_node.tokens = snapshot.tokens;
+                flow_node.node.tokens(snapshot.tokens);
+                flow_node.active_tokens = snapshot.active_tokens;
+                #[cfg(feature = "invariants")]
+                self.invariants
+                    .restore(&flow_node.id, snapshot.active_tokens);
+                self.started = self.started || snapshot.active_tokens > 0;
+            }
+        }
+        for (id, data_object) in snapshot.data_objects {
+            if let Some(container) = self.data_objects.get(&id) {
+                // container has just been created, so it's not locked by anybody
+                if let Ok(mut container) = container.try_write() {
+                    *container = data_object;
+                }
+            }
+        }
+        Ok(())
+    }
+
     // Main loop
     pub async fn run(mut self) {
         let mut join_handle = None;
         loop {
+            self.priority.stepped();
             task::yield_now().await;
+            let running = self.started && self.outcome.is_none();
+            self.priority
+                .update(Some(self.process.priority()).filter(|_| running));
+            self.report_upstream_tokens();
+            if !self.settle_waiters.is_empty() {
+                match self.flow_nodes.next().now_or_never() {
+                    Some(Some(next)) => {
+                        self.priority.give_way().await;
+                        self.process_flow_node_next(next).await;
+                        continue;
+                    }
+                    _ => {
+                        for waiter in self.settle_waiters.drain(..) {
+                            let _ = waiter.send(());
+                        }
+                    }
+                }
+            }
             tokio::select! {
                // Handle request processing
-               next = self.receiver.recv()  =>
+               next = self.receiver.recv()  => {
+                   // give way to busy instances of higher priority
+                   self.priority.give_way().await;
                    match next {
                        Some(Request::JoinHandle(handle)) => join_handle = Some(handle),
                        Some(Request::Terminate(sender)) => {
+                           self.finish(Outcome::Terminated).await;
                            let _ = sender.send(join_handle.take());
                            return;
                        }
-                       Some(Request::Start(sender)) => {
-                           self.start(sender);
+                       Some(Request::Abort) => return,
+                       Some(Request::Start(options, sender)) => {
+                           self.start(options, sender).await;
                        }
                        Some(Request::DataObject(id, sender)) => {
                            self.get_data_object(&id, sender);
                        }
+                       Some(Request::VariablesChanged) => {
+                           self.evaluate_conditions().await;
+                       }
+                       // picked up before the next step
+                       Some(Request::PriorityChanged) => {}
+                       Some(Request::Settled(sender)) => self.settle_waiters.push(sender),
+                       Some(Request::Snapshot(sender)) => {
+                           let _ = sender.send(self.snapshot().await);
+                       }
+                       Some(Request::TokensUpstream(node, sender)) => {
+                           let tokens = self.tokens_upstream_of(&node);
+                           let _ = sender.send(tokens.map(|tokens| tokens.to_vec()));
+                       }
+                       Some(Request::Modify(modifications, sender)) => {
+                           let _ = sender.send(self.modify(modifications).await);
+                       }
+                       #[cfg(feature = "unsafe-ops")]
+                       Some(Request::Inject(node, variables, sender)) => {
+                           let _ = sender.send(self.inject(&node, variables).await);
+                       }
+                       Some(Request::Incident(incident)) => {
+                           if self.outcome.is_none() {
+                               self.incident = Some(incident);
+                               self.finish(Outcome::Failed).await;
+                           }
+                       }
+                       Some(Request::TaskTimedOut(node, visit)) => {
+                           self.time_out(&node, visit).await;
+                       }
+                       Some(Request::BoundaryError(activity, error, sender)) => {
+                           let _ = sender.send(self.catch_on_boundary(&activity, &error).await);
+                       }
                        None => {}
-                   },
+                   }
+               }
                // Flow node processing
                next = self.flow_nodes.next() => {
+                   self.priority.give_way().await;
                    if let Some(next) = next {
                            self.process_flow_node_next(next).await;
                    }
@@ -216,15 +383,19 @@ impl Scheduler {
         ))) = seq_flow.condition_expression
         {
             let expr = expr.clone();
-            match self
+            let evaluation_started = instant::Instant::now();
+            let result = self
                 .expression_evaluator
                 .eval::<bool>(&expr, &mut self.expression_evaluator.new_context())
-                .await
-            {
+                .await;
+            self.process
+                .count_execution_time(evaluation_started.elapsed());
+            match result {
                 Ok(result) => result,
                 Err(err) => {
                     let _ = self.log_broadcast.send(Log::ExpressionError {
-                        error: format!("{:?}", err),
+                        element: seq_flow.id.clone(),
+                        error: ErrorReport::new(&err),
                     });
                     false
                 }
@@ -234,6 +405,60 @@ impl Scheduler {
         }
     }
 
+    /// Triggers conditional events awaited by catch events holding tokens, if their
+    /// conditions are satisfied
+    async fn evaluate_conditions(&mut self) {
+        let definitions = self.process.model().definitions();
+        let mut waiting = vec![];
+        for flow_node in self.flow_nodes.iter_mut() {
+            if flow_node.active_tokens == 0 {
+                continue;
+            }
+            if let Some(catch_event) = flow_node
+                .element()
+                .downcast_ref::<schema::IntermediateCatchEvent>()
+            {
+                waiting.extend(
+                    event::events(&catch_event.event_definitions, &definitions)
+                        .filter(|event| matches!(event, Event::ConditionalEvent { .. }))
+                        .map(|event| (catch_event.id.clone(), event)),
+                );
+            }
+        }
+        if waiting.is_empty() {
+            return;
+        }
+        let mut context = self.expression_evaluator.new_context();
+        for (id, value) in self.variables().await {
+            context.set(&id, value);
+        }
+        for (node, event) in waiting {
+            let expr = match event {
+                Event::ConditionalEvent {
+                    condition: Expr::FormalExpression(ref expr),
+                } => expr.clone(),
+                _ => continue,
+            };
+            let evaluation_started = instant::Instant::now();
+            let result = self
+                .expression_evaluator
+                .eval::<bool>(&expr, &mut context)
+                .await;
+            self.process
+                .count_execution_time(evaluation_started.elapsed());
+            match result {
+                Ok(true) => self.process.send_event(event),
+                Ok(false) => {}
+                Err(err) => {
+                    let _ = self.log_broadcast.send(Log::ExpressionError {
+                        element: node,
+                        error: ErrorReport::new(&err),
+                    });
+                }
+            }
+        }
+    }
+
     /// Figure out what should be the next course of action
     fn next_action(&mut self, action: Option<flow_node::Action>, token: usize) -> Control {
         if let Some(flow_node) = self.flow_nodes.get(token) {
@@ -269,14 +494,40 @@ impl Scheduler {
         }
     }
 
+    /// Passes an action through action middleware
+    fn intercept(&self, action: flow_node::Action, token: usize) -> Control {
+        let middleware = self.process.model().action_middleware();
+        let id = match self.flow_nodes.get(token) {
+            Some(flow_node) => &flow_node.id,
+            None => return Control::Drop,
+        };
+        middleware
+            .iter()
+            .try_fold(action, |action, middleware| {
+                middleware.handle_action(&self.process, id, action)
+            })
+            .map_or(Control::Drop, |action| Control::Proceed(Some(action)))
+    }
+
     async fn process_flow_node_next(&mut self, (next, token): (StreamYield<FlowNode>, usize)) {
         if self.flow_nodes.get(token).is_none() {
             // this shouldn't happen, but... (do nothing)
             // at least because of this check we can safely unwrap `flow_nodes.get*` below
             return;
         }
+        if self.exhausted {
+            // no more progress once the budget is exceeded
+            return;
+        }
+        self.process.count_step();
+        if self.check_budget().await {
+            return;
+        }
         if let StreamYield::Item(action) = next {
-            let next_action = self.next_action(Some(action), token);
+            let next_action = match self.next_action(Some(action), token) {
+                Control::Proceed(Some(action)) => self.intercept(action, token),
+                control => control,
+            };
             match next_action {
                 // We're good to proceed with the following probing action
                 Control::Proceed(Some(flow_node::Action::ProbeOutgoingSequenceFlows(indices))) => {
@@ -304,24 +555,102 @@ impl Scheduler {
                 }
                 // We're good to proceed with the following flow action
                 Control::Proceed(Some(flow_node::Action::Flow(ref indices))) => {
+                    self.cancel_alternatives(token);
+                    // flow node passes its tokens on (except for those waiting for a
+                    // subsequent activation)
+                    let flow_node = self.flow_nodes.get_mut(token).unwrap();
+                    flow_node.active_tokens = flow_node.node.waiting_tokens();
+                    self.tokens_changed = true;
                     let el = self.flow_nodes.get(token).unwrap().element();
                     let outgoings = el.outgoings();
-                    for index in indices {
+                    let id = self.flow_nodes.get(token).unwrap().id.clone();
+                    let tokens = self.flow_nodes.get(token).unwrap().tokens;
+                    if el.incomings().is_empty() {
+                        self.notify(Notification::Enter, &id, tokens).await;
+                    }
+                    self.notify(Notification::Leave, &id, tokens).await;
+                    #[cfg(feature = "invariants")]
+                    {
+                        let waiting = self.flow_nodes.get(token).unwrap().node.waiting_tokens();
+                        if let Err(violation) =
+                            self.invariants
+                                .flow(&id, !el.incomings().is_empty(), waiting)
+                        {
+                            self.report_violation(violation);
+                        }
+                    }
+                    // the default flow (of an activity) is only taken if no other flow is
+                    let default = self.default_flow(&id, outgoings);
+                    let mut ordered: SmallVec<
+                        [flow_node::OutgoingIndex; flow_node::SMALL_OUTGOING],
+                    > = indices
+                        .iter()
+                        .copied()
+                        .filter(|index| Some(*index) != default)
+                        .collect();
+                    if !indices.is_empty() {
+                        ordered.extend(default);
+                    }
+                    let mut taken = 0;
+                    for index in ordered.iter() {
+                        #[cfg(feature = "invariants")]
+                        {
+                            if *index >= outgoings.len() {
+                                self.report_violation(
+                                    super::invariants::Violation::InvalidOutgoing {
+                                        node: id.clone(),
+                                        index: *index,
+                                    },
+                                );
+                                continue;
+                            }
+                        }
                         let seq_flow = self
                             .element
                             .find_by_id(&outgoings[*index])
                             .and_then(|seq_flow| seq_flow.downcast_ref::<SequenceFlow>())
                             .cloned();
                         if let Some(seq_flow) = seq_flow {
-                            let success = self.probe_sequence_flow(&seq_flow).await;
+                            let success = if Some(*index) == default {
+                                taken == 0
+                            } else {
+                                self.probe_sequence_flow(&seq_flow).await
+                            };
                             if !success {
                                 continue;
                             }
-                            if let Some(next_node) = self
-                                .flow_nodes
-                                .iter_mut()
-                                .find(|next_node| next_node.id == seq_flow.target_ref)
+                            taken += 1;
+                            self.notify(
+                                Notification::Take,
+                                seq_flow.id.as_deref().unwrap_or_default(),
+                                tokens,
+                            )
+                            .await;
+                            #[cfg(feature = "invariants")]
                             {
+                                if seq_flow.source_ref != id {
+                                    self.report_violation(
+                                        super::invariants::Violation::UnmatchedIncoming {
+                                            seq_flow: seq_flow.id.clone().unwrap_or_default(),
+                                            node: id.clone(),
+                                        },
+                                    );
+                                }
+                                if self.token_of(&seq_flow.target_ref).is_none() {
+                                    self.report_violation(
+                                        super::invariants::Violation::LostToken {
+                                            seq_flow: seq_flow.id.clone().unwrap_or_default(),
+                                            node: seq_flow.target_ref.clone(),
+                                        },
+                                    );
+                                }
+                            }
+                            let mut entered = None;
+                            let next_node = match self.token_of(&seq_flow.target_ref) {
+                                Some(token) => self.flow_nodes.get_mut(token),
+                                None => None,
+                            };
+                            if let Some(next_node) = next_node {
                                 let node = &mut next_node.node;
                                 // match target's node incoming index for this sequence flow
                                 if let Some((_, index)) =
@@ -334,51 +663,1019 @@ impl Scheduler {
                                     });
                                     // increase the number of tokens by a number of added flows
                                     next_node.tokens += indices.len();
+                                    next_node.active_tokens += 1;
                                     // report it to the target node
                                     node.tokens(next_node.tokens);
                                     // and report the incoming
                                     node.incoming(*index);
+                                    self.tokens += 1;
+                                    self.tokens_changed = true;
+                                    *self.visits.entry(next_node.id.clone()).or_insert(0) += 1;
+                                    #[cfg(feature = "invariants")]
+                                    self.invariants.incoming(&next_node.id);
+                                    entered = Some((next_node.id.clone(), next_node.tokens));
                                 }
                             }
+                            if let Some((next_node, tokens)) = entered {
+                                self.arm_timeout(&next_node);
+                                self.notify(Notification::Enter, &next_node, tokens).await;
+                            }
                         }
                     }
+                    if taken == 0 && !ordered.is_empty() {
+                        // "If a default path is not specified and the Process is executed such
+                        // that none of the conditional Expressions evaluates to true, a runtime
+                        // exception occurs"
+                        let _ = self
+                            .log_broadcast
+                            .send(Log::NoDefaultPath { node: el.clone() });
+                    }
                 }
                 // flow node completion
                 Control::Proceed(Some(flow_node::Action::Complete)) => {
-                    let _ = self.log_broadcast.send(Log::FlowNodeCompleted {
-                        node: self.flow_nodes.get(token).unwrap().element().clone(),
-                    });
+                    // tokens may have been passed on already
+                    let leaving = self.flow_nodes.get(token).unwrap().active_tokens > 0;
+                    // flow node consumes its tokens
+                    self.flow_nodes.get_mut(token).unwrap().active_tokens = 0;
+                    self.tokens_changed = true;
+                    #[cfg(feature = "invariants")]
+                    {
+                        if let Err(violation) = self
+                            .invariants
+                            .complete(&self.flow_nodes.get(token).unwrap().id)
+                        {
+                            self.report_violation(violation);
+                        }
+                    }
+                    let node = self.flow_nodes.get(token).unwrap().element();
+                    let _ = self
+                        .log_broadcast
+                        .send(Log::FlowNodeCompleted { node: node.clone() });
+                    let (id, tokens) = {
+                        let flow_node = self.flow_nodes.get(token).unwrap();
+                        (flow_node.id.clone(), flow_node.tokens)
+                    };
+                    if leaving {
+                        self.notify(Notification::Leave, &id, tokens).await;
+                    }
+                    if let Some(incident) = self.uncaught_error(node.as_ref()) {
+                        self.incident = Some(incident);
+                        self.finish(Outcome::Failed).await;
+                    }
                 }
                 // nothing, don't reschedule this flow node anymore
                 Control::Proceed(None) => {
+                    self.unschedule(token);
                     if self.flow_nodes.is_empty() {
-                        let _ = self.log_broadcast.send(Log::Done);
+                        self.finish(Outcome::Completed).await;
                     }
-                    Pin::new(&mut self.flow_nodes).remove(token);
                 }
                 // no action to be taken
                 Control::Drop => {}
             }
+            #[cfg(feature = "invariants")]
+            {
+                let active = self
+                    .flow_nodes
+                    .iter_mut()
+                    .map(|flow_node| flow_node.active_tokens)
+                    .sum();
+                if let Err(violation) = self.invariants.check(self.tokens, active) {
+                    self.report_violation(violation);
+                }
+            }
+            self.check_completion().await;
         }
     }
 
-    fn start(&mut self, sender: oneshot::Sender<Result<(), StartError>>) {
-        if !self
-            .process
-            .element()
-            .flow_elements()
+    /// Cancels tokens of flow nodes that follow the same event-based gateway as a flow node
+    /// that is about to flow (the first one to catch its event wins)
+    fn cancel_alternatives(&mut self, token: usize) {
+        let id = match self.flow_nodes.get(token) {
+            Some(flow_node) => &flow_node.id,
+            None => return,
+        };
+        let alternatives: Vec<String> = match self.alternatives.get(id) {
+            Some(alternatives) => alternatives
+                .iter()
+                .filter(|target| self.has_active_tokens(target))
+                .cloned()
+                .collect(),
+            None => return,
+        };
+        for alternative in alternatives {
+            self.cancel(&alternative);
+        }
+    }
+
+    /// Returns index of the default outgoing flow of an activity
+    fn default_flow(&self, id: &str, outgoings: &[String]) -> Option<flow_node::OutgoingIndex> {
+        let default = self
+            .element
+            .find_by_id(id)
+            .and_then(|e| schema::Cast::<dyn schema::ActivityType>::cast(e))
+            .and_then(|activity| activity.default().clone())?;
+        outgoings.iter().position(|outgoing| outgoing == &default)
+    }
+
+    /// Notifies execution listeners covering an element
+    async fn notify(&self, notification: Notification, element: &str, tokens: usize) {
+        let listeners = self.process.model().execution_listeners();
+        let process = self.element.id.as_deref();
+        let mut listeners = listeners
             .iter()
-            .map(|e| e.clone().into_inner())
-            .any(|node| node.element() == E::StartEvent)
+            .filter(|(scope, _)| scope.covers(process, element))
+            .map(|(_, listener)| listener)
+            .peekable();
+        if listeners.peek().is_none() {
+            return;
+        }
+        let variables = self.variables().await;
+        let execution = Execution {
+            process: &self.process,
+            element,
+            tokens,
+            variables: &variables,
+        };
+        for listener in listeners {
+            match notification {
+                Notification::Enter => listener.on_enter(&execution).await,
+                Notification::Leave => listener.on_leave(&execution).await,
+                Notification::Take => listener.on_take(&execution).await,
+            }
+        }
+    }
+
+    /// Reports to flow nodes with more than one incoming flow through which of them tokens can
+    /// still arrive, if it has changed since the last report
+    fn report_upstream_tokens(&mut self) {
+        if !self.tokens_changed {
+            return;
+        }
+        self.tokens_changed = false;
+        let holding = self.holding_tokens();
+        for flow_node in self
+            .flow_nodes
+            .iter_mut()
+            .filter(|flow_node| !flow_node.upstream.is_empty())
         {
-            let _ = sender.send(Err(StartError::NoStartEvent));
+            let upstream_tokens: SmallVec<[bool; flow_node::SMALL_INCOMING]> = flow_node
+                .upstream
+                .iter()
+                .map(|nodes| nodes.iter().any(|node| holding.contains_key(node)))
+                .collect();
+            if upstream_tokens != flow_node.upstream_tokens {
+                flow_node.node.upstream_tokens(&upstream_tokens);
+                flow_node.upstream_tokens = upstream_tokens;
+            }
+        }
+    }
+
+    /// Returns the number of active tokens held by every flow node holding any
+    fn holding_tokens(&mut self) -> HashMap<String, usize> {
+        self.flow_nodes
+            .iter_mut()
+            .filter(|flow_node| flow_node.active_tokens > 0)
+            .map(|flow_node| (flow_node.id.clone(), flow_node.active_tokens))
+            .collect()
+    }
+
+    /// Returns, for every incoming sequence flow of a flow node, the number of tokens held by
+    /// flow nodes it can be reached from (without passing through the flow node itself)
+    ///
+    /// These are the tokens that can still arrive through the sequence flow. Returns `None`
+    /// if there's no such flow node.
+    pub(crate) fn tokens_upstream_of(
+        &mut self,
+        node_id: &str,
+    ) -> Option<SmallVec<[usize; flow_node::SMALL_INCOMING]>> {
+        let holding = self.holding_tokens();
+        let flow_node = self.flow_nodes.get(self.token_of(node_id)?)?;
+        let upstream = if flow_node.upstream.is_empty() {
+            // upstream flow nodes are only kept for joins
+            upstream_of(&self.element, node_id, flow_node.element().incomings())
         } else {
-            let event_broadcast = self.process.event_broadcast();
-            let _ = event_broadcast.send(Event::Start);
-            let _ = sender.send(Ok(()));
+            flow_node.upstream.clone()
+        };
+        Some(
+            upstream
+                .iter()
+                .map(|nodes| {
+                    nodes
+                        .iter()
+                        .filter_map(|node| holding.get(node))
+                        .sum::<usize>()
+                })
+                .collect(),
+        )
+    }
+
+    /// Completes the process if it has been started and no tokens are left
+    async fn check_completion(&mut self) {
+        if self.started
+            && self
+                .flow_nodes
+                .iter_mut()
+                .all(|flow_node| flow_node.active_tokens == 0)
+        {
+            self.finish(Outcome::Completed).await;
         }
     }
 
+    /// Records terminal outcome (only the first one counts)
+    async fn finish(&mut self, outcome: Outcome) {
+        if self.outcome.is_none() {
+            self.outcome = Some(outcome);
+            self.priority.update(None);
+            let variables = Arc::new(self.variables().await);
+            // logged first, so that whoever stops listening on termination doesn't miss it
+            let _ = self.log_broadcast.send(Log::Done {
+                outcome,
+                tokens: self.tokens,
+                duration: time::now()
+                    .duration_since(self.started_at)
+                    .unwrap_or_default(),
+                visits: self.visits.clone(),
+            });
+            let _ = self.termination_sender.send(Some(Termination {
+                outcome,
+                variables,
+                incident: self.incident.clone(),
+            }));
+        }
+    }
+
+    /// Fails the process with an incident if it has exceeded its budget
+    ///
+    /// Returns `true` if the budget has been exceeded.
+    async fn check_budget(&mut self) -> bool {
+        let budget = self.process.model().budget();
+        let usage = self.process.usage();
+        if !budget.is_exceeded_by(&usage) {
+            return false;
+        }
+        self.exhausted = true;
+        let _ = self
+            .log_broadcast
+            .send(Log::BudgetExceeded { budget, usage });
+        if self.outcome.is_none() {
+            self.incident = Some(Incident {
+                node: None,
+                error: Event::ErrorEvent {
+                    error_ref: None,
+                    error_code: Some("BudgetExceeded".into()),
+                    error_message: Some(format!("budget {:?} exceeded by {:?}", budget, usage)),
+                },
+            });
+            self.finish(Outcome::Failed).await;
+        }
+        true
+    }
+
+    /// Returns an incident if the flow node is an error end event whose error
+    /// can't be caught by any catch event of the process that is currently waiting
+    fn uncaught_error(&self, node: &dyn schema::FlowNodeType) -> Option<Incident> {
+        let end_event = node.downcast_ref::<schema::EndEvent>()?;
+        let definitions = self.process.model().definitions();
+        let error = event::events(&end_event.event_definitions, &definitions)
+            .filter(|event| matches!(event, Event::ErrorEvent { .. }))
+            .find(|error| !self.catches(error))?;
+        Some(Incident {
+            node: end_event.id.clone(),
+            error,
+        })
+    }
+
+    /// Passes the token of an activity on to an error boundary event attached to it that
+    /// catches an error, returning false if there's no such event
+    ///
+    /// If more than one catches it, the most specific one is chosen (see
+    /// [`Event::error_match_rank`]): one referencing the same error, then one matching its
+    /// code, then one catching all errors. Error boundary events always interrupt the
+    /// activity.
+    async fn catch_on_boundary(&mut self, activity: &str, error: &Event) -> bool {
+        if self.outcome.is_some() || !self.has_active_tokens(activity) {
+            return false;
+        }
+        let definitions = self.process.model().definitions();
+        let boundary_event = self
+            .element
+            .flow_elements
+            .iter()
+            .filter_map(|e| match e {
+                FlowElement::BoundaryEvent(boundary_event)
+                    if boundary_event.attached_toref == activity =>
+                {
+                    event::events(&boundary_event.event_definitions, &definitions)
+                        .filter_map(|catcher| catcher.error_match_rank(error))
+                        .min()
+                        .map(|rank| (rank, boundary_event))
+                }
+                _ => None,
+            })
+            // the first one in document order wins a tie
+            .min_by_key(|(rank, _)| *rank)
+            .map(|(_, boundary_event)| boundary_event.clone());
+        let boundary_event = match boundary_event {
+            Some(boundary_event) => boundary_event,
+            None => return false,
+        };
+        self.cancel(activity);
+        if let Some(ref id) = boundary_event.id {
+            *self.visits.entry(id.clone()).or_insert(0) += 1;
+        }
+        let outgoings = boundary_event.outgoings.clone();
+        let _ = self.log_broadcast.send(Log::FlowNodeCompleted {
+            node: Box::new(boundary_event),
+        });
+        for outgoing in outgoings {
+            self.take_sequence_flow(&outgoing);
+        }
+        self.check_completion().await;
+        true
+    }
+
+    /// Returns true if a catch event holding a token, or an error boundary event attached
+    /// to an activity holding a token, can catch an event
+    ///
+    /// Catch events that no token has reached (or that were already left) can't
+    /// catch anything, even if their definitions match.
+    fn catches(&self, event: &Event) -> bool {
+        let definitions = self.process.model().definitions();
+        self.element
+            .flow_elements
+            .iter()
+            .filter_map(|e| match e {
+                FlowElement::IntermediateCatchEvent(catch_event)
+                    if catch_event
+                        .id
+                        .as_deref()
+                        .map_or(false, |id| self.has_active_tokens(id)) =>
+                {
+                    Some((&catch_event.event_definitions, false))
+                }
+                FlowElement::BoundaryEvent(boundary_event)
+                    if self.has_active_tokens(&boundary_event.attached_toref) =>
+                {
+                    Some((&boundary_event.event_definitions, true))
+                }
+                _ => None,
+            })
+            .flat_map(|(event_definitions, boundary)| {
+                event::events(event_definitions, &definitions)
+                    .filter(move |catcher| !boundary || matches!(catcher, Event::ErrorEvent { .. }))
+            })
+            .any(|catcher| catcher.matches(event))
+    }
+
+    #[cfg(feature = "invariants")]
+    fn report_violation(&self, violation: super::invariants::Violation) {
+        let _ = self.log_broadcast.send(Log::InvariantViolation {
+            error: violation.to_string(),
+        });
+    }
+
+    async fn start(
+        &mut self,
+        mut options: StartOptions,
+        sender: oneshot::Sender<Result<(), StartError>>,
+    ) {
+        let variables = std::mem::take(&mut options.variables);
+        let priority = options.priority.take();
+        let result = self.try_start(options, &variables);
+        if result.is_ok() {
+            // flow nodes are only polled after this, so they observe initial variables
+            for (id, value) in variables {
+                if let Some(container) = self.data_objects.get(&id) {
+                    *container.write().await = value;
+                }
+            }
+            if let Some(priority) = priority {
+                *self.process.priority.lock().unwrap() = priority;
+            }
+        }
+        let _ = sender.send(result);
+    }
+
+    fn try_start(
+        &mut self,
+        options: StartOptions,
+        variables: &Variables,
+    ) -> Result<(), StartError> {
+        if self.started {
+            return Err(StartError::AlreadyRunning);
+        }
+        if let Some(id) = variables
+            .keys()
+            .find(|id| !self.data_objects.contains_key(*id))
+        {
+            return Err(StartError::UnknownDataObject(id.clone()));
+        }
+        if let Some(ref node) = options.start_before {
+            self.validate()?;
+            let incoming = self.first_incoming(node).map_err(StartError::StartBefore)?;
+            // start events never flow, so `Event::Start` is not sent
+            self.started = true;
+            self.started_at = time::now();
+            self.take_sequence_flow(&incoming);
+            return Ok(());
+        }
+        // receive tasks that instantiate the process are equivalent to message start events,
+        // so they are recorded along with start events (and whether they wait for an event)
+        let start_events: Vec<(Option<&String>, bool)> = self
+            .element
+            .flow_elements
+            .iter()
+            .filter_map(|e| match e {
+                FlowElement::StartEvent(start_event) => Some((
+                    start_event.id.as_ref(),
+                    !start_event.event_definitions.is_empty(),
+                )),
+                FlowElement::ReceiveTask(receive_task)
+                    if receive_task::instantiates(receive_task) =>
+                {
+                    Some((receive_task.id.as_ref(), true))
+                }
+                _ => None,
+            })
+            .collect();
+        if start_events.is_empty() {
+            return Err(StartError::NoStartEvent);
+        }
+        if let Some(ref start_event) = options.start_event {
+            if !start_events.iter().any(|(id, _)| *id == Some(start_event)) {
+                return Err(StartError::UnknownStartEvent(start_event.clone()));
+            }
+        } else if start_events.len() > 1 && start_events.iter().all(|(_, triggered)| *triggered) {
+            return Err(StartError::AmbiguousStartEvents {
+                start_events: start_events
+                    .iter()
+                    .map(|(id, _)| id.cloned().unwrap_or_default())
+                    .collect(),
+            });
+        }
+        self.validate()?;
+        // start events (and instantiating receive tasks) hold a token until they flow
+        for flow_node in self.flow_nodes.iter_mut() {
+            let element = flow_node.element();
+            let done = if element.downcast_ref::<schema::StartEvent>().is_some() {
+                flow_node::State::StartEvent(event::start_event::State::Done)
+            } else if element
+                .downcast_ref::<schema::ReceiveTask>()
+                .map_or(false, receive_task::instantiates)
+            {
+                flow_node::State::ReceiveTask(receive_task::State::Done)
+            } else {
+                continue;
+            };
+            if matches!(options.start_event, Some(ref id) if id != &flow_node.id) {
+                // start events that haven't been selected never flow
+                let _ = flow_node.node.set_state(done);
+                continue;
+            }
+            flow_node.active_tokens += 1;
+            self.tokens += 1;
+            self.tokens_changed = true;
+            *self.visits.entry(flow_node.id.clone()).or_insert(0) += 1;
+            #[cfg(feature = "invariants")]
+            self.invariants.incoming(&flow_node.id);
+        }
+        self.started = true;
+        self.started_at = time::now();
+        self.process.send_event(Event::Start);
+        Ok(())
+    }
+
+    /// Ensures every sequence flow connects two scheduled flow nodes, neither of which
+    /// is a compensation activity (those are only invoked through compensation)
+    fn validate(&self) -> Result<(), StartError> {
+        for seq_flow in self.element.flow_elements.iter().filter_map(|e| match e {
+            FlowElement::SequenceFlow(seq_flow) => Some(seq_flow),
+            _ => None,
+        }) {
+            let id = seq_flow.id.clone().unwrap_or_default();
+            if !self.flow_nodes_outgoing.contains_key(&id) {
+                return Err(StartError::InvalidDefinition {
+                    reason: format!(
+                        "sequence flow {} is not an outgoing of a supported flow node {}",
+                        id, seq_flow.source_ref
+                    ),
+                });
+            }
+            if !self.flow_nodes_incoming.contains_key(&id) {
+                return Err(StartError::InvalidDefinition {
+                    reason: format!(
+                        "sequence flow {} is not an incoming of a supported flow node {}",
+                        id, seq_flow.target_ref
+                    ),
+                });
+            }
+            for node in [&seq_flow.source_ref, &seq_flow.target_ref].iter() {
+                if self.is_for_compensation(node) {
+                    return Err(StartError::InvalidDefinition {
+                        reason: format!(
+                            "sequence flow {} connects compensation activity {}",
+                            id, node
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_for_compensation(&self, id: &str) -> bool {
+        self.element
+            .find_by_id(id)
+            .and_then(|e| schema::Cast::<dyn schema::ActivityType>::cast(e))
+            .map(|activity| activity.is_for_compensation() == &Some(true))
+            .unwrap_or(false)
+    }
+
+    /// Applies operator's modifications of the process instance
+    async fn modify(&mut self, modifications: Vec<Modification>) -> Result<(), ModificationError> {
+        if !self.started || self.outcome.is_some() {
+            return Err(ModificationError::NotRunning);
+        }
+        // check everything first, so that modifications are applied all or nothing
+        //
+        // Active tokens are tracked as earlier modifications change them, so that
+        // `[Cancel(a), Skip(a)]` is rejected. Tokens a skipped activity passes on depend
+        // on its conditions and aren't known until it's skipped.
+        let mut active_tokens: HashMap<String, usize> = HashMap::new();
+        for modification in modifications.iter() {
+            match modification {
+                Modification::Cancel(id) => {
+                    if self.flow_node_element(id).is_none() {
+                        return Err(ModificationError::UnknownFlowNode(id.clone()));
+                    }
+                    if self.take_active_tokens(&mut active_tokens, id) == 0 {
+                        return Err(ModificationError::NoActiveToken(id.clone()));
+                    }
+                }
+                Modification::Skip(id) => {
+                    if self.flow_node_element(id).is_none() {
+                        return Err(ModificationError::UnknownFlowNode(id.clone()));
+                    }
+                    if self
+                        .element
+                        .find_by_id(id)
+                        .and_then(|e| schema::Cast::<dyn schema::ActivityType>::cast(e))
+                        .is_none()
+                    {
+                        return Err(ModificationError::NotAnActivity(id.clone()));
+                    }
+                    if self.take_active_tokens(&mut active_tokens, id) == 0 {
+                        return Err(ModificationError::NoActiveToken(id.clone()));
+                    }
+                }
+                Modification::StartBefore(id) => {
+                    self.first_incoming(id)?;
+                    self.add_active_token(&mut active_tokens, id);
+                }
+                Modification::TakeSequenceFlow(id) => {
+                    if !self.flow_nodes_incoming.contains_key(id) {
+                        return Err(ModificationError::UnknownSequenceFlow(id.clone()));
+                    }
+                    let target = self
+                        .element
+                        .find_by_id(id)
+                        .and_then(|seq_flow| seq_flow.downcast_ref::<SequenceFlow>())
+                        .map(|seq_flow| seq_flow.target_ref.clone());
+                    if let Some(target) = target {
+                        self.add_active_token(&mut active_tokens, &target);
+                    }
+                }
+                Modification::SetVariable(id, _) => {
+                    if !self.data_objects.contains_key(id) {
+                        return Err(ModificationError::UnknownDataObject(id.clone()));
+                    }
+                }
+            }
+        }
+        let mut cancelled = vec![];
+        let mut started = vec![];
+        let mut variables = vec![];
+        // variables are written before any token moves, so that skipped activities pass
+        // their tokens on according to the variables set by the same modification
+        let (writes, modifications): (Vec<_>, Vec<_>) = modifications
+            .into_iter()
+            .partition(|modification| matches!(modification, Modification::SetVariable(..)));
+        for modification in writes.into_iter().chain(modifications) {
+            match modification {
+                Modification::Cancel(id) => {
+                    self.cancel(&id);
+                    cancelled.push(id);
+                }
+                Modification::Skip(id) => {
+                    self.skip(&id).await;
+                    let _ = self.log_broadcast.send(Log::ActivitySkipped {
+                        activity: id,
+                        variables: variables.clone(),
+                    });
+                }
+                Modification::StartBefore(id) => {
+                    let incoming = self
+                        .flow_node_element(&id)
+                        .and_then(|element| element.incomings().first().cloned());
+                    if let Some(incoming) = incoming {
+                        self.take_sequence_flow(&incoming);
+                    }
+                    started.push(id);
+                }
+                Modification::TakeSequenceFlow(id) => {
+                    if let Some(target) = self.take_sequence_flow(&id) {
+                        started.push(target);
+                    }
+                }
+                Modification::SetVariable(id, value) => {
+                    if let Some(container) = self.data_objects.get(&id) {
+                        *container.write().await = value;
+                    }
+                    variables.push(id);
+                }
+            }
+        }
+        let variables_changed = !variables.is_empty();
+        let _ = self.log_broadcast.send(Log::Modified {
+            cancelled,
+            started,
+            variables,
+        });
+        if variables_changed {
+            self.evaluate_conditions().await;
+        }
+        self.check_completion().await;
+        Ok(())
+    }
+
+    /// Sets variables and places a token before a flow node, starting the process if it
+    /// hasn't been started
+    ///
+    /// Nothing is changed unless both the flow node and all variables exist. Start events
+    /// of a process started this way never flow.
+    #[cfg(feature = "unsafe-ops")]
+    async fn inject(&mut self, node: &str, variables: Variables) -> Result<(), ModificationError> {
+        if self.outcome.is_some() {
+            return Err(ModificationError::NotRunning);
+        }
+        let incoming = self.first_incoming(node)?;
+        if let Some(id) = variables
+            .keys()
+            .find(|id| !self.data_objects.contains_key(*id))
+        {
+            return Err(ModificationError::UnknownDataObject(id.clone()));
+        }
+        let variables_changed = !variables.is_empty();
+        // the flow node is only polled after this, so it observes the variables
+        for (id, value) in variables {
+            if let Some(container) = self.data_objects.get(&id) {
+                *container.write().await = value;
+            }
+        }
+        if !self.started {
+            self.started = true;
+            self.started_at = time::now();
+        }
+        self.take_sequence_flow(&incoming);
+        let _ = self.log_broadcast.send(Log::TokenInjected {
+            node: node.to_string(),
+        });
+        if variables_changed {
+            self.evaluate_conditions().await;
+        }
+        Ok(())
+    }
+
+    /// Returns the first incoming sequence flow of a flow node, which is where tokens created
+    /// before it come from
+    fn first_incoming(&self, node: &str) -> Result<String, ModificationError> {
+        let incoming = self
+            .flow_node_element(node)
+            .ok_or_else(|| ModificationError::UnknownFlowNode(node.to_string()))?
+            .incomings()
+            .first()
+            .cloned()
+            .ok_or_else(|| ModificationError::NoIncoming(node.to_string()))?;
+        if !self.flow_nodes_incoming.contains_key(&incoming) {
+            return Err(ModificationError::UnknownFlowNode(node.to_string()));
+        }
+        Ok(incoming)
+    }
+
+    /// Takes all active tokens of a flow node from a running count that starts from
+    /// the current ones, returning how many there were
+    fn take_active_tokens(&self, active_tokens: &mut HashMap<String, usize>, id: &str) -> usize {
+        let count = self.active_token_count(active_tokens, id);
+        active_tokens.insert(id.to_string(), 0);
+        count
+    }
+
+    /// Adds an active token to a flow node in a running count that starts from the
+    /// current ones
+    fn add_active_token(&self, active_tokens: &mut HashMap<String, usize>, id: &str) {
+        let count = self.active_token_count(active_tokens, id);
+        active_tokens.insert(id.to_string(), count + 1);
+    }
+
+    fn active_token_count(&self, active_tokens: &HashMap<String, usize>, id: &str) -> usize {
+        active_tokens.get(id).copied().unwrap_or_else(|| {
+            self.token_of(id)
+                .and_then(|token| self.flow_nodes.get(token))
+                .map(|flow_node| flow_node.active_tokens)
+                .unwrap_or(0)
+        })
+    }
+
+    fn has_active_tokens(&self, id: &str) -> bool {
+        self.token_of(id)
+            .and_then(|token| self.flow_nodes.get(token))
+            .map(|flow_node| flow_node.active_tokens > 0)
+            .unwrap_or(false)
+    }
+
+    /// Drops tokens held by an activity and passes them on as if it has completed
+    ///
+    /// Like on completion, the default flow is only taken if no other flow is. Does nothing
+    /// if the activity holds no active tokens.
+    async fn skip(&mut self, id: &str) {
+        if !self.has_active_tokens(id) {
+            return;
+        }
+        self.cancel(id);
+        let element = match self.flow_node_element(id) {
+            Some(element) => dyn_clone::clone_box(element),
+            None => return,
+        };
+        let outgoings = element.outgoings().clone();
+        let default = self.default_flow(id, &outgoings);
+        let mut taken = 0;
+        for (index, outgoing) in outgoings.iter().enumerate() {
+            if Some(index) == default {
+                continue;
+            }
+            let seq_flow = self
+                .element
+                .find_by_id(outgoing)
+                .and_then(|seq_flow| seq_flow.downcast_ref::<SequenceFlow>())
+                .cloned();
+            if let Some(seq_flow) = seq_flow {
+                if self.probe_sequence_flow(&seq_flow).await {
+                    self.take_sequence_flow(outgoing);
+                    taken += 1;
+                }
+            }
+        }
+        match default {
+            Some(default) if taken == 0 => {
+                self.take_sequence_flow(&outgoings[default]);
+            }
+            None if taken == 0 && !outgoings.is_empty() => {
+                let _ = self
+                    .log_broadcast
+                    .send(Log::NoDefaultPath { node: element });
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops tokens held by a flow node, replacing it with a new one in its initial state
+    fn cancel(&mut self, id: &str) {
+        if let Some(token) = self.token_of(id) {
+            self.unschedule(token);
+        }
+        self.schedule(id);
+        #[cfg(feature = "invariants")]
+        self.invariants.cancel(id);
+    }
+
+    fn flow_node_element(&self, id: &str) -> Option<&dyn schema::FlowNodeType> {
+        self.element
+            .find_by_id(id)
+            .and_then(|e| schema::Cast::<dyn schema::FlowNodeType>::cast(e))
+    }
+
+    /// Returns token of a scheduled flow node
+    fn token_of(&self, id: &str) -> Option<usize> {
+        self.flow_nodes_by_id
+            .get(id)
+            .copied()
+            // finished flow nodes may have been dropped
+            .filter(|token| self.flow_nodes.get(*token).is_some())
+    }
+
+    /// Schedules a flow node in its initial state
+    ///
+    /// Returns its token.
+    fn schedule(&mut self, id: &str) -> Option<usize> {
+        let mut node = self
+            .element
+            .flow_elements
+            .iter()
+            .filter_map(|e| flow_node::new(e.clone().into_inner()))
+            .find(|node| node.element().id().as_deref() == Some(id))?;
+        node.set_process(self.process.clone());
+        let element = node.element();
+        let token = self.flow_nodes.insert(FlowNode {
+            id: id.to_string(),
+            node,
+            tokens: 0,
+            active_tokens: 0,
+            upstream: upstream(&self.element, id, element.incomings()),
+            upstream_tokens: SmallVec::new(),
+        });
+        for (index, outgoing) in element.outgoings().iter().enumerate() {
+            self.flow_nodes_outgoing
+                .insert(outgoing.to_owned(), (token, index));
+        }
+        for (index, incoming) in element.incomings().iter().enumerate() {
+            self.flow_nodes_incoming
+                .insert(incoming.to_owned(), (token, index));
+        }
+        self.flow_nodes_by_id.insert(id.to_string(), token);
+        // a newly scheduled join has to learn about upstream tokens
+        self.tokens_changed = true;
+        Some(token)
+    }
+
+    /// Removes a scheduled flow node, dropping tokens held by it
+    fn unschedule(&mut self, token: usize) {
+        if let Some(flow_node) = self.flow_nodes.get(token) {
+            if self.flow_nodes_by_id.get(&flow_node.id) == Some(&token) {
+                self.flow_nodes_by_id.remove(&flow_node.id);
+            }
+        }
+        Pin::new(&mut self.flow_nodes).remove(token);
+        self.tokens_changed = true;
+    }
+
+    /// Delivers a new token to the target of a sequence flow, scheduling it if necessary
+    ///
+    /// Returns the target's identifier.
+    fn take_sequence_flow(&mut self, seq_flow: &str) -> Option<String> {
+        let target = self
+            .element
+            .find_by_id(seq_flow)
+            .and_then(|seq_flow| seq_flow.downcast_ref::<SequenceFlow>())?
+            .target_ref
+            .clone();
+        if self.token_of(&target).is_none() {
+            self.schedule(&target)?;
+        }
+        let (token, index) = *self.flow_nodes_incoming.get(seq_flow)?;
+        let flow_node = self.flow_nodes.get_mut(token)?;
+        let _ = self.log_broadcast.send(Log::FlowNodeIncoming {
+            node: flow_node.element(),
+            incoming_index: index,
+        });
+        flow_node.tokens += 1;
+        flow_node.active_tokens += 1;
+        let tokens = flow_node.tokens;
+        flow_node.node.tokens(tokens);
+        flow_node.node.incoming(index);
+        self.tokens += 1;
+        self.tokens_changed = true;
+        *self.visits.entry(target.clone()).or_insert(0) += 1;
+        #[cfg(feature = "invariants")]
+        self.invariants.incoming(&target);
+        self.arm_timeout(&target);
+        Some(target)
+    }
+
+    /// Starts the timeout of a task that has just been entered, if it has one (see
+    /// [`crate::model::Model::with_task_timeout`])
+    fn arm_timeout(&self, node: &str) {
+        let timeout = match self.process.model().task_timeout(node) {
+            Some((timeout, _)) => timeout,
+            None => return,
+        };
+        // the timeout only applies if the task hasn't been entered again since
+        let visit = self.visits.get(node).copied().unwrap_or_default();
+        // the timeout doesn't keep the scheduler's channel open
+        let sender = self.process.sender.downgrade();
+        let node = node.to_string();
+        task::spawn(async move {
+            time::sleep(timeout).await;
+            if let Some(sender) = sender.upgrade() {
+                let _ = sender.send(Request::TaskTimedOut(node, visit)).await;
+            }
+        });
+    }
+
+    /// Cancels a task that still holds the token it was entered with when its timeout elapsed
+    async fn time_out(&mut self, node: &str, visit: usize) {
+        if self.outcome.is_some()
+            || self.visits.get(node).copied() != Some(visit)
+            || !self.has_active_tokens(node)
+        {
+            return;
+        }
+        let (timeout, action) = match self.process.model().task_timeout(node) {
+            Some(task_timeout) => task_timeout,
+            None => return,
+        };
+        let _ = self.log_broadcast.send(Log::FlowNodeTimedOut {
+            node: node.to_string(),
+            timeout,
+        });
+        match action {
+            TimeoutAction::Flow(seq_flow) => {
+                self.cancel(node);
+                let leaves = self
+                    .element
+                    .find_by_id(&seq_flow)
+                    .and_then(|e| e.downcast_ref::<SequenceFlow>())
+                    .map_or(false, |seq_flow| seq_flow.source_ref == node);
+                if leaves {
+                    self.take_sequence_flow(&seq_flow);
+                    self.check_completion().await;
+                } else {
+                    // only reported by model's diagnostics up to this point
+                    self.incident = Some(Incident {
+                        node: Some(node.to_string()),
+                        error: Event::ErrorEvent {
+                            error_ref: None,
+                            error_code: Some("InvalidTimeoutFlow".into()),
+                            error_message: Some(format!(
+                                "sequence flow {} doesn't leave {}",
+                                seq_flow, node
+                            )),
+                        },
+                    });
+                    self.finish(Outcome::Failed).await;
+                }
+            }
+            TimeoutAction::Error => {
+                let error = Event::ErrorEvent {
+                    error_ref: None,
+                    error_code: Some("TaskTimedOut".into()),
+                    error_message: Some(format!("timeout of {:?} exceeded", timeout)),
+                };
+                self.process
+                    .emit_event(Some(node.to_string()), error.clone());
+                if self.catch_on_boundary(node, &error).await {
+                    return;
+                }
+                if self.catches(&error) {
+                    self.cancel(node);
+                    self.check_completion().await;
+                    return;
+                }
+                match self.process.failure_policy(Some(node)) {
+                    Some(FailurePolicy::Suspend) => {
+                        // the token stays at the task until the process is modified
+                        let _ = self.log_broadcast.send(Log::ActivitySuspended {
+                            activity: Some(node.to_string()),
+                            error,
+                        });
+                    }
+                    Some(FailurePolicy::Skip) => {
+                        self.skip(node).await;
+                        self.check_completion().await;
+                    }
+                    Some(FailurePolicy::Fail) | None => {
+                        self.cancel(node);
+                        self.incident = Some(Incident {
+                            node: Some(node.to_string()),
+                            error,
+                        });
+                        self.finish(Outcome::Failed).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn snapshot(&mut self) -> Snapshot {
+        let flow_nodes = self
+            .flow_nodes
+            .iter_mut()
+            .map(|flow_node| {
+                (
+                    flow_node.id.clone(),
+                    FlowNodeSnapshot {
+                        state: flow_node.node.get_state(),
+                        tokens: flow_node.tokens,
+                        active_tokens: flow_node.active_tokens,
+                    },
+                )
+            })
+            .collect();
+        Snapshot {
+            flow_nodes,
+            data_objects: self.variables().await,
+        }
+    }
+
+    /// Returns a copy of all data objects
+    async fn variables(&self) -> Variables {
+        let mut variables = Variables::new();
+        for (id, container) in self.data_objects.iter() {
+            variables.insert(id.clone(), dyn_clone::clone_box(&**container.read().await));
+        }
+        variables
+    }
+
     fn get_data_object(
         &self,
         id: &str,
@@ -391,3 +1688,85 @@ impl Scheduler {
         }
     }
 }
+
+/// Returns, for every target of an event-based gateway, other targets of the same gateway
+fn alternatives(process: &Process) -> HashMap<String, Vec<String>> {
+    let mut alternatives: HashMap<String, Vec<String>> = HashMap::new();
+    for gateway in process.flow_elements.iter().filter_map(|e| match e {
+        FlowElement::EventBasedGateway(gateway) => Some(gateway),
+        _ => None,
+    }) {
+        let targets: Vec<&String> = gateway
+            .outgoings
+            .iter()
+            .filter_map(|outgoing| {
+                process
+                    .find_by_id(outgoing)
+                    .and_then(|e| e.downcast_ref::<SequenceFlow>())
+            })
+            .map(|seq_flow| &seq_flow.target_ref)
+            .collect();
+        for &target in targets.iter() {
+            let others = alternatives.entry(target.clone()).or_default();
+            for &other in targets.iter() {
+                if other != target && !others.contains(other) {
+                    others.push(other.clone());
+                }
+            }
+        }
+    }
+    alternatives
+}
+
+/// Returns identifiers of flow nodes every incoming flow of a flow node can be reached from
+/// without passing through the flow node itself (nothing, if it has just one incoming flow)
+fn upstream(process: &Process, node: &str, incomings: &[String]) -> Vec<Vec<String>> {
+    if incomings.len() < 2 {
+        return vec![];
+    }
+    upstream_of(process, node, incomings)
+}
+
+/// Returns flow nodes every incoming flow of a flow node can be reached from
+fn upstream_of(process: &Process, node: &str, incomings: &[String]) -> Vec<Vec<String>> {
+    let seq_flows: Vec<&SequenceFlow> = process
+        .flow_elements
+        .iter()
+        .filter_map(|e| match e {
+            FlowElement::SequenceFlow(seq_flow) => Some(seq_flow),
+            _ => None,
+        })
+        .collect();
+    incomings
+        .iter()
+        .map(|incoming| {
+            let mut queue: Vec<String> = seq_flows
+                .iter()
+                .filter(|seq_flow| seq_flow.id.as_ref() == Some(incoming))
+                .map(|seq_flow| seq_flow.source_ref.clone())
+                .collect();
+            let mut reachable = HashSet::new();
+            while let Some(id) = queue.pop() {
+                if id == node || !reachable.insert(id.clone()) {
+                    continue;
+                }
+                queue.extend(
+                    seq_flows
+                        .iter()
+                        .filter(|seq_flow| seq_flow.target_ref == id)
+                        .map(|seq_flow| seq_flow.source_ref.clone()),
+                );
+                // tokens held by an activity can leave through its boundary events
+                queue.extend(process.flow_elements.iter().filter_map(|e| match e {
+                    FlowElement::BoundaryEvent(boundary_event)
+                        if boundary_event.id.as_ref() == Some(&id) =>
+                    {
+                        Some(boundary_event.attached_toref.clone())
+                    }
+                    _ => None,
+                }));
+            }
+            reachable.into_iter().collect()
+        })
+        .collect()
+}
@@ -1,64 +1,74 @@
 //! # serde utilities
 use crate::bpmn::schema::*;
-use serde::ser::Error;
 use serde::*;
 
-macro_rules! downcast_and_serialize {
-    ($value: ident, $serializer: ident, $($type: ty),*) => {{
-        $(
-            if let Some(object) = $value.downcast_ref::<$type>() {
-                return object.serialize($serializer)
+macro_rules! flow_node_serde {
+    ($($type: ident),*) => {
+        // The below lint is enabled because if the advice is follwed,
+        // this will be the error:
+        //
+        // ```
+        // 66 | #[derive(Clone, Debug, Serialize)]
+        //                        ^^^^^^^^^ the trait `bpxe_bpmn_schema::FlowNodeType`
+        //                        is not implemented for `Box<(dyn bpxe_bpmn_schema::FlowNodeType + 'static)>`
+        // ```
+        //
+        // Flow nodes are serialized as [`FlowElement`]s, so they are tagged with their type.
+        #[allow(clippy::borrowed_box)]
+        pub(crate) fn serialize_flow_node<S>(
+            value: &Box<dyn FlowNodeType>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            $(
+                if let Some(node) = value.downcast_ref::<$type>() {
+                    return FlowElement::$type(node.clone()).serialize(serializer);
+                }
+            )*
+            Err(ser::Error::custom("unsupported type".to_string()))
+        }
+
+        pub(crate) fn deserialize_flow_node<'de, D>(
+            deserializer: D,
+        ) -> Result<Box<dyn FlowNodeType>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            match FlowElement::deserialize(deserializer)? {
+                $(FlowElement::$type(node) => Ok(Box::new(node)),)*
+                _ => Err(de::Error::custom("not a flow node".to_string())),
             }
-        )*
-        return Err(S::Error::custom("unsupported type".to_string()));
-    }};
+        }
+    };
 }
 
-// The below lint is enabled because if the advice is follwed,
-// this will be the error:
-//
-// ```
-// 66 | #[derive(Clone, Debug, Serialize)]
-//                        ^^^^^^^^^ the trait `bpxe_bpmn_schema::FlowNodeType`
-//                        is not implemented for `Box<(dyn bpxe_bpmn_schema::FlowNodeType + 'static)>`
-// ```
-#[allow(clippy::borrowed_box)]
-pub(crate) fn serialize_flow_node<S>(
-    value: &Box<dyn FlowNodeType>,
-    serializer: S,
-) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    downcast_and_serialize!(
-        value,
-        serializer,
-        AdHocSubProcess,
-        BoundaryEvent,
-        BusinessRuleTask,
-        CallActivity,
-        CallChoreography,
-        ChoreographyTask,
-        ComplexGateway,
-        EndEvent,
-        EventBasedGateway,
-        ExclusiveGateway,
-        Gateway,
-        ImplicitThrowEvent,
-        InclusiveGateway,
-        IntermediateCatchEvent,
-        IntermediateThrowEvent,
-        ManualTask,
-        ParallelGateway,
-        ReceiveTask,
-        ScriptTask,
-        SendTask,
-        ServiceTask,
-        StartEvent,
-        SubChoreography,
-        SubProcess,
-        Task,
-        Transaction,
-        UserTask
-    );
-}
+flow_node_serde!(
+    AdHocSubProcess,
+    BoundaryEvent,
+    BusinessRuleTask,
+    CallActivity,
+    CallChoreography,
+    ChoreographyTask,
+    ComplexGateway,
+    EndEvent,
+    EventBasedGateway,
+    ExclusiveGateway,
+    ImplicitThrowEvent,
+    InclusiveGateway,
+    IntermediateCatchEvent,
+    IntermediateThrowEvent,
+    ManualTask,
+    ParallelGateway,
+    ReceiveTask,
+    ScriptTask,
+    SendTask,
+    ServiceTask,
+    StartEvent,
+    SubChoreography,
+    SubProcess,
+    Task,
+    Transaction,
+    UserTask
+);
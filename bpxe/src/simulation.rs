@@ -0,0 +1,442 @@
+//! # Simulation
+//!
+//! Dry-run walker for process definitions. It doesn't evaluate scripts or conditions, doesn't
+//! throw or wait for events; instead, it follows sequence flows from start events and reports
+//! every path it could take along with its estimated duration. This is useful for validating
+//! models before deploying them against live systems.
+//!
+//! Diverging exclusive, inclusive, event-based and complex gateways lead to alternative paths,
+//! while every other flow node passes its tokens to all of its outgoing flows, forming
+//! concurrent branches of the same path. Converging parallel gateways wait for a token from
+//! each of their incoming flows, so they are passed when the slowest branch arrives.
+use crate::activity::receive_task::instantiates;
+use crate::bpmn::schema::{
+    Cast, ComplexGateway, DocumentElementContainer, EndEvent, EventBasedGateway, ExclusiveGateway,
+    FlowElement, FlowNodeType, InclusiveGateway, ParallelGateway, Process, SequenceFlow,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Default limit of visits of the same flow node within a single path
+pub const DEFAULT_MAX_VISITS: usize = 2;
+
+/// Simulated path
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Path {
+    /// Identifiers of flow nodes visited, in order (nodes of concurrent branches are
+    /// interleaved by the estimated time they were reached at)
+    pub nodes: Vec<String>,
+    /// Estimated time until the last of its branches has ended
+    pub estimated_duration: Duration,
+    /// `true` if every branch of the path has reached an end event
+    pub completed: bool,
+}
+
+/// Simulation report
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// All paths discovered
+    pub paths: Vec<Path>,
+}
+
+impl Report {
+    /// Returns the longest estimated duration among all paths
+    pub fn estimated_duration(&self) -> Duration {
+        self.paths
+            .iter()
+            .map(|path| path.estimated_duration)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Returns paths that never reach an end event
+    pub fn incomplete_paths(&self) -> impl Iterator<Item = &Path> {
+        self.paths.iter().filter(|path| !path.completed)
+    }
+}
+
+type Estimator<'a> = Box<dyn Fn(&dyn FlowNodeType) -> Duration + 'a>;
+type Decider<'a> = Box<dyn Fn(&SequenceFlow) -> bool + 'a>;
+
+/// Process simulation
+///
+/// By default, every node is estimated to take no time at all and every sequence flow
+/// is considered to be taken (so every possible path is explored).
+pub struct Simulation<'a> {
+    process: &'a Process,
+    estimator: Estimator<'a>,
+    decider: Decider<'a>,
+    max_visits: usize,
+}
+
+impl<'a> Simulation<'a> {
+    /// Creates a new simulation for a process
+    pub fn new(process: &'a Process) -> Self {
+        Self {
+            process,
+            estimator: Box::new(|_| Duration::default()),
+            decider: Box::new(|_| true),
+            max_visits: DEFAULT_MAX_VISITS,
+        }
+    }
+
+    /// Consumes simulation and returns it updated with a node duration estimator
+    pub fn with_estimator<F>(self, estimator: F) -> Self
+    where
+        F: Fn(&dyn FlowNodeType) -> Duration + 'a,
+    {
+        Self {
+            estimator: Box::new(estimator),
+            ..self
+        }
+    }
+
+    /// Consumes simulation and returns it updated with a stub sequence flow decision function
+    ///
+    /// It is used in lieu of evaluating condition expressions: sequence flows it returns
+    /// `false` for will not be followed.
+    pub fn with_decider<F>(self, decider: F) -> Self
+    where
+        F: Fn(&SequenceFlow) -> bool + 'a,
+    {
+        Self {
+            decider: Box::new(decider),
+            ..self
+        }
+    }
+
+    /// Consumes simulation and returns it updated with a new limit of visits of the same
+    /// node within a path (to keep loops finite)
+    pub fn with_max_visits(self, max_visits: usize) -> Self {
+        Self { max_visits, ..self }
+    }
+
+    /// Runs the simulation
+    pub fn run(&self) -> Report {
+        let mut report = Report::default();
+        for element in self.process.flow_elements.iter() {
+            let (id, incomings) = match element {
+                FlowElement::StartEvent(start_event) => (&start_event.id, &start_event.incomings),
+                // instantiating receive tasks are entry points, too
+                FlowElement::ReceiveTask(receive_task) if instantiates(receive_task) => {
+                    (&receive_task.id, &receive_task.incomings)
+                }
+                _ => continue,
+            };
+            if let (Some(id), true) = (id, incomings.is_empty()) {
+                let state = State {
+                    tokens: vec![Token {
+                        node: id.clone(),
+                        incoming: None,
+                        time: Duration::default(),
+                    }],
+                    ..Default::default()
+                };
+                self.walk(state, &mut report);
+            }
+        }
+        report
+    }
+
+    fn find_flow_node(&self, id: &str) -> Option<&dyn FlowNodeType> {
+        self.process
+            .find_by_id(id)
+            .and_then(|e| Cast::<dyn FlowNodeType>::cast(e))
+    }
+
+    fn walk(&self, mut state: State, report: &mut Report) {
+        // tokens are advanced in the order they have arrived in
+        while let Some(index) = state
+            .tokens
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, token)| token.time)
+            .map(|(index, _)| index)
+        {
+            let token = state.tokens.remove(index);
+            let node = match self.find_flow_node(&token.node) {
+                Some(node) => node,
+                None => {
+                    state.incomplete = true;
+                    continue;
+                }
+            };
+            let mut time = token.time;
+            if node.downcast_ref::<ParallelGateway>().is_some() && node.incomings().len() > 1 {
+                let arrivals = state.joins.entry(token.node.clone()).or_default();
+                arrivals.push((token.incoming, token.time));
+                let ready = node.incomings().iter().all(|incoming| {
+                    arrivals
+                        .iter()
+                        .any(|(seq_flow, _)| seq_flow.as_ref() == Some(incoming))
+                });
+                if !ready {
+                    continue;
+                }
+                // consume a token from every incoming flow, the join is passed when the
+                // last of them has arrived
+                for incoming in node.incomings() {
+                    if let Some(index) = arrivals
+                        .iter()
+                        .position(|(seq_flow, _)| seq_flow.as_ref() == Some(incoming))
+                    {
+                        time = time.max(arrivals.remove(index).1);
+                    }
+                }
+            }
+
+            let count = state.visits.entry(token.node.clone()).or_insert(0);
+            if *count >= self.max_visits {
+                state.incomplete = true;
+                continue;
+            }
+            *count += 1;
+
+            state.path.nodes.push(token.node.clone());
+            time += (self.estimator)(node);
+            state.path.estimated_duration = state.path.estimated_duration.max(time);
+
+            let outgoings: Vec<(Option<String>, String)> = node
+                .outgoings()
+                .iter()
+                .filter_map(|outgoing| {
+                    self.process
+                        .find_by_id(outgoing)
+                        .and_then(|e| e.downcast_ref::<SequenceFlow>())
+                })
+                .filter(|seq_flow| (self.decider)(*seq_flow))
+                .map(|seq_flow| (seq_flow.id.clone(), seq_flow.target_ref.clone()))
+                .collect();
+
+            if outgoings.is_empty() {
+                state.incomplete = state.incomplete || node.downcast_ref::<EndEvent>().is_none();
+            } else if outgoings.len() > 1 && is_choice(node) {
+                for (incoming, target) in outgoings {
+                    let mut state = state.clone();
+                    state.tokens.push(Token {
+                        node: target,
+                        incoming,
+                        time,
+                    });
+                    self.walk(state, report);
+                }
+                return;
+            } else {
+                for (incoming, target) in outgoings {
+                    state.tokens.push(Token {
+                        node: target,
+                        incoming,
+                        time,
+                    });
+                }
+            }
+        }
+        // tokens left waiting at joins never get anywhere
+        state.path.completed =
+            !state.incomplete && state.joins.values().all(|arrivals| arrivals.is_empty());
+        report.paths.push(state.path);
+    }
+}
+
+/// Returns true if the flow node passes its token to only one of its outgoing flows
+/// (as far as the simulation is concerned)
+fn is_choice(node: &dyn FlowNodeType) -> bool {
+    node.downcast_ref::<ExclusiveGateway>().is_some()
+        || node.downcast_ref::<InclusiveGateway>().is_some()
+        || node.downcast_ref::<EventBasedGateway>().is_some()
+        || node.downcast_ref::<ComplexGateway>().is_some()
+}
+
+/// Simulated token
+#[derive(Clone)]
+struct Token {
+    /// Flow node it has arrived at
+    node: String,
+    /// Sequence flow it has arrived through
+    incoming: Option<String>,
+    /// Estimated time of the arrival
+    time: Duration,
+}
+
+/// State of a path being simulated
+#[derive(Clone, Default)]
+struct State {
+    path: Path,
+    tokens: Vec<Token>,
+    /// Converging parallel gateway => tokens waiting at it (incoming flow, arrival time)
+    joins: HashMap<String, Vec<(Option<String>, Duration)>>,
+    /// Flow node => number of visits
+    visits: HashMap<String, usize>,
+    /// Set if any branch of the path has ended anywhere but an end event
+    incomplete: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpmn::parse;
+    use crate::bpmn::schema::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    fn process(definitions: &Definitions) -> &Process {
+        definitions
+            .find_by_id("proc1")
+            .and_then(|e| e.downcast_ref::<Process>())
+            .unwrap()
+    }
+
+    #[bpxe_im::test]
+    fn explores_every_path() {
+        let definitions =
+            parse(include_str!("gateway/test_models/exclusive_default.bpmn")).unwrap();
+        let report = Simulation::new(process(&definitions)).run();
+        assert_eq!(report.paths.len(), 3);
+        assert!(report.paths.iter().all(|path| path.completed));
+        assert!(report
+            .paths
+            .iter()
+            .any(|path| path.nodes == vec!["start", "excl", "f0", "end"]));
+    }
+
+    #[bpxe_im::test]
+    fn stub_decisions_and_estimates() {
+        let definitions =
+            parse(include_str!("gateway/test_models/exclusive_default.bpmn")).unwrap();
+        let report = Simulation::new(process(&definitions))
+            .with_decider(|seq_flow| seq_flow.target_ref != "f1" && seq_flow.target_ref != "f2")
+            .with_estimator(|node| {
+                if node.downcast_ref::<IntermediateThrowEvent>().is_some() {
+                    Duration::from_secs(10)
+                } else {
+                    Duration::from_secs(1)
+                }
+            })
+            .run();
+        assert_eq!(report.paths.len(), 1);
+        assert_eq!(report.estimated_duration(), Duration::from_secs(13));
+    }
+
+    #[bpxe_im::test]
+    fn concurrent_branches() {
+        let mut definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![
+                    StartEvent {
+                        id: Some("start".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                    ParallelGateway {
+                        id: Some("fork".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                    IntermediateThrowEvent {
+                        id: Some("short".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                    IntermediateThrowEvent {
+                        id: Some("long".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                    ParallelGateway {
+                        id: Some("join".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                    EndEvent {
+                        id: Some("end".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+
+        let proc1 = definitions
+            .find_by_id_mut("proc1")
+            .unwrap()
+            .downcast_mut::<Process>()
+            .unwrap();
+        for (source, target, id) in &[
+            ("start", "fork", "s1"),
+            ("fork", "short", "s2"),
+            ("fork", "long", "s3"),
+            ("short", "join", "s4"),
+            ("long", "join", "s5"),
+            ("join", "end", "s6"),
+        ] {
+            proc1
+                .establish_sequence_flow(*source, *target, *id, None::<FormalExpression>)
+                .unwrap();
+        }
+
+        let report = Simulation::new(process(&definitions))
+            .with_estimator(|node| match node.id().as_deref() {
+                Some("short") => Duration::from_secs(3),
+                Some("long") => Duration::from_secs(10),
+                _ => Duration::from_secs(1),
+            })
+            .run();
+        // both branches belong to the same path
+        assert_eq!(report.paths.len(), 1);
+        assert!(report.paths[0].completed);
+        assert_eq!(
+            report.paths[0].nodes,
+            vec!["start", "fork", "short", "long", "join", "end"]
+        );
+        // the join waits for the longest branch only
+        assert_eq!(report.estimated_duration(), Duration::from_secs(14));
+    }
+
+    #[bpxe_im::test]
+    fn unbalanced_joins_are_incomplete() {
+        let definitions =
+            parse(include_str!("gateway/test_models/parallel_unbalanced.bpmn")).unwrap();
+        let report = Simulation::new(process(&definitions)).run();
+        assert_eq!(report.paths.len(), 2);
+        assert_eq!(report.incomplete_paths().count(), 2);
+    }
+
+    #[bpxe_im::test]
+    fn dead_ends_are_incomplete() {
+        let mut definitions = Definitions {
+            root_elements: vec![Process {
+                id: Some("proc1".into()),
+                flow_elements: vec![
+                    StartEvent {
+                        id: Some("start".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                    IntermediateThrowEvent {
+                        id: Some("dead_end".into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                ],
+                ..Default::default()
+            }
+            .into()],
+            ..Default::default()
+        };
+
+        definitions
+            .find_by_id_mut("proc1")
+            .unwrap()
+            .downcast_mut::<Process>()
+            .unwrap()
+            .establish_sequence_flow("start", "dead_end", "s1", None::<FormalExpression>)
+            .unwrap();
+
+        let report = Simulation::new(process(&definitions)).run();
+        assert_eq!(report.incomplete_paths().count(), 1);
+        assert_eq!(report.paths[0].nodes, vec!["start", "dead_end"]);
+    }
+}
@@ -1,3 +1,4 @@
 //! # Helpers for system functionality
 
 pub(crate) mod task;
+pub(crate) mod time;
@@ -0,0 +1,174 @@
+//! # Time helpers
+//!
+use std::future::Future;
+#[cfg(target_arch = "wasm32")]
+use std::pin::Pin;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use std::sync::{Arc, Mutex};
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use std::task::Waker;
+#[cfg(target_arch = "wasm32")]
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+/// Returns current system time
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// Returns current system time
+///
+/// `SystemTime::now()` is not available on wasm32-unknown-unknown, so it's obtained
+/// from JavaScript instead.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn now() -> SystemTime {
+    std::time::UNIX_EPOCH + Duration::from_secs_f64(js_sys::Date::now() / 1000.0)
+}
+
+/// Waits until `duration` has elapsed
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Waits until `duration` has elapsed
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep(duration: Duration) {
+    Deadline::new(instant::Instant::now() + duration).await
+}
+
+/// Timeout has elapsed
+#[derive(Debug)]
+pub(crate) struct Elapsed;
+
+/// Awaits `future` for no longer than `duration`
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn timeout<F>(duration: Duration, future: F) -> Result<F::Output, Elapsed>
+where
+    F: Future,
+{
+    tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| Elapsed)
+}
+
+/// Awaits `future` for no longer than `duration`
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn timeout<F>(duration: Duration, future: F) -> Result<F::Output, Elapsed>
+where
+    F: Future,
+{
+    use futures::future::{select, Either};
+    let deadline = Deadline::new(instant::Instant::now() + duration);
+    futures::pin_mut!(future);
+    match select(future, deadline).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed),
+    }
+}
+
+/// Future that resolves once the deadline has passed
+///
+/// It's woken by a JavaScript timer (`setTimeout`) armed on its first pending poll.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+struct Deadline {
+    deadline: instant::Instant,
+    // waker to be woken by the armed timer (taken once it fires)
+    timer: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Deadline {
+    fn new(deadline: instant::Instant) -> Self {
+        Self {
+            deadline,
+            timer: None,
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Future for Deadline {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let now = instant::Instant::now();
+        if now >= self.deadline {
+            return Poll::Ready(());
+        }
+        if let Some(ref timer) = self.timer {
+            let mut waker = timer.lock().unwrap();
+            if waker.is_some() {
+                // still armed
+                waker.replace(cx.waker().clone());
+                return Poll::Pending;
+            }
+            // the timer has fired a bit early, it's armed again
+        }
+        let timer = Arc::new(Mutex::new(Some(cx.waker().clone())));
+        if set_timeout(timer.clone(), self.deadline - now) {
+            self.timer = Some(timer);
+        } else {
+            // there's no timer to wait for
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+/// Wakes the waker in `timer` once `duration` has elapsed, returning false if there's no
+/// `setTimeout` to do that
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn set_timeout(timer: Arc<Mutex<Option<Waker>>>, duration: Duration) -> bool {
+    use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+    let global = js_sys::global();
+    let set_timeout = match js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout"))
+        .ok()
+        .and_then(|set_timeout| set_timeout.dyn_into::<js_sys::Function>().ok())
+    {
+        Some(set_timeout) => set_timeout,
+        None => return false,
+    };
+    let callback = Closure::once_into_js(move || {
+        if let Some(waker) = timer.lock().unwrap().take() {
+            waker.wake();
+        }
+    });
+    // rounded up, so that the timer doesn't fire before the deadline
+    let millis = (duration.as_micros() + 999) / 1000;
+    set_timeout
+        .call2(&global, &callback, &JsValue::from_f64(millis as f64))
+        .is_ok()
+}
+
+/// Future that resolves once the deadline has passed
+///
+/// There are no timers to wake it on WASI, so the thread sleeps in short slices of the
+/// remaining time on every pending poll, rather than spinning.
+#[cfg(all(target_arch = "wasm32", not(target_os = "unknown")))]
+struct Deadline {
+    deadline: instant::Instant,
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_os = "unknown")))]
+impl Deadline {
+    fn new(deadline: instant::Instant) -> Self {
+        Self { deadline }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_os = "unknown")))]
+impl Future for Deadline {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let now = instant::Instant::now();
+        if now >= self.deadline {
+            return Poll::Ready(());
+        }
+        std::thread::sleep((self.deadline - now).min(Duration::from_millis(1)));
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -0,0 +1,439 @@
+//! # Tasks
+//!
+//! Task list of user tasks waiting for people to perform them (see
+//! [`crate::model::Handle::user_tasks`]).
+//!
+//! Every user task reached by a token opens a task on the list of its model. The token stays at
+//! the user task until the task is completed with [`UserTasks::complete`], optionally after
+//! being claimed by an assignee with [`UserTasks::claim`]. Tasks of process instances that are
+//! terminated before their tasks are completed are withdrawn from the list.
+//!
+//! User tasks can have forms (see [`crate::model::Model::with_form`]), which frontends render
+//! to collect variables the tasks are completed with. Variables submitted to tasks with a
+//! schema are validated against it before the tasks are completed.
+//!
+//! Open tasks can be searched, sorted and paginated with a [`TaskQuery`] (see
+//! [`crate::model::Handle::query_tasks`]).
+use crate::data_object::{self, DataObject};
+use crate::model::{self, VariablePredicate};
+use crate::process::{self, Variables};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+/// User task identifier
+///
+/// Unique within the running program
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TaskId(pub u64);
+
+impl TaskId {
+    fn next() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        Self(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl std::fmt::Display for TaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Open user task
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UserTask {
+    /// Task identifier
+    pub id: TaskId,
+    /// Process instance the task belongs to
+    pub instance: process::InstanceId,
+    /// Identifier of the process the user task belongs to
+    pub process: Option<String>,
+    /// Identifier of the user task
+    pub element: Option<String>,
+    /// Name of the user task
+    pub name: Option<String>,
+    /// Assignee who has claimed the task, if any
+    pub assignee: Option<String>,
+    /// Form of the user task, if any
+    pub form: Option<Form>,
+}
+
+/// Form of a user task
+///
+/// Only a subset of JSON Schema is enforced when tasks are completed: `required` variables,
+/// `type` of `properties` and `additionalProperties: false`. Variables are checked in their
+/// JSON form (see [`data_object::to_json`]).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Form {
+    /// Form key, referencing a form known to the frontend
+    pub key: Option<String>,
+    /// JSON Schema of variables the task is completed with
+    pub schema: Option<serde_json::Value>,
+}
+
+impl Form {
+    /// Creates a form referenced by a key
+    pub fn key<S: Into<String>>(key: S) -> Self {
+        Self {
+            key: Some(key.into()),
+            schema: None,
+        }
+    }
+
+    /// Creates a form described by a JSON Schema
+    pub fn schema(schema: serde_json::Value) -> Self {
+        Self {
+            key: None,
+            schema: Some(schema),
+        }
+    }
+
+    /// Validates variables against the schema of the form, returning the reason they are
+    /// rejected for
+    pub fn validate(&self, variables: &Variables) -> Result<(), String> {
+        let schema = match self.schema {
+            Some(ref schema) => schema,
+            None => return Ok(()),
+        };
+        if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+            for id in required.iter().filter_map(|id| id.as_str()) {
+                if !variables.contains_key(id) {
+                    return Err(format!("variable `{}` is required", id));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        let closed = schema.get("additionalProperties") == Some(&serde_json::Value::Bool(false));
+        for (id, value) in variables {
+            let property = match properties.and_then(|properties| properties.get(id)) {
+                Some(property) => property,
+                None if closed => return Err(format!("variable `{}` is not expected", id)),
+                None => continue,
+            };
+            let value = data_object::to_json(&**value)
+                .ok_or_else(|| format!("variable `{}` can't be represented as JSON", id))?;
+            let matches = match property.get("type") {
+                Some(serde_json::Value::String(kind)) => has_type(&value, kind),
+                Some(serde_json::Value::Array(kinds)) => kinds
+                    .iter()
+                    .filter_map(|kind| kind.as_str())
+                    .any(|kind| has_type(&value, kind)),
+                _ => true,
+            };
+            if !matches {
+                return Err(format!("variable `{}` doesn't match its type", id));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn has_type(value: &serde_json::Value, kind: &str) -> bool {
+    match kind {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+/// Task list error
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TaskError {
+    /// Task is not open (it has never been, or it has been completed or withdrawn)
+    #[error("task {0} not found")]
+    NotFound(TaskId),
+    /// Task has already been claimed by another assignee
+    #[error("task {task} has already been claimed by {assignee}")]
+    AlreadyClaimed { task: TaskId, assignee: String },
+    /// Submitted variables don't match the form of the task
+    #[error("invalid submission to task {task}: {reason}")]
+    InvalidSubmission { task: TaskId, reason: String },
+}
+
+/// Order of tasks found by a [`TaskQuery`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskOrder {
+    /// In the order tasks were opened
+    Opened,
+    /// By name of the user task
+    Name,
+    /// By assignee, unclaimed tasks first
+    Assignee,
+}
+
+impl Default for TaskOrder {
+    fn default() -> Self {
+        TaskOrder::Opened
+    }
+}
+
+/// User task query (see [`crate::model::Handle::query_tasks`])
+///
+/// Task matches the query if it satisfies all of its filters. Matching tasks are sorted
+/// (tasks that compare equal stay in the order they were opened) and then paginated.
+#[derive(Default, Clone)]
+pub struct TaskQuery {
+    assignee: Option<Option<String>>,
+    process: Option<String>,
+    element: Option<String>,
+    instance: Option<process::InstanceId>,
+    variables: Vec<(String, VariablePredicate)>,
+    order: TaskOrder,
+    descending: bool,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl TaskQuery {
+    /// Creates a query that matches all tasks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches tasks claimed by a given assignee
+    pub fn assignee<S>(self, assignee: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            assignee: Some(Some(assignee.into())),
+            ..self
+        }
+    }
+
+    /// Matches tasks that haven't been claimed
+    pub fn unassigned(self) -> Self {
+        Self {
+            assignee: Some(None),
+            ..self
+        }
+    }
+
+    /// Matches tasks of user tasks of a process with a given identifier
+    pub fn process<S>(self, process: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            process: Some(process.into()),
+            ..self
+        }
+    }
+
+    /// Matches tasks of a user task with a given identifier
+    pub fn element<S>(self, element: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            element: Some(element.into()),
+            ..self
+        }
+    }
+
+    /// Matches tasks of a given process instance
+    pub fn instance(self, instance: process::InstanceId) -> Self {
+        Self {
+            instance: Some(instance),
+            ..self
+        }
+    }
+
+    /// Matches tasks whose process instance has a variable (data object) satisfying
+    /// a predicate
+    pub fn variable<S, F>(mut self, id: S, predicate: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&dyn DataObject) -> bool + Send + Sync + 'static,
+    {
+        self.variables.push((id.into(), Arc::new(predicate)));
+        self
+    }
+
+    /// Sorts tasks in a given order (the order they were opened in, by default)
+    pub fn order_by(self, order: TaskOrder) -> Self {
+        Self { order, ..self }
+    }
+
+    /// Reverses the order of tasks
+    pub fn descending(self) -> Self {
+        Self {
+            descending: true,
+            ..self
+        }
+    }
+
+    /// Skips a number of sorted tasks
+    pub fn offset(self, offset: usize) -> Self {
+        Self { offset, ..self }
+    }
+
+    /// Returns no more than a number of sorted tasks
+    pub fn limit(self, limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..self
+        }
+    }
+
+    pub(crate) async fn matches(&self, model: &model::Handle, task: &UserTask) -> bool {
+        if matches!(self.assignee, Some(ref assignee) if assignee != &task.assignee) {
+            return false;
+        }
+        if self.process.is_some() && task.process != self.process {
+            return false;
+        }
+        if self.element.is_some() && task.element != self.element {
+            return false;
+        }
+        if matches!(self.instance, Some(instance) if instance != task.instance) {
+            return false;
+        }
+        if self.variables.is_empty() {
+            return true;
+        }
+        let instance = match model.instance(task.instance).await {
+            Ok(Some(instance)) => instance,
+            _ => return false,
+        };
+        for (id, predicate) in self.variables.iter() {
+            match instance.data_object(id).await {
+                Ok(container) => {
+                    if !predicate(&**container.read().await) {
+                        return false;
+                    }
+                }
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Sorts and paginates matching tasks, listed in the order they were opened
+    pub(crate) fn page(&self, mut tasks: Vec<UserTask>) -> Vec<UserTask> {
+        let order = self.order;
+        let descending = self.descending;
+        tasks.sort_by(|a, b| {
+            let ordering = match order {
+                TaskOrder::Opened => a.id.cmp(&b.id),
+                TaskOrder::Name => a.name.cmp(&b.name),
+                TaskOrder::Assignee => a.assignee.cmp(&b.assignee),
+            };
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        tasks
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+struct Entry {
+    task: UserTask,
+    completion: oneshot::Sender<Variables>,
+}
+
+/// Task list of a model
+#[derive(Clone, Default)]
+pub struct UserTasks {
+    entries: Arc<Mutex<HashMap<TaskId, Entry>>>,
+}
+
+impl UserTasks {
+    /// Returns open tasks, in the order they were opened
+    pub fn list(&self) -> Vec<UserTask> {
+        let mut tasks: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.task.clone())
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+        tasks
+    }
+
+    /// Claims an open task for an assignee
+    ///
+    /// Claiming a task already claimed by the same assignee succeeds.
+    pub fn claim<S: Into<String>>(&self, id: TaskId, assignee: S) -> Result<(), TaskError> {
+        let assignee = assignee.into();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(&id).ok_or(TaskError::NotFound(id))?;
+        match entry.task.assignee {
+            Some(ref claimed) if claimed != &assignee => Err(TaskError::AlreadyClaimed {
+                task: id,
+                assignee: claimed.clone(),
+            }),
+            _ => {
+                entry.task.assignee = Some(assignee);
+                Ok(())
+            }
+        }
+    }
+
+    /// Completes an open task, setting variables before the user task passes its token on
+    ///
+    /// Tasks don't have to be claimed to be completed. Tasks whose form has a schema are only
+    /// completed if variables match it, and stay open otherwise.
+    pub fn complete(&self, id: TaskId, variables: Variables) -> Result<(), TaskError> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&id).ok_or(TaskError::NotFound(id))?;
+        if let Some(ref form) = entry.task.form {
+            form.validate(&variables)
+                .map_err(|reason| TaskError::InvalidSubmission { task: id, reason })?;
+        }
+        let entry = entries.remove(&id).ok_or(TaskError::NotFound(id))?;
+        drop(entries);
+        entry
+            .completion
+            .send(variables)
+            .map_err(|_| TaskError::NotFound(id))
+    }
+
+    /// Opens a task, returning its identifier and a receiver of variables it is completed with
+    pub(crate) fn open(
+        &self,
+        instance: process::InstanceId,
+        process: Option<String>,
+        element: Option<String>,
+        name: Option<String>,
+        form: Option<Form>,
+    ) -> (TaskId, oneshot::Receiver<Variables>) {
+        let id = TaskId::next();
+        let (completion, receiver) = oneshot::channel();
+        let task = UserTask {
+            id,
+            instance,
+            process,
+            element,
+            name,
+            assignee: None,
+            form,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(id, Entry { task, completion });
+        (id, receiver)
+    }
+
+    /// Withdraws an open task
+    pub(crate) fn withdraw(&self, id: TaskId) {
+        self.entries.lock().unwrap().remove(&id);
+    }
+}
@@ -0,0 +1,239 @@
+//! # Process assertion helpers
+//!
+//! ```ignore
+//! let mut observer = Observer::new(&handle);
+//! handle.start().await?;
+//! observer.assert_path(&["start", "t1", "end"]).await;
+//! observer.wait_for_completion().await?;
+//! assert_variable_eq(&handle, "output", Container(1i64)).await;
+//! ```
+use crate::data_object::DataObject;
+use crate::process::{self, Log};
+use crate::test::{timeout, Timeout};
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+/// Observation error
+#[derive(Error, Debug, Clone)]
+pub enum ObservationError {
+    /// Expectation hasn't been met in time
+    #[error(transparent)]
+    Timeout(#[from] Timeout),
+    /// Process logs have ended before the expectation has been met
+    #[error("process logs have ended")]
+    Closed,
+}
+
+/// Process log observer
+///
+/// Observer should be created before the process is started, otherwise it may miss some of the
+/// logs.
+pub struct Observer {
+    receiver: broadcast::Receiver<Log>,
+    entered: Vec<String>,
+    completed: Vec<String>,
+    done: bool,
+}
+
+impl Observer {
+    /// Creates a new observer for a process
+    pub fn new(process: &process::Handle) -> Self {
+        Self {
+            receiver: process.log_receiver(),
+            entered: vec![],
+            completed: vec![],
+            done: false,
+        }
+    }
+
+    /// Returns identifiers of flow nodes entered through sequence flows so far, in order
+    pub fn entered(&self) -> &[String] {
+        &self.entered
+    }
+
+    /// Returns identifiers of flow nodes completed so far, in order
+    pub fn completed(&self) -> &[String] {
+        &self.completed
+    }
+
+    fn record(&mut self, log: Log) {
+        match log {
+            Log::FlowNodeIncoming { node, .. } => {
+                if let Some(id) = node.id() {
+                    self.entered.push(id.clone());
+                }
+            }
+            Log::FlowNodeCompleted { node } => {
+                if let Some(id) = node.id() {
+                    self.completed.push(id.clone());
+                }
+            }
+            Log::Done { .. } => self.done = true,
+            _ => {}
+        }
+    }
+
+    /// Receives logs until `predicate` is satisfied, or times out
+    async fn observe_until<F>(&mut self, predicate: F) -> Result<(), ObservationError>
+    where
+        F: Fn(&Self) -> bool,
+    {
+        timeout(async {
+            while !predicate(self) {
+                match self.receiver.recv().await {
+                    Ok(log) => self.record(log),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+        .await?;
+        if predicate(self) {
+            Ok(())
+        } else {
+            // log broadcast has been closed, it won't ever be satisfied
+            Err(ObservationError::Closed)
+        }
+    }
+
+    fn visited(&self, id: &str) -> bool {
+        self.entered
+            .iter()
+            .chain(self.completed.iter())
+            .any(|e| e == id)
+    }
+
+    /// Returns `true` if the path has been visited
+    ///
+    /// Start events aren't entered through sequence flows, so the first node of the path
+    /// is considered visited if it was either entered or completed. Every next node must have
+    /// been entered after the previous one, though not necessarily immediately after it (other
+    /// nodes, for example, on parallel branches, may be entered in between).
+    fn visited_path<S: AsRef<str>>(&self, path: &[S]) -> bool {
+        match path.split_first() {
+            None => true,
+            Some((first, rest)) => {
+                if !self.visited(first.as_ref()) {
+                    return false;
+                }
+                let mut entered = self.entered.iter();
+                rest.iter()
+                    .all(|id| entered.any(|entered| entered == id.as_ref()))
+            }
+        }
+    }
+
+    /// Waits until the flow node has been reached (entered or completed)
+    pub async fn node_reached(&mut self, id: &str) -> Result<(), ObservationError> {
+        self.observe_until(|observer| observer.visited(id)).await
+    }
+
+    /// Waits until the path has been visited
+    pub async fn path_visited<S: AsRef<str>>(
+        &mut self,
+        path: &[S],
+    ) -> Result<(), ObservationError> {
+        self.observe_until(|observer| observer.visited_path(path))
+            .await
+    }
+
+    /// Waits for the process to reach its terminal outcome
+    ///
+    /// Unlike [`process::Handle::join`], this also records all flow nodes visited on the way.
+    pub async fn wait_for_completion(&mut self) -> Result<(), ObservationError> {
+        self.observe_until(|observer| observer.done).await
+    }
+
+    /// Asserts that the flow node has been reached
+    pub async fn assert_node_reached(&mut self, id: &str) {
+        if let Err(err) = self.node_reached(id).await {
+            panic!(
+                "flow node {} has not been reached: {} (entered: {:?}, completed: {:?})",
+                id, err, self.entered, self.completed
+            );
+        }
+    }
+
+    /// Asserts that the path has been visited
+    ///
+    /// See [`Observer::path_visited`]
+    pub async fn assert_path<S: AsRef<str>>(&mut self, path: &[S]) {
+        if let Err(err) = self.path_visited(path).await {
+            panic!(
+                "path {:?} has not been visited: {} (entered: {:?}, completed: {:?})",
+                path.iter().map(|id| id.as_ref()).collect::<Vec<_>>(),
+                err,
+                self.entered,
+                self.completed
+            );
+        }
+    }
+}
+
+/// Asserts that process' data object is equal to `expected`
+pub async fn assert_variable_eq<T>(process: &process::Handle, id: &str, expected: T)
+where
+    T: DataObject + PartialEq,
+{
+    let container = match process.data_object(id).await {
+        Ok(container) => container,
+        Err(err) => panic!("can't retrieve data object {}: {}", id, err),
+    };
+    let data_object = container.read().await;
+    match data_object.downcast_ref::<T>() {
+        Some(value) => assert!(
+            value == &expected,
+            "data object {} is not equal to the expected value",
+            id
+        ),
+        None => panic!(
+            "data object {} is not of type {}",
+            id,
+            std::any::type_name::<T>()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpmn::parse;
+    use crate::model;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    async fn observe_path() {
+        let definitions = parse(include_str!(
+            "../gateway/test_models/exclusive_default.bpmn"
+        ))
+        .unwrap();
+        let model = model::Model::new(definitions).spawn().await;
+        let handle = model.processes().await.unwrap().pop().unwrap();
+        let mut observer = Observer::new(&handle);
+        assert!(handle.start().await.is_ok());
+        observer.assert_node_reached("excl").await;
+        observer.assert_path(&["start", "excl", "f0", "end"]).await;
+        assert!(observer.wait_for_completion().await.is_ok());
+        assert!(matches!(
+            observer.node_reached("nonexistent").await,
+            Err(ObservationError::Timeout(_))
+        ));
+        model.terminate().await;
+    }
+
+    #[bpxe_im::test]
+    async fn observe_closed() {
+        let (sender, receiver) = broadcast::channel(16);
+        let mut observer = Observer {
+            receiver,
+            entered: vec![],
+            completed: vec![],
+            done: false,
+        };
+        drop(sender);
+        assert!(matches!(
+            observer.wait_for_completion().await,
+            Err(ObservationError::Closed)
+        ));
+    }
+}
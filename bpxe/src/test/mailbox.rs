@@ -3,11 +3,14 @@
 use crate::test::*;
 use async_trait::async_trait;
 use bpxe_internal_macros as bpxe_im;
+use std::fmt::Debug;
+use std::time::Duration;
+use thiserror::Error;
 use tokio::sync::broadcast;
 
 /// Message receiver
 #[async_trait]
-pub(crate) trait Receiver<M> {
+pub trait Receiver<M> {
     /// Message receiver error
     type Error;
 
@@ -37,13 +40,30 @@ where
     }
 }
 
+/// Unmet mailbox expectation
+///
+/// Carries all messages received but not matched so far, for failure diagnostics.
+#[derive(Error, Debug)]
+#[error(
+    "expectation #{index} has not been met within {timeout:?}, unmatched messages: {unmatched:#?}"
+)]
+pub struct ExpectationError<M: Debug> {
+    /// Index of the expectation that hasn't been met
+    pub index: usize,
+    /// Time given to meet expectations
+    pub timeout: Duration,
+    /// Messages that didn't match any expectation
+    pub unmatched: Vec<M>,
+}
+
 /// Message mailbox
-pub(crate) struct Mailbox<M, R> {
+pub struct Mailbox<M, R> {
     messages: Vec<M>,
     receiver: R,
 }
 
 impl<M, R> Mailbox<M, R> {
+    /// Creates a new mailbox for a receiver
     pub fn new(receiver: R) -> Self {
         Self {
             messages: Default::default(),
@@ -95,6 +115,82 @@ where
         }
         return received;
     }
+
+    /// Returns messages received but not matched so far
+    pub fn unmatched(&self) -> &[M] {
+        &self.messages
+    }
+}
+
+impl<M, R> Mailbox<M, R>
+where
+    M: Clone + Debug,
+    R: Receiver<M>,
+{
+    /// Same as [`Mailbox::receive`], but gives up after `duration`
+    pub async fn receive_within<F>(
+        &mut self,
+        predicate: F,
+        duration: Duration,
+    ) -> Result<(), ExpectationError<M>>
+    where
+        F: Fn(&M) -> bool,
+    {
+        self.expect_in_order(&[&predicate], duration).await
+    }
+
+    /// Expects messages matching `predicates` to be received in the given order
+    /// within `duration`
+    ///
+    /// Previously received (but not matched) messages are considered, too.
+    pub async fn expect_in_order(
+        &mut self,
+        predicates: &[&dyn Fn(&M) -> bool],
+        duration: Duration,
+    ) -> Result<(), ExpectationError<M>> {
+        let mut index = 0;
+        let result = timeout_after(duration, async {
+            // position in `self.messages` matches can be looked for from
+            let mut cursor = 0;
+            while index < predicates.len() {
+                let predicate = predicates[index];
+                if let Some(position) = self.messages[cursor..]
+                    .iter()
+                    .position(|message| predicate(message))
+                {
+                    self.messages.remove(cursor + position);
+                    cursor += position;
+                    index += 1;
+                    continue;
+                }
+                tokio::task::yield_now().await;
+                match self.receiver.recv().await {
+                    Ok(message) => {
+                        if predicate(&message) {
+                            cursor = self.messages.len();
+                            index += 1;
+                        } else {
+                            self.messages.push(message);
+                        }
+                    }
+                    Err(e) => {
+                        if R::is_closed(e) {
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+        .await;
+        match result {
+            Ok(_) if index == predicates.len() => Ok(()),
+            _ => Err(ExpectationError {
+                index,
+                timeout: duration,
+                unmatched: self.messages.clone(),
+            }),
+        }
+    }
 }
 
 #[bpxe_im::test]
@@ -117,3 +213,48 @@ async fn refute_receive() {
         .await
         .is_ok());
 }
+
+#[bpxe_im::test]
+async fn receive_within() {
+    let (sender, receiver) = broadcast::channel(16);
+    let mut mailbox = Mailbox::new(receiver);
+    sender.send(1u8).unwrap();
+    sender.send(2u8).unwrap();
+    assert!(mailbox
+        .receive_within(|m| *m == 2u8, Duration::from_millis(100))
+        .await
+        .is_ok());
+    let err = mailbox
+        .receive_within(|m| *m == 3u8, Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    assert_eq!(err.index, 0);
+    assert_eq!(err.unmatched, vec![1u8]);
+}
+
+#[bpxe_im::test]
+async fn expect_in_order() {
+    let (sender, receiver) = broadcast::channel(16);
+    let mut mailbox = Mailbox::new(receiver);
+    sender.send(1u8).unwrap();
+    sender.send(2u8).unwrap();
+    sender.send(3u8).unwrap();
+    assert!(mailbox
+        .expect_in_order(
+            &[&|m: &u8| *m == 1u8, &|m: &u8| *m == 3u8],
+            Duration::from_millis(100),
+        )
+        .await
+        .is_ok());
+    sender.send(4u8).unwrap();
+    sender.send(5u8).unwrap();
+    let err = mailbox
+        .expect_in_order(
+            &[&|m: &u8| *m == 5u8, &|m: &u8| *m == 4u8],
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.index, 1);
+    assert_eq!(err.unmatched, vec![2u8, 4u8]);
+}
@@ -1,7 +1,10 @@
 //! # Testing Helpers
 //!
+//! Available to downstream crates with `testing` feature enabled.
+mod assert;
 mod mailbox;
-pub(crate) use mailbox::Mailbox;
+pub use assert::{assert_variable_eq, ObservationError, Observer};
+pub use mailbox::{ExpectationError, Mailbox, Receiver};
 
 use crate::sys::task;
 #[cfg(not(target_arch = "wasm32"))]
@@ -57,6 +60,17 @@ where
         .map_err(|_| Timeout::Elapsed(timeout))
 }
 
+/// Times out after `duration`
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn timeout_after<F, T>(duration: Duration, f: F) -> Result<T, Timeout>
+where
+    F: Future<Output = T>,
+{
+    tokio::time::timeout(duration, f)
+        .await
+        .map_err(|_| Timeout::Elapsed(duration))
+}
+
 /// Returns `Ok(())` if the future times out in 100ms (or whatever `TIMEOUT` env var is set to)
 #[cfg(not(target_arch = "wasm32"))]
 pub async fn expects_timeout<F, T>(f: F) -> Result<(), Timeout>
@@ -141,6 +155,14 @@ mod wasm {
         Timeout::new(duration, future).await
     }
 
+    /// Times out after `duration`
+    pub async fn timeout_after<F, T>(duration: Duration, future: F) -> Result<T, Error>
+    where
+        F: Future<Output = T>,
+    {
+        Timeout::new(duration, future).await
+    }
+
     /// Returns `Ok(())` if the future times out in 500ms
     pub async fn expects_timeout<F, T>(future: F) -> Result<(), Error>
     where
@@ -155,4 +177,4 @@ mod wasm {
     }
 }
 #[cfg(target_arch = "wasm32")]
-pub(crate) use wasm::*;
+pub use wasm::*;
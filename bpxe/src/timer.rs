@@ -0,0 +1,948 @@
+//! # Timers
+//!
+//! Timer event definitions are expressed in [ISO 8601](https://en.wikipedia.org/wiki/ISO_8601):
+//! `timeDate` is a date and time (`2021-02-21T10:00:00Z`), `timeDuration` is a duration
+//! (`PT10M`) and `timeCycle` is a repeating interval (`R5/PT10M`, `R/2021-02-21T00:00:00Z/P1D`).
+//!
+//! `timeCycle` can also be a [cron expression](Cron) (`0 9 * * MON-FRI`).
+//!
+//! Expressions can refer to process data objects with `${name}` placeholders
+//! (`PT${slaHours}H`, `${slaDuration}`), which are resolved when the timer is armed.
+//! Data objects holding strings and numbers can be used this way.
+//!
+//! Duration timers can be measured in business time (see [`BusinessCalendar`]).
+//!
+//! Dates and times without a timezone designator, as well as calendar arithmetic in cycles,
+//! use the model's [default timezone](crate::model::Model::with_default_timezone) (UTC, unless
+//! specified otherwise).
+//!
+//! Relative timers (durations) are measured with a monotonic clock, while absolute ones
+//! sleep until their deadline and check it against the wall clock when they wake up, so that
+//! a system clock set back doesn't fire them early. Absolute timers also re-check their
+//! deadline whenever the clock is reported to have been adjusted (see
+//! [`crate::model::Handle::clock_adjusted`]), so that a clock set forward doesn't stall them.
+use crate::bpmn::schema::{Expr, FormalExpression};
+use crate::data_object::{Container, DataObject};
+use crate::event::ProcessEvent;
+use crate::process;
+use crate::sys::time;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::sync::watch;
+
+mod calendar;
+pub use calendar::{BusinessCalendar, Calendar};
+mod cron;
+pub use cron::Cron;
+mod registry;
+pub(crate) use registry::Timers;
+pub use registry::{PendingTimer, TimerId};
+
+const SECONDS_IN_DAY: i64 = 86400;
+
+// Largest number of seconds a duration may have (a bit over 31,000 years), so that adding it
+// to a point in time can't overflow
+const MAX_SECONDS: f64 = 1e12;
+
+/// Timer error
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TimerError {
+    /// Invalid date and time
+    #[error("invalid date and time {0}")]
+    InvalidDateTime(String),
+    /// Invalid duration
+    #[error("invalid duration {0}")]
+    InvalidDuration(String),
+    /// Invalid repeating interval
+    #[error("invalid repeating interval {0}")]
+    InvalidCycle(String),
+    /// Invalid cron expression
+    #[error("invalid cron expression {0}")]
+    InvalidCron(String),
+    /// Invalid timezone
+    #[error("invalid timezone {0}")]
+    InvalidTimezone(String),
+    /// Placeholder can't be resolved
+    #[error("can't resolve placeholder {0} in a timer expression")]
+    UnresolvedPlaceholder(String),
+    /// There is no pending timer with this identifier
+    #[error("pending timer {0} not found")]
+    NotFound(TimerId),
+    /// Timer expression is not a literal
+    #[error("timer expression is not a formal expression with a literal value")]
+    NotLiteral,
+    /// Neither time date, duration nor cycle is specified
+    #[error("timer doesn't specify time date, duration or cycle")]
+    Unspecified,
+}
+
+/// Timezone with a fixed offset from UTC
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timezone {
+    offset: i32,
+}
+
+impl Timezone {
+    /// Coordinated Universal Time
+    pub const UTC: Timezone = Timezone { offset: 0 };
+
+    /// Creates a timezone from an offset in seconds east of UTC
+    ///
+    /// Returns `None` if the offset is a day or more.
+    pub fn from_offset(offset: i32) -> Option<Self> {
+        if (offset as i64).abs() < SECONDS_IN_DAY {
+            Some(Self { offset })
+        } else {
+            None
+        }
+    }
+
+    /// Returns timezone's offset in seconds east of UTC
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    // Converts time to the "UTC" time with the same local date and time of day
+    fn to_local(self, time: SystemTime) -> SystemTime {
+        shift(time, self.offset as i64)
+    }
+
+    // Reverse of `to_local`
+    fn to_utc(self, time: SystemTime) -> SystemTime {
+        shift(time, -(self.offset as i64))
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = TimerError;
+
+    /// Parses `Z`, `UTC` or an offset (`+02:00`, `-0530`, `+02`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Z" | "UTC" => Ok(Timezone::UTC),
+            _ => parse_offset(s)
+                .and_then(Timezone::from_offset)
+                .ok_or_else(|| TimerError::InvalidTimezone(s.to_string())),
+        }
+    }
+}
+
+/// ISO 8601 duration
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IsoDuration {
+    /// Years
+    pub years: u32,
+    /// Months
+    pub months: u32,
+    /// Weeks
+    pub weeks: u32,
+    /// Days
+    pub days: u32,
+    /// Hours
+    pub hours: u32,
+    /// Minutes
+    pub minutes: u32,
+    /// Seconds (may be fractional)
+    pub seconds: f64,
+}
+
+impl IsoDuration {
+    /// Adds the duration to a point in time
+    ///
+    /// Calendar components (years and months) are applied in UTC, clamping the day of month
+    /// if necessary (so `P1M` after January 31st is February 28th or 29th).
+    pub fn add_to(&self, time: SystemTime) -> SystemTime {
+        let (mut secs, nanos) = to_unix(time);
+        if self.years > 0 || self.months > 0 {
+            let days = secs.div_euclid(SECONDS_IN_DAY);
+            let secs_of_day = secs.rem_euclid(SECONDS_IN_DAY);
+            let (year, month, day) = civil_from_days(days);
+            let months = month as i64 - 1 + self.months as i64 + 12 * self.years as i64;
+            let year = year + months.div_euclid(12);
+            let month = (months.rem_euclid(12) + 1) as u32;
+            let day = day.min(days_in_month(year, month));
+            secs = days_from_civil(year, month, day) * SECONDS_IN_DAY + secs_of_day;
+        }
+        secs += (self.weeks as i64 * 7 + self.days as i64) * SECONDS_IN_DAY
+            + self.hours as i64 * 3600
+            + self.minutes as i64 * 60;
+        from_unix(secs, nanos) + seconds(self.seconds)
+    }
+
+    /// Adds the duration to a point in time, applying calendar components in a given timezone
+    pub fn add_to_in(&self, time: SystemTime, timezone: Timezone) -> SystemTime {
+        timezone.to_utc(self.add_to(timezone.to_local(time)))
+    }
+}
+
+impl FromStr for IsoDuration {
+    type Err = TimerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TimerError::InvalidDuration(s.to_string());
+        let rest = s.strip_prefix('P').ok_or_else(invalid)?;
+        let mut duration = IsoDuration::default();
+        let mut time = false;
+        let mut number = String::new();
+        let mut components = 0;
+        for c in rest.chars() {
+            match c {
+                'T' if !time && number.is_empty() => time = true,
+                '0'..='9' | '.' => number.push(c),
+                ',' => number.push('.'),
+                designator => {
+                    let value: f64 = number.parse().map_err(|_| invalid())?;
+                    number.clear();
+                    components += 1;
+                    let whole = if value.fract() == 0.0 && value <= u32::MAX as f64 {
+                        Ok(value as u32)
+                    } else {
+                        Err(invalid())
+                    };
+                    match (time, designator) {
+                        (false, 'Y') => duration.years = whole?,
+                        (false, 'M') => duration.months = whole?,
+                        (false, 'W') => duration.weeks = whole?,
+                        (false, 'D') => duration.days = whole?,
+                        (true, 'H') => duration.hours = whole?,
+                        (true, 'M') => duration.minutes = whole?,
+                        (true, 'S') if value <= MAX_SECONDS => duration.seconds = value,
+                        _ => return Err(invalid()),
+                    }
+                }
+            }
+        }
+        if !number.is_empty() || components == 0 {
+            return Err(invalid());
+        }
+        Ok(duration)
+    }
+}
+
+/// ISO 8601 repeating interval
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cycle {
+    /// Number of repetitions (`None` if unbounded)
+    pub repetitions: Option<u32>,
+    /// Time of the first repetition (if not specified, the first repetition happens
+    /// one interval after the timer has been armed)
+    pub start: Option<SystemTime>,
+    /// Interval between repetitions
+    pub interval: IsoDuration,
+    /// Time after which there are no more repetitions
+    pub end: Option<SystemTime>,
+    /// Timezone used for calendar arithmetic
+    pub timezone: Timezone,
+}
+
+impl Cycle {
+    /// Parses a repeating interval, using `timezone` for dates and times without a timezone
+    /// designator and for calendar arithmetic
+    pub fn parse_in(s: &str, timezone: Timezone) -> Result<Self, TimerError> {
+        let invalid = || TimerError::InvalidCycle(s.to_string());
+        let mut parts = s.split('/');
+        let repetitions = parts
+            .next()
+            .and_then(|repetitions| repetitions.strip_prefix('R'))
+            .ok_or_else(invalid)?;
+        let repetitions = if repetitions.is_empty() {
+            None
+        } else {
+            Some(repetitions.parse().map_err(|_| invalid())?)
+        };
+        let date_time = |s: &str| parse_date_time_in(s, timezone);
+        let parts: Vec<&str> = parts.collect();
+        let (start, interval, end) = match parts.as_slice() {
+            [interval] => (None, interval.parse()?, None),
+            [start, interval] if interval.starts_with('P') => {
+                (Some(date_time(start)?), interval.parse()?, None)
+            }
+            [interval, end] if interval.starts_with('P') => {
+                (None, interval.parse()?, Some(date_time(end)?))
+            }
+            [start, end] => {
+                let start = date_time(start)?;
+                let end = date_time(end)?;
+                let interval = IsoDuration {
+                    seconds: end
+                        .duration_since(start)
+                        .map_err(|_| invalid())?
+                        .as_secs_f64(),
+                    ..Default::default()
+                };
+                (Some(start), interval, None)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Cycle {
+            repetitions,
+            start,
+            interval,
+            end,
+            timezone,
+        })
+    }
+
+    // Returns the repetition following the one at `time`
+    fn step(&self, time: SystemTime) -> SystemTime {
+        self.interval.add_to_in(time, self.timezone)
+    }
+
+    /// Returns `n`-th (zero-based) repetition of the cycle armed at `armed_at`
+    pub fn occurrence(&self, armed_at: SystemTime, n: u32) -> Option<SystemTime> {
+        if matches!(self.repetitions, Some(repetitions) if n >= repetitions) {
+            return None;
+        }
+        let (mut time, steps) = match self.start {
+            Some(start) => (start, n),
+            None => (armed_at, n + 1),
+        };
+        for _ in 0..steps {
+            time = self.step(time);
+        }
+        match self.end {
+            Some(end) if time > end => None,
+            _ => Some(time),
+        }
+    }
+}
+
+impl FromStr for Cycle {
+    type Err = TimerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Cycle::parse_in(s, Timezone::UTC)
+    }
+}
+
+/// Point at which a timer is due
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Due {
+    /// After a period of time (measured with a monotonic clock)
+    After(Duration),
+    /// At a point in wall-clock time
+    At(SystemTime),
+}
+
+/// Waits until the timer is due
+///
+/// Absolute timers re-check the wall clock when they wake up and whenever `clock` reports
+/// that the clock has been adjusted.
+pub(crate) async fn wait(due: Due, mut clock: watch::Receiver<()>) {
+    match due {
+        Due::After(duration) => time::sleep(duration).await,
+        Due::At(at) => {
+            let mut adjustable = true;
+            loop {
+                match at.duration_since(time::now()) {
+                    Ok(remaining) if remaining > Duration::from_secs(0) => tokio::select! {
+                        _ = time::sleep(remaining) => {}
+                        changed = clock.changed(), if adjustable => adjustable = changed.is_ok(),
+                    },
+                    _ => break,
+                }
+            }
+        }
+    }
+}
+
+/// Timer schedule
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+    /// Fires once at a specific time
+    Date(SystemTime),
+    /// Fires once after a duration
+    Duration(IsoDuration),
+    /// Fires repeatedly
+    Cycle(Cycle),
+    /// Fires repeatedly at times matching a cron expression
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// Creates a schedule from a [`ProcessEvent::TimerEvent`]
+    ///
+    /// If more than one of time date, duration and cycle is specified, they take precedence
+    /// in that order.
+    pub fn from_event(event: &ProcessEvent) -> Result<Self, TimerError> {
+        Schedule::from_event_in(event, Timezone::UTC)
+    }
+
+    /// Creates a schedule from a [`ProcessEvent::TimerEvent`], using `timezone` for dates and
+    /// times without a timezone designator and for calendar arithmetic in cycles
+    pub fn from_event_in(event: &ProcessEvent, timezone: Timezone) -> Result<Self, TimerError> {
+        match event {
+            ProcessEvent::TimerEvent {
+                time_date: Some(expr),
+                ..
+            } => Ok(Schedule::Date(parse_date_time_in(
+                &literal(expr)?,
+                timezone,
+            )?)),
+            ProcessEvent::TimerEvent {
+                time_duration: Some(expr),
+                ..
+            } => Ok(Schedule::Duration(literal(expr)?.parse()?)),
+            ProcessEvent::TimerEvent {
+                time_cycle: Some(expr),
+                ..
+            } => {
+                let cycle = literal(expr)?;
+                if cycle.starts_with('R') {
+                    Ok(Schedule::Cycle(Cycle::parse_in(&cycle, timezone)?))
+                } else {
+                    Ok(Schedule::Cron(
+                        cycle.parse::<Cron>()?.with_timezone(timezone),
+                    ))
+                }
+            }
+            _ => Err(TimerError::Unspecified),
+        }
+    }
+
+    /// Returns `n`-th (zero-based) occurrence of the schedule armed at `armed_at`
+    pub fn occurrence(&self, armed_at: SystemTime, n: u32) -> Option<SystemTime> {
+        match self {
+            Schedule::Date(date) if n == 0 => Some(*date),
+            Schedule::Duration(duration) if n == 0 => Some(duration.add_to(armed_at)),
+            Schedule::Cycle(cycle) => cycle.occurrence(armed_at, n),
+            Schedule::Cron(cron) => {
+                let mut time = armed_at;
+                for _ in 0..=n {
+                    time = cron.next_after(time)?;
+                }
+                Some(time)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the first occurrence (along with its index) of the schedule armed at `armed_at`
+    /// that is not earlier than `now`
+    ///
+    /// Dates and durations always occur once, even if they are in the past already.
+    pub fn next(&self, armed_at: SystemTime, now: SystemTime) -> Option<(u32, SystemTime)> {
+        match self {
+            Schedule::Cycle(cycle) => {
+                let mut n = 0;
+                let mut time = cycle.occurrence(armed_at, 0)?;
+                while time < now {
+                    n += 1;
+                    if matches!(cycle.repetitions, Some(repetitions) if n >= repetitions) {
+                        return None;
+                    }
+                    let next = cycle.step(time);
+                    // empty interval would never catch up
+                    if next <= time {
+                        return None;
+                    }
+                    time = next;
+                }
+                match cycle.end {
+                    Some(end) if time > end => None,
+                    _ => Some((n, time)),
+                }
+            }
+            Schedule::Cron(cron) => {
+                let mut n = 0;
+                let mut time = cron.next_after(armed_at)?;
+                while time < now {
+                    n += 1;
+                    time = cron.next_after(time)?;
+                }
+                Some((n, time))
+            }
+            _ => self.occurrence(armed_at, 0).map(|time| (0, time)),
+        }
+    }
+
+    /// Returns when the schedule armed at `armed_at` is due next
+    ///
+    /// Durations are relative, everything else is due at a point in wall-clock time.
+    pub fn due(&self, armed_at: SystemTime) -> Option<Due> {
+        match self {
+            Schedule::Duration(duration) => Some(Due::After(
+                duration
+                    .add_to(armed_at)
+                    .duration_since(armed_at)
+                    .unwrap_or_default(),
+            )),
+            _ => self.next(armed_at, armed_at).map(|(_, time)| Due::At(time)),
+        }
+    }
+}
+
+/// Resolves `${name}` placeholders in timer event's expressions with values of process
+/// data objects
+pub(crate) async fn resolve(
+    event: &ProcessEvent,
+    process: &process::Handle,
+) -> Result<ProcessEvent, TimerError> {
+    match event {
+        ProcessEvent::TimerEvent {
+            time_date,
+            time_duration,
+            time_cycle,
+        } => Ok(ProcessEvent::TimerEvent {
+            time_date: resolve_expr(time_date, process).await?,
+            time_duration: resolve_expr(time_duration, process).await?,
+            time_cycle: resolve_expr(time_cycle, process).await?,
+        }),
+        _ => Ok(event.clone()),
+    }
+}
+
+async fn resolve_expr(
+    expr: &Option<Expr>,
+    process: &process::Handle,
+) -> Result<Option<Expr>, TimerError> {
+    let mut expr = expr.clone();
+    if let Some(Expr::FormalExpression(FormalExpression {
+        content: Some(content),
+        ..
+    })) = expr.as_mut()
+    {
+        let mut values = HashMap::new();
+        for name in placeholders(content) {
+            let value = match process.data_object(name).await {
+                Ok(data_object) => placeholder_value(&**data_object.read().await),
+                Err(_) => None,
+            };
+            values.insert(
+                name.to_string(),
+                value.ok_or_else(|| TimerError::UnresolvedPlaceholder(name.to_string()))?,
+            );
+        }
+        *content = substitute(content, |name| values.get(name).cloned())?;
+    }
+    Ok(expr)
+}
+
+fn placeholder_value(data_object: &dyn DataObject) -> Option<String> {
+    if let Some(value) = data_object.downcast_ref::<serde_json::Value>() {
+        match value {
+            serde_json::Value::String(s) => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    } else {
+        data_object
+            .downcast_ref::<Container<String>>()
+            .map(|container| container.0.clone())
+    }
+}
+
+// Returns names of `${name}` placeholders
+fn placeholders(s: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        rest = &rest[start + 2..];
+        match rest.find('}') {
+            Some(end) => {
+                names.push(rest[..end].trim());
+                rest = &rest[end + 1..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+// Replaces `${name}` placeholders with their values
+fn substitute<F>(s: &str, value: F) -> Result<String, TimerError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let placeholder = &rest[start + 2..];
+        let end = placeholder
+            .find('}')
+            .ok_or_else(|| TimerError::UnresolvedPlaceholder(rest[start..].to_string()))?;
+        let name = placeholder[..end].trim();
+        result.push_str(
+            &value(name).ok_or_else(|| TimerError::UnresolvedPlaceholder(name.to_string()))?,
+        );
+        rest = &placeholder[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn literal(expr: &Expr) -> Result<String, TimerError> {
+    match expr {
+        Expr::FormalExpression(FormalExpression {
+            content: Some(content),
+            ..
+        }) => Ok(content.trim().to_string()),
+        _ => Err(TimerError::NotLiteral),
+    }
+}
+
+/// Parses ISO 8601 date and time (`2021-02-21T10:00:00Z`, `2021-02-21T10:00:00+02:00`)
+///
+/// Date and time without a timezone designator is considered to be in UTC.
+pub fn parse_date_time(s: &str) -> Result<SystemTime, TimerError> {
+    parse_date_time_in(s, Timezone::UTC)
+}
+
+/// Parses ISO 8601 date and time, considering date and time without a timezone designator
+/// to be in `timezone`
+pub fn parse_date_time_in(s: &str, timezone: Timezone) -> Result<SystemTime, TimerError> {
+    let invalid = || TimerError::InvalidDateTime(s.to_string());
+    let (date, time) = match s.find('T') {
+        Some(index) => (&s[..index], &s[index + 1..]),
+        None => (s, ""),
+    };
+
+    let date: Vec<&str> = date.split('-').collect();
+    let (year, month, day) = match date.as_slice() {
+        [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => (
+            number::<i64>(year).ok_or_else(invalid)?,
+            number::<u32>(month).ok_or_else(invalid)?,
+            number::<u32>(day).ok_or_else(invalid)?,
+        ),
+        _ => return Err(invalid()),
+    };
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+
+    let (clock, offset) = if let Some(clock) = time.strip_suffix('Z') {
+        (clock, 0)
+    } else if let Some(index) = time.rfind(|c| c == '+' || c == '-') {
+        (
+            &time[..index],
+            parse_offset(&time[index..]).ok_or_else(invalid)?,
+        )
+    } else {
+        (time, timezone.offset)
+    };
+
+    let mut seconds = 0.0;
+    if !clock.is_empty() {
+        let clock: Vec<&str> = clock.split(':').collect();
+        let (hours, minutes, secs) = match clock.as_slice() {
+            [hours, minutes] => (*hours, *minutes, "0"),
+            [hours, minutes, secs] => (*hours, *minutes, *secs),
+            _ => return Err(invalid()),
+        };
+        let hours = number::<u32>(hours)
+            .filter(|h| *h < 24)
+            .ok_or_else(invalid)?;
+        let minutes = number::<u32>(minutes)
+            .filter(|m| *m < 60)
+            .ok_or_else(invalid)?;
+        let secs = secs
+            .parse::<f64>()
+            .ok()
+            .filter(|s| (0.0..61.0).contains(s))
+            .ok_or_else(invalid)?;
+        seconds = (hours * 3600 + minutes * 60) as f64 + secs;
+    }
+
+    let secs = days_from_civil(year, month, day) * SECONDS_IN_DAY - offset as i64;
+    Ok(from_unix(secs, 0) + self::seconds(seconds))
+}
+
+/// Parses timezone offset (`+02:00`, `-0530`, `+02`) into seconds east of UTC
+fn parse_offset(s: &str) -> Option<i32> {
+    let (sign, rest) = match s.chars().next()? {
+        '+' => (1, &s[1..]),
+        '-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let rest = rest.replace(':', "");
+    let (hours, minutes) = match rest.len() {
+        2 => (number::<i32>(&rest)?, 0),
+        4 => (number::<i32>(&rest[..2])?, number::<i32>(&rest[2..])?),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn number<T: FromStr>(s: &str) -> Option<T> {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn to_unix(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        Err(err) => {
+            let duration = err.duration();
+            match duration.subsec_nanos() {
+                0 => (-(duration.as_secs() as i64), 0),
+                nanos => (-(duration.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+fn shift(time: SystemTime, secs: i64) -> SystemTime {
+    let (time_secs, nanos) = to_unix(time);
+    from_unix(time_secs + secs, nanos)
+}
+
+// Converts seconds of a duration into `Duration`
+//
+// Parsed durations never exceed `MAX_SECONDS`, but fields of `IsoDuration` are public, so
+// anything that isn't a finite non-negative number is treated as no seconds at all.
+fn seconds(seconds: f64) -> Duration {
+    if seconds.is_finite() && seconds > 0.0 {
+        Duration::from_secs_f64(seconds.min(MAX_SECONDS))
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+fn from_unix(secs: i64, nanos: u32) -> SystemTime {
+    let time = if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    };
+    time + Duration::from_nanos(nanos as u64)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since Unix epoch of a proleptic Gregorian calendar date
+// (see http://howardhinnant.github.io/date_algorithms.html)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Proleptic Gregorian calendar date of a number of days since Unix epoch
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    fn date_time(s: &str) -> SystemTime {
+        parse_date_time(s).unwrap()
+    }
+
+    #[bpxe_im::test]
+    fn durations() {
+        assert_eq!(
+            "P1Y2M3W4DT5H6M7.5S".parse::<IsoDuration>(),
+            Ok(IsoDuration {
+                years: 1,
+                months: 2,
+                weeks: 3,
+                days: 4,
+                hours: 5,
+                minutes: 6,
+                seconds: 7.5,
+            })
+        );
+        assert!("P".parse::<IsoDuration>().is_err());
+        assert!("PT".parse::<IsoDuration>().is_err());
+        assert!("P1H".parse::<IsoDuration>().is_err());
+        assert!("10M".parse::<IsoDuration>().is_err());
+        // out of range components are rejected instead of overflowing later
+        assert_eq!(
+            "PT99999999999999999999S".parse::<IsoDuration>(),
+            Err(TimerError::InvalidDuration(
+                "PT99999999999999999999S".into()
+            ))
+        );
+        assert!("P99999999999D".parse::<IsoDuration>().is_err());
+        let time = date_time("2021-01-31T10:00:00Z");
+        assert_eq!(
+            IsoDuration {
+                seconds: f64::NAN,
+                ..Default::default()
+            }
+            .add_to(time),
+            time
+        );
+        assert_eq!(
+            "P1M"
+                .parse::<IsoDuration>()
+                .unwrap()
+                .add_to(date_time("2021-01-31T10:00:00Z")),
+            date_time("2021-02-28T10:00:00Z")
+        );
+        assert_eq!(
+            "P1DT1H"
+                .parse::<IsoDuration>()
+                .unwrap()
+                .add_to(date_time("2020-02-28T23:30:00Z")),
+            date_time("2020-03-01T00:30:00Z")
+        );
+    }
+
+    #[bpxe_im::test]
+    fn date_times() {
+        assert_eq!(
+            date_time("1970-01-02T00:00:00Z"),
+            UNIX_EPOCH + Duration::from_secs(86400)
+        );
+        assert_eq!(date_time("1970-01-02"), date_time("1970-01-02T00:00Z"));
+        assert_eq!(
+            date_time("2021-02-21T12:00:00+02:00"),
+            date_time("2021-02-21T10:00:00Z")
+        );
+        assert_eq!(
+            date_time("2021-02-21T04:30:00-0530"),
+            date_time("2021-02-21T10:00:00Z")
+        );
+        let timezone = "+02:00".parse().unwrap();
+        assert_eq!(
+            parse_date_time_in("2021-02-21T12:00:00", timezone),
+            Ok(date_time("2021-02-21T10:00:00Z"))
+        );
+        assert_eq!(
+            parse_date_time_in("2021-02-21T12:00:00-01:00", timezone),
+            Ok(date_time("2021-02-21T13:00:00Z"))
+        );
+        assert_eq!("UTC".parse(), Ok(Timezone::UTC));
+        assert!("+25:00".parse::<Timezone>().is_err());
+        assert!(parse_date_time("2021-02-29T00:00:00Z").is_err());
+        assert!(parse_date_time("2021-13-01").is_err());
+        assert!(parse_date_time("2021-01-01T25:00:00Z").is_err());
+    }
+
+    #[bpxe_im::test]
+    fn cycles() {
+        let armed_at = date_time("2021-02-21T10:00:00Z");
+
+        let cycle: Cycle = "R2/PT10M".parse().unwrap();
+        assert_eq!(cycle.repetitions, Some(2));
+        assert_eq!(
+            cycle.occurrence(armed_at, 0),
+            Some(date_time("2021-02-21T10:10:00Z"))
+        );
+        assert_eq!(
+            cycle.occurrence(armed_at, 1),
+            Some(date_time("2021-02-21T10:20:00Z"))
+        );
+        assert_eq!(cycle.occurrence(armed_at, 2), None);
+
+        let cycle: Cycle = "R/2021-01-01T00:00:00Z/P1D".parse().unwrap();
+        assert_eq!(cycle.repetitions, None);
+        let schedule = Schedule::Cycle(cycle);
+        assert_eq!(
+            schedule.next(armed_at, armed_at),
+            Some((52, date_time("2021-02-22T00:00:00Z")))
+        );
+
+        let cycle: Cycle = "R/PT1H/2021-02-21T11:30:00Z".parse().unwrap();
+        assert_eq!(
+            cycle.occurrence(armed_at, 0),
+            Some(date_time("2021-02-21T11:00:00Z"))
+        );
+        assert_eq!(cycle.occurrence(armed_at, 1), None);
+
+        // calendar arithmetic in local time
+        let cycle =
+            Cycle::parse_in("R/2021-01-31T00:30:00/P1M", "+02:00".parse().unwrap()).unwrap();
+        assert_eq!(cycle.start, Some(date_time("2021-01-30T22:30:00Z")));
+        assert_eq!(
+            cycle.occurrence(armed_at, 1),
+            Some(date_time("2021-02-27T22:30:00Z"))
+        );
+
+        assert!("PT10M".parse::<Cycle>().is_err());
+        assert!("R2".parse::<Cycle>().is_err());
+    }
+
+    #[bpxe_im::test]
+    fn placeholder_substitution() {
+        let value = |name: &str| match name {
+            "hours" => Some("4".to_string()),
+            "sla" => Some("P2D".to_string()),
+            _ => None,
+        };
+        assert_eq!(placeholders("PT${hours}H/${ sla }"), vec!["hours", "sla"]);
+        assert_eq!(substitute("PT${hours}H", value), Ok("PT4H".to_string()));
+        assert_eq!(substitute("${ sla }", value), Ok("P2D".to_string()));
+        assert_eq!(substitute("PT10M", value), Ok("PT10M".to_string()));
+        assert_eq!(
+            substitute("PT${minutes}M", value),
+            Err(TimerError::UnresolvedPlaceholder("minutes".to_string()))
+        );
+        assert!(substitute("PT${hours", value).is_err());
+    }
+
+    #[bpxe_im::test]
+    fn due() {
+        let armed_at = date_time("2021-02-21T10:00:00Z");
+        assert_eq!(
+            Schedule::Duration("PT10M".parse().unwrap()).due(armed_at),
+            Some(Due::After(Duration::from_secs(600)))
+        );
+        assert_eq!(
+            Schedule::Cycle("R/PT10M".parse().unwrap()).due(armed_at),
+            Some(Due::At(date_time("2021-02-21T10:10:00Z")))
+        );
+    }
+
+    #[bpxe_im::test]
+    async fn wait_for_past_time() {
+        wait(
+            Due::At(date_time("2021-02-21T10:00:00Z")),
+            watch::channel(()).1,
+        )
+        .await;
+    }
+
+    #[bpxe_im::test]
+    async fn wait_through_clock_adjustment() {
+        let (clock, adjustments) = watch::channel(());
+        let at = time::now() + Duration::from_millis(500);
+        let waiting = wait(Due::At(at), adjustments);
+        futures::pin_mut!(waiting);
+        assert!(time::timeout(Duration::from_millis(50), &mut waiting)
+            .await
+            .is_err());
+        // the deadline is re-checked, but it's not there yet
+        let _ = clock.send(());
+        assert!(time::timeout(Duration::from_millis(50), &mut waiting)
+            .await
+            .is_err());
+        // nobody can report adjustments anymore, the deadline is still honored
+        drop(clock);
+        waiting.await;
+        assert!(time::now() >= at);
+    }
+}
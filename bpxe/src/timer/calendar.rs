@@ -0,0 +1,208 @@
+//! # Business calendars
+//!
+//! By default, durations are measured in calendar time. A [`Calendar`] registered with
+//! [`crate::model::Model::with_business_calendar`] changes that for duration timers, so that,
+//! for example, `P2D` means two business days.
+use super::{days_from_civil, from_unix, seconds, to_unix, IsoDuration, SECONDS_IN_DAY};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+// How far ahead to look for the next working day
+const LOOKAHEAD_DAYS: i64 = 366 * 8;
+
+/// Calendar used to measure durations
+pub trait Calendar: Send + Sync {
+    /// Returns the point in time at which `duration` has elapsed since `time`
+    fn add(&self, time: SystemTime, duration: &IsoDuration) -> SystemTime;
+}
+
+/// Business calendar with working days, holidays and business hours
+///
+/// Duration components are counted as follows:
+///
+/// * years and months are applied in calendar time;
+/// * days are counted in working days (and weeks in as many working days as there are
+///   in a week), keeping the time of day;
+/// * hours, minutes and seconds are counted in business hours only.
+///
+/// A duration that starts outside of business hours starts counting at the next opening.
+/// All dates and times are in UTC.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BusinessCalendar {
+    // bit per day of week, Sunday being 0
+    working_days: u8,
+    holidays: HashSet<i64>,
+    opens: Duration,
+    closes: Duration,
+}
+
+impl Default for BusinessCalendar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BusinessCalendar {
+    /// Creates a calendar with working days from Monday to Friday, business hours from
+    /// 9:00 to 17:00 and no holidays
+    pub fn new() -> Self {
+        Self {
+            working_days: 0b0111110,
+            holidays: HashSet::new(),
+            opens: Duration::from_secs(9 * 3600),
+            closes: Duration::from_secs(17 * 3600),
+        }
+    }
+
+    /// Consumes calendar and returns it updated with working days of week
+    ///
+    /// Days of week are numbered from 0 (Sunday) to 6 (Saturday).
+    pub fn with_working_days<I>(self, days: I) -> Self
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        Self {
+            working_days: days
+                .into_iter()
+                .filter(|day| *day < 7)
+                .fold(0, |days, day| days | (1 << day)),
+            ..self
+        }
+    }
+
+    /// Consumes calendar and returns it updated with a holiday
+    pub fn with_holiday(mut self, year: i64, month: u32, day: u32) -> Self {
+        self.holidays.insert(days_from_civil(year, month, day));
+        self
+    }
+
+    /// Consumes calendar and returns it updated with business hours
+    ///
+    /// Both `opens` and `closes` are offsets from midnight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `opens` is not earlier than `closes` or if `closes` is later than midnight.
+    pub fn with_business_hours(self, opens: Duration, closes: Duration) -> Self {
+        assert!(opens < closes && closes <= Duration::from_secs(SECONDS_IN_DAY as u64));
+        Self {
+            opens,
+            closes,
+            ..self
+        }
+    }
+
+    fn is_working_day(&self, day: i64) -> bool {
+        // 1970-01-01 was Thursday
+        let day_of_week = (day + 4).rem_euclid(7);
+        self.working_days & (1 << day_of_week) != 0 && !self.holidays.contains(&day)
+    }
+
+    // If there are no working days at all, every day is considered one
+    fn next_working_day(&self, day: i64) -> i64 {
+        (day + 1..=day + LOOKAHEAD_DAYS)
+            .find(|day| self.is_working_day(*day))
+            .unwrap_or(day + 1)
+    }
+}
+
+impl Calendar for BusinessCalendar {
+    fn add(&self, time: SystemTime, duration: &IsoDuration) -> SystemTime {
+        let time = IsoDuration {
+            years: duration.years,
+            months: duration.months,
+            ..Default::default()
+        }
+        .add_to(time);
+        let (secs, nanos) = to_unix(time);
+        let mut day = secs.div_euclid(SECONDS_IN_DAY);
+        let mut time_of_day = Duration::new(secs.rem_euclid(SECONDS_IN_DAY) as u64, nanos);
+
+        if !self.is_working_day(day) || time_of_day >= self.closes {
+            day = self.next_working_day(day);
+            time_of_day = self.opens;
+        } else if time_of_day < self.opens {
+            time_of_day = self.opens;
+        }
+
+        let days_per_week = self.working_days.count_ones();
+        let days = duration
+            .weeks
+            .checked_mul(days_per_week)
+            .and_then(|days| days.checked_add(duration.days))
+            .unwrap_or(u32::MAX);
+        for _ in 0..days {
+            day = self.next_working_day(day);
+        }
+
+        let mut remaining = Duration::from_secs(duration.hours as u64 * 3600)
+            + Duration::from_secs(duration.minutes as u64 * 60)
+            + seconds(duration.seconds);
+        loop {
+            let available = self.closes - time_of_day;
+            if remaining <= available {
+                time_of_day += remaining;
+                break;
+            }
+            remaining -= available;
+            day = self.next_working_day(day);
+            time_of_day = self.opens;
+        }
+
+        from_unix(
+            day * SECONDS_IN_DAY + time_of_day.as_secs() as i64,
+            time_of_day.subsec_nanos(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_date_time;
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    fn add(calendar: &BusinessCalendar, time: &str, duration: &str) -> SystemTime {
+        calendar.add(
+            parse_date_time(time).unwrap(),
+            &duration.parse::<IsoDuration>().unwrap(),
+        )
+    }
+
+    #[bpxe_im::test]
+    fn business_time() {
+        let calendar = BusinessCalendar::new().with_holiday(2021, 2, 23);
+        // 2021-02-19 is Friday
+        assert_eq!(
+            add(&calendar, "2021-02-19T10:00:00Z", "P2D"),
+            parse_date_time("2021-02-24T10:00:00Z").unwrap()
+        );
+        assert_eq!(
+            add(&calendar, "2021-02-19T16:00:00Z", "PT2H30M"),
+            parse_date_time("2021-02-22T10:30:00Z").unwrap()
+        );
+        // starts at the next opening
+        assert_eq!(
+            add(&calendar, "2021-02-20T12:00:00Z", "PT1H"),
+            parse_date_time("2021-02-22T10:00:00Z").unwrap()
+        );
+        assert_eq!(
+            add(&calendar, "2021-02-19T10:00:00Z", "P1W"),
+            parse_date_time("2021-03-01T10:00:00Z").unwrap()
+        );
+    }
+
+    #[bpxe_im::test]
+    fn custom_calendar() {
+        let calendar = BusinessCalendar::new()
+            .with_working_days(vec![0, 6])
+            .with_business_hours(
+                Duration::from_secs(10 * 3600),
+                Duration::from_secs(12 * 3600),
+            );
+        assert_eq!(
+            add(&calendar, "2021-02-19T10:00:00Z", "PT3H"),
+            parse_date_time("2021-02-21T11:00:00Z").unwrap()
+        );
+    }
+}
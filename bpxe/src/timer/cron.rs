@@ -0,0 +1,238 @@
+//! # Cron expressions
+//!
+//! Supports standard five field (`minute hour day-of-month month day-of-week`) and six field
+//! (with leading seconds) expressions. Every field accepts `*` (`?` is treated the same way),
+//! values, ranges (`1-5`), steps (`*/15`, `5/15`, `1-30/5`) and lists of those (`1,15,30`).
+//! Months and days of week also accept three letter English names (`JAN`, `MON`).
+//!
+//! As in most cron implementations, if both day of month and day of week are restricted, a day
+//! matching either of them is a match.
+//!
+//! Expressions are evaluated in UTC, unless a timezone is specified with [`Cron::with_timezone`].
+use super::{civil_from_days, from_unix, to_unix, TimerError, Timezone, SECONDS_IN_DAY};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+const MONTHS: &[&str] = &[
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const DAYS_OF_WEEK: &[&str] = &["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+// How far ahead to look for the next occurrence (covers leap days)
+const LOOKAHEAD_DAYS: i64 = 366 * 8;
+
+/// Cron expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+    timezone: Timezone,
+}
+
+impl Cron {
+    /// Consumes expression and returns it updated to be evaluated in a given timezone
+    pub fn with_timezone(self, timezone: Timezone) -> Self {
+        Self { timezone, ..self }
+    }
+
+    /// Returns the earliest time matching the expression strictly after `time`
+    ///
+    /// Returns `None` if the expression can never match (for example, `0 0 31 2 *`).
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let (secs, _) = to_unix(self.timezone.to_local(time));
+        let start = secs + 1;
+        let mut day = start.div_euclid(SECONDS_IN_DAY);
+        let mut from = start.rem_euclid(SECONDS_IN_DAY) as u32;
+        for _ in 0..LOOKAHEAD_DAYS {
+            if self.matches_day(day) {
+                if let Some(time_of_day) = self.first_time_of_day(from) {
+                    return Some(
+                        self.timezone
+                            .to_utc(from_unix(day * SECONDS_IN_DAY + time_of_day as i64, 0)),
+                    );
+                }
+            }
+            day += 1;
+            from = 0;
+        }
+        None
+    }
+
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        // 1970-01-01 was Thursday
+        let day_of_week = (day + 4).rem_euclid(7) as u32;
+        if !bit(self.months, month) {
+            return false;
+        }
+        let day_of_month = bit(self.days_of_month, day_of_month);
+        let day_of_week = bit(self.days_of_week, day_of_week);
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    fn first_time_of_day(&self, from: u32) -> Option<u32> {
+        let (from_hour, from_minute, from_second) = (from / 3600, from % 3600 / 60, from % 60);
+        for hour in (from_hour..24).filter(|hour| bit(self.hours, *hour)) {
+            let first_minute = if hour == from_hour { from_minute } else { 0 };
+            for minute in (first_minute..60).filter(|minute| bit(self.minutes, *minute)) {
+                let first_second = if hour == from_hour && minute == from_minute {
+                    from_second
+                } else {
+                    0
+                };
+                if let Some(second) = (first_second..60).find(|second| bit(self.seconds, *second)) {
+                    return Some(hour * 3600 + minute * 60 + second);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for Cron {
+    type Err = TimerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TimerError::InvalidCron(s.to_string());
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (seconds, fields) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            _ => return Err(invalid()),
+        };
+        let days_of_week = parse_field(fields[4], 0, 7, DAYS_OF_WEEK).ok_or_else(invalid)?;
+        Ok(Cron {
+            seconds: parse_field(seconds, 0, 59, &[]).ok_or_else(invalid)?,
+            minutes: parse_field(fields[0], 0, 59, &[]).ok_or_else(invalid)?,
+            hours: parse_field(fields[1], 0, 23, &[]).ok_or_else(invalid)?,
+            days_of_month: parse_field(fields[2], 1, 31, &[]).ok_or_else(invalid)?,
+            months: parse_field(fields[3], 1, 12, MONTHS).ok_or_else(invalid)?,
+            // both 0 and 7 stand for Sunday
+            days_of_week: (days_of_week | (days_of_week >> 7)) & 0x7f,
+            any_day_of_month: is_any(fields[2]),
+            any_day_of_week: is_any(fields[4]),
+            timezone: Timezone::UTC,
+        })
+    }
+}
+
+fn bit(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+fn is_any(field: &str) -> bool {
+    field == "*" || field == "?"
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+    let value = |s: &str| -> Option<u32> {
+        names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(s))
+            .map(|index| min + index as u32)
+            .or_else(|| s.parse().ok())
+    };
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(index) => (&part[..index], part[index + 1..].parse().ok()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return None;
+        }
+        let (start, end) = if is_any(range) {
+            (min, max)
+        } else if let Some(index) = range.find('-') {
+            (value(&range[..index])?, value(&range[index + 1..])?)
+        } else {
+            let start = value(range)?;
+            // `5/15` means "every 15 starting at 5"
+            (start, if step > 1 { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for n in (start..=end).step_by(step) {
+            set |= 1 << n;
+        }
+    }
+    Some(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_date_time;
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+
+    fn next(cron: &str, time: &str) -> Option<SystemTime> {
+        cron.parse::<Cron>()
+            .unwrap()
+            .next_after(parse_date_time(time).unwrap())
+    }
+
+    #[bpxe_im::test]
+    fn next_occurrences() {
+        // every 15 minutes
+        assert_eq!(
+            next("*/15 * * * *", "2021-02-21T10:07:00Z"),
+            parse_date_time("2021-02-21T10:15:00Z").ok()
+        );
+        // strictly after
+        assert_eq!(
+            next("*/15 * * * *", "2021-02-21T10:15:00Z"),
+            parse_date_time("2021-02-21T10:30:00Z").ok()
+        );
+        // weekdays at 9:30 (2021-02-20 is Saturday)
+        assert_eq!(
+            next("30 9 * * MON-FRI", "2021-02-20T12:00:00Z"),
+            parse_date_time("2021-02-22T09:30:00Z").ok()
+        );
+        // with seconds
+        assert_eq!(
+            next("10,40 0 0 1 JAN ?", "2021-02-21T10:00:00Z"),
+            parse_date_time("2022-01-01T00:00:10Z").ok()
+        );
+        // either day of month or day of week (2021-02-21 is Sunday)
+        assert_eq!(
+            next("0 0 13 * 0", "2021-02-20T12:00:00Z"),
+            parse_date_time("2021-02-21T00:00:00Z").ok()
+        );
+        // leap day
+        assert_eq!(
+            next("0 0 29 2 *", "2021-02-21T10:00:00Z"),
+            parse_date_time("2024-02-29T00:00:00Z").ok()
+        );
+        // never
+        assert_eq!(next("0 0 31 2 *", "2021-02-21T10:00:00Z"), None);
+        // in a timezone
+        assert_eq!(
+            "0 9 * * *"
+                .parse::<Cron>()
+                .unwrap()
+                .with_timezone("+02:00".parse().unwrap())
+                .next_after(parse_date_time("2021-02-21T10:00:00Z").unwrap()),
+            parse_date_time("2021-02-22T07:00:00Z").ok()
+        );
+    }
+
+    #[bpxe_im::test]
+    fn invalid_expressions() {
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("* * 0 * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+        assert!("* * * FOO *".parse::<Cron>().is_err());
+    }
+}
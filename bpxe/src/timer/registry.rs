@@ -0,0 +1,247 @@
+//! # Pending timers
+//!
+//! Every process instance keeps track of its armed timers, so that they can be listed,
+//! rescheduled or fired immediately (see [`crate::process::Handle::timers`]).
+//!
+//! Timers don't fire while they are paused (see [`crate::model::Handle::pause_timers`]).
+//! Firing a timer explicitly overrides the pause.
+//!
+//! Timers due at a point in time re-check it when the clock is reported to have been adjusted
+//! (see [`crate::model::Handle::clock_adjusted`]).
+use super::{wait, Due, TimerError};
+use crate::sys::time;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::{mpsc, watch};
+
+/// Timer identifier (unique within a process instance)
+pub type TimerId = u64;
+
+/// Pending timer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PendingTimer {
+    /// Timer identifier
+    pub id: TimerId,
+    /// Flow node that has armed the timer
+    pub node: Option<String>,
+    /// Time at which the timer is due
+    pub due: SystemTime,
+    /// Zero-based repetition index (always zero for timers that fire once)
+    pub repetition: u32,
+}
+
+enum Command {
+    Reschedule(SystemTime),
+    Fire,
+    Cancel,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: TimerId,
+    timers: HashMap<TimerId, (PendingTimer, mpsc::UnboundedSender<Command>)>,
+}
+
+/// Registry of pending timers
+#[derive(Clone)]
+pub(crate) struct Timers {
+    registry: Arc<Mutex<Registry>>,
+    paused: watch::Receiver<bool>,
+    clock: watch::Receiver<()>,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self::new(watch::channel(false).1, watch::channel(()).1)
+    }
+}
+
+impl Timers {
+    /// Creates a registry with timers paused according to `paused` and clock adjustments
+    /// reported by `clock`
+    pub(crate) fn new(paused: watch::Receiver<bool>, clock: watch::Receiver<()>) -> Self {
+        Self {
+            registry: Default::default(),
+            paused,
+            clock,
+        }
+    }
+
+    /// Registers a new timer
+    pub(crate) fn arm(&self, node: Option<String>, due: Due, repetition: u32) -> ArmedTimer {
+        let (sender, commands) = mpsc::unbounded_channel();
+        let mut registry = self.registry.lock().unwrap();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let pending = PendingTimer {
+            id,
+            node,
+            due: match due {
+                Due::After(duration) => time::now() + duration,
+                Due::At(time) => time,
+            },
+            repetition,
+        };
+        registry.timers.insert(id, (pending, sender));
+        ArmedTimer {
+            id,
+            due,
+            commands,
+            paused: self.paused.clone(),
+            clock: self.clock.clone(),
+            timers: self.clone(),
+        }
+    }
+
+    /// Returns all pending timers, earliest first
+    pub(crate) fn list(&self) -> Vec<PendingTimer> {
+        let registry = self.registry.lock().unwrap();
+        let mut timers: Vec<_> = registry
+            .timers
+            .values()
+            .map(|(pending, _)| pending.clone())
+            .collect();
+        timers.sort_by_key(|timer| (timer.due, timer.id));
+        timers
+    }
+
+    /// Changes the time at which a pending timer is due
+    pub(crate) fn reschedule(&self, id: TimerId, due: SystemTime) -> Result<(), TimerError> {
+        let mut registry = self.registry.lock().unwrap();
+        let (pending, sender) = registry
+            .timers
+            .get_mut(&id)
+            .ok_or(TimerError::NotFound(id))?;
+        pending.due = due;
+        sender
+            .send(Command::Reschedule(due))
+            .map_err(|_| TimerError::NotFound(id))
+    }
+
+    /// Makes a pending timer fire immediately
+    pub(crate) fn fire(&self, id: TimerId) -> Result<(), TimerError> {
+        self.send(id, Command::Fire)
+    }
+
+    /// Cancels a pending timer
+    pub(crate) fn cancel(&self, id: TimerId) {
+        let _ = self.send(id, Command::Cancel);
+    }
+
+    fn send(&self, id: TimerId, command: Command) -> Result<(), TimerError> {
+        let registry = self.registry.lock().unwrap();
+        let (_, sender) = registry.timers.get(&id).ok_or(TimerError::NotFound(id))?;
+        sender.send(command).map_err(|_| TimerError::NotFound(id))
+    }
+}
+
+/// Registered timer
+///
+/// Stays pending until it is awaited with [`ArmedTimer::fired`] or dropped.
+pub(crate) struct ArmedTimer {
+    id: TimerId,
+    due: Due,
+    commands: mpsc::UnboundedReceiver<Command>,
+    paused: watch::Receiver<bool>,
+    clock: watch::Receiver<()>,
+    timers: Timers,
+}
+
+impl ArmedTimer {
+    /// Returns timer's identifier
+    pub(crate) fn id(&self) -> TimerId {
+        self.id
+    }
+
+    /// Waits until the timer is due (and not paused), rescheduled to be due or fired
+    ///
+    /// Returns `false` if the timer has been cancelled.
+    pub(crate) async fn fired(mut self) -> bool {
+        let mut due = self.due;
+        let mut elapsed = false;
+        loop {
+            if elapsed && !*self.paused.borrow() {
+                return true;
+            }
+            tokio::select! {
+                _ = wait(due, self.clock.clone()), if !elapsed => elapsed = true,
+                changed = self.paused.changed(), if elapsed => {
+                    if changed.is_err() {
+                        // nobody can resume timers anymore
+                        return true;
+                    }
+                }
+                command = self.commands.recv() => match command {
+                    Some(Command::Reschedule(time)) => {
+                        due = Due::At(time);
+                        elapsed = false;
+                    }
+                    Some(Command::Fire) => return true,
+                    Some(Command::Cancel) | None => return false,
+                },
+            }
+        }
+    }
+}
+
+impl Drop for ArmedTimer {
+    fn drop(&mut self) {
+        self.timers.registry.lock().unwrap().timers.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bpxe_internal_macros as bpxe_im;
+    use std::time::Duration;
+
+    #[bpxe_im::test]
+    async fn reschedule_and_fire() {
+        let timers = Timers::default();
+        let timer = timers.arm(
+            Some("timer".into()),
+            Due::After(Duration::from_secs(3600)),
+            0,
+        );
+        let id = timer.id();
+        assert_eq!(timers.list().len(), 1);
+        assert_eq!(timers.list()[0].node.as_deref(), Some("timer"));
+
+        let due = time::now() + Duration::from_secs(7200);
+        assert!(timers.reschedule(id, due).is_ok());
+        assert_eq!(timers.list()[0].due, due);
+
+        assert!(timers.fire(id).is_ok());
+        assert!(timer.fired().await);
+        assert!(timers.list().is_empty());
+        assert_eq!(timers.fire(id), Err(TimerError::NotFound(id)));
+    }
+
+    #[bpxe_im::test]
+    async fn pause() {
+        let (pause, paused) = watch::channel(true);
+        let timers = Timers::new(paused, watch::channel(()).1);
+        let timer = timers.arm(None, Due::After(Duration::from_millis(10)), 0);
+        let fired = timer.fired();
+        futures::pin_mut!(fired);
+        assert!(time::timeout(Duration::from_millis(100), &mut fired)
+            .await
+            .is_err());
+        assert_eq!(timers.list().len(), 1);
+        let _ = pause.send(false);
+        assert!(fired.await);
+        assert!(timers.list().is_empty());
+    }
+
+    #[bpxe_im::test]
+    async fn cancel() {
+        let timers = Timers::default();
+        let timer = timers.arm(None, Due::After(Duration::from_secs(3600)), 0);
+        timers.cancel(timer.id());
+        assert!(!timer.fired().await);
+        assert!(timers.list().is_empty());
+    }
+}
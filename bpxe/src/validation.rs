@@ -0,0 +1,433 @@
+//! # Validation
+//!
+//! Static analysis of process definitions that finds problems which would otherwise only show
+//! up at runtime (see [`crate::model::Model::validate`]).
+//!
+//! Parallel joins are checked by exploring every way tokens can move through a process without
+//! evaluating conditions or waiting for events: exclusive and event-based gateways take any one
+//! of their outgoing flows, inclusive and complex gateways (as well as conditional flows of
+//! other flow nodes) any combination of them. A parallel join is unbalanced if tokens can get
+//! stuck at it with nothing left to deliver the tokens they should be joined with.
+//!
+//! Exploration is bounded by [`MAX_STATES`], and stops following tokens that pile up on a
+//! sequence flow (which happens in unstructured loops), so such models may not be checked
+//! completely.
+use crate::activity::receive_task::instantiates;
+use crate::bpmn::schema::{
+    ActivityType, Cast, Definitions, FlowElement, FlowNodeType, Process, RootElement,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Maximum number of token distributions explored per process
+pub const MAX_STATES: usize = 10_000;
+
+// tokens on a single sequence flow beyond which exploration doesn't continue
+const MAX_TOKENS: u8 = 2;
+
+// conditional flows of a single flow node beyond which their combinations are not enumerated
+// (all of them are taken instead)
+const MAX_COMBINATIONS: usize = 6;
+
+/// Validation diagnostic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// Tokens can get stuck at a parallel join because some of its incoming flows may never
+    /// deliver tokens to join them with
+    UnbalancedJoin {
+        /// Process the join belongs to
+        process: Option<String>,
+        /// Parallel gateway
+        gateway: Option<String>,
+        /// Incoming flows that may never deliver a token
+        missing: Vec<String>,
+    },
+    /// Fallback sequence flow of a task timeout doesn't exist or doesn't leave the task (see
+    /// [`crate::model::Model::with_task_timeout`])
+    InvalidTimeoutFlow {
+        /// Task the timeout applies to
+        task: String,
+        /// Fallback sequence flow
+        sequence_flow: String,
+    },
+}
+
+/// Validates all processes of definitions
+pub fn validate(definitions: &Definitions) -> Vec<Diagnostic> {
+    definitions
+        .root_elements
+        .iter()
+        .filter_map(|e| match e {
+            RootElement::Process(process) => Some(process),
+            _ => None,
+        })
+        .flat_map(validate_process)
+        .collect()
+}
+
+/// Validates fallback sequence flows of task timeouts, given as pairs of tasks and sequence
+/// flows
+pub(crate) fn validate_timeout_flows<'a, I>(
+    definitions: &Definitions,
+    timeouts: I,
+) -> Vec<Diagnostic>
+where
+    I: Iterator<Item = (&'a String, &'a String)>,
+{
+    let leaves = |task: &str, seq_flow: &str| {
+        definitions.root_elements.iter().any(|e| match e {
+            RootElement::Process(process) => process.flow_elements.iter().any(|e| {
+                matches!(e, FlowElement::SequenceFlow(flow)
+                    if flow.id.as_deref() == Some(seq_flow) && flow.source_ref == task)
+            }),
+            _ => false,
+        })
+    };
+    timeouts
+        .filter(|(task, seq_flow)| !leaves(task, seq_flow))
+        .map(|(task, seq_flow)| Diagnostic::InvalidTimeoutFlow {
+            task: task.clone(),
+            sequence_flow: seq_flow.clone(),
+        })
+        .collect()
+}
+
+/// Validates a process
+pub fn validate_process(process: &Process) -> Vec<Diagnostic> {
+    Net::new(process).unbalanced_joins(process)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Parallel,
+    Exclusive,
+    Inclusive,
+    Other,
+}
+
+struct Node {
+    id: Option<String>,
+    kind: Kind,
+    incomings: Vec<usize>,
+    // sets of outgoing flows the flow node may pass its token on to
+    choices: Vec<Vec<usize>>,
+    start: bool,
+}
+
+impl Node {
+    fn is_join(&self) -> bool {
+        self.incomings.len() > 1
+    }
+}
+
+/// Sequence flows (as places holding tokens) and flow nodes (as transitions moving them)
+struct Net {
+    flows: Vec<String>,
+    nodes: Vec<Node>,
+}
+
+type Marking = Vec<u8>;
+
+impl Net {
+    fn new(process: &Process) -> Self {
+        let mut flows = vec![];
+        let mut conditional = HashSet::new();
+        for element in process.flow_elements.iter() {
+            if let FlowElement::SequenceFlow(seq_flow) = element {
+                if let Some(ref id) = seq_flow.id {
+                    if seq_flow.condition_expression.is_some() {
+                        conditional.insert(flows.len());
+                    }
+                    flows.push(id.clone());
+                }
+            }
+        }
+        let index: HashMap<&str, usize> = flows
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.as_str(), i))
+            .collect();
+        let indices = |ids: &[String]| -> Vec<usize> {
+            ids.iter()
+                .filter_map(|id| index.get(id.as_str()).copied())
+                .collect()
+        };
+        let mut nodes = vec![];
+        for element in process.flow_elements.iter() {
+            let (kind, default) = match element {
+                FlowElement::ParallelGateway(_) => (Kind::Parallel, None),
+                FlowElement::ExclusiveGateway(gateway) => {
+                    (Kind::Exclusive, gateway.default.clone())
+                }
+                FlowElement::EventBasedGateway(_) => (Kind::Exclusive, None),
+                FlowElement::InclusiveGateway(gateway) => {
+                    (Kind::Inclusive, gateway.default.clone())
+                }
+                FlowElement::ComplexGateway(gateway) => (Kind::Inclusive, gateway.default.clone()),
+                FlowElement::SequenceFlow(_) => continue,
+                element => (
+                    Kind::Other,
+                    Cast::<dyn ActivityType>::cast(element)
+                        .and_then(|activity| activity.default().clone()),
+                ),
+            };
+            let node = match Cast::<dyn FlowNodeType>::cast(element) {
+                Some(node) => node,
+                None => continue,
+            };
+            let incomings = indices(node.incomings());
+            let outgoings = indices(node.outgoings());
+            let default = default.and_then(|default| index.get(default.as_str()).copied());
+            let choices = choices(kind, &outgoings, default, &conditional);
+            nodes.push(Node {
+                id: node.id().clone(),
+                kind,
+                start: incomings.is_empty()
+                    && match element {
+                        FlowElement::StartEvent(_) => true,
+                        FlowElement::ReceiveTask(receive_task) => instantiates(receive_task),
+                        _ => false,
+                    },
+                incomings,
+                choices,
+            });
+        }
+        Self { flows, nodes }
+    }
+
+    /// Explores token distributions reachable from start events and reports parallel joins
+    /// tokens got stuck at when nothing could move anymore
+    fn unbalanced_joins(&self, process: &Process) -> Vec<Diagnostic> {
+        let mut queue: VecDeque<Marking> = VecDeque::new();
+        for node in self.nodes.iter().filter(|node| node.start) {
+            for choice in node.choices.iter() {
+                let mut marking = vec![0; self.flows.len()];
+                for flow in choice {
+                    marking[*flow] += 1;
+                }
+                queue.push_back(marking);
+            }
+        }
+        let mut seen: HashSet<Marking> = queue.iter().cloned().collect();
+        let mut missing: HashMap<usize, HashSet<usize>> = HashMap::new();
+        while let Some(marking) = queue.pop_front() {
+            let successors = self.successors(&marking);
+            if successors.is_empty() {
+                self.stuck_joins(&marking, &mut missing);
+            }
+            for successor in successors {
+                if seen.len() >= MAX_STATES {
+                    break;
+                }
+                if successor.iter().any(|tokens| *tokens > MAX_TOKENS) {
+                    continue;
+                }
+                if seen.insert(successor.clone()) {
+                    queue.push_back(successor);
+                }
+            }
+        }
+        let mut diagnostics: Vec<_> = missing
+            .into_iter()
+            .map(|(node, flows)| {
+                let mut missing: Vec<String> = flows
+                    .into_iter()
+                    .map(|flow| self.flows[flow].clone())
+                    .collect();
+                missing.sort();
+                Diagnostic::UnbalancedJoin {
+                    process: process.id.clone(),
+                    gateway: self.nodes[node].id.clone(),
+                    missing,
+                }
+            })
+            .collect();
+        diagnostics.sort_by(|a, b| match (a, b) {
+            (
+                Diagnostic::UnbalancedJoin { gateway: a, .. },
+                Diagnostic::UnbalancedJoin { gateway: b, .. },
+            ) => a.cmp(b),
+            _ => std::cmp::Ordering::Equal,
+        });
+        diagnostics
+    }
+
+    /// Records incoming flows of parallel joins that hold some, but not all, tokens
+    fn stuck_joins(&self, marking: &[u8], missing: &mut HashMap<usize, HashSet<usize>>) {
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.kind != Kind::Parallel || !node.is_join() {
+                continue;
+            }
+            if node.incomings.iter().any(|flow| marking[*flow] > 0) {
+                missing.entry(index).or_default().extend(
+                    node.incomings
+                        .iter()
+                        .filter(|flow| marking[**flow] == 0)
+                        .copied(),
+                );
+            }
+        }
+    }
+
+    fn successors(&self, marking: &[u8]) -> Vec<Marking> {
+        let mut successors = vec![];
+        for node in self.nodes.iter() {
+            if node.kind == Kind::Inclusive && node.is_join() {
+                continue;
+            }
+            self.fire(node, marking, &mut successors);
+        }
+        // inclusive joins wait for everything else that can still move
+        if successors.is_empty() {
+            for node in self.nodes.iter() {
+                if node.kind == Kind::Inclusive && node.is_join() {
+                    self.fire(node, marking, &mut successors);
+                }
+            }
+        }
+        successors
+    }
+
+    fn fire(&self, node: &Node, marking: &[u8], successors: &mut Vec<Marking>) {
+        let holding: Vec<usize> = node
+            .incomings
+            .iter()
+            .copied()
+            .filter(|flow| marking[*flow] > 0)
+            .collect();
+        let consumed: Vec<Vec<usize>> = match node.kind {
+            Kind::Parallel if node.is_join() => {
+                if holding.len() == node.incomings.len() {
+                    vec![holding]
+                } else {
+                    vec![]
+                }
+            }
+            Kind::Inclusive if node.is_join() => {
+                if holding.is_empty() {
+                    vec![]
+                } else {
+                    vec![holding]
+                }
+            }
+            _ => holding.into_iter().map(|flow| vec![flow]).collect(),
+        };
+        for consumed in consumed {
+            let mut marking = marking.to_vec();
+            for flow in consumed {
+                marking[flow] -= 1;
+            }
+            for choice in node.choices.iter() {
+                let mut successor = marking.clone();
+                for flow in choice {
+                    successor[*flow] = successor[*flow].saturating_add(1);
+                }
+                successors.push(successor);
+            }
+        }
+    }
+}
+
+/// Returns sets of outgoing flows a flow node may pass its token on to
+fn choices(
+    kind: Kind,
+    outgoings: &[usize],
+    default: Option<usize>,
+    conditional: &HashSet<usize>,
+) -> Vec<Vec<usize>> {
+    if outgoings.is_empty() {
+        return vec![vec![]];
+    }
+    let non_default: Vec<usize> = outgoings
+        .iter()
+        .copied()
+        .filter(|flow| Some(*flow) != default)
+        .collect();
+    let (always, optional): (Vec<usize>, Vec<usize>) = match kind {
+        Kind::Parallel => return vec![outgoings.to_vec()],
+        Kind::Exclusive => return outgoings.iter().map(|flow| vec![*flow]).collect(),
+        Kind::Inclusive => (vec![], non_default),
+        Kind::Other => non_default
+            .into_iter()
+            .partition(|flow| !conditional.contains(flow)),
+    };
+    if optional.len() > MAX_COMBINATIONS {
+        return vec![always.into_iter().chain(optional).collect()];
+    }
+    let mut choices = vec![];
+    for mask in 0..(1usize << optional.len()) {
+        let mut choice = always.clone();
+        choice.extend(
+            optional
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, flow)| *flow),
+        );
+        if choice.is_empty() {
+            // nothing else is taken, so it's the default flow (if any)
+            choice.extend(default);
+        }
+        if !choice.is_empty() || kind == Kind::Other {
+            choices.push(choice);
+        }
+    }
+    choices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bpmn::parse;
+    use bpxe_internal_macros as bpxe_im;
+
+    #[bpxe_im::test]
+    fn unbalanced_join() {
+        let definitions =
+            parse(include_str!("gateway/test_models/parallel_unbalanced.bpmn")).unwrap();
+        assert_eq!(
+            validate(&definitions),
+            vec![Diagnostic::UnbalancedJoin {
+                process: Some("proc1".into()),
+                gateway: Some("join".into()),
+                missing: vec!["Flow_a_join".into(), "Flow_b_join".into()],
+            }]
+        );
+    }
+
+    #[bpxe_im::test]
+    fn timeout_flows() {
+        let definitions = parse(include_str!("process/test_models/task_timeout.bpmn")).unwrap();
+        let flow = |task: &str, seq_flow: &str| (task.to_string(), seq_flow.to_string());
+        let timeouts = vec![
+            flow("sign", "Flow_escalate"),
+            flow("sign", "Flow_unknown"),
+            flow("end", "Flow_escalate"),
+        ];
+        assert_eq!(
+            validate_timeout_flows(&definitions, timeouts.iter().map(|(a, b)| (a, b))),
+            vec![
+                Diagnostic::InvalidTimeoutFlow {
+                    task: "sign".into(),
+                    sequence_flow: "Flow_unknown".into(),
+                },
+                Diagnostic::InvalidTimeoutFlow {
+                    task: "end".into(),
+                    sequence_flow: "Flow_escalate".into(),
+                },
+            ]
+        );
+    }
+
+    #[bpxe_im::test]
+    fn balanced_join() {
+        // the join doesn't happen at runtime, but only because of an event that never comes
+        let definitions = parse(include_str!(
+            "gateway/test_models/parallel_not_enough_to_join.bpmn"
+        ))
+        .unwrap();
+        assert!(validate(&definitions).is_empty());
+        let definitions =
+            parse(include_str!("gateway/test_models/inclusive_or_join.bpmn")).unwrap();
+        assert!(validate(&definitions).is_empty());
+    }
+}